@@ -0,0 +1,15 @@
+//! Loads arbitrary bytes as a tagged tree file
+//! Loading must never panic, and a loaded tree must be consistent and encode back to its input
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_merkle_tree::merkle::MerkleTree;
+use sha3::Sha3_256;
+
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(tree) = MerkleTree::from_tagged_bytes(Sha3_256::default(), bytes) {
+        tree.verify_self().expect("A loaded tree should be consistent");
+        let encoded = tree.to_tagged_bytes().expect("Should encode tree");
+        assert_eq!(encoded, bytes, "The tree file should round trip");
+    }
+});
@@ -0,0 +1,22 @@
+//! Decodes arbitrary bytes as a tagged proof for a known tree, then verifies it
+//! Decoding must never panic, and a decoded proof must never verify data outside the tree
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_merkle_tree::merkle::MerkleTree;
+
+fuzz_target!(|input: &[u8]| {
+    let data: Vec<Vec<u8>> = (0..5).map(|i| format!("block{}", i).into_bytes()).collect();
+    let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+
+    // The first byte picks the data being proven, the rest is the proof
+    let Some((&pick, bytes)) = input.split_first() else {
+        return;
+    };
+    let element = format!("block{}", pick).into_bytes();
+    if let Ok(proof) = tree.decode_proof(bytes) {
+        if tree.verify_proof(&element, &proof) {
+            assert!(data.contains(&element), "A forged proof verified");
+        }
+    }
+});
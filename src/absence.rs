@@ -0,0 +1,354 @@
+//! Proofs that an element is not in a canonical tree
+//!
+//! The leaves of a tree created with `MerkleTree::canonical` are sorted by hash, so an element
+//! whose hash falls strictly between two adjacent leaves can't be in the tree. The proof holds
+//! those two leaves with their proofs of membership, and the directions of the proofs give
+//! their indices, which have to follow each other. An element before the first leaf or after
+//! the last one only has one neighbor, whose proof shows it is at the edge of the tree.
+//! The proof carries the number of leaves, so each proof of membership must have the exact
+//! shape of the proof of a leaf in a tree of that size, and no internal node passes as a leaf.
+
+use crate::merkle::{
+    hash, hashes_equal, index_from_directions, positioned_root, Hash, Hashable, MerkleError,
+    MerkleProof, MerkleTree, OddNodePolicy, PositionedProof, TreeHasher,
+};
+use sha3::Sha3_256;
+
+/// A leaf next to the missing element, with its proof of membership
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "[u8; N]: serde::Serialize, MerkleProof<N>: serde::Serialize",
+        deserialize = "[u8; N]: serde::Deserialize<'de>, MerkleProof<N>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct Neighbor<const N: usize = 32> {
+    pub leaf: [u8; N],
+    pub proof: MerkleProof<N>,
+}
+
+/// A proof that an element is not in a canonical tree
+/// `predecessor` is the last leaf below the hash of the element and `successor` the first leaf
+/// above it, the element is before the first leaf or after the last one when one is missing
+/// `leaf_count` is the number of leaves of the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "Neighbor<N>: serde::Serialize",
+        deserialize = "Neighbor<N>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct AbsenceProof<const N: usize = 32> {
+    pub leaf_count: usize,
+    pub predecessor: Option<Neighbor<N>>,
+    pub successor: Option<Neighbor<N>>,
+}
+
+impl<D: TreeHasher<N>, const N: usize> MerkleTree<D, N> {
+    /// Generates a proof that `data` is not in the tree
+    ///
+    /// # Returns
+    /// The proof, `DuplicateLeaf` with the index of the element if it is in the tree,
+    /// `EmptyData` for a tree without leaves, or `UnsupportedProof` for a tree that isn't
+    /// canonical, doesn't have an arity of 2 or doesn't pair the last node of odd levels with
+    /// itself
+    ///
+    /// # Example
+    /// ```
+    /// use rusty_merkle_tree::absence::verify_absence;
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let tree = MerkleTree::canonical(&["a", "b", "c"]).expect("Should create merkle tree");
+    /// let proof = tree.prove_absence("d").expect("Should generate proof");
+    /// assert!(verify_absence(&tree.root(), "d", &proof));
+    /// assert!(!verify_absence(&tree.root(), "a", &proof));
+    /// ```
+    pub fn prove_absence<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<AbsenceProof<N>, MerkleError> {
+        if !self.is_canonical()
+            || self.arity() != 2
+            || self.odd_node_policy() != OddNodePolicy::DuplicateLast
+            || self.max_leaves().is_some()
+        {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        if self.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let leaves = self.leaves();
+        let position = match leaves.binary_search(&self.hash_leaf(data)) {
+            Ok(index) => return Err(MerkleError::DuplicateLeaf(index)),
+            Err(position) => position,
+        };
+        let neighbor = |index: usize| -> Result<Neighbor<N>, MerkleError> {
+            Ok(Neighbor {
+                leaf: leaves[index],
+                proof: self.generate_proof_by_index(index)?,
+            })
+        };
+
+        Ok(AbsenceProof {
+            leaf_count: leaves.len(),
+            predecessor: position.checked_sub(1).map(neighbor).transpose()?,
+            successor: (position < leaves.len())
+                .then(|| neighbor(position))
+                .transpose()?,
+        })
+    }
+}
+
+/// The index of a neighbor, if it is a leaf of the tree of `root` with `leaf_count` leaves
+/// Its proof must have the length and directions of the proof of that leaf
+fn member_index(root: &Hash, leaf_count: usize, neighbor: &Neighbor) -> Option<usize> {
+    let proof = PositionedProof {
+        index: index_from_directions(&neighbor.proof)?,
+        leaf_count,
+        proof: neighbor.proof.clone(),
+    };
+    positioned_root(&Sha3_256::default(), neighbor.leaf, &proof)
+        .filter(|computed| hashes_equal(computed, root))
+        .map(|_| proof.index)
+}
+
+/// Verifies a proof of `prove_absence` for `data` with nothing but the root of the tree
+/// The tree must hash like `MerkleTree::canonical`, see `merkle::verify`
+///
+/// # Returns
+///
+/// `true` if the neighbors are leaves of the tree of `proof.leaf_count` leaves on each side of
+/// the hash of `data` and adjacent, or a single neighbor is the first or the last leaf
+pub fn verify_absence<T: Hashable + ?Sized>(root: &Hash, data: &T, proof: &AbsenceProof) -> bool {
+    let element = hash(data.leaf_bytes());
+    let index_of = |neighbor: &Neighbor| member_index(root, proof.leaf_count, neighbor);
+    match (&proof.predecessor, &proof.successor) {
+        (Some(predecessor), Some(successor)) => {
+            predecessor.leaf < element
+                && element < successor.leaf
+                && index_of(predecessor)
+                    .zip(index_of(successor))
+                    .is_some_and(|(before, after)| before + 1 == after)
+        }
+        (None, Some(successor)) => element < successor.leaf && index_of(successor) == Some(0),
+        (Some(predecessor), None) => {
+            predecessor.leaf < element
+                && index_of(predecessor).is_some_and(|index| index + 1 == proof.leaf_count)
+        }
+        (None, None) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect()
+    }
+
+    /// Elements that are not in a tree over `blocks`
+    fn missing(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| format!("missing{}", i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_absence_proofs() {
+        for size in 1..=9 {
+            let tree = MerkleTree::canonical(&blocks(size)).expect("Should create merkle tree");
+            for element in missing(40) {
+                let proof = tree.prove_absence(&element).expect("Should generate proof");
+                assert!(
+                    verify_absence(&tree.root(), &element, &proof),
+                    "size {}",
+                    size
+                );
+            }
+            for block in blocks(size) {
+                assert!(matches!(
+                    tree.prove_absence(&block),
+                    Err(MerkleError::DuplicateLeaf(found)) if tree.leaf(found) == Some(hash(&block))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_absence_at_the_edges() {
+        let tree = MerkleTree::canonical(&blocks(5)).expect("Should create merkle tree");
+        let first = tree.leaf(0).expect("Should have a leaf");
+        let last = tree.leaf(4).expect("Should have a leaf");
+
+        let elements = missing(200);
+        let before = elements
+            .iter()
+            .find(|element| hash(element) < first)
+            .expect("Some element should hash below the first leaf");
+        let after = elements
+            .iter()
+            .find(|element| hash(element) > last)
+            .expect("Some element should hash above the last leaf");
+
+        let proof = tree.prove_absence(before).expect("Should generate proof");
+        assert!(proof.predecessor.is_none());
+        assert!(verify_absence(&tree.root(), before, &proof));
+
+        let proof = tree.prove_absence(after).expect("Should generate proof");
+        assert!(proof.successor.is_none());
+        assert!(verify_absence(&tree.root(), after, &proof));
+
+        // A single leaf is both the first and the last one
+        let single = MerkleTree::canonical(&blocks(1)).expect("Should create merkle tree");
+        let leaf = single.leaf(0).expect("Should have a leaf");
+        for element in missing(10) {
+            let proof = single
+                .prove_absence(&element)
+                .expect("Should generate proof");
+            assert_eq!(proof.predecessor.is_some(), hash(&element) > leaf);
+            assert!(verify_absence(&single.root(), &element, &proof));
+        }
+    }
+
+    #[test]
+    fn test_forged_absence_proofs() {
+        let data = blocks(8);
+        let tree = MerkleTree::canonical(&data).expect("Should create merkle tree");
+        let neighbor = |index: usize| Neighbor {
+            leaf: tree.leaf(index).expect("Should have a leaf"),
+            proof: tree
+                .generate_proof_by_index(index)
+                .expect("Should generate proof"),
+        };
+
+        // Leaves 2 and 4 around leaf 3, which is in the tree
+        let present = tree.leaf(3).expect("Should have a leaf");
+        let element = data
+            .iter()
+            .find(|element| hash(element) == present)
+            .expect("Should find the data of a leaf");
+        let skipping = AbsenceProof {
+            leaf_count: 8,
+            predecessor: Some(neighbor(2)),
+            successor: Some(neighbor(4)),
+        };
+        assert!(!verify_absence(&tree.root(), element, &skipping));
+
+        // Only one neighbor, in the middle of the tree
+        let only_successor = AbsenceProof {
+            leaf_count: 8,
+            predecessor: None,
+            successor: Some(neighbor(4)),
+        };
+        let only_predecessor = AbsenceProof {
+            leaf_count: 8,
+            predecessor: Some(neighbor(2)),
+            successor: None,
+        };
+        assert!(!verify_absence(&tree.root(), element, &only_successor));
+        assert!(!verify_absence(&tree.root(), element, &only_predecessor));
+        assert!(!verify_absence(
+            &tree.root(),
+            element,
+            &AbsenceProof {
+                leaf_count: 8,
+                predecessor: None,
+                successor: None,
+            }
+        ));
+
+        // Adjacent leaves that don't surround the element
+        let proof = AbsenceProof {
+            leaf_count: 8,
+            predecessor: Some(neighbor(5)),
+            successor: Some(neighbor(6)),
+        };
+        assert!(!verify_absence(&tree.root(), element, &proof));
+
+        // A neighbor from another tree
+        let other = MerkleTree::canonical(&blocks(9)).expect("Should create merkle tree");
+        for element in missing(20) {
+            let mut proof = other
+                .prove_absence(&element)
+                .expect("Should generate proof");
+            assert!(!verify_absence(&tree.root(), &element, &proof));
+            proof.predecessor = None;
+            assert!(!verify_absence(&tree.root(), &element, &proof));
+        }
+    }
+
+    #[test]
+    fn test_absence_rejects_internal_nodes_as_neighbors() {
+        let data = blocks(64);
+        let tree = MerkleTree::canonical(&data).expect("Should create merkle tree");
+        let levels = tree.levels();
+        // The node at `index` of `level`, with the proof of its first leaf without the
+        // siblings below it
+        let internal = |level: usize, index: usize| Neighbor {
+            leaf: levels[level][index],
+            proof: tree
+                .generate_proof_by_index(index << level)
+                .expect("Should generate proof")
+                .iter()
+                .skip(level)
+                .map(|(sibling, direction)| (*sibling, direction))
+                .collect::<Vec<_>>()
+                .into(),
+        };
+
+        let mut forgeries = 0;
+        for element in &data {
+            let element_hash = hash(element);
+            for (level, nodes) in levels.iter().enumerate().take(levels.len() - 1).skip(1) {
+                for (index, &node) in nodes.iter().enumerate() {
+                    let mut candidates = vec![];
+                    if index + 1 < nodes.len()
+                        && node < element_hash
+                        && element_hash < nodes[index + 1]
+                    {
+                        candidates.push(AbsenceProof {
+                            leaf_count: tree.len(),
+                            predecessor: Some(internal(level, index)),
+                            successor: Some(internal(level, index + 1)),
+                        });
+                    }
+                    if index == 0 && element_hash < node {
+                        candidates.push(AbsenceProof {
+                            leaf_count: tree.len(),
+                            predecessor: None,
+                            successor: Some(internal(level, index)),
+                        });
+                    }
+                    if index + 1 == nodes.len() && node < element_hash {
+                        candidates.push(AbsenceProof {
+                            leaf_count: tree.len(),
+                            predecessor: Some(internal(level, index)),
+                            successor: None,
+                        });
+                    }
+                    for proof in candidates {
+                        forgeries += 1;
+                        assert!(!verify_absence(&tree.root(), element, &proof));
+                    }
+                }
+            }
+        }
+        assert!(forgeries > 0);
+    }
+
+    #[test]
+    fn test_absence_unsupported_trees() {
+        let tree = MerkleTree::new(&blocks(4)).expect("Should create merkle tree");
+        assert!(matches!(
+            tree.prove_absence(b"missing"),
+            Err(MerkleError::UnsupportedProof)
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+//! Regression tests for second-preimage attacks on unprefixed trees
+//!
+//! Without prefixes a leaf is `H(data)` and a node is `H(left || right)`, so the 64 bytes
+//! `left || right` hash to the same value as their parent. Anyone who knows two sibling nodes
+//! can pass them off as a single leaf, using the proof of their parent. Each test builds the
+//! forged input from public values only and checks which tree settings accept it.
+
+use crate::merkle::{hash_internal_node, verify, MerkleError, MerkleProof, MerkleTree};
+
+fn blocks() -> Vec<Vec<u8>> {
+    (0..4).map(|i| format!("block{}", i).into_bytes()).collect()
+}
+
+/// The concatenation of the first two leaves, with the proof of their parent
+fn forge_parent(tree: &MerkleTree) -> (Vec<u8>, MerkleProof) {
+    let left = tree.leaf(0).expect("Should have a leaf");
+    let right = tree.leaf(1).expect("Should have a leaf");
+    let proof = tree
+        .generate_proof_by_index(0)
+        .expect("Should generate proof");
+    // The proof of the leaf without its first sibling is the proof of its parent
+    ([left, right].concat(), proof.into_iter().skip(1).collect())
+}
+
+#[test]
+fn test_forgery_verifies_in_permissive_mode() {
+    let tree = MerkleTree::new(&blocks()).expect("Should create merkle tree");
+    let (forged, proof) = forge_parent(&tree);
+    assert_eq!(forged.len(), 64);
+    assert!(verify(&tree.root(), &forged, &proof));
+    // A tree checks that the proof is as long as the proof of a leaf
+    assert!(matches!(
+        tree.verify_proof_strict(&forged, &proof),
+        Err(MerkleError::InvalidProofShape)
+    ));
+    // The forged data is not one of the leaves
+    assert!(!blocks().contains(&forged));
+
+    // The two nodes below the root are a preimage of the root, with an empty proof
+    let left = hash_internal_node(
+        &tree.leaf(0).expect("Should have a leaf"),
+        &tree.leaf(1).expect("Should have a leaf"),
+    );
+    let right = hash_internal_node(
+        &tree.leaf(2).expect("Should have a leaf"),
+        &tree.leaf(3).expect("Should have a leaf"),
+    );
+    assert!(verify(
+        &tree.root(),
+        &[left, right].concat(),
+        &MerkleProof::new()
+    ));
+    assert!(!tree.verify_proof(&[left, right].concat(), &MerkleProof::new()));
+}
+
+#[test]
+fn test_strict_leaf_length_blocks_forgery() {
+    let permissive = MerkleTree::new(&blocks()).expect("Should create merkle tree");
+    let strict = MerkleTree::with_strict_leaf_length(&blocks()).expect("Should create merkle tree");
+    assert!(strict.has_strict_leaf_length());
+    assert!(!permissive.has_strict_leaf_length());
+    // The roots of unprefixed trees are kept
+    assert_eq!(strict.root(), permissive.root());
+
+    let (forged, proof) = forge_parent(&strict);
+    assert!(verify(&permissive.root(), &forged, &proof));
+    // The shape check refuses the proof in both trees, only the strict one refuses the data
+    assert!(matches!(
+        permissive.verify_proof_strict(&forged, &proof),
+        Err(MerkleError::InvalidProofShape)
+    ));
+    assert!(matches!(
+        strict.verify_proof_strict(&forged, &proof),
+        Err(MerkleError::AmbiguousLeafLength)
+    ));
+    let committed = strict.committed_root();
+    assert!(!strict.verify_committed_proof(&forged, &proof, &committed, 4));
+
+    // 64-byte data can't enter the tree either
+    assert!(matches!(
+        MerkleTree::with_strict_leaf_length(&[&forged[..]]),
+        Err(MerkleError::AmbiguousLeafLength)
+    ));
+    let mut grown = strict.clone();
+    assert!(matches!(
+        grown.add(&forged[..]),
+        Err(MerkleError::AmbiguousLeafLength)
+    ));
+    assert!(matches!(
+        grown.update(0, &forged),
+        Err(MerkleError::AmbiguousLeafLength)
+    ));
+    assert!(matches!(
+        grown.extend_from(&[b"block4".to_vec(), forged.clone()]),
+        Err(MerkleError::AmbiguousLeafLength)
+    ));
+    assert_eq!(grown.root(), strict.root());
+
+    // Other lengths are accepted
+    grown.add(&[7u8; 63][..]).expect("Should add leaf");
+    grown.add(&[7u8; 65][..]).expect("Should add leaf");
+    for element in [&[7u8; 63][..], &[7u8; 65][..], b"block0"] {
+        let proof = grown
+            .generate_proof(element)
+            .expect("Should generate proof");
+        assert!(grown.verify_proof(element, &proof));
+    }
+}
+
+#[test]
+fn test_domain_separation_blocks_forgery() {
+    let prefixed =
+        MerkleTree::with_domain_separation(&blocks()).expect("Should create merkle tree");
+    let (forged, proof) = forge_parent(&prefixed);
+    assert!(!prefixed.verify_proof(&forged, &proof));
+
+    // With prefixes 64-byte data is an ordinary leaf
+    let mut grown = prefixed.clone();
+    grown.add(&forged[..]).expect("Should add leaf");
+    let proof = grown
+        .generate_proof(&forged)
+        .expect("Should generate proof");
+    assert!(grown.verify_proof(&forged, &proof));
+}
@@ -0,0 +1,144 @@
+use crate::merkle::{Hash, Hashable, MerkleProof};
+
+/// When `verify_batch` stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchMode {
+    /// Stop at the first proof that fails
+    FailFast,
+    /// Verify every proof, reporting all the failures
+    #[default]
+    Exhaustive,
+}
+
+/// The outcome of `verify_batch`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BatchResult {
+    /// Indices of the items whose proof failed, in increasing order
+    /// With `BatchMode::FailFast` only the first one
+    pub failed: Vec<usize>,
+}
+
+impl BatchResult {
+    /// Returns true if every proof of the batch was valid
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Verifies many `(data, proof)` pairs against the same root, see `merkle::verify`
+///
+/// # Returns
+/// The indices of the items that failed, all of them or only the first depending on `mode`
+///
+/// # Example
+/// ```
+/// use rusty_merkle_tree::batch::{verify_batch, BatchMode};
+/// use rusty_merkle_tree::merkle::MerkleTree;
+///
+/// let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+/// let proof = tree.generate_proof("b").expect("Should generate proof");
+/// let result = verify_batch(&tree.root(), &[("b", &proof), ("c", &proof)], BatchMode::Exhaustive);
+/// assert_eq!(result.failed, vec![1]);
+/// ```
+pub fn verify_batch(
+    root: &Hash,
+    items: &[(impl Hashable, &MerkleProof)],
+    mode: BatchMode,
+) -> BatchResult {
+    let mut failures = items
+        .iter()
+        .enumerate()
+        .filter(|(_, (data, proof))| !proof.verify(root, data))
+        .map(|(index, _)| index);
+    let failed = match mode {
+        BatchMode::FailFast => failures.next().into_iter().collect(),
+        BatchMode::Exhaustive => failures.collect(),
+    };
+    BatchResult { failed }
+}
+
+/// Same as `verify_batch`, verifying the proofs on the rayon thread pool
+/// The result is the same, in fail-fast mode it is still the first failing item
+#[cfg(feature = "rayon")]
+pub fn par_verify_batch<T: Hashable + Sync>(
+    root: &Hash,
+    items: &[(T, &MerkleProof)],
+    mode: BatchMode,
+) -> BatchResult {
+    use rayon::prelude::*;
+
+    let fails = |(data, proof): &(T, &MerkleProof)| !proof.verify(root, data);
+    let failed = match mode {
+        BatchMode::FailFast => items.par_iter().position_first(fails).into_iter().collect(),
+        BatchMode::Exhaustive => items
+            .par_iter()
+            .enumerate()
+            .filter(|(_, item)| fails(item))
+            .map(|(index, _)| index)
+            .collect(),
+    };
+    BatchResult { failed }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::MerkleTree;
+
+    fn blocks(size: usize) -> Vec<Vec<u8>> {
+        (0..size)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_reports_failures() {
+        let data = blocks(1000);
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let mut proofs: Vec<MerkleProof> = (0..data.len())
+            .map(|index| {
+                tree.generate_proof_by_index(index)
+                    .expect("Should generate proof")
+            })
+            .collect();
+        let root = tree.root();
+
+        let items: Vec<(&Vec<u8>, &MerkleProof)> = data.iter().zip(&proofs).collect();
+        assert!(verify_batch(&root, &items, BatchMode::Exhaustive).is_ok());
+        assert!(verify_batch(&root, &items, BatchMode::FailFast).is_ok());
+
+        let corrupted = [3, 500, 501, 999];
+        for &index in &corrupted {
+            let mut siblings = Vec::from(proofs[index].clone());
+            siblings[0].0[0] ^= 1;
+            proofs[index] = MerkleProof::from(siblings);
+        }
+        let items: Vec<(&Vec<u8>, &MerkleProof)> = data.iter().zip(&proofs).collect();
+        let result = verify_batch(&root, &items, BatchMode::Exhaustive);
+        assert!(!result.is_ok());
+        assert_eq!(result.failed, corrupted);
+        assert_eq!(
+            verify_batch(&root, &items, BatchMode::FailFast).failed,
+            vec![3]
+        );
+
+        #[cfg(feature = "rayon")]
+        {
+            assert_eq!(
+                par_verify_batch(&root, &items, BatchMode::Exhaustive),
+                result
+            );
+            assert_eq!(
+                par_verify_batch(&root, &items, BatchMode::FailFast).failed,
+                vec![3]
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_batch() {
+        let tree = MerkleTree::new(&blocks(4)).expect("Should create merkle tree");
+        let items: [(&[u8], &MerkleProof); 0] = [];
+        assert!(verify_batch(&tree.root(), &items, BatchMode::FailFast).is_ok());
+    }
+}
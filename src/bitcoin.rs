@@ -0,0 +1,544 @@
+use crate::merkle::{
+    hashes_equal, Hash, MerkleError, MerkleProof, MerkleTree, TreeHasher, ZERO_HASH,
+};
+use crate::multiproof::{helper_positions, PartialMerkleTree};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// A transaction id in the byte order Bitcoin displays it, as shown by block explorers
+/// and `bitcoin-cli`, which is the reverse of the order it is hashed in
+pub type Txid = Hash;
+
+/// Double SHA-256, `SHA-256(SHA-256(data))`, the hash of Bitcoin transactions and block trees
+/// A tree hashed with it over raw transactions has the txids, in internal byte order, as leaves
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256d;
+
+impl TreeHasher for Sha256d {
+    fn hash_parts(&self, parts: &[&[u8]]) -> Hash {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        Sha256::digest(hasher.finalize()).into()
+    }
+}
+
+/// Reverses a hash between the displayed and the internal byte order
+fn reversed(mut hash: Hash) -> Hash {
+    hash.reverse();
+    hash
+}
+
+impl MerkleTree<Sha256d> {
+    /// Creates the Merkle Tree of a Bitcoin block from its transaction ids, in block order
+    /// Internal nodes are hashed with SHA256d, and the last node of odd levels is paired with
+    /// itself like Bitcoin Core does, so the root is the one of the block header
+    ///
+    /// # Returns
+    /// A MerkleTree instance if there is at least one txid, otherwise an error
+    pub fn from_txids(txids: &[Txid]) -> Result<Self, MerkleError> {
+        Self::from_leaves_with_digest(txids.iter().copied().map(reversed).collect())
+    }
+
+    /// Returns the root in the byte order Bitcoin displays it
+    pub fn display_root(&self) -> Hash {
+        reversed(self.root())
+    }
+
+    /// Generates a proof of membership for a transaction given its id
+    pub fn generate_proof_for_txid(&self, txid: &Txid) -> Result<MerkleProof, MerkleError> {
+        self.generate_proof_for_hash(&reversed(*txid))
+    }
+
+    /// Validates a proof for a transaction given its id
+    /// The proof must have the shape of the proof of a transaction of the block, see
+    /// `verify_proof_strict`, so an internal node doesn't pass as a txid
+    pub fn verify_proof_for_txid(&self, txid: &Txid, proof: &MerkleProof) -> bool {
+        self.verify_proof_for_hash(&reversed(*txid), proof)
+    }
+}
+
+/// Computes the merkle root of a Bitcoin block header from its transaction ids, in block order
+/// Both the txids and the root are in the byte order Bitcoin displays them
+/// An empty list has `ZERO_HASH` as its root, like Bitcoin Core returns
+///
+/// # Example
+/// ```
+/// # use rusty_merkle_tree::bitcoin::merkle_root_from_txids;
+/// // A block with a single transaction has its txid as the merkle root
+/// let coinbase = [7u8; 32];
+/// assert_eq!(merkle_root_from_txids(&[coinbase]), coinbase);
+/// ```
+pub fn merkle_root_from_txids(txids: &[Txid]) -> Hash {
+    MerkleTree::from_txids(txids).map_or(ZERO_HASH, |tree| tree.display_root())
+}
+
+/// Most transactions a block can hold, the bound Bitcoin Core puts on the transaction count of
+/// a partial merkle tree, `MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT`
+pub const MAX_MERKLEBLOCK_TRANSACTIONS: usize = 4_000_000 / 240;
+
+/// Number of nodes at `height` in the tree of a block of `count` transactions
+fn tree_width(count: usize, height: usize) -> usize {
+    (count + (1 << height) - 1) >> height
+}
+
+fn write_compact_size(bytes: &mut Vec<u8>, value: usize) {
+    match value {
+        0..=0xfc => bytes.push(value as u8),
+        0xfd..=0xffff => {
+            bytes.push(0xfd);
+            bytes.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            bytes.push(0xfe);
+            bytes.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        _ => {
+            bytes.push(0xff);
+            bytes.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+    }
+}
+
+/// Reads `len` bytes from the front of `bytes`
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], MerkleError> {
+    if bytes.len() < len {
+        return Err(MerkleError::TruncatedInput);
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+/// Reads a CompactSize, refusing the encodings that aren't the shortest like Bitcoin Core does
+fn read_compact_size(bytes: &mut &[u8]) -> Result<usize, MerkleError> {
+    let (value, min) = match take(bytes, 1)?[0] {
+        0xfd => {
+            let mut word = [0u8; 2];
+            word.copy_from_slice(take(bytes, 2)?);
+            (u16::from_le_bytes(word) as u64, 0xfd)
+        }
+        0xfe => {
+            let mut word = [0u8; 4];
+            word.copy_from_slice(take(bytes, 4)?);
+            (u32::from_le_bytes(word) as u64, 0x1_0000)
+        }
+        0xff => {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(take(bytes, 8)?);
+            (u64::from_le_bytes(word), 0x1_0000_0000)
+        }
+        byte => (byte as u64, 0),
+    };
+    if value < min {
+        return Err(MerkleError::InvalidEncoding);
+    }
+    usize::try_from(value).map_err(|_| MerkleError::InvalidEncoding)
+}
+
+impl PartialMerkleTree {
+    /// Encodes the partial tree of a block as Bitcoin's `CPartialMerkleTree`, the part of a
+    /// `merkleblock` message after the block header:
+    /// - 4 bytes: the number of transactions, little-endian
+    /// - a CompactSize count of hashes, then the hashes in internal byte order
+    /// - a CompactSize count of flag bytes, then the flag bits, the first one in the lowest bit
+    ///   and the unused bits of the last byte set to 0
+    ///
+    /// The tree is walked depth-first. Each node has a bit telling whether a kept leaf is below
+    /// it, and the nodes with a clear bit and the kept leaves carry their hash
+    ///
+    /// # Returns
+    /// The bytes, or `InvalidEncoding` if the partial tree is malformed or has more than
+    /// `MAX_MERKLEBLOCK_TRANSACTIONS` leaves
+    pub fn to_merkleblock_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        let positions = helper_positions(&self.indices, self.leaf_count);
+        if !self.is_well_formed()
+            || self.leaf_count > MAX_MERKLEBLOCK_TRANSACTIONS
+            || positions.len() != self.hashes.len()
+        {
+            return Err(MerkleError::InvalidEncoding);
+        }
+        let helpers: HashMap<(usize, usize), Hash> = positions
+            .into_iter()
+            .zip(self.hashes.iter().copied())
+            .collect();
+
+        let mut hashes = Vec::with_capacity(self.hashes.len() + self.leaves.len());
+        let mut bits = Vec::new();
+        let height = self.leaf_count.next_power_of_two().trailing_zeros() as usize;
+        self.build_merkleblock(height, 0, &helpers, &mut hashes, &mut bits);
+
+        let mut bytes = Vec::with_capacity(4 + 9 + 32 * hashes.len() + 9 + bits.len().div_ceil(8));
+        bytes.extend_from_slice(&(self.leaf_count as u32).to_le_bytes());
+        write_compact_size(&mut bytes, hashes.len());
+        for hash in &hashes {
+            bytes.extend_from_slice(hash);
+        }
+        write_compact_size(&mut bytes, bits.len().div_ceil(8));
+        let mut flags = vec![0u8; bits.len().div_ceil(8)];
+        for (position, bit) in bits.into_iter().enumerate() {
+            flags[position / 8] |= (bit as u8) << (position % 8);
+        }
+        bytes.extend_from_slice(&flags);
+        Ok(bytes)
+    }
+
+    /// `TraverseAndBuild` of Bitcoin Core, for the node at `position` of `height`
+    fn build_merkleblock(
+        &self,
+        height: usize,
+        position: usize,
+        helpers: &HashMap<(usize, usize), Hash>,
+        hashes: &mut Vec<Hash>,
+        bits: &mut Vec<bool>,
+    ) {
+        let first = self
+            .indices
+            .partition_point(|&index| index < position << height);
+        let parent_of_match = self
+            .indices
+            .get(first)
+            .is_some_and(|&index| index < (position + 1) << height);
+        bits.push(parent_of_match);
+        if height == 0 && parent_of_match {
+            hashes.push(self.leaves[first]);
+        } else if !parent_of_match {
+            hashes.push(helpers[&(height, position)]);
+        } else {
+            self.build_merkleblock(height - 1, position * 2, helpers, hashes, bits);
+            if position * 2 + 1 < tree_width(self.leaf_count, height - 1) {
+                self.build_merkleblock(height - 1, position * 2 + 1, helpers, hashes, bits);
+            }
+        }
+    }
+
+    /// Decodes a `CPartialMerkleTree`, see `to_merkleblock_bytes`
+    /// It makes the checks of Bitcoin Core's `ExtractMatches`: every hash and every flag byte
+    /// is used, and the two children of a node can't be equal, which would let a tree with a
+    /// repeated last transaction have the root of the real block (CVE-2012-2459). The flags
+    /// must also be the ones `to_merkleblock_bytes` writes, with the unused bits set to 0, and
+    /// a message without any kept transaction has no partial tree
+    /// The root of the result is `root_with(&Sha256d)`, to compare with the header
+    ///
+    /// # Returns
+    /// The partial tree, `TruncatedInput` if the bytes end early, `EmptyData` if no leaf is
+    /// kept, `DuplicatedNode` with the (level, index) of a node whose children are equal, or
+    /// `InvalidEncoding` for any other malformed input
+    pub fn from_merkleblock_bytes(mut bytes: &[u8]) -> Result<Self, MerkleError> {
+        let input = &mut bytes;
+        let mut count = [0u8; 4];
+        count.copy_from_slice(take(input, 4)?);
+        let leaf_count = u32::from_le_bytes(count) as usize;
+        let hash_count = read_compact_size(input)?;
+        if hash_count > input.len() / 32 {
+            return Err(MerkleError::TruncatedInput);
+        }
+        let hashes: Vec<Hash> = take(input, 32 * hash_count)?
+            .chunks_exact(32)
+            .map(|chunk| chunk.try_into().expect("Chunks have 32 bytes"))
+            .collect();
+        let flag_count = read_compact_size(input)?;
+        let flags = take(input, flag_count)?;
+        if !input.is_empty()
+            || leaf_count == 0
+            || leaf_count > MAX_MERKLEBLOCK_TRANSACTIONS
+            || hashes.len() > leaf_count
+            || flags.len() * 8 < hashes.len()
+        {
+            return Err(MerkleError::InvalidEncoding);
+        }
+
+        let mut extraction = Extraction {
+            leaf_count,
+            flags,
+            bits_used: 0,
+            hashes: &hashes,
+            hashes_used: 0,
+            matches: Vec::new(),
+            helpers: HashMap::new(),
+        };
+        let height = leaf_count.next_power_of_two().trailing_zeros() as usize;
+        extraction.extract(height, 0)?;
+        let bits_used = extraction.bits_used;
+        let padding_set = (bits_used..flags.len() * 8)
+            .any(|position| flags[position / 8] >> (position % 8) & 1 == 1);
+        if bits_used.div_ceil(8) != flags.len()
+            || padding_set
+            || extraction.hashes_used != hashes.len()
+        {
+            return Err(MerkleError::InvalidEncoding);
+        }
+        if extraction.matches.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+
+        let (indices, leaves): (Vec<usize>, Vec<Hash>) = extraction.matches.into_iter().unzip();
+        // Only the siblings of the paths to the kept leaves can be helpers
+        let positions = helper_positions(&indices, leaf_count);
+        if positions.len() != extraction.helpers.len() {
+            return Err(MerkleError::InvalidEncoding);
+        }
+        let hashes = positions
+            .iter()
+            .map(|position| extraction.helpers.get(position).copied())
+            .collect::<Option<Vec<_>>>()
+            .ok_or(MerkleError::InvalidEncoding)?;
+        Ok(PartialMerkleTree {
+            leaf_count,
+            indices,
+            leaves,
+            hashes,
+        })
+    }
+}
+
+/// The state of `TraverseAndExtract` of Bitcoin Core
+struct Extraction<'a> {
+    leaf_count: usize,
+    flags: &'a [u8],
+    bits_used: usize,
+    hashes: &'a [Hash],
+    hashes_used: usize,
+    /// The kept leaves and their index, in increasing order
+    matches: Vec<(usize, Hash)>,
+    /// The hashes of the nodes without a kept leaf below them, by (height, position)
+    helpers: HashMap<(usize, usize), Hash>,
+}
+
+impl Extraction<'_> {
+    /// Returns the hash of the node at `position` of `height`
+    fn extract(&mut self, height: usize, position: usize) -> Result<Hash, MerkleError> {
+        if self.bits_used >= self.flags.len() * 8 {
+            return Err(MerkleError::InvalidEncoding);
+        }
+        let parent_of_match = self.flags[self.bits_used / 8] >> (self.bits_used % 8) & 1 == 1;
+        self.bits_used += 1;
+        if height == 0 || !parent_of_match {
+            let hash = *self
+                .hashes
+                .get(self.hashes_used)
+                .ok_or(MerkleError::InvalidEncoding)?;
+            self.hashes_used += 1;
+            if parent_of_match {
+                self.matches.push((position, hash));
+            } else {
+                self.helpers.insert((height, position), hash);
+            }
+            return Ok(hash);
+        }
+        let left = self.extract(height - 1, position * 2)?;
+        let right = if position * 2 + 1 < tree_width(self.leaf_count, height - 1) {
+            let right = self.extract(height - 1, position * 2 + 1)?;
+            if hashes_equal(&left, &right) {
+                return Err(MerkleError::DuplicatedNode(height, position));
+            }
+            right
+        } else {
+            left
+        };
+        Ok(Sha256d.hash_parts(&[&left, &right]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{bytes_from_hex, from_hex};
+
+    #[test]
+    fn test_mainnet_block_100000() {
+        let txids = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ]
+        .map(from_hex);
+        let header_root =
+            from_hex("f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766");
+        assert_eq!(merkle_root_from_txids(&txids), header_root);
+
+        let tree = MerkleTree::from_txids(&txids).expect("Should create merkle tree");
+        assert_eq!(tree.display_root(), header_root);
+        for txid in &txids {
+            let proof = tree
+                .generate_proof_for_txid(txid)
+                .expect("Should generate proof");
+            assert!(tree.verify_proof_for_txid(txid, &proof));
+        }
+        let proof = tree
+            .generate_proof_for_txid(&txids[0])
+            .expect("Should generate proof");
+        assert!(!tree.verify_proof_for_txid(&txids[1], &proof));
+    }
+
+    #[test]
+    fn test_rejects_internal_node_as_txid() {
+        let txids: Vec<Txid> = (0..4u8).map(|i| [i + 1; 32]).collect();
+        let tree = MerkleTree::from_txids(&txids).expect("Should create merkle tree");
+        // The parent of the first two transactions, with the proof of the first one without
+        // its first sibling
+        let node = reversed(tree.levels()[1][0]);
+        let shortened: MerkleProof = tree
+            .generate_proof_for_txid(&txids[0])
+            .expect("Should generate proof")
+            .iter()
+            .skip(1)
+            .map(|(sibling, direction)| (*sibling, direction))
+            .collect::<Vec<_>>()
+            .into();
+        assert!(!tree.verify_proof_for_txid(&node, &shortened));
+    }
+
+    #[test]
+    fn test_odd_levels() {
+        let txids: Vec<Txid> = (0..3u8).map(|i| [i + 1; 32]).collect();
+        // Bitcoin Core pairs the last node with itself, so repeating it keeps the root
+        let mut repeated = txids.clone();
+        repeated.push(txids[2]);
+        assert_eq!(
+            merkle_root_from_txids(&txids),
+            merkle_root_from_txids(&repeated)
+        );
+
+        let leaves: Vec<Hash> = txids.iter().copied().map(reversed).collect();
+        let left = Sha256d.hash_parts(&[&leaves[0], &leaves[1]]);
+        let right = Sha256d.hash_parts(&[&leaves[2], &leaves[2]]);
+        assert_eq!(
+            merkle_root_from_txids(&txids),
+            reversed(Sha256d.hash_parts(&[&left, &right]))
+        );
+        assert_eq!(merkle_root_from_txids(&[]), ZERO_HASH);
+    }
+
+    #[test]
+    fn test_raw_transactions() {
+        // Hashing raw transactions with SHA256d gives their txids as leaves
+        let transactions = [b"tx1".to_vec(), b"tx2".to_vec(), b"tx3".to_vec()];
+        let txids: Vec<Txid> = transactions
+            .iter()
+            .map(|transaction| reversed(Sha256d.hash_parts(&[transaction])))
+            .collect();
+        let tree =
+            MerkleTree::<Sha256d>::with_digest(&transactions).expect("Should create merkle tree");
+        assert_eq!(tree.display_root(), merkle_root_from_txids(&txids));
+        let proof = tree
+            .generate_proof(&transactions[2])
+            .expect("Should generate proof");
+        assert!(tree.verify_proof(&transactions[2], &proof));
+        assert!(tree.verify_proof_for_txid(&txids[2], &proof));
+    }
+
+    #[test]
+    fn test_merkleblock_of_mainnet_block() {
+        // The partial tree of the merkleblock message for block
+        // 000000000000b731f2eef9e8c63173adfb07e41bd53eb0ef0a6b720d6cb6dea4, keeping one of 7 txs
+        let bytes = bytes_from_hex(concat!(
+            "07000000",
+            "04",
+            "3612262624047ee87660be1a707519a443b1c1ce3d248cbfc6c15870f6c5daa2",
+            "019f5b01d4195ecbc9398fbf3c3b1fa9bb3183301d7a1fb3bd174fcfa40a2b65",
+            "41ed70551dd7e841883ab8f0b16bf04176b7d1480e4f0af9f3d4c3595768d068",
+            "20d2a7bc994987302e5b1ac80fc425fe25f8b63169ea78e68fbaaefa59379bbf",
+            "01",
+            "1d",
+        ));
+        let header_root =
+            from_hex("7f16c5962e8bd963659c793ce370d95f093bc7e367117b3c30c1f8fdd0d97287");
+
+        let partial =
+            PartialMerkleTree::from_merkleblock_bytes(&bytes).expect("Should decode merkleblock");
+        assert_eq!(partial.leaf_count, 7);
+        assert_eq!(partial.indices, vec![4]);
+        assert_eq!(partial.root_with(&Sha256d), Some(header_root));
+        assert_eq!(
+            partial
+                .to_merkleblock_bytes()
+                .expect("Should encode merkleblock"),
+            bytes
+        );
+    }
+
+    #[test]
+    fn test_merkleblock_round_trip() {
+        for size in [1usize, 2, 3, 5, 7, 8, 13] {
+            let txids: Vec<Txid> = (0..size)
+                .map(|i| Sha256d.hash_parts(&[&(i as u64).to_le_bytes()]))
+                .collect();
+            let tree = MerkleTree::from_txids(&txids).expect("Should create merkle tree");
+            let selections: [Vec<usize>; 3] =
+                [vec![0], vec![size - 1], (0..size).step_by(2).collect()];
+            for indices in selections {
+                let partial = tree
+                    .extract_partial(&indices)
+                    .expect("Should extract partial tree");
+                let bytes = partial
+                    .to_merkleblock_bytes()
+                    .expect("Should encode merkleblock");
+                let decoded = PartialMerkleTree::from_merkleblock_bytes(&bytes)
+                    .expect("Should decode merkleblock");
+                assert_eq!(decoded, partial);
+                assert_eq!(decoded.root_with(&Sha256d), Some(tree.root()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_merkleblock_rejects_duplicated_transaction() {
+        let txids: Vec<Txid> = (0..3u64)
+            .map(|i| Sha256d.hash_parts(&[&i.to_le_bytes()]))
+            .collect();
+        let real = MerkleTree::from_txids(&txids).expect("Should create merkle tree");
+        let mut mutated = txids.clone();
+        mutated.push(txids[2]);
+        let mutated = MerkleTree::from_txids(&mutated).expect("Should create merkle tree");
+        assert_eq!(mutated.root(), real.root());
+
+        let bytes = mutated
+            .extract_partial(&[3])
+            .expect("Should extract partial tree")
+            .to_merkleblock_bytes()
+            .expect("Should encode merkleblock");
+        assert!(matches!(
+            PartialMerkleTree::from_merkleblock_bytes(&bytes),
+            Err(MerkleError::DuplicatedNode(1, 1))
+        ));
+    }
+
+    #[test]
+    fn test_merkleblock_rejects_malformed_input() {
+        let txids: Vec<Txid> = (0..5u64)
+            .map(|i| Sha256d.hash_parts(&[&i.to_le_bytes()]))
+            .collect();
+        let tree = MerkleTree::from_txids(&txids).expect("Should create merkle tree");
+        let bytes = tree
+            .extract_partial(&[1])
+            .expect("Should extract partial tree")
+            .to_merkleblock_bytes()
+            .expect("Should encode merkleblock");
+
+        let mut padded = bytes.clone();
+        *padded.last_mut().expect("Should have flags") |= 0x80;
+        assert!(matches!(
+            PartialMerkleTree::from_merkleblock_bytes(&padded),
+            Err(MerkleError::InvalidEncoding)
+        ));
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(matches!(
+            PartialMerkleTree::from_merkleblock_bytes(&extended),
+            Err(MerkleError::InvalidEncoding)
+        ));
+        assert!(matches!(
+            PartialMerkleTree::from_merkleblock_bytes(&bytes[..bytes.len() - 1]),
+            Err(MerkleError::TruncatedInput)
+        ));
+        let mut no_transactions = bytes.clone();
+        no_transactions[..4].copy_from_slice(&0u32.to_le_bytes());
+        assert!(matches!(
+            PartialMerkleTree::from_merkleblock_bytes(&no_transactions),
+            Err(MerkleError::InvalidEncoding)
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+use crate::merkle::{hash, Hash, Hashable, MerkleError, MerkleTree};
+
+/// Collects the leaves of a tree before building it
+/// `push` only hashes and buffers each element, no internal node is computed until `finalize`
+/// builds the whole tree at once, so data from several sources can be fed in before
+///
+/// # Example
+/// ```
+/// # use rusty_merkle_tree::builder::TreeBuilder;
+/// let mut builder = TreeBuilder::new();
+/// builder.push(b"block1");
+/// builder.push(b"block2");
+/// let tree = builder.finalize().expect("Should build tree");
+/// assert_eq!(tree.len(), 2);
+/// ```
+#[derive(Debug, Default)]
+pub struct TreeBuilder {
+    leaves: Vec<Hash>,
+}
+
+impl TreeBuilder {
+    /// Creates a builder without any leaves
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a builder with room for `capacity` leaves
+    pub fn with_capacity(capacity: usize) -> Self {
+        TreeBuilder {
+            leaves: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Hashes `item` and buffers it as the next leaf
+    pub fn push<T: Hashable>(&mut self, item: T) {
+        self.leaves.push(hash(item.leaf_bytes()));
+    }
+
+    /// Returns the number of buffered leaves
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns true if nothing was pushed
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Builds the tree over every pushed element, in order
+    /// The result is the same tree `MerkleTree::new()` builds over the same elements
+    ///
+    /// # Returns
+    /// The tree, or `EmptyData` if nothing was pushed
+    pub fn finalize(self) -> Result<MerkleTree, MerkleError> {
+        MerkleTree::from_leaves(self.leaves)
+    }
+}
+
+impl<T: Hashable> Extend<T> for TreeBuilder {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.leaves
+            .extend(iter.into_iter().map(|item| hash(item.leaf_bytes())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finalize_matches_new() {
+        let data: Vec<Vec<u8>> = (0..3000)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let mut builder = TreeBuilder::with_capacity(data.len());
+        // Feed the data from two sources
+        for element in &data[..1000] {
+            builder.push(element);
+        }
+        builder.extend(&data[1000..]);
+        assert_eq!(builder.len(), data.len());
+
+        let tree = builder.finalize().expect("Should build tree");
+        let expected = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(tree.root(), expected.root());
+        let proof = tree
+            .generate_proof(&data[1234])
+            .expect("Should generate proof");
+        assert!(expected.verify_proof(&data[1234], &proof));
+    }
+
+    #[test]
+    fn test_finalize_empty() {
+        let builder = TreeBuilder::new();
+        assert!(builder.is_empty());
+        assert!(matches!(builder.finalize(), Err(MerkleError::EmptyData)));
+    }
+}
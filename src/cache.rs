@@ -0,0 +1,375 @@
+//! The proof cache of `MerkleTree::enable_proof_cache`
+
+use crate::merkle::MerkleProof;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Proofs of `generate_proof_by_index` by leaf index, evicting the least recently used one
+/// when full. The proofs are only valid for the levels they were generated from, so every
+/// change to the levels goes through `clear`, `invalidate_from` or `invalidate_path`
+/// The proofs are behind a mutex, since they are generated from a shared reference to the
+/// tree. It is only held to look up and insert, never while a proof is generated, and a
+/// disabled cache has no mutex at all
+#[derive(Debug, Default)]
+pub(crate) struct ProofCache<const N: usize> {
+    state: Option<Mutex<CacheState<N>>>,
+}
+
+#[derive(Debug, Clone)]
+struct CacheState<const N: usize> {
+    capacity: usize,
+    /// Each proof with the tick of its last use
+    proofs: HashMap<usize, (MerkleProof<N>, u64)>,
+    tick: u64,
+}
+
+impl<const N: usize> Clone for ProofCache<N> {
+    fn clone(&self) -> Self {
+        ProofCache {
+            state: self
+                .state
+                .as_ref()
+                .map(|state| Mutex::new(lock(state).clone())),
+        }
+    }
+}
+
+fn lock<const N: usize>(state: &Mutex<CacheState<N>>) -> MutexGuard<'_, CacheState<N>> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<const N: usize> CacheState<N> {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+impl<const N: usize> ProofCache<N> {
+    /// The proofs of an enabled cache, without locking since the cache is borrowed mutably
+    fn state_mut(&mut self) -> Option<&mut CacheState<N>> {
+        self.state
+            .as_mut()
+            .map(|state| state.get_mut().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Starts caching up to `capacity` proofs, dropping the ones already cached
+    pub(crate) fn enable(&mut self, capacity: usize) {
+        self.state = Some(Mutex::new(CacheState {
+            capacity,
+            proofs: HashMap::with_capacity(capacity),
+            tick: 0,
+        }));
+    }
+
+    /// Stops caching and drops the cached proofs
+    pub(crate) fn disable(&mut self) {
+        self.state = None;
+    }
+
+    /// Returns the number of cached proofs
+    pub(crate) fn len(&self) -> usize {
+        self.state
+            .as_ref()
+            .map_or(0, |state| lock(state).proofs.len())
+    }
+
+    /// Returns the cached proof of the leaf at `index`, or the one `generate` computes, which
+    /// is cached if the cache is enabled
+    /// Proofs of the same index generated at once by several threads are the same, since the
+    /// levels can't change while the tree is shared, the last one inserted is kept
+    pub(crate) fn get_or_insert_with(
+        &self,
+        index: usize,
+        generate: impl FnOnce() -> MerkleProof<N>,
+    ) -> MerkleProof<N> {
+        let Some(state) = &self.state else {
+            return generate();
+        };
+        {
+            let mut state = lock(state);
+            let tick = state.next_tick();
+            if let Some((proof, last_use)) = state.proofs.get_mut(&index) {
+                *last_use = tick;
+                return proof.clone();
+            }
+        }
+        let proof = generate();
+
+        let mut state = lock(state);
+        if state.capacity == 0 {
+            return proof;
+        }
+        let tick = state.next_tick();
+        if state.proofs.len() == state.capacity && !state.proofs.contains_key(&index) {
+            let oldest = state
+                .proofs
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(&oldest, _)| oldest)
+                .expect("A full cache has a proof");
+            state.proofs.remove(&oldest);
+        }
+        state.proofs.insert(index, (proof.clone(), tick));
+        proof
+    }
+
+    /// Drops every cached proof, for levels rebuilt from scratch
+    pub(crate) fn clear(&mut self) {
+        if let Some(state) = self.state_mut() {
+            state.proofs.clear();
+        }
+    }
+
+    /// Drops the proofs that can change when the leaves from `start` on change, and every
+    /// proof if the height of the tree changes from `old_height` to `new_height`
+    /// The sibling of a leaf at `level` covers the leaves from `s << level` to
+    /// `(s + 1) << level`, where `s` is the index of the sibling, so the proof changes only if
+    /// one of them ends past `start`. A node without a sibling, which is duplicated, padded or
+    /// promoted, has a sibling index past the last node and counts as changed as well
+    pub(crate) fn invalidate_from(&mut self, start: usize, old_height: usize, new_height: usize) {
+        if old_height != new_height {
+            return self.clear();
+        }
+        self.retain(|index| {
+            (0..new_height.saturating_sub(1)).all(|level| {
+                let sibling = (index >> level) ^ 1;
+                (sibling + 1)
+                    .checked_shl(level as u32)
+                    .is_some_and(|end| end <= start)
+            })
+        });
+    }
+
+    /// Drops the proofs with a sibling on the path of the leaf at `changed`, the nodes that
+    /// change when only that leaf does. `level_sizes` are the number of nodes of each level
+    /// A node without a sibling may be paired with itself, so it counts as its own sibling
+    pub(crate) fn invalidate_path(&mut self, changed: usize, level_sizes: &[usize]) {
+        let height = level_sizes.len().saturating_sub(1);
+        self.retain(|index| {
+            level_sizes[..height]
+                .iter()
+                .enumerate()
+                .all(|(level, &size)| {
+                    let node = index >> level;
+                    let sibling = if node ^ 1 < size { node ^ 1 } else { node };
+                    sibling != changed >> level
+                })
+        });
+    }
+
+    fn retain(&mut self, keep: impl Fn(usize) -> bool) {
+        if let Some(state) = self.state_mut() {
+            state.proofs.retain(|&index, _| keep(index));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProofCache;
+    use crate::merkle::{MerkleError, MerkleTree};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// A tree with the proof of every leaf cached
+    fn cached_tree(count: usize) -> MerkleTree {
+        let data: Vec<String> = (0..count).map(|i| format!("leaf {i}")).collect();
+        let mut tree = MerkleTree::new(&data).expect("Should build the tree");
+        tree.enable_proof_cache(64);
+        for index in 0..count {
+            tree.generate_proof_by_index(index)
+                .expect("Should generate the proof");
+        }
+        tree
+    }
+
+    /// Every proof, cached or not, is the one computed without a cache and proves the leaf
+    fn assert_fresh(tree: &MerkleTree) {
+        let mut fresh = tree.clone();
+        fresh.disable_proof_cache();
+        for index in 0..tree.len() {
+            let proof = tree
+                .generate_proof_by_index(index)
+                .expect("Should generate the proof");
+            let expected = fresh
+                .generate_proof_by_index(index)
+                .expect("Should generate the fresh proof");
+            assert_eq!(proof, expected);
+            let leaf = tree.leaf(index).expect("Should have the leaf");
+            assert_eq!(proof.compute_root_from_leaf(&leaf), tree.root());
+        }
+    }
+
+    #[test]
+    fn test_cache_hits_match_fresh_proofs() {
+        let tree = cached_tree(7);
+        assert_eq!(tree.cached_proof_count(), 7);
+        assert_fresh(&tree);
+    }
+
+    #[test]
+    fn test_append_never_returns_a_stale_proof() {
+        let mut tree = cached_tree(5);
+        let stale = tree
+            .generate_proof_by_index(4)
+            .expect("Should generate the proof");
+        tree.add("leaf 5").expect("Should add the leaf");
+
+        let proof = tree
+            .generate_proof_by_index(4)
+            .expect("Should generate the proof");
+        assert_ne!(proof, stale);
+        assert!(tree.verify_proof("leaf 4", &proof));
+        assert_fresh(&tree);
+
+        // Including appends that add a level
+        for count in 6..10 {
+            tree.add(format!("leaf {count}").as_str())
+                .expect("Should add the leaf");
+            assert_fresh(&tree);
+        }
+    }
+
+    #[test]
+    fn test_update_keeps_unchanged_proofs() {
+        let mut tree = cached_tree(8);
+        tree.update(7, &"leaf 7 updated")
+            .expect("Should update the leaf");
+        // Every other proof has an ancestor of the updated leaf as a sibling
+        assert_eq!(tree.cached_proof_count(), 1);
+        assert_fresh(&tree);
+
+        // The last node of an odd level is paired with itself
+        let mut tree = cached_tree(5);
+        tree.update(4, &"leaf 4 updated")
+            .expect("Should update the leaf");
+        assert_eq!(tree.cached_proof_count(), 0);
+        assert_fresh(&tree);
+    }
+
+    #[test]
+    fn test_remove_and_truncate_invalidate_proofs() {
+        let mut tree = cached_tree(9);
+        tree.remove(3).expect("Should remove the leaf");
+        assert_fresh(&tree);
+
+        let mut tree = cached_tree(8);
+        tree.truncate(7);
+        assert!(matches!(
+            tree.generate_proof_by_index(7),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        assert_fresh(&tree);
+        tree.truncate(3);
+        assert_fresh(&tree);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut tree = cached_tree(4);
+        let mut cache = ProofCache::default();
+        cache.enable(2);
+        for index in [0, 1, 0, 2] {
+            cache.get_or_insert_with(index, || {
+                tree.generate_proof_by_index(index)
+                    .expect("Should generate the proof")
+            });
+        }
+        // The proof of leaf 1 was the least recently used one
+        for index in [0, 2] {
+            let proof = cache.get_or_insert_with(index, || panic!("Should be cached"));
+            assert_eq!(
+                proof,
+                tree.generate_proof_by_index(index)
+                    .expect("Should generate the proof")
+            );
+        }
+        let mut generated = false;
+        cache.get_or_insert_with(1, || {
+            generated = true;
+            tree.generate_proof_by_index(1)
+                .expect("Should generate the proof")
+        });
+        assert!(generated);
+        assert_eq!(cache.len(), 2);
+
+        tree.enable_proof_cache(2);
+        assert_eq!(tree.cached_proof_count(), 0);
+        tree.disable_proof_cache();
+        assert_eq!(tree.cached_proof_count(), 0);
+        assert_fresh(&tree);
+    }
+
+    /// Generates a proof in each of `threads` threads at once, each generation waiting for
+    /// the others to start. Returns how many were running at once, at most, as seen by each
+    fn concurrent_generations(
+        cache: &ProofCache<32>,
+        tree: &MerkleTree,
+        threads: usize,
+    ) -> Vec<usize> {
+        let running = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|index| {
+                    let running = &running;
+                    scope.spawn(move || {
+                        let mut seen = 0;
+                        let proof = cache.get_or_insert_with(index, || {
+                            running.fetch_add(1, Ordering::SeqCst);
+                            let deadline = Instant::now() + Duration::from_secs(5);
+                            while running.load(Ordering::SeqCst) < threads
+                                && Instant::now() < deadline
+                            {
+                                thread::yield_now();
+                            }
+                            seen = running.load(Ordering::SeqCst);
+                            tree.generate_proof_by_index(index)
+                                .expect("Should generate the proof")
+                        });
+                        let leaf = tree.leaf(index).expect("Should have the leaf");
+                        assert_eq!(proof.compute_root_from_leaf(&leaf), tree.root());
+                        seen
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Should not panic"))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_proofs_are_generated_outside_the_lock() {
+        let tree = cached_tree(8);
+        let mut cache = ProofCache::default();
+        assert_eq!(concurrent_generations(&cache, &tree, 4), [4; 4]);
+
+        cache.enable(16);
+        assert_eq!(concurrent_generations(&cache, &tree, 4), [4; 4]);
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn test_concurrent_proofs_from_a_shared_tree() {
+        let tree = cached_tree(64);
+        thread::scope(|scope| {
+            for prover in 0..4 {
+                let tree = &tree;
+                scope.spawn(move || {
+                    for round in 0..200 {
+                        let index = (round * 7 + prover) % tree.len();
+                        let proof = tree
+                            .generate_proof_by_index(index)
+                            .expect("Should generate the proof");
+                        let leaf = tree.leaf(index).expect("Should have the leaf");
+                        assert_eq!(proof.compute_root_from_leaf(&leaf), tree.root());
+                    }
+                });
+            }
+        });
+        assert_fresh(&tree);
+    }
+}
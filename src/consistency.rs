@@ -0,0 +1,416 @@
+//! Consistency proofs between two sizes of an append-only tree, as in RFC 6962 §2.1.2
+//!
+//! A tree created with `MerkleTree::new_rfc6962` splits its leaves at the largest power of two
+//! smaller than their number, so the tree over the first `m` leaves is made of complete
+//! subtrees of any later tree. A consistency proof holds the roots of the subtrees a verifier
+//! needs to compute both the old and the new root, which proves the new tree only appended
+//! leaves to the old one.
+
+use crate::merkle::{
+    hashes_equal, Hash, Hashable, MerkleError, MerkleProof, MerkleTree, OddNodePolicy, NODE_PREFIX,
+};
+use sha2::{Digest, Sha256};
+
+/// Type alias for a consistency proof, from the lowest subtree up to the highest
+pub type ConsistencyProof = Vec<Hash>;
+
+/// What an append-only log returns for an appended entry, see `MerkleTree::append_and_prove`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppendReceipt {
+    /// The index of the entry, which is also the size of the tree before the append
+    pub index: usize,
+    /// The inclusion proof of the entry against `new_root`
+    pub proof: MerkleProof,
+    /// The root of the tree after the append
+    pub new_root: Hash,
+    /// The proof that the tree of `new_root` extends the tree of `index` leaves, empty for
+    /// the first entry since there is no previous root
+    pub consistency: ConsistencyProof,
+}
+
+/// `SHA-256(0x01 || left || right)`, an internal node of RFC 6962
+pub(crate) fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The largest power of two smaller than `size`, which has to be at least 2
+fn split_point(size: usize) -> usize {
+    1 << (usize::BITS - 1 - (size - 1).leading_zeros())
+}
+
+impl MerkleTree<Sha256> {
+    /// Generates the proof that this tree extends the tree over its first `old_size` leaves,
+    /// `PROOF(old_size, D[n])` of RFC 6962 §2.1.2
+    ///
+    /// # Returns
+    /// The proof, empty when `old_size` is the size of the tree, `IndexOutOfBounds` if
+    /// `old_size` is 0 or larger than the tree, or `UnsupportedProof` for a tree not created
+    /// with `new_rfc6962`
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// use rusty_merkle_tree::consistency::verify_consistency;
+    ///
+    /// let old = MerkleTree::new_rfc6962(&[b"a", b"b", b"c"]).expect("Should create merkle tree");
+    /// let mut new = old.clone();
+    /// new.add(b"d".to_vec()).expect("Should add leaf");
+    /// let proof = new.consistency_proof(3).expect("Should generate proof");
+    /// assert!(verify_consistency(&old.root(), 3, &new.root(), 4, &proof));
+    /// ```
+    pub fn consistency_proof(&self, old_size: usize) -> Result<ConsistencyProof, MerkleError> {
+        if !self.is_rfc6962() {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        if old_size == 0 || old_size > self.len() {
+            return Err(MerkleError::IndexOutOfBounds);
+        }
+        let mut proof = Vec::new();
+        self.subproof(0, self.len(), old_size, true, &mut proof);
+        Ok(proof)
+    }
+
+    /// `SUBPROOF(old_end - start, D[start:end], complete)`, appending to `proof`
+    fn subproof(
+        &self,
+        start: usize,
+        end: usize,
+        old_end: usize,
+        complete: bool,
+        proof: &mut ConsistencyProof,
+    ) {
+        if old_end == end {
+            // The old tree has this subtree, which the verifier already knows if it is the
+            // whole old tree
+            if !complete {
+                proof.push(self.subtree_root(start, end));
+            }
+            return;
+        }
+        let split = start + split_point(end - start);
+        if old_end <= split {
+            self.subproof(start, split, old_end, complete, proof);
+            proof.push(self.subtree_root(split, end));
+        } else {
+            self.subproof(split, end, old_end, false, proof);
+            proof.push(self.subtree_root(start, split));
+        }
+    }
+
+    /// Appends `data` and returns its receipt, all computed from the same state of the tree
+    /// Since the tree is borrowed mutably for the whole call, a log that shares it behind a
+    /// lock gets receipts that agree with each other whatever the order of the appends
+    ///
+    /// # Returns
+    /// The receipt, `UnsupportedProof` for a tree not created with `new_rfc6962`,
+    /// `DuplicateLeaf` if the data is already in a tree that doesn't repeat leaves, or the
+    /// error of `MerkleTree::add()`, in which case the tree is unchanged
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// use rusty_merkle_tree::consistency::verify_consistency;
+    ///
+    /// let mut tree = MerkleTree::new_rfc6962(&[b"a", b"b"]).expect("Should create merkle tree");
+    /// let old_root = tree.root();
+    /// let receipt = tree.append_and_prove(b"c".to_vec()).expect("Should append entry");
+    /// assert_eq!(receipt.index, 2);
+    /// assert!(verify_consistency(&old_root, 2, &receipt.new_root, 3, &receipt.consistency));
+    /// ```
+    pub fn append_and_prove<T: Hashable>(&mut self, data: T) -> Result<AppendReceipt, MerkleError> {
+        if !self.is_rfc6962() {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        let old_size = self.len();
+        let index = self.add(data)?;
+        if self.len() == old_size {
+            return Err(MerkleError::DuplicateLeaf(index));
+        }
+        let consistency = if old_size == 0 {
+            ConsistencyProof::new()
+        } else {
+            self.consistency_proof(old_size)?
+        };
+        Ok(AppendReceipt {
+            index,
+            proof: self.generate_proof_by_index(index)?,
+            new_root: self.root(),
+            consistency,
+        })
+    }
+
+    /// True if the tree hashes like the trees of `new_rfc6962`
+    pub(crate) fn is_rfc6962(&self) -> bool {
+        self.has_domain_separation()
+            && self.odd_node_policy() == OddNodePolicy::PromoteToNextLevel
+            && self.arity() == 2
+            && !self.has_sorted_pairs()
+            && self.max_leaves().is_none()
+    }
+
+    /// The root of the leaves `start..end`
+    /// Every subtree of a consistency proof starts at a multiple of the power of two at least
+    /// as large as it, so it is a node of the levels
+    fn subtree_root(&self, start: usize, end: usize) -> Hash {
+        let level = (end - start).next_power_of_two().trailing_zeros() as usize;
+        self.levels()[level][start >> level]
+    }
+}
+
+/// Verifies that the tree of `new_root` over `new_size` leaves extends the tree of `old_root`
+/// over `old_size` leaves, with the algorithm of RFC 9162 §2.1.4.2 for RFC 6962 trees
+///
+/// # Returns
+///
+/// `true` if the proof leads to both roots. Equal sizes need an empty proof and equal roots,
+/// and an old size of 0 or larger than the new one always fails
+pub fn verify_consistency(
+    old_root: &Hash,
+    old_size: usize,
+    new_root: &Hash,
+    new_size: usize,
+    proof: &ConsistencyProof,
+) -> bool {
+    if old_size == 0 || old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && hashes_equal(old_root, new_root);
+    }
+
+    // The old root starts the path when it is itself a subtree of the new tree
+    let mut path = proof.iter();
+    let first = if old_size.is_power_of_two() {
+        old_root
+    } else {
+        match path.next() {
+            Some(first) => first,
+            None => return false,
+        }
+    };
+    let mut old_index = old_size - 1;
+    let mut new_index = new_size - 1;
+    while old_index & 1 == 1 {
+        old_index >>= 1;
+        new_index >>= 1;
+    }
+
+    let mut old_hash = *first;
+    let mut new_hash = *first;
+    for sibling in path {
+        if new_index == 0 {
+            return false;
+        }
+        if old_index & 1 == 1 || old_index == new_index {
+            old_hash = node_hash(sibling, &old_hash);
+            new_hash = node_hash(sibling, &new_hash);
+            if old_index & 1 == 0 {
+                while old_index & 1 == 0 && old_index != 0 {
+                    old_index >>= 1;
+                    new_index >>= 1;
+                }
+            }
+        } else {
+            new_hash = node_hash(&new_hash, sibling);
+        }
+        old_index >>= 1;
+        new_index >>= 1;
+    }
+
+    new_index == 0 && hashes_equal(&old_hash, old_root) && hashes_equal(&new_hash, new_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{from_hex, RFC6962};
+
+    fn rfc6962_tree(size: usize) -> MerkleTree<Sha256> {
+        MerkleTree::new_rfc6962(&RFC6962.leaves[..size]).expect("Should create merkle tree")
+    }
+
+    #[test]
+    fn test_consistency_test_vectors() {
+        for vector in RFC6962.consistency {
+            let (old_size, new_size) = (vector.old_size, vector.new_size);
+            let old = rfc6962_tree(old_size);
+            let new = rfc6962_tree(new_size);
+            let proof = new
+                .consistency_proof(old_size)
+                .expect("Should generate proof");
+            let expected: Vec<Hash> = vector.proof.iter().map(|hex| from_hex(hex)).collect();
+            assert_eq!(proof, expected, "{} to {}", old_size, new_size);
+            assert!(verify_consistency(
+                &old.root(),
+                old_size,
+                &new.root(),
+                new_size,
+                &proof
+            ));
+        }
+    }
+
+    #[test]
+    fn test_consistency_every_size() {
+        for new_size in 1..=8 {
+            let new = rfc6962_tree(new_size);
+            for old_size in 1..=new_size {
+                let old = rfc6962_tree(old_size);
+                let proof = new
+                    .consistency_proof(old_size)
+                    .expect("Should generate proof");
+                assert!(
+                    verify_consistency(&old.root(), old_size, &new.root(), new_size, &proof),
+                    "{} to {}",
+                    old_size,
+                    new_size
+                );
+
+                for position in 0..proof.len() {
+                    let mut tampered = proof.clone();
+                    tampered[position][0] ^= 1;
+                    assert!(!verify_consistency(
+                        &old.root(),
+                        old_size,
+                        &new.root(),
+                        new_size,
+                        &tampered
+                    ));
+                }
+                if old_size < new_size {
+                    assert!(!verify_consistency(
+                        &new.root(),
+                        old_size,
+                        &old.root(),
+                        new_size,
+                        &proof
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_consistency_larger_trees() {
+        let data: Vec<Vec<u8>> = (0..300)
+            .map(|i| format!("entry{}", i).into_bytes())
+            .collect();
+        let new = MerkleTree::new_rfc6962(&data).expect("Should create merkle tree");
+        for old_size in [1, 2, 37, 64, 100, 255, 256, 257, 299] {
+            let old = MerkleTree::new_rfc6962(&data[..old_size]).expect("Should create tree");
+            let proof = new
+                .consistency_proof(old_size)
+                .expect("Should generate proof");
+            assert!(
+                verify_consistency(&old.root(), old_size, &new.root(), 300, &proof),
+                "{} to 300",
+                old_size
+            );
+        }
+    }
+
+    #[test]
+    fn test_consistency_rejects_rewritten_history() {
+        let old = rfc6962_tree(3);
+        let mut leaves = RFC6962.leaves[..7].to_vec();
+        leaves[1] = b"rewritten";
+        let rewritten = MerkleTree::new_rfc6962(&leaves).expect("Should create merkle tree");
+        let proof = rewritten
+            .consistency_proof(3)
+            .expect("Should generate proof");
+        assert!(!verify_consistency(
+            &old.root(),
+            3,
+            &rewritten.root(),
+            7,
+            &proof
+        ));
+    }
+
+    #[test]
+    fn test_consistency_errors() {
+        let tree = rfc6962_tree(5);
+        assert!(matches!(
+            tree.consistency_proof(0),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        assert!(matches!(
+            tree.consistency_proof(6),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        assert!(!verify_consistency(
+            &tree.root(),
+            0,
+            &tree.root(),
+            5,
+            &vec![]
+        ));
+        assert!(!verify_consistency(
+            &tree.root(),
+            5,
+            &tree.root(),
+            5,
+            &vec![tree.root()]
+        ));
+
+        let duplicated =
+            MerkleTree::<Sha256>::with_digest(RFC6962.leaves).expect("Should create merkle tree");
+        assert!(matches!(
+            duplicated.consistency_proof(3),
+            Err(MerkleError::UnsupportedProof)
+        ));
+    }
+
+    #[test]
+    fn test_append_and_prove_receipts() {
+        let mut tree =
+            MerkleTree::<Sha256>::new_rfc6962::<&[u8]>(&[]).expect("Should create merkle tree");
+        let mut previous: Option<AppendReceipt> = None;
+        for entry in 0..20u32 {
+            let data = entry.to_be_bytes().to_vec();
+            let receipt = tree
+                .append_and_prove(data.clone())
+                .expect("Should append entry");
+            assert_eq!(receipt.index, entry as usize);
+            assert_eq!(receipt.new_root, tree.root());
+
+            let audit_path: Vec<Hash> = receipt.proof.iter().map(|(hash, _)| *hash).collect();
+            let leaf = tree.leaf(receipt.index).expect("Should have leaf");
+            assert!(crate::ct::verify_ct_inclusion(
+                &leaf,
+                receipt.index as u64,
+                receipt.index as u64 + 1,
+                &receipt.new_root,
+                &audit_path
+            ));
+            assert!(tree.verify_proof(&data, &receipt.proof));
+
+            match &previous {
+                Some(previous) => assert!(verify_consistency(
+                    &previous.new_root,
+                    receipt.index,
+                    &receipt.new_root,
+                    receipt.index + 1,
+                    &receipt.consistency
+                )),
+                None => assert!(receipt.consistency.is_empty()),
+            }
+            previous = Some(receipt);
+        }
+    }
+
+    #[test]
+    fn test_append_and_prove_errors() {
+        let mut plain =
+            MerkleTree::<Sha256>::with_digest(RFC6962.leaves).expect("Should create merkle tree");
+        assert!(matches!(
+            plain.append_and_prove(b"x".to_vec()),
+            Err(MerkleError::UnsupportedProof)
+        ));
+        assert_eq!(plain.len(), RFC6962.leaves.len());
+    }
+}
@@ -0,0 +1,583 @@
+//! Verification of the inclusion proofs of Certificate Transparency logs, RFC 6962 §2.1.1
+//!
+//! A log answers `get-proof-by-hash` with the index of the leaf and its audit path, the
+//! sibling hashes from the leaf up, in base64. Unlike a `MerkleProof` the path has no
+//! directions: they follow from the leaf index and the tree size of the signed tree head.
+//! With the `signing` feature, a tree created with `MerkleTree::new_rfc6962` can publish its
+//! own signed tree heads, see `SignedTreeHead`.
+
+use crate::consistency::node_hash;
+#[cfg(feature = "signing")]
+use crate::merkle::MerkleTree;
+use crate::merkle::{hashes_equal, Hash, MerkleError};
+#[cfg(feature = "signing")]
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+#[cfg(feature = "signing")]
+use sha2::Sha256;
+
+/// Verifies that `leaf_hash` is the leaf at `leaf_index` of the log tree of `tree_size` leaves
+/// and root `root`, with the algorithm of RFC 9162 §2.1.3.2
+/// `leaf_hash` is the hash of the `MerkleTreeLeaf` with its `0x00` prefix, as sent to
+/// `get-proof-by-hash`
+///
+/// # Returns
+/// `true` if the audit path leads from the leaf to the root. An index outside the tree and a
+/// path that is too short or too long always fail
+///
+/// # Example
+/// ```
+/// use rusty_merkle_tree::ct::{decode_audit_path, verify_ct_inclusion};
+/// # use rusty_merkle_tree::merkle::MerkleTree;
+///
+/// let tree = MerkleTree::new_rfc6962(&[b"a", b"b", b"c"]).expect("Should create merkle tree");
+/// let leaf_hash = tree.leaf(2).expect("Should have leaf");
+/// let audit_path = decode_audit_path(&["sTeYX/SE+2ANuTEHx3sDZcgNePW0Kd7Q/Zc2HQd5mes="])
+///     .expect("Should decode audit path");
+/// assert!(verify_ct_inclusion(&leaf_hash, 2, 3, &tree.root(), &audit_path));
+/// ```
+pub fn verify_ct_inclusion(
+    leaf_hash: &Hash,
+    leaf_index: u64,
+    tree_size: u64,
+    root: &Hash,
+    audit_path: &[Hash],
+) -> bool {
+    if leaf_index >= tree_size {
+        return false;
+    }
+
+    let mut index = leaf_index;
+    let mut last = tree_size - 1;
+    let mut hash = *leaf_hash;
+    for sibling in audit_path {
+        if last == 0 {
+            return false;
+        }
+        if index & 1 == 1 || index == last {
+            hash = node_hash(sibling, &hash);
+            // A left node without a right sibling is promoted over the levels it spans
+            while index & 1 == 0 && index != 0 {
+                index >>= 1;
+                last >>= 1;
+            }
+        } else {
+            hash = node_hash(&hash, sibling);
+        }
+        index >>= 1;
+        last >>= 1;
+    }
+
+    last == 0 && hashes_equal(&hash, root)
+}
+
+const BASE64_DIGITS: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `bytes` in padded standard base64, the encoding of the JSON API of CT logs
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for position in 0..4 {
+            if position <= chunk.len() {
+                let digit = (bits >> (18 - 6 * position)) & 63;
+                encoded.push(BASE64_DIGITS[digit as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes padded standard base64
+///
+/// # Returns
+/// The bytes, or `InvalidEncoding` for a length that isn't a multiple of 4, another character,
+/// or set bits after the last byte, which the canonical encoding doesn't have
+pub fn decode_base64(base64: &str) -> Result<Vec<u8>, MerkleError> {
+    let digits = base64.as_bytes();
+    if !digits.len().is_multiple_of(4) {
+        return Err(MerkleError::InvalidEncoding);
+    }
+    let padding = digits
+        .iter()
+        .rev()
+        .take_while(|&&digit| digit == b'=')
+        .count();
+    if padding > 2 {
+        return Err(MerkleError::InvalidEncoding);
+    }
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    let mut bytes = Vec::with_capacity(digits.len() / 4 * 3);
+    for &digit in &digits[..digits.len() - padding] {
+        let value = BASE64_DIGITS
+            .iter()
+            .position(|&candidate| candidate == digit)
+            .ok_or(MerkleError::InvalidEncoding)?;
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    if bits & ((1 << bit_count) - 1) != 0 {
+        return Err(MerkleError::InvalidEncoding);
+    }
+    Ok(bytes)
+}
+
+/// Decodes a hash from the padded standard base64 of the JSON API of CT logs
+///
+/// # Returns
+/// The hash, or `InvalidEncoding` if `base64` isn't the 44 characters of a 32-byte value
+pub fn decode_hash(base64: &str) -> Result<Hash, MerkleError> {
+    decode_base64(base64)?
+        .try_into()
+        .map_err(|_| MerkleError::InvalidEncoding)
+}
+
+/// Decodes the `audit_path` of a `get-proof-by-hash` response, see `decode_hash`
+///
+/// # Returns
+/// The audit path, or `InvalidEncoding` if one of the hashes is malformed
+pub fn decode_audit_path<S: AsRef<str>>(audit_path: &[S]) -> Result<Vec<Hash>, MerkleError> {
+    audit_path
+        .iter()
+        .map(|hash| decode_hash(hash.as_ref()))
+        .collect()
+}
+
+/// `Version` of RFC 6962 §3.2, `v1`
+pub const VERSION_V1: u8 = 0;
+
+/// `SignatureType` of the tree heads, `tree_hash`
+pub const SIGNATURE_TYPE_TREE_HASH: u8 = 1;
+
+/// `HashAlgorithm` of the `DigitallySigned` struct for ed25519, which hashes the message
+/// itself: the `ed25519` scheme `0x0807` of TLS 1.3
+pub const HASH_ALGORITHM_INTRINSIC: u8 = 8;
+
+/// `SignatureAlgorithm` of the `DigitallySigned` struct for ed25519
+pub const SIGNATURE_ALGORITHM_ED25519: u8 = 7;
+
+/// The `TreeHeadSignature` struct of RFC 6962 §3.5, the input of the signature of a tree
+/// head: the version, the signature type, the timestamp and the tree size as big-endian
+/// 64-bit integers, and the root
+pub fn tree_head_signature_input(timestamp: u64, tree_size: u64, root: &Hash) -> [u8; 50] {
+    let mut input = [0u8; 50];
+    input[0] = VERSION_V1;
+    input[1] = SIGNATURE_TYPE_TREE_HASH;
+    input[2..10].copy_from_slice(&timestamp.to_be_bytes());
+    input[10..18].copy_from_slice(&tree_size.to_be_bytes());
+    input[18..].copy_from_slice(root);
+    input
+}
+
+/// A signed tree head, with the fields of the `get-sth` response of RFC 6962 §4.3
+/// With the `serde` feature it (de)serializes to that JSON shape, with the root and the
+/// signature in base64
+#[cfg(feature = "signing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignedTreeHead {
+    pub tree_size: u64,
+    /// Milliseconds since the epoch
+    pub timestamp: u64,
+    #[cfg_attr(feature = "serde", serde(with = "base64_hash"))]
+    pub sha256_root_hash: Hash,
+    /// The TLS encoding of the `DigitallySigned` struct: the hash and signature algorithms,
+    /// then the signature with a 2-byte big-endian length
+    #[cfg_attr(feature = "serde", serde(with = "base64_bytes"))]
+    pub tree_head_signature: Vec<u8>,
+}
+
+#[cfg(feature = "signing")]
+impl SignedTreeHead {
+    /// Signs the head of `tree` at `timestamp`, in milliseconds since the epoch, with ed25519
+    ///
+    /// # Returns
+    /// The signed tree head, or `UnsupportedProof` for a tree not created with `new_rfc6962`
+    ///
+    /// # Example
+    /// ```
+    /// use ed25519_dalek::SigningKey;
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// use rusty_merkle_tree::ct::SignedTreeHead;
+    ///
+    /// let signer = SigningKey::from_bytes(&[7u8; 32]);
+    /// let tree = MerkleTree::new_rfc6962(&[b"a", b"b"]).expect("Should create merkle tree");
+    /// let sth = SignedTreeHead::new(&tree, 1_700_000_000_000, &signer)
+    ///     .expect("Should sign tree head");
+    /// assert!(sth.verify(&signer.verifying_key()));
+    /// ```
+    pub fn new(
+        tree: &MerkleTree<Sha256>,
+        timestamp: u64,
+        signer: &SigningKey,
+    ) -> Result<Self, MerkleError> {
+        if !tree.is_rfc6962() {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        let root = tree.root();
+        let tree_size = tree.len() as u64;
+        let signature = signer.sign(&tree_head_signature_input(timestamp, tree_size, &root));
+        let mut tree_head_signature = vec![HASH_ALGORITHM_INTRINSIC, SIGNATURE_ALGORITHM_ED25519];
+        tree_head_signature.extend_from_slice(&(Signature::BYTE_SIZE as u16).to_be_bytes());
+        tree_head_signature.extend_from_slice(&signature.to_bytes());
+        Ok(SignedTreeHead {
+            tree_size,
+            timestamp,
+            sha256_root_hash: root,
+            tree_head_signature,
+        })
+    }
+
+    /// Returns the bytes the signature is over, see `tree_head_signature_input`
+    pub fn signature_input(&self) -> [u8; 50] {
+        tree_head_signature_input(self.timestamp, self.tree_size, &self.sha256_root_hash)
+    }
+
+    /// Returns true if `tree_head_signature` is an ed25519 `DigitallySigned` struct of the
+    /// signature input, valid for `verifying_key`
+    pub fn verify(&self, verifying_key: &VerifyingKey) -> bool {
+        let [HASH_ALGORITHM_INTRINSIC, SIGNATURE_ALGORITHM_ED25519, rest @ ..] =
+            &self.tree_head_signature[..]
+        else {
+            return false;
+        };
+        let Some((length, signature)) = rest.split_first_chunk::<2>() else {
+            return false;
+        };
+        let Ok(signature) = <[u8; Signature::BYTE_SIZE]>::try_from(signature) else {
+            return false;
+        };
+        u16::from_be_bytes(*length) as usize == Signature::BYTE_SIZE
+            && verifying_key
+                .verify_strict(&self.signature_input(), &Signature::from_bytes(&signature))
+                .is_ok()
+    }
+}
+
+#[cfg(all(feature = "signing", feature = "serde"))]
+mod base64_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::encode_base64(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        super::decode_base64(&encoded).map_err(|_| serde::de::Error::custom("invalid base64"))
+    }
+}
+
+#[cfg(all(feature = "signing", feature = "serde"))]
+mod base64_hash {
+    use crate::merkle::Hash;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hash: &Hash, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::encode_base64(hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Hash, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        super::decode_hash(&encoded).map_err(|_| serde::de::Error::custom("invalid hash"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::MerkleTree;
+    use crate::test_vectors::{from_hex, RFC6962};
+
+    #[test]
+    fn test_ct_inclusion_test_vectors() {
+        // The inclusion proofs of the RFC 6962 test vectors in the form of `get-proof-by-hash`
+        // responses, as (leaf hash, leaf index, tree size, root, audit path)
+        let tree = MerkleTree::new_rfc6962(RFC6962.leaves).expect("Should create merkle tree");
+        let responses = RFC6962.inclusion.iter().map(|vector| {
+            let leaf_hash = tree.leaf(vector.leaf_index).expect("Should have leaf");
+            let audit_path: Vec<String> = vector
+                .path
+                .iter()
+                .map(|hash| encode_base64(&from_hex(hash)))
+                .collect();
+            (
+                encode_base64(&leaf_hash),
+                vector.leaf_index as u64,
+                vector.tree_size as u64,
+                RFC6962.roots[vector.tree_size],
+                audit_path,
+            )
+        });
+        assert_eq!(
+            encode_base64(&tree.leaf(0).expect("Should have leaf")),
+            "bjQLnP+zepicpUTmu3gKLHiQHT+zNzh2hRGjBhevoB0="
+        );
+
+        for (leaf_hash, leaf_index, tree_size, root, audit_path) in responses {
+            let leaf_hash = decode_hash(&leaf_hash).expect("Should decode leaf hash");
+            let root = from_hex(root);
+            let audit_path = decode_audit_path(&audit_path).expect("Should decode audit path");
+            assert!(
+                verify_ct_inclusion(&leaf_hash, leaf_index, tree_size, &root, &audit_path),
+                "leaf {} of {}",
+                leaf_index,
+                tree_size
+            );
+            assert!(!verify_ct_inclusion(
+                &leaf_hash,
+                leaf_index ^ 1,
+                tree_size,
+                &root,
+                &audit_path
+            ));
+            assert!(!verify_ct_inclusion(
+                &leaf_hash,
+                leaf_index,
+                tree_size * 2,
+                &root,
+                &audit_path
+            ));
+            assert!(!verify_ct_inclusion(
+                &leaf_hash,
+                leaf_index,
+                tree_size,
+                &root,
+                &audit_path[..audit_path.len() - 1]
+            ));
+        }
+    }
+
+    #[test]
+    fn test_ct_inclusion_matches_rfc6962_trees() {
+        for size in 1..=RFC6962.leaves.len() {
+            let tree = MerkleTree::new_rfc6962(&RFC6962.leaves[..size])
+                .expect("Should create merkle tree");
+            for (index, leaf) in RFC6962.leaves[..size].iter().enumerate() {
+                let proof = tree.generate_proof(leaf).expect("Should generate proof");
+                let audit_path: Vec<Hash> = proof.iter().map(|(hash, _)| *hash).collect();
+                let leaf_hash = tree.leaf(index).expect("Should have leaf");
+                assert!(verify_ct_inclusion(
+                    &leaf_hash,
+                    index as u64,
+                    size as u64,
+                    &tree.root(),
+                    &audit_path
+                ));
+                let mut extended = audit_path.clone();
+                extended.push(tree.root());
+                assert!(!verify_ct_inclusion(
+                    &leaf_hash,
+                    index as u64,
+                    size as u64,
+                    &tree.root(),
+                    &extended
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_ct_inclusion_of_production_log() {
+        // Responses of the Google Argon2020 log, https://ct.googleapis.com/logs/argon2020/,
+        // captured on 2024-09-16 for the entry at index 10000, as vendored by the `tlog_tiles`
+        // crate from the `sumdb/tlog` tests of Go
+        // get-sth
+        let tree_size = 961984011;
+        let sha256_root_hash = "AWxD3p811N3m4WTinWRXPvBZHfT9IR9g/R4C7SKLPiU=";
+        // get-proof-by-hash?tree_size=961984011&hash=nCs3mfIydh9x4CKyQ%2Bu2xtmpxmFggA5MLymHwNQSlLg%3D
+        // where the hash is SHA-256(0x00 || leaf_input) of get-entries?start=10000&end=10000
+        let leaf_hash = "nCs3mfIydh9x4CKyQ+u2xtmpxmFggA5MLymHwNQSlLg=";
+        let leaf_index = 10000;
+        let audit_path = [
+            "T46AmJsuOM/gFiGlUp+IJJebp+1Z5qratFNW7U0JyOQ=",
+            "9Jj1Feodn42uRYEfffkV4HF/EIPFQ5Zo9IwkxbQ2uhk=",
+            "D53/1oCNebF4J59HHgioovKZxrmrKcVuCVhy3Izg8Ck=",
+            "wSFDgGSrsXRPA+1c8WHoAFCsWeUv8NDyP/Q/SgsyVyY=",
+            "516/RcwsgdwdzHn18v1dJHqBkgFUbNhmCIWOGjsMJaE=",
+            "naTFY2ajLFgrgs5XUl+zJYZnnlJksLGsA1VYwpLfqbY=",
+            "mKBRJo6qao+0Kdh/rhGkZUbHUAxMhrtrclrTpIbNjIg=",
+            "Co4ueOFE7lpifLio94irxxsv1wFMMaK3tPu2ZQkHlro=",
+            "yRgy800K6JMYCC05sWfqF2kvCg2riT/pVM+nEa6V5+0=",
+            "n2O38XJZC8/Wpbwi9HhgVizlUyeUHVZvYyQB1607MRc=",
+            "go3tAjuNfNSCj87y2Rg1xOroDknGN1eaf3cgvzObf7Q=",
+            "8D0Mj3/J7ySSnFb0afIHYXv6rlOhECnsebY6XKi1IR8=",
+            "6rcez46ZU8M5vPiWVMAUfJfAOV0pPP/mch/lYzZhj/M=",
+            "2rtIeXrPvSdUyHc5maIgSAVB2BzaKaupWxSAiVayDE4=",
+            "nniW/IMFQPN5xw8UsHFn7CRStYbm1gLu7NNa6kkRqJQ=",
+            "mRPPi7sQS22MduIEyvcNzCNFqIt6kLwYPkNKrVtIcu8=",
+            "JvTivzESNS9wHI8aph5a7uieVmtS/p9PciMZ5D6GyOo=",
+            "UTyQd+Ym0wY4zG+qK806bBORwi1CSVvFQ/KknnqoZj8=",
+            "hXODrqN+ff+U6zPqRQ+ZQKs6im1fPcxIerDSfNwflhE=",
+            "II6GE+WY/DrpZzfHztLwkR46o37zVkNLCyPYlf68zA0=",
+            "d609kWWcNBllbA8Jb/ad4XAVVGnScLRmtP8FYyr+Dck=",
+            "rikBsP0G0gioXRpBFED2CzQdSLrGPvRd0bEcbEZw1rI=",
+            "GXIElfmnRuWfbA4z7ubV/UwlpSRUbUeec7oL5HDDnh0=",
+            "pR0SH/NYP6s9+8azWwaeUXA4Ze/3WCIgMfh5YW6wvcE=",
+            "f3GuAKYVrF8RBg3s15jvA/YvV9oAliWtzP/imwPhQVA=",
+            "WHn8zDxlVgkV+lSlmzaOdfSBrTHoRr5nT3d76q0KKWE=",
+            "f2uV2ZxVgULzLPKuZcowRY3ZY7fRlX59iDBjV1R8kNQ=",
+            "NzQVjJsvQwbi7WStMr9oFkh9VbHfK7LB6o7WwAqwNTs=",
+            "C0LttBeYHZHgL+032JIcJC3CTBIyza/hsbmJUgJfmDI=",
+            "30HzZbwT4DdqHQi4f7+WRG6M4YwVGaKgGXicDvSFT6k=",
+        ];
+
+        let root = decode_hash(sha256_root_hash).expect("Should decode root");
+        let leaf_hash = decode_hash(leaf_hash).expect("Should decode leaf hash");
+        let audit_path = decode_audit_path(&audit_path).expect("Should decode audit path");
+        assert!(verify_ct_inclusion(
+            &leaf_hash,
+            leaf_index,
+            tree_size,
+            &root,
+            &audit_path
+        ));
+        assert!(!verify_ct_inclusion(
+            &leaf_hash,
+            leaf_index + 1,
+            tree_size,
+            &root,
+            &audit_path
+        ));
+        assert!(!verify_ct_inclusion(
+            &leaf_hash,
+            leaf_index,
+            tree_size * 2,
+            &root,
+            &audit_path
+        ));
+        let mut tampered = audit_path.clone();
+        tampered[17][0] ^= 1;
+        assert!(!verify_ct_inclusion(
+            &leaf_hash, leaf_index, tree_size, &root, &tampered
+        ));
+    }
+
+    #[test]
+    fn test_ct_inclusion_rejects_out_of_range_index() {
+        let leaf_hash = [0u8; 32];
+        assert!(!verify_ct_inclusion(&leaf_hash, 0, 0, &leaf_hash, &[]));
+        assert!(verify_ct_inclusion(&leaf_hash, 0, 1, &leaf_hash, &[]));
+        assert!(!verify_ct_inclusion(&leaf_hash, 1, 1, &leaf_hash, &[]));
+    }
+
+    #[test]
+    fn test_decode_hash_rejects_malformed_base64() {
+        let valid = "bjQLnP+zepicpUTmu3gKLHiQHT+zNzh2hRGjBhevoB0=";
+        assert_eq!(
+            decode_hash(valid).expect("Should decode hash"),
+            from_hex("6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d")
+        );
+        for invalid in [
+            "bjQLnP+zepicpUTmu3gKLHiQHT+zNzh2hRGjBhevoB0",
+            "bjQLnP+zepicpUTmu3gKLHiQHT+zNzh2hRGjBhevoB1=",
+            "bjQLnP-zepicpUTmu3gKLHiQHT+zNzh2hRGjBhevoB0=",
+            "bjQLnP+zepicpUTmu3gKLHiQHT+zNzh2hRGjBhevoB0==",
+        ] {
+            assert!(matches!(
+                decode_hash(invalid),
+                Err(MerkleError::InvalidEncoding)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        for length in 0..10usize {
+            let bytes: Vec<u8> = (0..length).map(|i| (i * 37 + 5) as u8).collect();
+            let encoded = encode_base64(&bytes);
+            assert_eq!(encoded.len(), length.div_ceil(3) * 4);
+            assert_eq!(
+                decode_base64(&encoded).expect("Should decode base64"),
+                bytes
+            );
+        }
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert!(matches!(
+            decode_base64("Zm9=="),
+            Err(MerkleError::InvalidEncoding)
+        ));
+        assert!(matches!(
+            decode_base64("Zm9==="),
+            Err(MerkleError::InvalidEncoding)
+        ));
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_tree_head_signature_input_fixture() {
+        // The `TreeHeadSignature` of the RFC 6962 test tree of 8 leaves, packed with Python's
+        // struct.pack(">BBQQ", 0, 1, timestamp, 8) followed by the root
+        let tree = MerkleTree::new_rfc6962(RFC6962.leaves).expect("Should create merkle tree");
+        let signer = SigningKey::from_bytes(&[1u8; 32]);
+        let sth =
+            SignedTreeHead::new(&tree, 1_700_000_000_123, &signer).expect("Should sign tree head");
+        let hex: String = sth
+            .signature_input()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(
+            hex,
+            concat!(
+                "0001",
+                "0000018bcfe5687b",
+                "0000000000000008",
+                "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+            )
+        );
+        assert_eq!(&sth.tree_head_signature[..4], &[8, 7, 0, 64]);
+        assert!(sth.verify(&signer.verifying_key()));
+        assert!(!sth.verify(&SigningKey::from_bytes(&[2u8; 32]).verifying_key()));
+
+        let mut tampered = sth.clone();
+        tampered.tree_size = 7;
+        assert!(!tampered.verify(&signer.verifying_key()));
+        let mut tampered = sth.clone();
+        tampered.tree_head_signature.pop();
+        assert!(!tampered.verify(&signer.verifying_key()));
+
+        let plain =
+            MerkleTree::<Sha256>::with_digest(RFC6962.leaves).expect("Should create merkle tree");
+        assert!(matches!(
+            SignedTreeHead::new(&plain, 0, &signer),
+            Err(MerkleError::UnsupportedProof)
+        ));
+    }
+
+    #[cfg(all(feature = "signing", feature = "serde"))]
+    #[test]
+    fn test_signed_tree_head_json() {
+        let tree =
+            MerkleTree::new_rfc6962(&RFC6962.leaves[..5]).expect("Should create merkle tree");
+        let signer = SigningKey::from_bytes(&[1u8; 32]);
+        let sth =
+            SignedTreeHead::new(&tree, 1_700_000_000_000, &signer).expect("Should sign tree head");
+
+        let json: serde_json::Value = serde_json::to_value(&sth).expect("Should serialize STH");
+        assert_eq!(json["tree_size"], 5);
+        assert_eq!(json["timestamp"], 1_700_000_000_000u64);
+        assert_eq!(
+            json["sha256_root_hash"],
+            "Tju7H3tHjc/nH7YxYxUZo7yhLJrvyhYSv85ME6hiZNQ="
+        );
+        assert!(json["tree_head_signature"]
+            .as_str()
+            .expect("Should be a string")
+            .starts_with("CAcAQ"));
+
+        let decoded: SignedTreeHead = serde_json::from_value(json).expect("Should deserialize STH");
+        assert_eq!(decoded, sth);
+        assert!(decoded.verify(&signer.verifying_key()));
+    }
+}
@@ -0,0 +1,342 @@
+//! Several trees committed to by a single root
+//!
+//! The forest root is the SHA3-256 hash of the committed roots of the shards in order, so adding
+//! a shard changes the forest root but not the proofs inside the existing shards. Each committed
+//! root binds the number of leaves of its shard, which fixes the length of its proofs.
+
+use crate::merkle::{
+    digest_parts, hash, hashes_equal, index_from_directions, positioned_root, Hash, Hashable,
+    MerkleError, MerkleProof, MerkleTree, PositionedProof,
+};
+use sha3::{Digest, Sha3_256};
+
+/// An ordered list of shard trees, each with the default settings of `MerkleTree::new`
+#[derive(Debug, Clone, Default)]
+pub struct Forest {
+    shards: Vec<MerkleTree>,
+}
+
+/// A proof that a data element is in one shard of a forest, verified with
+/// `verify_forest_proof`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForestProof {
+    /// The position of the shard among the roots
+    pub shard: usize,
+    /// The committed roots of every shard of the forest, in order, see
+    /// `MerkleTree::committed_root`
+    pub roots: Vec<Hash>,
+    /// The number of leaves of the shard, which fixes the length of the proof
+    pub leaf_count: usize,
+    /// The proof of the data inside its shard
+    pub proof: MerkleProof,
+}
+
+impl ForestProof {
+    /// Returns the committed root of the shard holding the data, or None if `shard` is out of
+    /// bounds
+    pub fn shard_root(&self) -> Option<Hash> {
+        self.roots.get(self.shard).copied()
+    }
+}
+
+/// `SHA3-256(root_0 || root_1 || ...)`, the root of a forest with these committed shard roots
+pub fn forest_root_of(roots: &[Hash]) -> Hash {
+    let mut hasher = Sha3_256::new();
+    for root in roots {
+        hasher.update(root);
+    }
+    hasher.finalize().into()
+}
+
+impl Forest {
+    /// Creates a forest without shards
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a forest from its shards, in order
+    ///
+    /// # Returns
+    /// The forest, or `IncompatibleTrees` if a shard doesn't have the default settings, since
+    /// `verify_forest_proof` hashes the data like `MerkleTree::new`
+    pub fn from_shards(shards: Vec<MerkleTree>) -> Result<Self, MerkleError> {
+        if !shards.iter().all(MerkleTree::has_default_settings) {
+            return Err(MerkleError::IncompatibleTrees);
+        }
+        Ok(Forest { shards })
+    }
+
+    /// Appends a shard after the existing ones
+    ///
+    /// # Returns
+    /// The position of the new shard, or `IncompatibleTrees` if it doesn't have the default
+    /// settings
+    pub fn add_shard(&mut self, shard: MerkleTree) -> Result<usize, MerkleError> {
+        if !shard.has_default_settings() {
+            return Err(MerkleError::IncompatibleTrees);
+        }
+        self.shards.push(shard);
+        Ok(self.shards.len() - 1)
+    }
+
+    /// Returns the shard at `index`, or None if there is no such shard
+    pub fn shard(&self, index: usize) -> Option<&MerkleTree> {
+        self.shards.get(index)
+    }
+
+    /// Returns the number of shards
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns true if the forest has no shards
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// Returns the committed roots of the shards, in order
+    pub fn roots(&self) -> Vec<Hash> {
+        self.shards.iter().map(MerkleTree::committed_root).collect()
+    }
+
+    /// Returns the root of the forest, see `forest_root_of`
+    pub fn forest_root(&self) -> Hash {
+        forest_root_of(&self.roots())
+    }
+
+    /// Generates the proof of `data` in the shard at `shard_index`
+    ///
+    /// # Returns
+    /// The proof, `IndexOutOfBounds` if there is no such shard, or `LeafNotFound` if the data
+    /// isn't in that shard
+    ///
+    /// # Example
+    /// ```
+    /// use rusty_merkle_tree::forest::{verify_forest_proof, Forest};
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let mut forest = Forest::new();
+    /// let shards = [
+    ///     MerkleTree::new(&["a", "b"]).expect("Should create merkle tree"),
+    ///     MerkleTree::new(&["c", "d", "e"]).expect("Should create merkle tree"),
+    /// ];
+    /// for shard in shards {
+    ///     forest.add_shard(shard).expect("Should add shard");
+    /// }
+    /// let proof = forest.prove(1, "d").expect("Should generate proof");
+    /// assert!(verify_forest_proof(&forest.forest_root(), &proof, "d"));
+    /// ```
+    pub fn prove<T: Hashable + ?Sized>(
+        &self,
+        shard_index: usize,
+        data: &T,
+    ) -> Result<ForestProof, MerkleError> {
+        let shard = self
+            .shards
+            .get(shard_index)
+            .ok_or(MerkleError::IndexOutOfBounds)?;
+        Ok(ForestProof {
+            shard: shard_index,
+            roots: self.roots(),
+            leaf_count: shard.len(),
+            proof: shard.generate_proof(data)?,
+        })
+    }
+}
+
+/// Verifies that `data` is in the shard at `proof.shard` of the forest of `forest_root`
+///
+/// # Returns
+/// `true` if the roots of the proof hash to `forest_root`, and the proof of the data leads to
+/// the committed root at the claimed position. The proof must have the shape of the proof of a
+/// leaf in a shard of `proof.leaf_count` leaves, which the committed root binds, so an internal
+/// node of the shard doesn't pass as data
+pub fn verify_forest_proof<T: Hashable + ?Sized>(
+    forest_root: &Hash,
+    proof: &ForestProof,
+    data: &T,
+) -> bool {
+    let Some(index) = index_from_directions(&proof.proof) else {
+        return false;
+    };
+    let positioned = PositionedProof {
+        index,
+        leaf_count: proof.leaf_count,
+        proof: proof.proof.clone(),
+    };
+    hashes_equal(&forest_root_of(&proof.roots), forest_root)
+        && proof.shard_root().is_some_and(|shard_root| {
+            positioned_root(&Sha3_256::default(), hash(data.leaf_bytes()), &positioned).is_some_and(
+                |computed| {
+                    let count = (proof.leaf_count as u64).to_le_bytes();
+                    let committed = digest_parts::<Sha3_256, 32>(&[&count, &computed]);
+                    hashes_equal(&committed, &shard_root)
+                },
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::OddNodePolicy;
+    use std::borrow::Cow;
+
+    fn shard(name: &str, count: usize) -> MerkleTree {
+        let data: Vec<String> = (0..count).map(|i| format!("{}-{}", name, i)).collect();
+        MerkleTree::new(&data).expect("Should create merkle tree")
+    }
+
+    #[test]
+    fn test_forest_proofs() {
+        let forest = Forest::from_shards(vec![shard("a", 3), shard("b", 8), shard("c", 5)])
+            .expect("Should create forest");
+        let root = forest.forest_root();
+        for (shard_index, item) in [(0, "a-0"), (1, "b-5"), (2, "c-4")] {
+            let proof = forest
+                .prove(shard_index, item)
+                .expect("Should generate proof");
+            assert_eq!(
+                proof.shard_root(),
+                Some(
+                    forest
+                        .shard(shard_index)
+                        .expect("Should have shard")
+                        .committed_root()
+                )
+            );
+            assert!(verify_forest_proof(&root, &proof, item));
+            assert!(!verify_forest_proof(&root, &proof, "other"));
+        }
+        assert!(matches!(
+            forest.prove(3, "a-0"),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        assert!(matches!(
+            forest.prove(0, "b-0"),
+            Err(MerkleError::LeafNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_forest_rejects_tampered_shard_position() {
+        let forest = Forest::from_shards(vec![shard("a", 4), shard("b", 4), shard("c", 4)])
+            .expect("Should create forest");
+        let root = forest.forest_root();
+        let mut proof = forest.prove(1, "b-2").expect("Should generate proof");
+        for shard in [0, 2, 3, usize::MAX] {
+            proof.shard = shard;
+            assert!(!verify_forest_proof(&root, &proof, "b-2"));
+        }
+
+        // Swapping the roots to match the claimed position changes the forest root
+        let mut proof = forest.prove(1, "b-2").expect("Should generate proof");
+        proof.roots.swap(0, 1);
+        proof.shard = 0;
+        assert!(!verify_forest_proof(&root, &proof, "b-2"));
+    }
+
+    #[test]
+    fn test_forest_rejects_internal_node_as_data() {
+        let forest =
+            Forest::from_shards(vec![shard("a", 3), shard("b", 8)]).expect("Should create forest");
+        let root = forest.forest_root();
+        let inner = forest.shard(1).expect("Should have shard");
+        // The children of the first internal node hash to it, with the proof of that node
+        let forged = [
+            inner.leaf(0).expect("Should have a leaf"),
+            inner.leaf(1).expect("Should have a leaf"),
+        ]
+        .concat();
+        let mut proof = forest.prove(1, "b-0").expect("Should generate proof");
+        proof.proof = proof.proof.iter().skip(1).map(|(s, d)| (*s, d)).collect();
+        assert!(proof.proof.verify(&inner.root(), &forged));
+        assert!(!verify_forest_proof(&root, &proof, &forged));
+
+        // The committed root binds the size, so claiming a smaller shard fails too
+        proof.leaf_count = 4;
+        assert!(!verify_forest_proof(&root, &proof, &forged));
+    }
+
+    #[test]
+    fn test_adding_shard_keeps_inner_proofs() {
+        let mut forest =
+            Forest::from_shards(vec![shard("a", 3), shard("b", 6)]).expect("Should create forest");
+        let old_root = forest.forest_root();
+        let old_proof = forest.prove(1, "b-4").expect("Should generate proof");
+        assert_eq!(
+            forest.add_shard(shard("c", 2)).expect("Should add shard"),
+            2
+        );
+        assert_ne!(forest.forest_root(), old_root);
+
+        let new_proof = forest.prove(1, "b-4").expect("Should generate proof");
+        assert_eq!(new_proof.proof, old_proof.proof);
+        assert!(verify_forest_proof(
+            &forest.forest_root(),
+            &new_proof,
+            "b-4"
+        ));
+        assert!(!verify_forest_proof(
+            &forest.forest_root(),
+            &old_proof,
+            "b-4"
+        ));
+        assert!(verify_forest_proof(&old_root, &old_proof, "b-4"));
+    }
+
+    #[test]
+    fn test_forest_rejects_shards_with_other_settings() {
+        let sorted = MerkleTree::with_sorted_pairs(&["a-0", "a-1", "a-2"])
+            .expect("Should create merkle tree");
+        let promoted = MerkleTree::with_odd_node_policy(
+            &["b-0", "b-1", "b-2"],
+            OddNodePolicy::PromoteToNextLevel,
+        )
+        .expect("Should create merkle tree");
+        for other in [sorted, promoted] {
+            assert!(matches!(
+                Forest::from_shards(vec![shard("c", 2), other.clone()]),
+                Err(MerkleError::IncompatibleTrees)
+            ));
+            let mut forest =
+                Forest::from_shards(vec![shard("c", 2)]).expect("Should create forest");
+            assert!(matches!(
+                forest.add_shard(other),
+                Err(MerkleError::IncompatibleTrees)
+            ));
+            assert_eq!(forest.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_forest_proofs_of_hashable_structs() {
+        struct Account(u64);
+        impl Hashable for Account {
+            fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+                Cow::Owned(self.0.to_be_bytes().to_vec())
+            }
+        }
+
+        let accounts: Vec<Account> = (0..5).map(Account).collect();
+        let forest = Forest::from_shards(vec![
+            shard("a", 3),
+            MerkleTree::new(&accounts).expect("Should create merkle tree"),
+        ])
+        .expect("Should create forest");
+        let proof = forest
+            .prove(1, &accounts[3])
+            .expect("Should generate proof");
+        assert!(verify_forest_proof(
+            &forest.forest_root(),
+            &proof,
+            &accounts[3]
+        ));
+        assert!(!verify_forest_proof(
+            &forest.forest_root(),
+            &proof,
+            &accounts[2]
+        ));
+    }
+}
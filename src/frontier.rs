@@ -0,0 +1,278 @@
+use crate::merkle::{
+    empty_root, hash, hash_internal_node, hashes_equal, Hash, Hashable, LeafHashing, MerkleError,
+    MerkleTree, OddNodePolicy,
+};
+
+/// An append-only commitment that only keeps the right edge of the tree
+/// For each set bit `h` of the number of leaves it stores the root of the complete subtree of
+/// height `h` on the right edge, so it uses O(log n) memory whatever the number of leaves.
+/// Its roots are the same as the ones of a `MerkleTree` with the default settings over the
+/// same data, but it can't generate proofs
+#[derive(Debug, Clone, Default)]
+pub struct MerkleFrontier {
+    /// Indexed by height, only set for the bits set in `len`
+    frontier: Vec<Option<Hash>>,
+    len: usize,
+}
+
+impl MerkleFrontier {
+    /// Creates a frontier without any leaves
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restores a frontier of `len` leaves from the roots of its complete subtrees, from the
+    /// lowest to the highest
+    ///
+    /// # Returns
+    /// The frontier, or None if there aren't as many subtrees as bits set in `len`
+    pub fn from_subtrees(len: usize, subtrees: &[Hash]) -> Option<Self> {
+        if subtrees.len() != len.count_ones() as usize {
+            return None;
+        }
+        let mut subtrees = subtrees.iter();
+        let height = (usize::BITS - len.leading_zeros()) as usize;
+        let frontier = (0..height)
+            .map(|h| (len >> h & 1 == 1).then(|| *subtrees.next().expect("One per set bit")))
+            .collect();
+        Some(Self { frontier, len })
+    }
+
+    /// Appends an element, merging the complete subtrees it completes
+    ///
+    /// # Returns
+    /// The index of the new leaf
+    pub fn append<T: Hashable + ?Sized>(&mut self, data: &T) -> usize {
+        let mut node = hash(data.leaf_bytes());
+        let mut height = 0;
+        while let Some(left) = self.frontier.get_mut(height).and_then(Option::take) {
+            node = hash_internal_node(&left, &node);
+            height += 1;
+        }
+        if height == self.frontier.len() {
+            self.frontier.push(None);
+        }
+        self.frontier[height] = Some(node);
+        self.len += 1;
+        self.len - 1
+    }
+
+    /// Returns the number of leaves appended
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if nothing was appended yet
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the root hash, `empty_root()` if nothing was appended yet
+    /// The last node of each level is paired with its complete left sibling if it has one,
+    /// and with itself otherwise, costing O(log n) hashes
+    pub fn root(&self) -> Hash {
+        if self.len == 0 {
+            return empty_root();
+        }
+        // The smallest complete subtree is the last node of its level
+        let lowest = self.len.trailing_zeros() as usize;
+        let mut node = self.frontier[lowest].expect("The lowest set bit has a subtree");
+        let top = self.len.next_power_of_two().trailing_zeros() as usize;
+        for height in lowest..top {
+            node = match self.frontier[height].filter(|_| height > lowest) {
+                Some(left) => hash_internal_node(&left, &node),
+                None => hash_internal_node(&node, &node),
+            };
+        }
+        node
+    }
+}
+
+/// Proof that a tree only appended some leaves to an older tree, so that the new root can be
+/// computed from the old root and the new leaves
+/// It is the frontier of the old tree, the roots of its complete subtrees from the lowest
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AppendProof {
+    pub subtrees: Vec<Hash>,
+}
+
+impl MerkleTree {
+    /// Generates a proof that the tree is its first `old_size` leaves followed by `appended`
+    ///
+    /// # Returns
+    /// The proof, `IndexOutOfBounds` if the tree doesn't have `old_size + appended.len()`
+    /// leaves, `LeafNotFound` if a leaf after `old_size` isn't the same element of
+    /// `appended` hashed like `MerkleTree::new`, or `UnsupportedProof` for a tree that doesn't
+    /// hash its leaves and nodes like `MerkleTree::new` or keeps its leaves sorted
+    ///
+    /// # Example
+    /// ```
+    /// use rusty_merkle_tree::frontier::verify_append;
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let mut tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+    /// let old_root = tree.root();
+    /// tree.add("d").expect("Should add leaf");
+    /// tree.add("e").expect("Should add leaf");
+    /// let proof = tree.prove_append(3, &["d", "e"]).expect("Should generate proof");
+    /// assert_eq!(verify_append(&old_root, 3, &["d", "e"], &proof), Some(tree.root()));
+    /// ```
+    pub fn prove_append<T: Hashable>(
+        &self,
+        old_size: usize,
+        appended: &[T],
+    ) -> Result<AppendProof, MerkleError> {
+        if self.arity() != 2
+            || self.odd_node_policy() != OddNodePolicy::DuplicateLast
+            || self.max_leaves().is_some()
+            || self.has_sorted_pairs()
+            || self.has_domain_separation()
+            || self.is_canonical()
+            || self.leaf_hashing() != LeafHashing::Single
+            || self.normalize_nfc()
+        {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        if old_size.checked_add(appended.len()) != Some(self.len()) {
+            return Err(MerkleError::IndexOutOfBounds);
+        }
+        for (offset, data) in appended.iter().enumerate() {
+            if self.leaf(old_size + offset) != Some(hash(data.leaf_bytes())) {
+                return Err(MerkleError::LeafNotFound);
+            }
+        }
+
+        // The complete subtree of height h ends at the last old leaf, it is shared by both trees
+        let levels = self.levels();
+        let subtrees = (0..levels.len())
+            .filter(|&h| old_size >> h & 1 == 1)
+            .map(|h| levels[h][(old_size >> h) - 1])
+            .collect();
+        Ok(AppendProof { subtrees })
+    }
+}
+
+/// Verifies a proof of `prove_append` against the root of the tree of `old_size` leaves
+///
+/// # Returns
+/// The root of the tree with the `appended` leaves, hashed like `MerkleTree::new`, or None if
+/// the proof doesn't hash to `old_root`
+pub fn verify_append<T: Hashable>(
+    old_root: &Hash,
+    old_size: usize,
+    appended: &[T],
+    proof: &AppendProof,
+) -> Option<Hash> {
+    let mut frontier = MerkleFrontier::from_subtrees(old_size, &proof.subtrees)?;
+    if !hashes_equal(&frontier.root(), old_root) {
+        return None;
+    }
+    for data in appended {
+        frontier.append(data);
+    }
+    Some(frontier.root())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::MerkleTree;
+
+    #[test]
+    fn test_matches_merkle_tree() {
+        let mut frontier = MerkleFrontier::new();
+        let mut tree = MerkleTree::empty();
+        assert_eq!(frontier.root(), tree.root());
+
+        for n in 1..=200 {
+            let element = format!("block{}", n).into_bytes();
+            assert_eq!(frontier.append(&element), n - 1);
+            tree.add(element).expect("Should add leaf");
+            assert_eq!(
+                frontier.root(),
+                tree.root(),
+                "root mismatch at {} leaves",
+                n
+            );
+        }
+        assert_eq!(frontier.len(), 200);
+        // 200 is 0b11001000, only three subtrees are kept
+        assert_eq!(frontier.frontier.iter().flatten().count(), 3);
+    }
+
+    #[test]
+    fn test_chained_appends() {
+        let mut tree = MerkleTree::empty();
+        let mut root = tree.root();
+        let mut next = 0;
+        for batch in [3, 1, 4, 1, 5, 9, 2, 6, 0, 64] {
+            let old_size = tree.len();
+            let appended: Vec<Vec<u8>> = (next..next + batch)
+                .map(|n| format!("block{}", n).into_bytes())
+                .collect();
+            next += batch;
+            for data in &appended {
+                tree.add(data).expect("Should add leaf");
+            }
+
+            let proof = tree
+                .prove_append(old_size, &appended)
+                .expect("Should generate proof");
+            assert_eq!(proof.subtrees.len(), old_size.count_ones() as usize);
+            let new_root =
+                verify_append(&root, old_size, &appended, &proof).expect("Should verify proof");
+            assert_eq!(new_root, tree.root(), "{} + {}", old_size, batch);
+            root = new_root;
+        }
+    }
+
+    #[test]
+    fn test_invalid_appends() {
+        let data: Vec<Vec<u8>> = (0..7).map(|n| format!("block{}", n).into_bytes()).collect();
+        let old = MerkleTree::new(&data[..5]).expect("Should create merkle tree");
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = tree
+            .prove_append(5, &data[5..])
+            .expect("Should generate proof");
+
+        assert!(verify_append(&tree.root(), 5, &data[5..], &proof).is_none());
+        assert!(verify_append(&old.root(), 4, &data[5..], &proof).is_none());
+        assert!(verify_append(&old.root(), 6, &data[5..], &proof).is_none());
+        let mut forged = proof.clone();
+        forged.subtrees[0][0] ^= 1;
+        assert!(verify_append(&old.root(), 5, &data[5..], &forged).is_none());
+
+        assert!(matches!(
+            tree.prove_append(4, &data[5..]),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        assert!(matches!(
+            tree.prove_append(5, &data[4..6]),
+            Err(MerkleError::LeafNotFound)
+        ));
+        let sorted = MerkleTree::with_sorted_pairs(&data).expect("Should create merkle tree");
+        assert!(matches!(
+            sorted.prove_append(5, &data[5..]),
+            Err(MerkleError::UnsupportedProof)
+        ));
+        // The leaves of a double-hashed tree never match the appended data
+        let double = MerkleTree::with_leaf_hashing(&data, LeafHashing::Double)
+            .expect("Should create merkle tree");
+        assert!(matches!(
+            double.prove_append(5, &data[5..]),
+            Err(MerkleError::UnsupportedProof)
+        ));
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_normalized_tree_is_unsupported() {
+        let tree = MerkleTree::with_normalize_nfc(&["a", "b", "cafe\u{301}"], true)
+            .expect("Should create merkle tree");
+        assert!(matches!(
+            tree.prove_append(2, &["cafe\u{301}"]),
+            Err(MerkleError::UnsupportedProof)
+        ));
+    }
+}
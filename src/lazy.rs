@@ -0,0 +1,244 @@
+use crate::merkle::{DuplicatePolicy, Hash, Hashable, MerkleError, MerkleProof, MerkleTree};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A Merkle Tree that defers hashing its nodes until they are needed
+/// `add()` and `extend_from()` only hash the new leaves and queue them, and the first call that
+/// reads the tree, like `root()` or `generate_proof()`, rebuilds it once for all of them.
+/// Reads only take `&self`: the queued leaves live behind a lock, so the tree can still be
+/// shared between threads.
+/// Roots and proofs are the same as the ones of the wrapped tree with every leaf added eagerly.
+#[derive(Debug)]
+pub struct LazyMerkleTree {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    tree: MerkleTree,
+    /// Hashed leaves not in `tree` yet
+    pending: Vec<Hash>,
+}
+
+impl LazyMerkleTree {
+    /// Creates a lazy tree over `data`
+    ///
+    /// # Returns
+    /// A LazyMerkleTree instance if the data is not empty, otherwise an error
+    pub fn new<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        Self::from_tree(MerkleTree::new(data)?)
+    }
+
+    /// Creates a lazy tree without any leaves
+    pub fn empty() -> Self {
+        Self::from_tree(MerkleTree::empty()).expect("The empty tree allows duplicates")
+    }
+
+    /// Makes an existing tree lazy, every queued leaf follows its settings
+    ///
+    /// # Returns
+    /// The lazy tree, or `IncompatibleTrees` if `tree` rejects duplicates, since a duplicate
+    /// would only be found once the result of the `add()` call has already been returned,
+    /// or if it retains data, since only the hashes of queued leaves are kept
+    pub fn from_tree(tree: MerkleTree) -> Result<Self, MerkleError> {
+        if tree.duplicate_policy() == DuplicatePolicy::Reject || tree.retains_data() {
+            return Err(MerkleError::IncompatibleTrees);
+        }
+        Ok(LazyMerkleTree {
+            inner: Mutex::new(Inner {
+                tree,
+                pending: Vec::new(),
+            }),
+        })
+    }
+
+    /// Queues a new element, its nodes are hashed on the next read
+    ///
+    /// # Returns
+    /// `TreeFull` if the tree has a fixed depth and no room left, or `AmbiguousLeafLength` if
+    /// the tree has a strict leaf length and refuses `data`
+    pub fn add<T: Hashable>(&mut self, data: T) -> Result<(), MerkleError> {
+        self.extend_from(&[data])
+    }
+
+    /// Queues a batch of elements, their nodes are hashed on the next read
+    ///
+    /// # Returns
+    /// `TreeFull` if the tree has a fixed depth and the batch doesn't fit, or
+    /// `AmbiguousLeafLength` if the tree has a strict leaf length and refuses an element,
+    /// and nothing is queued
+    pub fn extend_from<T: Hashable>(&mut self, items: &[T]) -> Result<(), MerkleError> {
+        let inner = self.inner.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let queued = inner.tree.len() + inner.pending.len();
+        if let Some(max) = inner.tree.max_leaves() {
+            if queued.saturating_add(items.len()) > max {
+                return Err(MerkleError::TreeFull);
+            }
+        }
+        let leaves = items
+            .iter()
+            .map(|item| inner.tree.checked_leaf(item))
+            .collect::<Result<Vec<_>, _>>()?;
+        inner.pending.extend(leaves);
+        Ok(())
+    }
+
+    /// Returns the root hash, rebuilding the tree first if leaves were queued
+    pub fn root(&self) -> Hash {
+        self.synced().tree.root()
+    }
+
+    /// Returns the number of leaves, rebuilding the tree first if leaves were queued
+    pub fn len(&self) -> usize {
+        self.synced().tree.len()
+    }
+
+    /// Returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Generates a proof of membership, rebuilding the tree first if leaves were queued
+    pub fn generate_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<MerkleProof, MerkleError> {
+        self.synced().tree.generate_proof(data)
+    }
+
+    /// Validates a proof, rebuilding the tree first if leaves were queued
+    pub fn verify_proof<T: Hashable + ?Sized>(&self, data: &T, proof: &MerkleProof) -> bool {
+        self.synced().tree.verify_proof(data, proof)
+    }
+
+    /// Returns true if there are queued leaves the next read will have to hash
+    pub fn is_dirty(&self) -> bool {
+        !self.lock().pending.is_empty()
+    }
+
+    /// Rebuilds the tree if needed and returns it
+    pub fn into_tree(self) -> MerkleTree {
+        let mut inner = self
+            .inner
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        inner.flush();
+        inner.tree
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks the tree with every queued leaf added to it
+    fn synced(&self) -> MutexGuard<'_, Inner> {
+        let mut inner = self.lock();
+        inner.flush();
+        inner
+    }
+}
+
+impl Inner {
+    /// Adds the queued leaves with a single rebuild
+    fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.tree
+                .extend_leaves(pending)
+                .expect("Queued leaves were checked when they were added");
+        }
+    }
+}
+
+impl Default for LazyMerkleTree {
+    /// The default tree is the empty tree
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_eager_tree() {
+        let data: Vec<Vec<u8>> = (0..12)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let mut lazy = LazyMerkleTree::empty();
+        let mut eager = MerkleTree::empty();
+
+        for (i, element) in data.iter().enumerate() {
+            lazy.add(element.clone()).expect("Should add leaf");
+            eager.add(element.clone()).expect("Should add leaf");
+            assert!(lazy.is_dirty());
+            // Query the root only now and then, several adds are then rebuilt at once
+            if i % 3 == 2 {
+                assert_eq!(lazy.root(), eager.root());
+                assert!(!lazy.is_dirty());
+            }
+        }
+        lazy.extend_from(&data[..2]).expect("Should extend tree");
+        eager.extend_from(&data[..2]).expect("Should extend tree");
+
+        let proof = lazy
+            .generate_proof(&data[7])
+            .expect("Should generate proof");
+        assert_eq!(
+            proof,
+            eager
+                .generate_proof(&data[7])
+                .expect("Should generate proof")
+        );
+        assert!(lazy.verify_proof(&data[7], &proof));
+        assert_eq!(lazy.len(), eager.len());
+
+        let tree = lazy.into_tree();
+        assert_eq!(tree.root(), eager.root());
+    }
+
+    #[test]
+    fn test_keeps_tree_settings() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+
+        let fixed = MerkleTree::with_depth(2, &data[..2]).expect("Should create merkle tree");
+        let mut lazy = LazyMerkleTree::from_tree(fixed).expect("Should make tree lazy");
+        lazy.extend_from(&data[..2]).expect("Should extend tree");
+        assert!(matches!(
+            lazy.add(data[2].clone()),
+            Err(MerkleError::TreeFull)
+        ));
+        assert_eq!(lazy.len(), 4);
+
+        let dedup = MerkleTree::with_duplicate_policy(&data, DuplicatePolicy::Deduplicate)
+            .expect("Should create merkle tree");
+        let mut lazy = LazyMerkleTree::from_tree(dedup).expect("Should make tree lazy");
+        lazy.add(data[0].clone()).expect("Should add leaf");
+        assert_eq!(lazy.len(), 3);
+
+        let reject = MerkleTree::with_duplicate_policy(&data, DuplicatePolicy::Reject)
+            .expect("Should create merkle tree");
+        assert!(matches!(
+            LazyMerkleTree::from_tree(reject),
+            Err(MerkleError::IncompatibleTrees)
+        ));
+    }
+
+    #[test]
+    fn test_keeps_strict_leaf_length() {
+        let data: Vec<Vec<u8>> = (0..3).map(|i| format!("block{}", i).into_bytes()).collect();
+        let strict = MerkleTree::with_strict_leaf_length(&data).expect("Should create merkle tree");
+        let mut lazy = LazyMerkleTree::from_tree(strict).expect("Should create lazy tree");
+        let node_sized = vec![0u8; 64];
+        assert!(matches!(
+            lazy.extend_from(&[b"block3".to_vec(), node_sized.clone()]),
+            Err(MerkleError::AmbiguousLeafLength)
+        ));
+        assert!(!lazy.is_dirty());
+        assert!(matches!(
+            lazy.add(node_sized),
+            Err(MerkleError::AmbiguousLeafLength)
+        ));
+        assert_eq!(lazy.len(), 3);
+    }
+}
@@ -0,0 +1,36 @@
+pub mod absence;
+#[cfg(test)]
+mod attacks;
+pub mod batch;
+#[cfg(feature = "sha2")]
+pub mod bitcoin;
+pub mod builder;
+mod cache;
+#[cfg(feature = "sha2")]
+pub mod consistency;
+#[cfg(feature = "sha2")]
+pub mod ct;
+pub mod forest;
+pub mod frontier;
+pub mod lazy;
+pub mod light;
+pub mod merkle;
+pub mod meta;
+pub mod multiproof;
+pub mod persistent;
+#[cfg(feature = "poseidon")]
+pub mod poseidon;
+pub mod rolling;
+pub mod salted;
+#[cfg(feature = "serde")]
+pub mod serialized;
+pub mod shared;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod snapshot;
+#[cfg(feature = "proptest")]
+pub mod strategies;
+pub mod stream;
+pub mod test_vectors;
+pub mod transaction;
+pub mod versioned;
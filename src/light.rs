@@ -0,0 +1,236 @@
+use crate::merkle::{
+    hashes_equal, position_of, DuplicatePolicy, Hash, Hashable, MerkleError, MerkleProof,
+    MerkleTree,
+};
+
+/// A Merkle Tree that only stores its leaves
+/// Every internal node is recomputed from the leaves when it is needed, so `root()` and
+/// `generate_proof()` cost O(n) hashes but only O(log n) memory on top of the leaves.
+/// Roots and proofs are the same as the ones of the full tree with the same settings
+#[derive(Debug, Clone)]
+pub struct LightMerkleTree {
+    /// A tree without leaves, only used for its settings
+    settings: MerkleTree,
+    leaves: Vec<Hash>,
+}
+
+impl LightMerkleTree {
+    /// Creates a light tree over `data`
+    ///
+    /// # Returns
+    /// A LightMerkleTree instance if the data is not empty, otherwise an error
+    pub fn new<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let settings = MerkleTree::empty();
+        let leaves = data
+            .iter()
+            .map(|element| settings.hash_leaf(element))
+            .collect();
+        Ok(LightMerkleTree { settings, leaves })
+    }
+
+    /// Keeps the settings and the leaves of `tree`, dropping its levels
+    ///
+    /// # Returns
+    /// The light tree, or `IncompatibleTrees` if `tree` is canonical or doesn't allow
+    /// duplicates, since the light tree only appends its leaves
+    pub fn from_tree(tree: &MerkleTree) -> Result<Self, MerkleError> {
+        if tree.is_canonical() || tree.duplicate_policy() != DuplicatePolicy::Allow {
+            return Err(MerkleError::IncompatibleTrees);
+        }
+        Ok(LightMerkleTree {
+            settings: tree.settings_only(),
+            leaves: (0..tree.len())
+                .filter_map(|index| tree.leaf(index))
+                .collect(),
+        })
+    }
+
+    /// Appends an element, no node is hashed
+    ///
+    /// # Returns
+    /// The index of the new leaf, `TreeFull` if the tree has a fixed depth and no room left, or
+    /// `AmbiguousLeafLength` if the tree has a strict leaf length and refuses `data`
+    pub fn add<T: Hashable>(&mut self, data: T) -> Result<usize, MerkleError> {
+        if self
+            .settings
+            .max_leaves()
+            .is_some_and(|max| self.leaves.len() >= max)
+        {
+            return Err(MerkleError::TreeFull);
+        }
+        let leaf = self.settings.checked_leaf(&data)?;
+        self.leaves.push(leaf);
+        Ok(self.leaves.len() - 1)
+    }
+
+    /// Returns the number of leaves
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Computes the root hash from the leaves
+    pub fn root(&self) -> Hash {
+        self.settings.root_from_leaves(&self.leaves)
+    }
+
+    /// Generates a proof of membership, computing each sibling from the leaves
+    pub fn generate_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<MerkleProof, MerkleError> {
+        if self.settings.arity() != 2 {
+            return Err(MerkleError::UnsupportedArity);
+        }
+        let leaf = self.settings.hash_leaf(data);
+        let index = position_of(&self.leaves, &leaf).ok_or(MerkleError::LeafNotFound)?;
+        Ok(self.settings.proof_from_leaves(&self.leaves, index))
+    }
+
+    /// Validates a proof against the root, which is computed from the leaves
+    /// Like `MerkleTree::verify_proof`, the proof must have the shape of the proof of a leaf
+    /// in a tree of `len()` leaves, and data a strict leaf length refuses fails
+    pub fn verify_proof<T: Hashable + ?Sized>(&self, data: &T, proof: &MerkleProof) -> bool {
+        if self.is_empty()
+            || self.settings.arity() != 2
+            || self.settings.implied_index(proof, self.len()).is_none()
+        {
+            return false;
+        }
+        self.settings.checked_leaf(data).is_ok_and(|leaf| {
+            hashes_equal(&self.settings.root_from_proof(leaf, proof), &self.root())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::OddNodePolicy;
+
+    #[test]
+    fn test_matches_full_tree() {
+        let data: Vec<Vec<u8>> = (0..13)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let policies = [
+            OddNodePolicy::DuplicateLast,
+            OddNodePolicy::PromoteToNextLevel,
+            OddNodePolicy::PadWithConstant([7u8; 32]),
+        ];
+        for policy in policies {
+            for n in 1..=data.len() {
+                let full = MerkleTree::with_odd_node_policy(&data[..n], policy)
+                    .expect("Should create merkle tree");
+                let light = LightMerkleTree::from_tree(&full).expect("Should create light tree");
+                assert_eq!(light.root(), full.root());
+                for element in &data[..n] {
+                    let proof = light
+                        .generate_proof(element)
+                        .expect("Should generate proof");
+                    assert_eq!(
+                        proof,
+                        full.generate_proof(element).expect("Should generate proof")
+                    );
+                    assert!(light.verify_proof(element, &proof));
+                }
+            }
+        }
+
+        let fixed = MerkleTree::with_depth(4, &data[..5]).expect("Should create merkle tree");
+        let mut light = LightMerkleTree::from_tree(&fixed).expect("Should create light tree");
+        assert_eq!(light.root(), fixed.root());
+        light.add(data[5].clone()).expect("Should add leaf");
+        let proof = light
+            .generate_proof(&data[2])
+            .expect("Should generate proof");
+        assert!(light.verify_proof(&data[2], &proof));
+
+        let kary = MerkleTree::with_arity(3, &data).expect("Should create merkle tree");
+        let light = LightMerkleTree::from_tree(&kary).expect("Should create light tree");
+        assert_eq!(light.root(), kary.root());
+    }
+
+    #[test]
+    fn test_rejects_internal_node_as_leaf() {
+        let data: Vec<Vec<u8>> = (0..4).map(|i| format!("block{}", i).into_bytes()).collect();
+        let full = MerkleTree::new(&data).expect("Should create merkle tree");
+        let light = LightMerkleTree::new(&data).expect("Should create light tree");
+        // The children of the first internal node as data, with the proof of that node
+        let leaves = full.leaves();
+        let forged_data = [leaves[0], leaves[1]].concat();
+        let shortened: MerkleProof = light
+            .generate_proof(&data[0])
+            .expect("Should generate proof")
+            .iter()
+            .skip(1)
+            .map(|(sibling, direction)| (*sibling, direction))
+            .collect::<Vec<_>>()
+            .into();
+        assert!(!full.verify_proof(&forged_data, &shortened));
+        assert!(!light.verify_proof(&forged_data, &shortened));
+    }
+
+    #[test]
+    fn test_keeps_strict_leaf_length() {
+        let data: Vec<Vec<u8>> = (0..3).map(|i| format!("block{}", i).into_bytes()).collect();
+        let strict = MerkleTree::with_strict_leaf_length(&data).expect("Should create merkle tree");
+        let mut light = LightMerkleTree::from_tree(&strict).expect("Should create light tree");
+        assert!(matches!(
+            light.add(vec![0u8; 64]),
+            Err(MerkleError::AmbiguousLeafLength)
+        ));
+        assert_eq!(light.len(), 3);
+        assert_eq!(light.root(), strict.root());
+    }
+
+    #[test]
+    fn test_add() {
+        let data: Vec<Vec<u8>> = (0..6).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut light = LightMerkleTree::new(&data[..1]).expect("Should create light tree");
+        for element in &data[1..] {
+            light.add(element.clone()).expect("Should add leaf");
+        }
+        assert_eq!(
+            light.root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+        assert!(matches!(
+            LightMerkleTree::new::<Vec<u8>>(&[]),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    struct Reading(u32);
+
+    impl Hashable for Reading {
+        fn leaf_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+            std::borrow::Cow::Owned(self.0.to_be_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn test_hashable_structs() {
+        let readings: Vec<Reading> = (0..5).map(Reading).collect();
+        let mut light = LightMerkleTree::new(&readings[..3]).expect("Should create light tree");
+        light.add(Reading(3)).expect("Should add leaf");
+        light.add(Reading(4)).expect("Should add leaf");
+        let full = MerkleTree::new(&readings).expect("Should create merkle tree");
+        assert_eq!(light.root(), full.root());
+        for reading in &readings {
+            let proof = light
+                .generate_proof(reading)
+                .expect("Should generate proof");
+            assert!(light.verify_proof(reading, &proof));
+        }
+    }
+}
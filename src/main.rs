@@ -1,6 +1,4 @@
-mod merkle;
-
-use merkle::MerkleTree;
+use rusty_merkle_tree::merkle::MerkleTree;
 
 fn main() {
     let block = vec![
@@ -11,6 +9,12 @@ fn main() {
         "Transaction 5",
     ];
 
-    let mut tree = MerkleTree::new(&block);
-}
+    let mut tree = MerkleTree::new(&block).expect("Should create merkle tree");
+    tree.add(b"Transaction 6".to_vec())
+        .expect("Should add leaf");
 
+    let proof = tree
+        .generate_proof(&block[2])
+        .expect("Should generate proof");
+    assert!(tree.verify_proof(&block[2], &proof));
+}
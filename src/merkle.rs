@@ -1,401 +1,7018 @@
-use sha3::{Digest, Sha3_256};
+use crate::cache::ProofCache;
+use hmac::{Hmac, Mac};
+#[cfg(feature = "sha2")]
+use sha2::Sha256;
+use sha3::digest::Output;
+use sha3::{Digest, Keccak256, Sha3_256};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::Arc;
+#[cfg(feature = "subtle")]
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+#[cfg(feature = "unicode")]
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+#[cfg(feature = "zeroize")]
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-type Hash = [u8; 32];
+pub type Hash = [u8; 32];
+
+/// A value that can be stored as a leaf
+/// Its leaf is `leaf_bytes` hashed with the settings of the tree, so a structured type only
+/// has to define its canonical encoding once. Every `AsRef<[u8]>` type is hashable as its own
+/// bytes
+/// There is no hook to hash a leaf directly, since the settings of the tree decide how its
+/// leaves are hashed. The wrappers and the stateless verifiers accept any `Hashable` too
+///
+/// # Example
+/// ```
+/// # use rusty_merkle_tree::merkle::{Hashable, MerkleTree};
+/// # use std::borrow::Cow;
+/// struct Payment {
+///     id: u64,
+///     amount: u64,
+/// }
+///
+/// impl Hashable for Payment {
+///     fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+///         Cow::Owned([self.id.to_be_bytes(), self.amount.to_be_bytes()].concat())
+///     }
+/// }
+///
+/// let payments = [Payment { id: 1, amount: 10 }, Payment { id: 2, amount: 20 }];
+/// let tree = MerkleTree::new(&payments).expect("Should create merkle tree");
+/// let proof = tree.generate_proof(&payments[1]).expect("Should generate proof");
+/// assert!(tree.verify_proof(&payments[1], &proof));
+/// ```
+pub trait Hashable {
+    /// The canonical encoding of the value, hashed into its leaf by the tree's settings
+    fn leaf_bytes(&self) -> Cow<'_, [u8]>;
+}
+
+impl<T: AsRef<[u8]> + ?Sized> Hashable for T {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_ref())
+    }
+}
 
 #[derive(Debug)]
 pub enum MerkleError {
     LeafNotFound,
+    /// The positions, in the requested items, of the ones that are not in the tree
+    LeavesNotFound(Vec<usize>),
     EmptyData,
+    IndexOutOfBounds,
+    InvalidChunkSize,
+    TreeFull,
+    InvalidArity,
+    UnsupportedArity,
+    IncompatibleTrees,
+    /// The settings of the tree don't allow the requested kind of proof
+    UnsupportedProof,
+    /// The leaf is already in the tree, at the carried index
+    DuplicateLeaf(usize),
+    /// The element at the carried index is not the canonical encoding of a field element
+    NotInField(usize),
+    /// The data is as long as the children of a node together, which a tree created with
+    /// `with_strict_leaf_length` refuses since it could be passed off as an internal node
+    AmbiguousLeafLength,
+    /// No version with that number was recorded, or it was dropped by a rollback
+    UnknownVersion,
+    /// Encoded bytes start with a format version this crate can't read, carried
+    UnknownFormatVersion(u8),
+    /// The input ends before the encoded value does
+    TruncatedInput,
+    /// Encoded bytes hold a value outside of its allowed ones, or more bytes than the value
+    InvalidEncoding,
+    /// The name doesn't match any `HashAlgorithm`
+    UnknownAlgorithm(String),
+    /// A decoded proof has more siblings than the proof of any tree, at most `MAX_PROOF_LEN`
+    ProofTooLong,
+    /// A serialized proof or tree was produced with another hash function than the expected one
+    AlgorithmMismatch {
+        expected: HashAlgorithm,
+        found: HashAlgorithm,
+    },
+    InvalidWindowSize,
+    /// The tree doesn't match its leaves from the carried (level, index) on, either because
+    /// that node isn't the hash of its children or because the level has the wrong size
+    CorruptNode(usize, usize),
+    /// Two proofs give different siblings at the same (level, index), so they don't come from
+    /// the same tree
+    ConflictingSibling(usize, usize),
+    /// The two children of the node at (level, index) are equal where the tree has no lone
+    /// node, the mutation that gives a Bitcoin block with a repeated transaction the root of
+    /// the real one
+    DuplicatedNode(usize, usize),
+    /// The proof doesn't have the length and directions of the proof of any leaf of the tree,
+    /// or not the expected depth
+    InvalidProofShape,
+    /// The root computed from the data and the proof isn't the expected one
+    RootMismatch,
+    Io(io::Error),
+    #[cfg(feature = "serde")]
+    Serialization(bincode::Error),
 }
 
-#[derive(Debug)]
+/// Why `verify_proof_detailed` rejected a proof
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError<const N: usize = 32> {
+    /// The proof has the shape of the proof of the leaf at `index`, which doesn't hold the
+    /// data. Also returned for an empty tree, and for data a tree with a strict leaf length
+    /// refuses, without an index
+    LeafMismatch { index: Option<usize> },
+    /// The proof doesn't have as many siblings as the proof of the data, or of any leaf if the
+    /// data isn't in the tree
+    WrongProofLength { expected: usize, found: usize },
+    /// The sibling at position `level` of the proof is on the wrong side for the leaf holding
+    /// the data
+    WrongDirection { level: usize },
+    /// The sibling at position `level` of the proof isn't the one of the tree, so the proof
+    /// computes another root. A verifier without the tree reports the length of the proof
+    RootMismatch {
+        computed: [u8; N],
+        expected: [u8; N],
+        level: usize,
+    },
+    /// The tree doesn't have an arity of 2, its proofs are verified with `verify_kary_proof`
+    UnsupportedArity,
+    /// The tree of `leaf_count` leaves has no leaf at `index`
+    IndexOutOfBounds { index: usize, leaf_count: usize },
+    /// Another data was already verified as the leaf at `index`
+    ConflictingLeaf { index: usize },
+}
+
+impl From<io::Error> for MerkleError {
+    fn from(error: io::Error) -> Self {
+        MerkleError::Io(error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for MerkleError {
+    fn from(error: bincode::Error) -> Self {
+        MerkleError::Serialization(error)
+    }
+}
+
+#[derive(Debug, Clone)]
 /// Represents a Merkle Tree data structure
 /// The tree is represented as a list of levels, where each level is a list of hashes
 /// The leaves are stored separately from the internal nodes
 /// The root hash is the first element of the last level
-pub struct MerkleTree {
-    tree: Vec<Vec<Hash>>,
-    leaves: Vec<Hash>,
+/// `N` is the width of the hashes in bytes, 32 unless the hash function outputs another size
+pub struct MerkleTree<D = Sha3_256, const N: usize = 32> {
+    /// The levels and the leaves are shared with the snapshots of the tree, and copied by the
+    /// first mutation while a snapshot still holds them
+    tree: Arc<Vec<Vec<[u8; N]>>>,
+    leaves: Arc<Vec<[u8; N]>>,
+    /// Only set for fixed-depth trees: the root of an all-zero subtree for each level,
+    /// used as the sibling of nodes that don't have one
+    zero_hashes: Option<Vec<[u8; N]>>,
+    odd_node_policy: OddNodePolicy<N>,
+    /// Number of children of each internal node
+    arity: usize,
+    /// Hash function of the leaves and internal nodes
+    hasher: D,
+    /// Whether leaves and internal nodes are hashed with the RFC 6962 prefixes
+    domain_separation: bool,
+    duplicate_policy: DuplicatePolicy,
+    /// Whether leaves are kept sorted by hash, making the root independent of insertion order
+    sorted: bool,
+    /// Whether the two children of a node are sorted before hashing them, like OpenZeppelin does
+    sorted_pairs: bool,
+    leaf_hashing: LeafHashing,
+    /// Whether UTF-8 data is NFC-normalized before hashing, only enabled with the `unicode` feature
+    normalize_nfc: bool,
+    /// Whether data as long as the children of a node is refused, see `with_strict_leaf_length`
+    strict_leaf_length: bool,
+    /// Only set for trees created with `with_retained_data`
+    retained: Option<RetainedData<N>>,
+    /// Every leaf of the tree, only tracked when duplicates are not allowed
+    leaf_set: HashSet<[u8; N]>,
+    /// Proofs returned by `generate_proof_by_index`, see `enable_proof_cache`
+    proof_cache: ProofCache<N>,
+}
+
+/// Original data of each distinct leaf, with the number of leaves holding it
+pub(crate) type RetainedData<const N: usize = 32> = HashMap<[u8; N], (Vec<u8>, usize)>;
+
+/// Prefix of leaf hashes when domain separation is enabled, `H(0x00 || data)`
+pub const LEAF_PREFIX: u8 = 0x00;
+/// Prefix of internal node hashes when domain separation is enabled, `H(0x01 || left || right)`
+pub const NODE_PREFIX: u8 = 0x01;
+
+/// The hash function of a tree's leaves and internal nodes
+/// Implemented by every RustCrypto digest for its output size `N`, by `Truncated` digests,
+/// by `HashAlgorithm` for trees whose hash function is only known at runtime, and by keyed
+/// hash functions, which are the only ones without a `Default` value
+pub trait TreeHasher<const N: usize = 32>: Clone {
+    /// Hashes the concatenation of `parts`
+    fn hash_parts(&self, parts: &[&[u8]]) -> [u8; N];
+
+    /// Hashes a data element into a leaf, the same as its single part by default
+    fn hash_leaf_bytes(&self, data: &[u8]) -> [u8; N] {
+        self.hash_parts(&[data])
+    }
+
+    /// True if `other` hashes the same way, which is always the case for a digest type
+    fn is_same_as(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl<D: Digest + Clone + Default, const N: usize> TreeHasher<N> for D
+where
+    [u8; N]: From<Output<D>>,
+{
+    fn hash_parts(&self, parts: &[&[u8]]) -> [u8; N] {
+        digest_parts::<D, N>(parts)
+    }
+}
+
+/// The digest `D` truncated to its first `N` bytes, like a 20-byte SHA3-256 for compact storage
+/// Use it as `MerkleTree::<Truncated<Sha3_256>, 20>::with_digest`
+///
+/// # Panics
+/// Hashing panics if `N` is larger than the output of `D`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Truncated<D>(PhantomData<D>);
+
+impl<D: Digest + Clone + Default, const N: usize> TreeHasher<N> for Truncated<D> {
+    fn hash_parts(&self, parts: &[&[u8]]) -> [u8; N] {
+        let mut hasher = D::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize()[..N]
+            .try_into()
+            .expect("N should not be larger than the digest output")
+    }
+}
+
+/// BLAKE3 in its default mode, a faster alternative to the SHA families for large trees
+/// Use it as `MerkleTree::<Blake3>::with_digest`, or at runtime with `HashAlgorithm::Blake3`
+#[cfg(feature = "blake3")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3;
+
+#[cfg(feature = "blake3")]
+impl TreeHasher for Blake3 {
+    fn hash_parts(&self, parts: &[&[u8]]) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// BLAKE3 in keyed or key derivation mode, for trees created with `MerkleTree::new_keyed`
+/// and `MerkleTree::new_derive_key`
+/// The same data under different keys or contexts gives unrelated roots
+/// With the `zeroize` feature, the key or context is wiped when the value is dropped
+#[cfg(feature = "blake3")]
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "zeroize", derive(Zeroize, ZeroizeOnDrop))]
+pub enum KeyedBlake3 {
+    /// `blake3::keyed_hash` with a secret key
+    Keyed([u8; 32]),
+    /// `blake3::derive_key` with a context string
+    DeriveKey(String),
+}
+
+#[cfg(feature = "blake3")]
+impl TreeHasher for KeyedBlake3 {
+    fn hash_parts(&self, parts: &[&[u8]]) -> Hash {
+        let mut hasher = match self {
+            KeyedBlake3::Keyed(key) => blake3::Hasher::new_keyed(key),
+            KeyedBlake3::DeriveKey(context) => blake3::Hasher::new_derive_key(context),
+        };
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+
+    fn is_same_as(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl std::fmt::Debug for KeyedBlake3 {
+    /// The key is never printed
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyedBlake3::Keyed(_) => f.write_str("Keyed(..)"),
+            KeyedBlake3::DeriveKey(context) => f.debug_tuple("DeriveKey").field(context).finish(),
+        }
+    }
+}
+
+/// SHA3-256 with leaves hashed as `HMAC-SHA3-256(key, data)`, for trees created with
+/// `MerkleTree::with_key`
+/// Internal nodes are hashed without the key, like in `new()`, since they only hash leaves
+/// With the `zeroize` feature, the key is wiped when the value is dropped
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "zeroize", derive(Zeroize, ZeroizeOnDrop))]
+pub struct HmacSha3 {
+    key: Vec<u8>,
+}
+
+impl TreeHasher for HmacSha3 {
+    fn hash_parts(&self, parts: &[&[u8]]) -> Hash {
+        digest_parts::<Sha3_256, 32>(parts)
+    }
+
+    fn hash_leaf_bytes(&self, data: &[u8]) -> Hash {
+        let mut mac =
+            Hmac::<Sha3_256>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+        mac.update(data);
+        mac.finalize().into_bytes().into()
+    }
+
+    fn is_same_as(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl std::fmt::Debug for HmacSha3 {
+    /// The key is never printed
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HmacSha3(..)")
+    }
+}
+
+/// A hash function chosen at runtime, for trees created with `MerkleTree::with_algorithm`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha3_256,
+    #[cfg(feature = "sha2")]
+    Sha2_256,
+    /// The original Keccak padding used by Ethereum, which differs from the finalized SHA3-256
+    Keccak256,
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Every algorithm available with the enabled features
+    pub const ALL: &'static [HashAlgorithm] = &[
+        HashAlgorithm::Sha3_256,
+        #[cfg(feature = "sha2")]
+        HashAlgorithm::Sha2_256,
+        HashAlgorithm::Keccak256,
+        #[cfg(feature = "blake3")]
+        HashAlgorithm::Blake3,
+    ];
+
+    /// The name of the algorithm, as accepted by `from_str`
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha3_256 => "sha3-256",
+            #[cfg(feature = "sha2")]
+            HashAlgorithm::Sha2_256 => "sha2-256",
+            HashAlgorithm::Keccak256 => "keccak256",
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = MerkleError;
+
+    /// Parses an algorithm name, like `"sha3-256"`
+    /// `UnknownAlgorithm` is returned for unknown names and for algorithms whose feature is disabled
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        HashAlgorithm::ALL
+            .iter()
+            .find(|algorithm| algorithm.name() == name)
+            .copied()
+            .ok_or_else(|| MerkleError::UnknownAlgorithm(name.to_string()))
+    }
+}
+
+impl TreeHasher for HashAlgorithm {
+    fn hash_parts(&self, parts: &[&[u8]]) -> Hash {
+        match self {
+            HashAlgorithm::Sha3_256 => digest_parts::<Sha3_256, 32>(parts),
+            #[cfg(feature = "sha2")]
+            HashAlgorithm::Sha2_256 => digest_parts::<Sha256, 32>(parts),
+            HashAlgorithm::Keccak256 => digest_parts::<Keccak256, 32>(parts),
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => Blake3.hash_parts(parts),
+        }
+    }
+
+    fn is_same_as(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+/// What to do with the last node of a level that has an odd number of nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OddNodePolicy<const N: usize = 32> {
+    /// Pair the node with itself, `parent = H(node || node)`
+    /// Repeating the last leaf of an odd level keeps the same root, like Bitcoin's CVE-2012-2459,
+    /// use `PromoteToNextLevel` when the root has to identify the leaf set
+    #[default]
+    DuplicateLast,
+    /// Move the node up to the next level unchanged, `parent = node`
+    /// Proofs have no sibling for the levels where this happens
+    PromoteToNextLevel,
+    /// Pair the node with a fixed hash, `parent = H(node || constant)`
+    PadWithConstant([u8; N]),
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum Direction {
+/// How many times the data of a leaf is hashed before entering the tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LeafHashing {
+    /// `leaf = H(data)`
+    #[default]
+    Single,
+    /// `leaf = H(H(data))`
+    Double,
+}
+
+/// What to do with a leaf equal to one already in the tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Keep every occurrence, proofs by data are generated for the first one
+    #[default]
+    Allow,
+    /// Fail with `DuplicateLeaf`, carrying the index of the existing occurrence
+    Reject,
+    /// Keep only the first occurrence, later ones are silently dropped
+    Deduplicate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
     Left,
     Right,
 }
 
-/// Type alias for a Merkle proof
-/// A proof is a list of hashes that can be used to verify the membership of a leaf in the tree
-/// Each hash has associated a Direction Enum that indicates if the hash is a left or right sibling
-type MerkleProof = Vec<(Hash, Direction)>;
+/// The most siblings a proof can have: a tree with `usize::MAX` leaves has that many levels
+/// above them
+pub const MAX_PROOF_LEN: usize = usize::BITS as usize;
 
-fn hash<T: AsRef<[u8]>>(element: T) -> Hash {
-    Sha3_256::digest(element).into()
+/// Version byte of the encoding of `MerkleProof::to_bytes`
+pub const PROOF_FORMAT_VERSION: u8 = 1;
+
+/// The binary encodings of a `MerkleProof`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofEncoding {
+    /// `MerkleProof::to_bytes`, with a version byte and a direction byte per sibling
+    Plain,
+    /// `MerkleProof::to_compact_bytes`, with the directions packed into a bitfield
+    Compact,
 }
 
-/// Hashes two hashes together to create a new hash
-fn hash_internal_node(left: &Hash, right: &Hash) -> Hash {
-    let mut hasher = Sha3_256::new();
-    hasher.update(left);
-    hasher.update(right);
-    hasher.finalize().into()
+impl ProofEncoding {
+    /// Returns the number of bytes of a proof of `proof_len` siblings of `hash_len` bytes in
+    /// this encoding, with `expected_proof_len` it sizes proofs before building a tree
+    ///
+    /// # Example
+    /// ```
+    /// use rusty_merkle_tree::merkle::{expected_proof_len, ProofEncoding};
+    ///
+    /// let siblings = expected_proof_len(1_000_000);
+    /// assert_eq!(ProofEncoding::Plain.encoded_size(siblings, 32), 665);
+    /// assert_eq!(ProofEncoding::Compact.encoded_size(siblings, 32), 644);
+    /// ```
+    pub fn encoded_size(self, proof_len: usize, hash_len: usize) -> usize {
+        match self {
+            ProofEncoding::Plain => 1 + 4 + proof_len * (hash_len + 1),
+            ProofEncoding::Compact => 1 + proof_len.div_ceil(8) + proof_len * hash_len,
+        }
+    }
 }
 
-fn determine_direction(index: usize) -> Direction {
-    if index % 2 == 0 {
-        Direction::Left
-    } else {
-        Direction::Right
+/// A Merkle proof
+/// A proof is a list of hashes that can be used to verify the membership of a leaf in the tree
+/// Each hash has associated a Direction Enum that indicates if the hash is a left or right sibling
+/// The siblings go from the leaf up to the root. It is serialized as the list of its siblings
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        transparent,
+        bound(
+            serialize = "Vec<([u8; N], Direction)>: serde::Serialize",
+            deserialize = "Vec<([u8; N], Direction)>: serde::Deserialize<'de>"
+        )
+    )
+)]
+pub struct MerkleProof<const N: usize = 32> {
+    siblings: Vec<([u8; N], Direction)>,
+}
+
+impl<const N: usize> MerkleProof<N> {
+    /// Creates a proof without siblings, the proof of the only leaf of a tree
+    pub fn new() -> Self {
+        MerkleProof {
+            siblings: Vec::new(),
+        }
+    }
+
+    /// Creates a proof without siblings with room for `capacity` of them
+    pub fn with_capacity(capacity: usize) -> Self {
+        MerkleProof {
+            siblings: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of siblings, one per hashed level between the leaf and the root
+    pub fn len(&self) -> usize {
+        self.siblings.len()
+    }
+
+    /// Returns true if the proof has no siblings
+    pub fn is_empty(&self) -> bool {
+        self.siblings.is_empty()
+    }
+
+    /// Returns an iterator over the siblings and their directions, from the leaf up to the root
+    pub fn iter(&self) -> ProofIter<'_, N> {
+        self.siblings
+            .iter()
+            .map(|(sibling, direction)| (sibling, *direction))
+    }
+
+    /// Appends the sibling of the next level up
+    pub fn push(&mut self, sibling: [u8; N], direction: Direction) {
+        self.siblings.push((sibling, direction));
+    }
+
+    /// Encodes the proof in its binary format:
+    ///
+    /// - 1 byte: the format version, `PROOF_FORMAT_VERSION`
+    /// - 4 bytes: the number of siblings, as a little-endian `u32`
+    /// - `N + 1` bytes per sibling, from the leaf up to the root: the hash, then `0` for a
+    ///   `Direction::Left` sibling or `1` for a `Direction::Right` one
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rusty_merkle_tree::merkle::{MerkleProof, MerkleTree};
+    ///
+    /// let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+    /// let proof = tree.generate_proof(&"b").expect("Should generate proof");
+    /// let bytes = proof.to_bytes();
+    /// assert_eq!(bytes.len(), 1 + 4 + 2 * 33);
+    /// assert_eq!(MerkleProof::from_bytes(&bytes).expect("Should decode proof"), proof);
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let count = u32::try_from(self.len()).expect("A proof has fewer than 2^32 siblings");
+        let mut bytes = Vec::with_capacity(self.serialized_size(ProofEncoding::Plain));
+        bytes.push(PROOF_FORMAT_VERSION);
+        bytes.extend_from_slice(&count.to_le_bytes());
+        for (sibling, direction) in self {
+            bytes.extend_from_slice(sibling);
+            bytes.push(match direction {
+                Direction::Left => 0,
+                Direction::Right => 1,
+            });
+        }
+        bytes
+    }
+
+    /// Returns the number of bytes of the proof in `encoding`, the length of `to_bytes` or
+    /// `to_compact_bytes` without encoding it
+    pub fn serialized_size(&self, encoding: ProofEncoding) -> usize {
+        encoding.encoded_size(self.len(), N)
+    }
+
+    /// Decodes a proof encoded with `to_bytes`
+    ///
+    /// # Returns
+    /// The proof, or an error if the input is malformed:
+    /// `TruncatedInput` if it ends early, `UnknownFormatVersion` for another version byte,
+    /// `ProofTooLong` for a count above `MAX_PROOF_LEN`, and `InvalidEncoding` for a
+    /// direction byte other than `0` or `1` or bytes after the last sibling
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let (&version, rest) = bytes.split_first().ok_or(MerkleError::TruncatedInput)?;
+        if version != PROOF_FORMAT_VERSION {
+            return Err(MerkleError::UnknownFormatVersion(version));
+        }
+        let (count, rest) = rest
+            .split_first_chunk::<4>()
+            .ok_or(MerkleError::TruncatedInput)?;
+        let count = u32::from_le_bytes(*count) as usize;
+        if count > MAX_PROOF_LEN {
+            return Err(MerkleError::ProofTooLong);
+        }
+        if rest.len() < count * (N + 1) {
+            return Err(MerkleError::TruncatedInput);
+        }
+        if rest.len() > count * (N + 1) {
+            return Err(MerkleError::InvalidEncoding);
+        }
+
+        rest.chunks_exact(N + 1)
+            .map(|element| {
+                let (sibling, direction) = element.split_at(N);
+                let direction = match direction[0] {
+                    0 => Direction::Left,
+                    1 => Direction::Right,
+                    _ => return Err(MerkleError::InvalidEncoding),
+                };
+                let sibling: [u8; N] = sibling.try_into().expect("The chunk holds N bytes");
+                Ok((sibling, direction))
+            })
+            .collect()
+    }
+
+    /// Encodes the proof with its directions packed into a bitfield:
+    ///
+    /// - 1 byte: the number of siblings, at most `MAX_PROOF_LEN`
+    /// - one bit per sibling, rounded up to whole bytes: bit `i % 8` of byte `i / 8` is `1` if
+    ///   the sibling of level `i` is a `Direction::Right` one, and the unused bits are `0`
+    /// - `N` bytes per sibling, from the leaf up to the root
+    ///
+    /// A proof of 20 siblings takes 644 bytes, against 665 with `to_bytes`. The format has no
+    /// version byte, it is meant for space-constrained places like calldata
+    ///
+    /// # Panics
+    /// If the proof has more than `MAX_PROOF_LEN` siblings, which no tree generates
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        assert!(
+            self.len() <= MAX_PROOF_LEN,
+            "A proof has at most MAX_PROOF_LEN siblings"
+        );
+        let mut bytes = vec![0u8; 1 + self.len().div_ceil(8)];
+        bytes[0] = self.len() as u8;
+        for (level, (_, direction)) in self.iter().enumerate() {
+            if direction == Direction::Right {
+                bytes[1 + level / 8] |= 1 << (level % 8);
+            }
+        }
+        bytes.reserve_exact(self.len() * N);
+        for (sibling, _) in self {
+            bytes.extend_from_slice(sibling);
+        }
+        bytes
+    }
+
+    /// Decodes a proof encoded with `to_compact_bytes`
+    ///
+    /// # Returns
+    /// The proof, or an error if the input is malformed: `TruncatedInput` if it ends early,
+    /// `ProofTooLong` for a count above `MAX_PROOF_LEN`, and `InvalidEncoding` for a set unused
+    /// bit or bytes after the last sibling
+    pub fn from_compact_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let (&count, rest) = bytes.split_first().ok_or(MerkleError::TruncatedInput)?;
+        let count = count as usize;
+        if count > MAX_PROOF_LEN {
+            return Err(MerkleError::ProofTooLong);
+        }
+        let bitfield_len = count.div_ceil(8);
+        if rest.len() < bitfield_len + count * N {
+            return Err(MerkleError::TruncatedInput);
+        }
+        if rest.len() > bitfield_len + count * N {
+            return Err(MerkleError::InvalidEncoding);
+        }
+        let (bitfield, siblings) = rest.split_at(bitfield_len);
+        if !count.is_multiple_of(8) && bitfield[bitfield_len - 1] >> (count % 8) != 0 {
+            return Err(MerkleError::InvalidEncoding);
+        }
+
+        Ok(siblings
+            .chunks_exact(N)
+            .enumerate()
+            .map(|(level, sibling)| {
+                let direction = if bitfield[level / 8] >> (level % 8) & 1 == 1 {
+                    Direction::Right
+                } else {
+                    Direction::Left
+                };
+                let sibling: [u8; N] = sibling.try_into().expect("The chunk holds N bytes");
+                (sibling, direction)
+            })
+            .collect())
+    }
+
+    /// Flattens the proof into the witness of a circuit of `depth` levels, padding the levels
+    /// past the proof as described in `ProofWitness`
+    ///
+    /// # Returns
+    /// The witness, or `ProofTooLong` if the proof has more than `depth` siblings
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    /// use sha3::Sha3_256;
+    ///
+    /// let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+    /// let proof = tree.generate_proof(&"c").expect("Should generate proof");
+    /// let witness = proof.to_witness(20).expect("Should fit the circuit");
+    /// assert_eq!(witness.siblings.len(), 20);
+    /// assert_eq!(witness.path_bits[..2], [false, true]);
+    /// let leaf = tree.leaf(2).expect("Should have leaf");
+    /// assert_eq!(witness.compute_root(&Sha3_256::default(), &leaf), tree.root());
+    /// ```
+    pub fn to_witness(&self, depth: usize) -> Result<ProofWitness<N>, MerkleError> {
+        if self.len() > depth {
+            return Err(MerkleError::ProofTooLong);
+        }
+        let mut siblings = Vec::with_capacity(depth);
+        let mut path_bits = Vec::with_capacity(depth);
+        for (sibling, direction) in self {
+            siblings.push(*sibling);
+            path_bits.push(direction == Direction::Left);
+        }
+        siblings.resize(depth, [0u8; N]);
+        path_bits.resize(depth, false);
+        Ok(ProofWitness {
+            siblings,
+            path_bits,
+            levels: self.len(),
+        })
+    }
+
+    /// Works up from `leaf` to the root, hashing each pair of nodes with `hash_nodes`
+    pub(crate) fn fold(
+        &self,
+        leaf: [u8; N],
+        hash_nodes: impl Fn(&[u8; N], &[u8; N]) -> [u8; N],
+    ) -> [u8; N] {
+        self.iter()
+            .fold(leaf, |current, (sibling, direction)| match direction {
+                Direction::Left => hash_nodes(sibling, &current),
+                Direction::Right => hash_nodes(&current, sibling),
+            })
     }
 }
 
-impl MerkleTree {
-    /// Creates a new Merkle Tree from a list of data elements
-    /// The data elements are hashed to create the leaves of the tree
-    /// The tree is then built using a recursive bottom-up approach
+impl MerkleProof {
+    /// Reconstructs the root of the tree from `data` and the proof
+    /// The tree must hash like `MerkleTree::new`, see `verify`
     ///
-    /// # Arguments
-    /// a list of data elements that implement AsRef<[u8]>
+    /// # Returns
+    ///
+    /// The root the proof leads to. A root that doesn't match the expected one can be logged
+    /// to debug the mismatch
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+    /// let proof = tree.generate_proof(&"b").expect("Should generate proof");
+    /// assert_eq!(proof.compute_root("b"), tree.root());
+    /// ```
+    pub fn compute_root<T: Hashable + ?Sized>(&self, data: &T) -> Hash {
+        self.compute_root_from_leaf(&hash(data.leaf_bytes()))
+    }
+
+    /// Reconstructs the root of the tree from an already hashed leaf and the proof
+    pub fn compute_root_from_leaf(&self, leaf: &Hash) -> Hash {
+        self.fold(*leaf, hash_internal_node)
+    }
+
+    /// Returns true if the root reconstructed from `data` is `expected_root`
+    pub fn verify<T: Hashable + ?Sized>(&self, expected_root: &Hash, data: &T) -> bool {
+        hashes_equal(&self.compute_root(data), expected_root)
+    }
+
+    /// Composes the proof of `inner_leaf` in a subtree with the proof of the root of that
+    /// subtree in a parent tree, whose leaves are the roots of its subtrees as they are, like
+    /// a tree created with `MerkleTree::from_leaves`
+    /// The result is the proof of `inner_leaf` against the root of the parent tree
     ///
     /// # Returns
-    /// A MerkleTree instance if the data is not empty, otherwise an error
+    /// The spliced proof, or `RootMismatch` if `inner` doesn't lead from `inner_leaf` to
+    /// `outer_leaf`, the leaf that `outer` proves
     ///
     /// # Example
+    ///
     /// ```
-    /// let data = vec![b"block1", b"block2", b"block3"];
-    /// let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+    /// use rusty_merkle_tree::merkle::{verify, MerkleProof, MerkleTree};
+    ///
+    /// let day = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+    /// let top = MerkleTree::from_leaves(vec![[7u8; 32], day.root()])
+    ///     .expect("Should create merkle tree");
+    /// let inner = day.generate_proof(&"b").expect("Should generate proof");
+    /// let outer = top.generate_proof_for_hash(&day.root()).expect("Should generate proof");
+    /// let leaf = day.leaf(1).expect("Should have leaf");
+    /// let proof = MerkleProof::splice(&leaf, inner, &day.root(), outer).expect("Should splice");
+    /// assert!(verify(&top.root(), "b", &proof));
     /// ```
-    pub fn new<T: AsRef<[u8]>>(data: &[T]) -> Result<Self, MerkleError> {
-        if data.is_empty() {
-            return Err(MerkleError::EmptyData);
+    pub fn splice(
+        inner_leaf: &Hash,
+        inner: MerkleProof,
+        outer_leaf: &Hash,
+        outer: MerkleProof,
+    ) -> Result<MerkleProof, MerkleError> {
+        if !hashes_equal(&inner.compute_root_from_leaf(inner_leaf), outer_leaf) {
+            return Err(MerkleError::RootMismatch);
         }
-        let leaves: Vec<Hash> = data.iter().map(hash).collect();
-        let mut tree = MerkleTree {
-            tree: vec![],
-            leaves,
-        };
-        tree.build();
-        Ok(tree)
+        let mut siblings = inner.siblings;
+        siblings.extend(outer.siblings);
+        Ok(MerkleProof { siblings })
     }
 
-    /// Builds the Merkle Tree using a recursive bottom-up approach.
-    fn build(&mut self) {
-        let mut levels: Vec<Vec<Hash>> = Vec::new();
-        // Add the leaves at first level
-        levels.push(self.leaves.clone());
+    /// Returns the siblings as the `bytes32` values of a Solidity `bytes32[]`, from the leaf up
+    /// to the root. The directions are dropped, like in `generate_sorted_proof`
+    pub fn to_bytes32_array(&self) -> Vec<[u8; 32]> {
+        self.iter().map(|(sibling, _)| *sibling).collect()
+    }
 
-        // Build subsequent levels until we reach the root
-        let mut current_level = self.leaves.clone();
-        while current_level.len() > 1 {
-            let mut next_level: Vec<Hash> = Vec::new();
+    /// Returns the index whose bits give the directions of the proof: bit `i` is `1` if the
+    /// sibling of level `i` is a `Direction::Left` one, so the node is a right child
+    /// For trees that duplicate odd nodes it is the index of the leaf
+    pub fn direction_index(&self) -> u64 {
+        self.iter()
+            .enumerate()
+            .filter(|(_, (_, direction))| *direction == Direction::Left)
+            .fold(0, |index, (level, _)| index | 1 << level)
+    }
 
-            // process pair of nodes
-            for i in (0..current_level.len()).step_by(2) {
-                let left = current_level[i];
-                let right = if i + 1 < current_level.len() {
-                    current_level[i + 1]
-                } else {
-                    left
-                };
+    /// ABI-encodes the proof as `abi.encode(bytes32[] siblings, uint256 index)`, the arguments
+    /// of Solidity verifiers that take the path of the leaf as an index, where `index` is
+    /// `direction_index`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+    /// let proof = tree.generate_proof(&"b").expect("Should generate proof");
+    /// let abi = proof.to_eth_abi();
+    /// // The offset of the array, the index, the length and one word per sibling
+    /// assert_eq!(abi.len(), 32 * (3 + 2));
+    /// assert_eq!(abi[63], 1);
+    /// ```
+    pub fn to_eth_abi(&self) -> Vec<u8> {
+        let mut abi = Vec::with_capacity(32 * (3 + self.len()));
+        abi.extend_from_slice(&abi_word(64));
+        abi.extend_from_slice(&abi_word(self.direction_index()));
+        self.extend_abi_array(&mut abi);
+        abi
+    }
 
-                let parent = hash_internal_node(&left, &right);
-                next_level.push(parent);
-            }
-            levels.push(next_level.clone());
-            current_level = next_level;
+    /// ABI-encodes the siblings as `abi.encode(bytes32[] proof)`, the argument of sorted-pair
+    /// verifiers such as OpenZeppelin's `MerkleProof.verify`
+    pub fn to_eth_abi_sorted(&self) -> Vec<u8> {
+        let mut abi = Vec::with_capacity(32 * (2 + self.len()));
+        abi.extend_from_slice(&abi_word(32));
+        self.extend_abi_array(&mut abi);
+        abi
+    }
+
+    /// Appends the length word and the siblings of the tail of a `bytes32[]`
+    fn extend_abi_array(&self, abi: &mut Vec<u8>) {
+        abi.extend_from_slice(&abi_word(self.len() as u64));
+        for (sibling, _) in self {
+            abi.extend_from_slice(sibling);
         }
-        self.tree = levels;
     }
+}
 
-    /// Returns the root hash of the Merkle tree.
-    pub fn root(&self) -> Hash {
-        *self.tree.last().unwrap().first().unwrap()
+/// A `uint256` ABI word holding `value`
+fn abi_word(value: u64) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// Iterator over the siblings of a `MerkleProof`, returned by `MerkleProof::iter`
+pub type ProofIter<'a, const N: usize> = std::iter::Map<
+    std::slice::Iter<'a, ([u8; N], Direction)>,
+    fn(&'a ([u8; N], Direction)) -> (&'a [u8; N], Direction),
+>;
+
+impl<const N: usize> From<Vec<([u8; N], Direction)>> for MerkleProof<N> {
+    fn from(siblings: Vec<([u8; N], Direction)>) -> Self {
+        MerkleProof { siblings }
     }
+}
 
-    /// A proof is a list of hashes that can be used to verify the membership of a leaf in the tree
-    /// For now the proof is simply that a  list of hashes.
-    /// Possible improvements:
-    /// 1. Store the direction of the hash (left or right) and the level of the tree
-    pub fn generate_proof<T: AsRef<[u8]>>(&self, data: &T) -> Result<MerkleProof, MerkleError> {
-        // Find index of the leaf that corresponds to the given data
-        let leaf_index = self
-            .leaves
-            .iter()
-            .position(|leaf| hash(data) == *leaf)
-            .ok_or(MerkleError::LeafNotFound)?;
+impl<const N: usize> From<MerkleProof<N>> for Vec<([u8; N], Direction)> {
+    fn from(proof: MerkleProof<N>) -> Self {
+        proof.siblings
+    }
+}
 
-        let mut proof: MerkleProof = Vec::new();
-        let mut current_index = leaf_index;
-        // loop each level of the tree
-        for level in 0..self.tree.len() - 1 {
-            let current_level = &self.tree[level];
-            // let is_left = current_index % 2 == 0;
-            let current_direction = determine_direction(current_index);
-            let sibling_index = match current_direction {
-                Direction::Left => {
-                    if current_index + 1 < current_level.len() {
-                        current_index + 1
-                    } else {
-                        current_index
-                    }
-                }
-                Direction::Right => current_index - 1,
-            };
-            let sibling_direction = match current_direction {
-                Direction::Left => Direction::Right,
-                Direction::Right => Direction::Left,
-            };
-            proof.push((current_level[sibling_index], sibling_direction));
+impl<const N: usize> FromIterator<([u8; N], Direction)> for MerkleProof<N> {
+    fn from_iter<I: IntoIterator<Item = ([u8; N], Direction)>>(iter: I) -> Self {
+        MerkleProof {
+            siblings: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<const N: usize> Extend<([u8; N], Direction)> for MerkleProof<N> {
+    fn extend<I: IntoIterator<Item = ([u8; N], Direction)>>(&mut self, iter: I) {
+        self.siblings.extend(iter);
+    }
+}
+
+impl<const N: usize> IntoIterator for MerkleProof<N> {
+    type Item = ([u8; N], Direction);
+    type IntoIter = std::vec::IntoIter<([u8; N], Direction)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.siblings.into_iter()
+    }
+}
+
+impl<'a, const N: usize> IntoIterator for &'a MerkleProof<N> {
+    type Item = (&'a [u8; N], Direction);
+    type IntoIter = ProofIter<'a, N>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Type alias for a proof of a tree created with `with_sorted_pairs`
+/// Pairs are sorted before hashing, so the siblings don't need a Direction
+pub type SortedProof<const N: usize = 32> = Vec<[u8; N]>;
+
+/// Type alias for a proof whose directions are derived from the index of the leaf
+/// At each level the sibling is on the left if the index of the current node is odd
+pub type IndexProof<const N: usize = 32> = Vec<[u8; N]>;
+
+/// A proof that also claims the index of its leaf and the number of leaves of the tree
+/// Verified with `verify_positioned`, which checks that the directions are the ones of that
+/// index, so the proof can't be presented for another slot
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "MerkleProof<N>: serde::Serialize",
+        deserialize = "MerkleProof<N>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct PositionedProof<const N: usize = 32> {
+    pub index: usize,
+    pub leaf_count: usize,
+    pub proof: MerkleProof<N>,
+}
+
+/// A proof laid out as the inputs of a circuit of fixed depth, returned by
+/// `MerkleProof::to_witness`
+/// The levels past the proof are padding: their sibling is all zeros and their path bit is
+/// `false`, and the circuit passes the node up unchanged instead of hashing it, which keeps
+/// the root of the tree. `levels` tells the circuit where the padding starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofWitness<const N: usize = 32> {
+    /// The sibling of each level, from the leaf up, then the padding
+    pub siblings: Vec<[u8; N]>,
+    /// For each level, `true` if the node is the right child, so its sibling is on the left
+    pub path_bits: Vec<bool>,
+    /// The number of levels of the proof, the ones before the padding
+    pub levels: usize,
+}
+
+impl<const N: usize> ProofWitness<N> {
+    /// Works up from `leaf` like the circuit does, hashing the levels of the proof with
+    /// `hasher` and passing the node through the padding levels
+    pub fn compute_root<D: TreeHasher<N>>(&self, hasher: &D, leaf: &[u8; N]) -> [u8; N] {
+        self.siblings
+            .iter()
+            .zip(&self.path_bits)
+            .take(self.levels)
+            .fold(*leaf, |current, (sibling, &is_right)| {
+                if is_right {
+                    hasher.hash_parts(&[sibling, &current])
+                } else {
+                    hasher.hash_parts(&[&current, sibling])
+                }
+            })
+    }
+}
+
+/// One level of a proof for a tree of any arity
+/// The parent is the hash of `siblings` with the proven node inserted at `position`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KaryProofLevel<const N: usize = 32> {
+    pub position: usize,
+    pub siblings: Vec<[u8; N]>,
+}
+
+/// Type alias for a Merkle proof of a tree of any arity, from the leaf level up to the root
+/// Levels where the node was promoted without being hashed don't appear in the proof
+pub type KaryProof<const N: usize = 32> = Vec<KaryProofLevel<N>>;
+
+pub(crate) fn hash<T: AsRef<[u8]>>(element: T) -> Hash {
+    Sha3_256::digest(element).into()
+}
+
+/// The root of a tree without leaves: the hash of the empty string
+pub fn empty_root() -> Hash {
+    hash([])
+}
+
+/// The value of a missing leaf in a fixed-depth tree
+pub const ZERO_HASH: Hash = [0u8; 32];
+
+/// Roots of all-zero subtrees of height 0 to `depth`
+/// The subtree of height 0 is a single `ZERO_HASH` leaf, and each one above hashes two copies of the one below
+fn zero_hashes(depth: usize) -> Vec<Hash> {
+    let mut hashes = Vec::with_capacity(depth + 1);
+    hashes.push(ZERO_HASH);
+    for level in 0..depth {
+        hashes.push(hash_internal_node(&hashes[level], &hashes[level]));
+    }
+    hashes
+}
+
+/// Returns the number of levels above the leaves of a tree of `leaf_count` leaves,
+/// `ceil(log2(leaf_count))`, so 0 for a single leaf. The last node of an odd level is paired
+/// like the others, and a count that is not a power of two takes the depth of the next one
+///
+/// # Example
+/// ```
+/// use rusty_merkle_tree::merkle::expected_depth;
+///
+/// assert_eq!(expected_depth(1), 0);
+/// assert_eq!(expected_depth(4), 2);
+/// assert_eq!(expected_depth(5), 3);
+/// ```
+pub fn expected_depth(leaf_count: usize) -> usize {
+    (usize::BITS - leaf_count.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// Returns the number of siblings of the proof of any leaf of a tree of `leaf_count` leaves
+/// built with the default settings, one per level above the leaves
+/// A tree that promotes odd nodes generates proofs with at most this many siblings
+pub fn expected_proof_len(leaf_count: usize) -> usize {
+    expected_depth(leaf_count)
+}
+
+/// Number of levels of a tree with `leaf_count` leaves, counting the leaves and the root
+fn level_count(leaf_count: usize) -> usize {
+    let mut levels = 1;
+    let mut size = leaf_count;
+    while size > 1 {
+        size = size.div_ceil(2);
+        levels += 1;
+    }
+    levels
+}
+
+/// Hashes the concatenation of `parts` with the digest `D`
+pub(crate) fn digest_parts<D: Digest, const N: usize>(parts: &[&[u8]]) -> [u8; N]
+where
+    [u8; N]: From<Output<D>>,
+{
+    let mut hasher = D::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// The NFC form of `bytes` if they are valid UTF-8, otherwise `bytes` unchanged
+#[cfg(feature = "unicode")]
+fn nfc(bytes: Cow<'_, [u8]>) -> Cow<'_, [u8]> {
+    match std::str::from_utf8(&bytes) {
+        Ok(text) if !is_nfc(text) => {
+            // NFC is at most three times longer, so the string never reallocates
+            let mut normalized = String::with_capacity(3 * text.len());
+            normalized.extend(text.nfc());
+            Cow::Owned(normalized.into_bytes())
+        }
+        _ => bytes,
+    }
+}
+
+/// Wipes a temporary copy of leaf data, borrowed data is left to its owner
+#[cfg(feature = "zeroize")]
+pub(crate) fn wipe(bytes: Cow<'_, [u8]>) {
+    if let Cow::Owned(mut bytes) = bytes {
+        bytes.zeroize();
+    }
+}
+
+/// Compares two hashes in constant time when the `subtle` feature is enabled, as it is by default,
+/// so the time taken doesn't reveal how many leading bytes match
+pub(crate) fn hashes_equal<const N: usize>(a: &[u8; N], b: &[u8; N]) -> bool {
+    #[cfg(feature = "subtle")]
+    {
+        a.ct_eq(b).into()
+    }
+    #[cfg(not(feature = "subtle"))]
+    {
+        a == b
+    }
+}
+
+/// Index of the first of `hashes` equal to `target`
+/// With the `subtle` feature every hash is compared, so the time taken doesn't reveal the index
+pub(crate) fn position_of<const N: usize>(hashes: &[[u8; N]], target: &[u8; N]) -> Option<usize> {
+    #[cfg(feature = "subtle")]
+    {
+        let mut index = hashes.len() as u64;
+        for (candidate_index, candidate) in hashes.iter().enumerate().rev() {
+            index.conditional_assign(&(candidate_index as u64), candidate.ct_eq(target));
+        }
+        (index < hashes.len() as u64).then_some(index as usize)
+    }
+    #[cfg(not(feature = "subtle"))]
+    {
+        hashes.iter().position(|candidate| candidate == target)
+    }
+}
+
+/// Hashes two hashes together to create a new hash
+pub(crate) fn hash_internal_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha3_256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Verifies a proof of inclusion of `data` with nothing but the root of the tree
+/// The tree must hash like `MerkleTree::new`: SHA3-256 leaves and nodes without prefixes,
+/// for other settings use the tree's `verify_proof`
+///
+/// # Returns
+///
+/// `true` if the root computed from `data` and the proof matches `root`. The root of a tree
+/// without leaves is `hash("")`, so an empty proof of `""` verifies against `empty_root()`.
+/// A proof of any length is folded, use `verify_strict` when the height of the tree is known
+///
+/// # Example
+///
+/// ```
+/// use rusty_merkle_tree::merkle::{verify, MerkleTree};
+///
+/// let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+/// let proof = tree.generate_proof(&"b").unwrap();
+/// assert!(verify(&tree.root(), "b", &proof));
+/// ```
+pub fn verify<T: Hashable + ?Sized>(root: &Hash, data: &T, proof: &MerkleProof) -> bool {
+    proof.verify(root, data)
+}
+
+/// Same as `verify`, also checking that the proof has `depth` siblings, the height of the tree
+/// the verifier expects, so that an empty or truncated proof doesn't reach an internal node
+///
+/// # Returns
+/// `Ok(())` if the root computed from `data` matches `root`, `InvalidProofShape` if the proof
+/// doesn't have `depth` siblings, or `RootMismatch`
+pub fn verify_strict<T: Hashable + ?Sized>(
+    root: &Hash,
+    data: &T,
+    proof: &MerkleProof,
+    depth: usize,
+) -> Result<(), MerkleError> {
+    if proof.len() != depth {
+        return Err(MerkleError::InvalidProofShape);
+    }
+    if !proof.verify(root, data) {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
+}
+
+/// Verifies a proof of inclusion of an already hashed leaf with nothing but the root of the tree
+/// Same as `verify`, for a client that only received the leaf hash
+pub fn verify_leaf(root: &Hash, leaf: &Hash, proof: &MerkleProof) -> bool {
+    hashes_equal(&proof.compute_root_from_leaf(leaf), root)
+}
+
+/// Verifies a proof of `generate_index_proof` for the leaf at `index` with nothing but the root
+/// The tree must hash like `MerkleTree::new`, see `verify`
+///
+/// # Returns
+///
+/// `true` if the root computed from `data` matches `root`, folding each sibling on the side
+/// given by the bits of `index`. An index with bits above the height of the proof fails.
+/// In a tree that duplicates odd nodes, the last node of an odd level also verifies at the
+/// index of its duplicate, compare `index` with the number of leaves if that matters
+///
+/// # Example
+///
+/// ```
+/// use rusty_merkle_tree::merkle::{verify_index_proof, MerkleTree};
+///
+/// let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+/// let proof = tree.generate_index_proof(1).expect("Should generate proof");
+/// assert!(verify_index_proof(&tree.root(), 1, "b", &proof));
+/// assert!(!verify_index_proof(&tree.root(), 0, "b", &proof));
+/// ```
+pub fn verify_index_proof<T: Hashable + ?Sized>(
+    root: &Hash,
+    index: usize,
+    data: &T,
+    proof: &IndexProof,
+) -> bool {
+    if index.checked_shr(proof.len() as u32).unwrap_or(0) != 0 {
+        return false;
+    }
+    let (computed, _) = proof.iter().fold(
+        (hash(data.leaf_bytes()), index),
+        |(current, index), sibling| {
+            let parent = match determine_direction(index) {
+                Direction::Left => hash_internal_node(&current, sibling),
+                Direction::Right => hash_internal_node(sibling, &current),
+            };
+            (parent, index / 2)
+        },
+    );
+    hashes_equal(&computed, root)
+}
+
+/// Verifies a proof of `generate_positioned_proof` with nothing but the root of the tree
+/// The tree must hash like `MerkleTree::new`, see `verify`
+///
+/// # Returns
+///
+/// `true` if the root computed from `data` matches `root` and the proof has the siblings of
+/// the leaf at the claimed index: one per level of a tree of `leaf_count` leaves, on the left
+/// where the bit of the index is set. The root doesn't commit to the number of leaves, so the
+/// right half of an odd level can also be claimed as the copy of its last node, compare
+/// `leaf_count` with a trusted count or use `committed_root` if that matters
+pub fn verify_positioned<T: Hashable + ?Sized>(
+    root: &Hash,
+    data: &T,
+    proof: &PositionedProof,
+) -> bool {
+    let Some(width) = proof.leaf_count.checked_next_power_of_two() else {
+        return false;
+    };
+    if proof.index >= proof.leaf_count || proof.proof.len() != width.trailing_zeros() as usize {
+        return false;
+    }
+    directions_match_index(&proof.proof, proof.index) && proof.proof.verify(root, data)
+}
+
+/// True if the sibling of each level of `proof` is on the side given by the bit of `index` for
+/// that level: on the left where the bit is set
+fn directions_match_index<const N: usize>(proof: &MerkleProof<N>, index: usize) -> bool {
+    proof.iter().enumerate().all(|(level, (_, direction))| {
+        let expected = match determine_direction(index >> level) {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        };
+        direction == expected
+    })
+}
+
+/// Verifies a proof of `generate_proof` for the leaf at `index` with nothing but the root
+/// The tree must hash like `MerkleTree::new`, see `verify`, and duplicate odd nodes: promoted
+/// nodes have no sibling, so the levels of the proof are no longer the bits of the index
+///
+/// # Returns
+///
+/// `true` if the directions of the proof are the ones given by the bits of `index`, and the
+/// root computed from `data` matches `root`. An index with bits above the height of the proof
+/// fails, as a proof replayed for any index other than the one of its leaf
+///
+/// # Example
+///
+/// ```
+/// use rusty_merkle_tree::merkle::{verify_proof_at_index, MerkleTree};
+///
+/// let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+/// let proof = tree.generate_proof(&"b").expect("Should generate proof");
+/// assert!(verify_proof_at_index(&tree.root(), 1, "b", &proof));
+/// assert!(!verify_proof_at_index(&tree.root(), 3, "b", &proof));
+/// ```
+pub fn verify_proof_at_index<T: Hashable + ?Sized>(
+    root: &Hash,
+    index: usize,
+    data: &T,
+    proof: &MerkleProof,
+) -> bool {
+    index.checked_shr(proof.len() as u32).unwrap_or(0) == 0
+        && directions_match_index(proof, index)
+        && proof.verify(root, data)
+}
+
+/// Same as `verify_positioned` for a tree the verifier knows has `size` leaves
+/// The proof must have been generated against a tree of that size, and the last node of each
+/// odd level must be paired with itself, so a proof of a bigger tree whose root is published
+/// for a tree of `size` leaves is rejected
+///
+/// # Returns
+///
+/// `true` if the proof is valid for a tree of `size` leaves with the given `root`. A tree whose
+/// extra leaves only repeat its last leaf has the same root and the same proofs, it passes
+/// for the size of either tree
+pub fn verify_with_size<T: Hashable + ?Sized>(
+    root: &Hash,
+    size: usize,
+    data: &T,
+    proof: &PositionedProof,
+) -> bool {
+    proof.leaf_count == size
+        && positioned_root(&Sha3_256::default(), hash(data.leaf_bytes()), proof)
+            .is_some_and(|computed| hashes_equal(&computed, root))
+}
+
+/// The index of the leaf a proof is for: each sibling on the left is a set bit
+pub(crate) fn index_from_directions(proof: &MerkleProof) -> Option<usize> {
+    if proof.len() > MAX_PROOF_LEN {
+        return None;
+    }
+    Some(
+        proof
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, direction))| *direction == Direction::Left)
+            .fold(0, |index, (level, _)| index | 1 << level),
+    )
+}
+
+/// The root computed from `leaf` with a proof of `generate_positioned_proof`, hashing the nodes
+/// with `hasher` like a tree with the default settings
+/// None unless the proof has the directions of its index in a tree of `leaf_count` leaves and
+/// pairs the last node of each odd level with itself
+pub(crate) fn positioned_root<H: TreeHasher>(
+    hasher: &H,
+    leaf: Hash,
+    proof: &PositionedProof,
+) -> Option<Hash> {
+    let width = proof.leaf_count.checked_next_power_of_two()?;
+    if proof.index >= proof.leaf_count || proof.proof.len() != width.trailing_zeros() as usize {
+        return None;
+    }
+    let mut node = leaf;
+    let mut index = proof.index;
+    let mut width = proof.leaf_count;
+    for (sibling, direction) in proof.proof.iter() {
+        let lone = index == width - 1 && index.is_multiple_of(2);
+        node = match (determine_direction(index), direction) {
+            (Direction::Left, Direction::Right) if !lone || hashes_equal(sibling, &node) => {
+                hasher.hash_parts(&[&node, sibling])
+            }
+            (Direction::Right, Direction::Left) => hasher.hash_parts(&[sibling, &node]),
+            _ => return None,
+        };
+        index /= 2;
+        width = width.div_ceil(2);
+    }
+    Some(node)
+}
+
+fn determine_direction(index: usize) -> Direction {
+    if index.is_multiple_of(2) {
+        Direction::Left
+    } else {
+        Direction::Right
+    }
+}
+
+/// Reads from `reader` until `buffer` is full or the stream ends
+/// Returns the number of bytes written to `buffer`
+fn read_chunk<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+impl MerkleTree {
+    /// Creates a new Merkle Tree from a list of data elements
+    /// The data elements are hashed to create the leaves of the tree
+    /// The tree is then built using a recursive bottom-up approach
+    ///
+    /// # Arguments
+    /// a list of data elements that implement Hashable, like any AsRef<[u8]>
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// let data = vec![b"block1", b"block2", b"block3"];
+    /// let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+    /// ```
+    pub fn new<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        Self::with_digest(data)
+    }
+
+    /// Creates a Merkle Tree without any leaves
+    /// Its root is `empty_root()` and it can be grown with `add()`
+    pub fn empty() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Returns true if the tree hashes and shapes its nodes like one created with `new`
+    pub(crate) fn has_default_settings(&self) -> bool {
+        self.has_same_settings(&Self::empty())
+    }
+
+    /// Creates a tree over `n` deterministic elements, `dummy_leaf(0)` to `dummy_leaf(n - 1)`,
+    /// for tests that need a tree of some size. `dummy(0)` is the empty tree
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// let tree = MerkleTree::dummy(10);
+    /// let proof = tree.generate_proof(&MerkleTree::dummy_leaf(3)).expect("Should generate proof");
+    /// assert!(tree.verify_proof(&MerkleTree::dummy_leaf(3), &proof));
+    /// ```
+    #[cfg(feature = "test-util")]
+    pub fn dummy(n: usize) -> Self {
+        let mut tree = Self::with_capacity(n);
+        tree.extend_from(&(0..n).map(Self::dummy_leaf).collect::<Vec<_>>())
+            .expect("A tree without a fixed depth has room for any leaf");
+        tree
+    }
+
+    /// The element at `index` of the trees of `dummy`, `b"leaf-{index}"`
+    #[cfg(feature = "test-util")]
+    pub fn dummy_leaf(index: usize) -> Vec<u8> {
+        format!("leaf-{}", index).into_bytes()
+    }
+
+    /// Creates an empty Merkle Tree with room for `capacity` leaves
+    /// The leaves and every level of the tree are sized up front,
+    /// so adding up to `capacity` elements allocates each level only once
+    /// Adding more than `capacity` elements is still possible, the levels just grow as needed
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut tree = Vec::with_capacity(level_count(capacity));
+        tree.push(Vec::with_capacity(capacity));
+        MerkleTree {
+            tree: Arc::new(tree),
+            ..Self::unbuilt(Vec::with_capacity(capacity))
+        }
+    }
+
+    /// Creates a new Merkle Tree from an iterator of data elements
+    /// Each element is hashed as soon as it is yielded, so only the leaf hashes are kept in memory
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the iterator yields at least one element, otherwise an error
+    /// Use `collect()` instead to get an empty tree from an empty iterator
+    pub fn try_from_iter<T: Hashable, I: IntoIterator<Item = T>>(
+        iter: I,
+    ) -> Result<Self, MerkleError> {
+        let mut tree = Self::unbuilt(vec![]);
+        tree.leaves = Arc::new(iter.into_iter().map(|item| tree.hash_leaf(&item)).collect());
+        if tree.leaves.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree from a stream of bytes
+    /// The stream is read in chunks of `chunk_size` bytes and each chunk is hashed as a leaf,
+    /// so the whole input never needs to be in memory at once
+    /// The last chunk may be shorter than `chunk_size` and is hashed as it is, without padding
+    ///
+    /// # Returns
+    /// A MerkleTree instance, `InvalidChunkSize` if `chunk_size` is 0,
+    /// `EmptyData` if the stream is empty, or `Io` if reading fails
+    pub fn from_reader<R: Read>(mut reader: R, chunk_size: usize) -> Result<Self, MerkleError> {
+        if chunk_size == 0 {
+            return Err(MerkleError::InvalidChunkSize);
+        }
+        let mut leaves: Vec<Hash> = Vec::new();
+        let buffer = vec![0u8; chunk_size];
+        // The buffer is never reallocated, so wiping it on drop leaves no copy of the data
+        #[cfg(feature = "zeroize")]
+        let mut buffer = Zeroizing::new(buffer);
+        #[cfg(not(feature = "zeroize"))]
+        let mut buffer = buffer;
+        loop {
+            let filled = read_chunk(&mut reader, &mut buffer)?;
+            if filled == 0 {
+                break;
+            }
+            leaves.push(hash(&buffer[..filled]));
+            if filled < chunk_size {
+                break;
+            }
+        }
+        Self::from_leaves(leaves)
+    }
+
+    /// Creates a new Merkle Tree from already hashed leaves
+    /// The leaves are used as they are, without hashing them again,
+    /// so the original data doesn't need to be kept around
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the leaves are not empty, otherwise an error
+    pub fn from_leaves(leaves: Vec<Hash>) -> Result<Self, MerkleError> {
+        Self::from_leaves_with_odd_node_policy(leaves, OddNodePolicy::default())
+    }
+
+    /// Creates a new Merkle Tree handling odd levels according to `policy`
+    /// The policy is stored on the tree, so its proofs and later mutations follow it too
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn with_odd_node_policy<T: Hashable>(
+        data: &[T],
+        policy: OddNodePolicy,
+    ) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            odd_node_policy: policy,
+            ..Self::unbuilt(vec![])
+        };
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Same as `with_odd_node_policy`, but from already hashed leaves
+    pub fn from_leaves_with_odd_node_policy(
+        leaves: Vec<Hash>,
+        policy: OddNodePolicy,
+    ) -> Result<Self, MerkleError> {
+        if leaves.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            odd_node_policy: policy,
+            ..Self::unbuilt(leaves)
+        };
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree where each internal node hashes up to `arity` children, in order
+    /// The last group of a level may have fewer children, and is completed according to the
+    /// default `OddNodePolicy`, duplicating its last child
+    /// Proofs for these trees are generated with `generate_kary_proof`, with an arity of 2
+    /// this is the same tree as `new()` builds
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    /// `InvalidArity` is returned if `arity` is less than 2
+    pub fn with_arity<T: Hashable>(arity: usize, data: &[T]) -> Result<Self, MerkleError> {
+        if arity < 2 {
+            return Err(MerkleError::InvalidArity);
+        }
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            arity,
+            ..Self::unbuilt(vec![])
+        };
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree that handles repeated elements according to `policy`
+    /// The policy is stored on the tree and enforced by every later mutation too
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    /// `DuplicateLeaf` is returned if `policy` is `Reject` and an element is repeated
+    pub fn with_duplicate_policy<T: Hashable>(
+        data: &[T],
+        policy: DuplicatePolicy,
+    ) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            duplicate_policy: policy,
+            ..Self::unbuilt(vec![])
+        };
+        let leaves = data.iter().map(|element| tree.hash_leaf(element)).collect();
+        tree.leaves = Arc::new(tree.admit(leaves)?);
+        tree.track(&tree.leaves.clone());
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree hashing the data of each leaf according to `leaf_hashing`
+    /// The option is stored on the tree, so proofs and later mutations hash data the same way
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn with_leaf_hashing<T: Hashable>(
+        data: &[T],
+        leaf_hashing: LeafHashing,
+    ) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            leaf_hashing,
+            ..Self::unbuilt(vec![])
+        };
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree that NFC-normalizes UTF-8 data before hashing it if
+    /// `normalize_nfc` is true, so composed and decomposed forms of a string give the same leaf
+    /// The option is stored on the tree, so `add`, `generate_proof` and `verify_proof`
+    /// normalize their data too. Data that isn't valid UTF-8 is hashed unchanged
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// let merkle = MerkleTree::with_normalize_nfc(&["caf\u{e9}"], true)
+    ///     .expect("Should create merkle tree");
+    /// let proof = merkle.generate_proof("cafe\u{301}").expect("Should generate proof");
+    /// assert!(merkle.verify_proof("caf\u{e9}", &proof));
+    /// ```
+    #[cfg(feature = "unicode")]
+    pub fn with_normalize_nfc<T: Hashable>(
+        data: &[T],
+        normalize_nfc: bool,
+    ) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            normalize_nfc,
+            ..Self::unbuilt(vec![])
+        };
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree without prefixes that refuses data exactly as long as two hashes
+    /// Without prefixes, `left || right` hashes to the parent of `left` and `right`, so it would
+    /// verify as a member with the proof of that parent. Refusing data of that length in `new`,
+    /// `add`, `update` and every `verify_*` method blocks the forgery while keeping the roots
+    /// of the unprefixed trees. `with_domain_separation` blocks it for data of any length
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    /// `AmbiguousLeafLength` is returned if an element is 64 bytes long
+    pub fn with_strict_leaf_length<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            strict_leaf_length: true,
+            ..Self::unbuilt(vec![])
+        };
+        tree.leaves = Arc::new(
+            data.iter()
+                .map(|element| tree.checked_leaf(element))
+                .collect::<Result<_, _>>()?,
+        );
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree with RFC 6962 style domain separation
+    /// Leaves are hashed as `H(0x00 || data)` and internal nodes as `H(0x01 || left || right)`,
+    /// so a crafted leaf equal to two concatenated nodes can't pass as an internal node
+    /// Other constructors keep the unprefixed hashing, so their roots don't change
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn with_domain_separation<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            domain_separation: true,
+            ..Self::unbuilt(vec![])
+        };
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new canonical Merkle Tree, whose leaves are sorted by hash before building
+    /// The root only depends on the set of elements and not on the order they are given in,
+    /// and the leaves stay sorted on every later mutation, so `add()` inserts each new leaf
+    /// at its sorted position instead of appending it
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn canonical<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            sorted: true,
+            ..Self::unbuilt(vec![])
+        };
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        Arc::make_mut(&mut tree.leaves).sort_unstable();
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree that keeps a copy of the original data of each leaf
+    /// The data is available through `data(index)` and follows the leaves through every
+    /// later mutation, trees created any other way don't store it at all
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn with_retained_data<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        let mut tree = Self::new(data)?;
+        tree.retained = Some(HashMap::new());
+        for (leaf, element) in Arc::clone(&tree.leaves).iter().zip(data) {
+            tree.retain(*leaf, &element.leaf_bytes());
+        }
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree with exactly `depth` levels above the leaves
+    /// The tree has room for `2^depth` leaves, and the missing ones are `ZERO_HASH`
+    /// Instead of following an `OddNodePolicy`, the last node of odd levels is paired with the root
+    /// of an all-zero subtree, so every proof has exactly `depth` elements
+    ///
+    /// # Returns
+    /// A MerkleTree instance, or `TreeFull` if there are more than `2^depth` elements
+    /// Unlike `new()`, an empty list of elements is accepted
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// let merkle = MerkleTree::with_depth(20, &[b"block1"]).expect("Should create merkle tree");
+    /// let proof = merkle.generate_proof(b"block1").expect("Should generate proof");
+    /// assert_eq!(proof.len(), 20);
+    /// ```
+    pub fn with_depth<T: Hashable>(depth: usize, data: &[T]) -> Result<Self, MerkleError> {
+        let mut tree = MerkleTree {
+            zero_hashes: Some(zero_hashes(depth)),
+            ..Self::unbuilt(vec![])
+        };
+        tree.check_room(data.len())?;
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        tree.build();
+        Ok(tree)
+    }
+}
+
+#[cfg(feature = "sha2")]
+impl MerkleTree<Sha256> {
+    /// Creates a new Merkle Tree hashed with SHA-256 instead of SHA3-256
+    /// Leaves are hashed as `SHA-256(data)` and internal nodes as `SHA-256(left || right)`,
+    /// with the same shape as `new()`, so the roots match the usual SHA-256 Merkle trees
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn new_sha256<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        Self::with_digest(data)
+    }
+
+    /// Creates a new Merkle Tree following RFC 6962 (Certificate Transparency), section 2.1
+    /// Leaves are hashed as `SHA-256(0x00 || data)` and internal nodes as `SHA-256(0x01 || left || right)`.
+    /// An n-leaf tree is split at the largest power of two smaller than n, which is the same as
+    /// promoting the last node of odd levels, so proofs follow the RFC audit paths.
+    ///
+    /// # Returns
+    /// A MerkleTree instance, an empty list of elements is accepted and has `SHA-256()` as its root
+    pub fn new_rfc6962<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        let mut tree = MerkleTree {
+            odd_node_policy: OddNodePolicy::PromoteToNextLevel,
+            domain_separation: true,
+            ..Self::unbuilt(vec![])
+        };
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        tree.build();
+        Ok(tree)
+    }
+}
+
+impl MerkleTree<HashAlgorithm> {
+    /// Creates a new Merkle Tree hashed with `algorithm`, for when it is only known at runtime
+    /// The algorithm is stored on the tree, so its proofs and later mutations use it too,
+    /// and the roots are the same as `with_digest` with the matching digest type
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::{HashAlgorithm, MerkleTree};
+    /// let algorithm: HashAlgorithm = "keccak256".parse().expect("Should parse algorithm");
+    /// let merkle = MerkleTree::with_algorithm(algorithm, &[b"block1", b"block2"])
+    ///     .expect("Should create merkle tree");
+    /// let proof = merkle.generate_proof(b"block1").expect("Should generate proof");
+    /// assert!(merkle.verify_proof(b"block1", &proof));
+    /// ```
+    pub fn with_algorithm<T: Hashable>(
+        algorithm: HashAlgorithm,
+        data: &[T],
+    ) -> Result<Self, MerkleError> {
+        Self::built_with(algorithm, data)
+    }
+
+    /// Returns the hash function of the tree
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.hasher
+    }
+}
+
+impl MerkleTree<Keccak256> {
+    /// Creates a new Merkle Tree hashed with Keccak-256, the hash function of Ethereum
+    /// It uses the original Keccak padding, so its roots differ from `new()` and can be
+    /// checked by EVM contracts with `keccak256(abi.encodePacked(left, right))`
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn new_keccak256<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        Self::with_digest(data)
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl MerkleTree<KeyedBlake3> {
+    /// Creates a new Merkle Tree whose leaves and nodes are hashed with `blake3::keyed_hash`
+    /// The key is stored on the tree, so only trees with the same key verify the same proofs
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn new_keyed<T: Hashable>(key: [u8; 32], data: &[T]) -> Result<Self, MerkleError> {
+        Self::built_with(KeyedBlake3::Keyed(key), data)
+    }
+
+    /// Creates a new Merkle Tree whose leaves and nodes are hashed with `blake3::derive_key`
+    /// `context` should be hardcoded, globally unique and application-specific, as BLAKE3 requires
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn new_derive_key<T: Hashable>(context: &str, data: &[T]) -> Result<Self, MerkleError> {
+        Self::built_with(KeyedBlake3::DeriveKey(context.to_string()), data)
+    }
+}
+
+impl MerkleTree<HmacSha3> {
+    /// Creates a new Merkle Tree whose leaves are `HMAC-SHA3-256(key, data)`
+    /// The key is stored on the tree and used to hash the data of every later mutation
+    /// and proof, so the leaves can't be recomputed from the data alone
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    pub fn with_key<T: Hashable>(key: &[u8], data: &[T]) -> Result<Self, MerkleError> {
+        Self::built_with(HmacSha3 { key: key.to_vec() }, data)
+    }
+}
+
+impl<D: TreeHasher<N>, const N: usize> MerkleTree<D, N> {
+    /// Creates a new Merkle Tree whose leaves and internal nodes are hashed with the digest `D`
+    /// Any RustCrypto digest can be used, with `N` set to its output size when it isn't 32 bytes.
+    /// `new()` is the same as `with_digest` with `Sha3_256`.
+    /// Proofs are generated and verified with the tree's digest
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// use sha3::Keccak256;
+    ///
+    /// let data = [b"block1", b"block2", b"block3"];
+    /// let merkle = MerkleTree::<Keccak256>::with_digest(&data).expect("Should create merkle tree");
+    /// let proof = merkle.generate_proof(b"block2").expect("Should generate proof");
+    /// assert!(merkle.verify_proof(b"block2", &proof));
+    /// ```
+    ///
+    /// Trees with different digests are different types, so they can't be mixed
+    /// ```compile_fail
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// # use sha3::Keccak256;
+    /// let sha3 = MerkleTree::new(&[b"block1"]).expect("Should create merkle tree");
+    /// let keccak = MerkleTree::<Keccak256>::with_digest(&[b"block2"]).expect("Should create merkle tree");
+    /// let merged = sha3.merge(keccak);
+    /// ```
+    pub fn with_digest<T: Hashable>(data: &[T]) -> Result<Self, MerkleError>
+    where
+        D: Default,
+    {
+        Self::built_with(D::default(), data)
+    }
+
+    /// A tree with the default settings over `data`, hashed with `hasher`
+    fn built_with<T: Hashable>(hasher: D, data: &[T]) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = Self::unbuilt_with(hasher, vec![]);
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Same as `with_digest`, but from leaves already hashed with `D`
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the leaves are not empty, otherwise an error
+    pub fn from_leaves_with_digest(leaves: Vec<[u8; N]>) -> Result<Self, MerkleError>
+    where
+        D: Default,
+    {
+        if leaves.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = Self::unbuilt(leaves);
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Creates a new Merkle Tree compatible with OpenZeppelin's `MerkleProof.verify`
+    /// The two children of each node are sorted before hashing, `parent = H(min || max)`,
+    /// and the last node of odd levels is promoted, like `merkletreejs` with `sortPairs`
+    /// Proofs are generated with `generate_sorted_proof`, as a plain list of siblings
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the data is not empty, otherwise an error
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// use sha3::Keccak256;
+    ///
+    /// let data = [b"a", b"b", b"c"];
+    /// let merkle = MerkleTree::<Keccak256>::with_sorted_pairs(&data).expect("Should create merkle tree");
+    /// let proof = merkle.generate_sorted_proof(b"c").expect("Should generate proof");
+    /// assert!(merkle.verify_sorted_proof(b"c", &proof));
+    /// ```
+    pub fn with_sorted_pairs<T: Hashable>(data: &[T]) -> Result<Self, MerkleError>
+    where
+        D: Default,
+    {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let mut tree = MerkleTree {
+            odd_node_policy: OddNodePolicy::PromoteToNextLevel,
+            sorted_pairs: true,
+            ..Self::unbuilt(vec![])
+        };
+        tree.leaves = Arc::new(data.iter().map(|element| tree.hash_leaf(element)).collect());
+        tree.build();
+        Ok(tree)
+    }
+
+    /// Returns true if the children of each node are sorted before hashing them
+    pub fn has_sorted_pairs(&self) -> bool {
+        self.sorted_pairs
+    }
+
+    /// A tree with the default settings whose levels haven't been built yet
+    fn unbuilt(leaves: Vec<[u8; N]>) -> Self
+    where
+        D: Default,
+    {
+        Self::unbuilt_with(D::default(), leaves)
+    }
+
+    /// Same as `unbuilt`, hashed with `hasher`
+    fn unbuilt_with(hasher: D, leaves: Vec<[u8; N]>) -> Self {
+        MerkleTree {
+            tree: Arc::new(vec![]),
+            leaves: Arc::new(leaves),
+            zero_hashes: None,
+            odd_node_policy: OddNodePolicy::default(),
+            arity: 2,
+            hasher,
+            domain_separation: false,
+            duplicate_policy: DuplicatePolicy::default(),
+            leaf_set: HashSet::new(),
+            sorted: false,
+            sorted_pairs: false,
+            leaf_hashing: LeafHashing::default(),
+            normalize_nfc: false,
+            strict_leaf_length: false,
+            retained: None,
+            proof_cache: ProofCache::default(),
+        }
+    }
+
+    /// Returns the number of leaves the tree can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        self.leaves.capacity()
+    }
+
+    /// Returns how many times the data of a leaf is hashed
+    pub fn leaf_hashing(&self) -> LeafHashing {
+        self.leaf_hashing
+    }
+
+    /// Returns true if UTF-8 data is NFC-normalized before hashing
+    pub fn normalize_nfc(&self) -> bool {
+        self.normalize_nfc
+    }
+
+    /// Returns true if data as long as the children of a node is refused
+    pub fn has_strict_leaf_length(&self) -> bool {
+        self.strict_leaf_length
+    }
+
+    /// Returns true if leaves and internal nodes are hashed with different prefixes
+    pub fn has_domain_separation(&self) -> bool {
+        self.domain_separation
+    }
+
+    /// Returns true if the leaves are kept sorted by hash
+    pub fn is_canonical(&self) -> bool {
+        self.sorted
+    }
+
+    /// Returns the index of the first leaf holding `data`, or None if it is not in the tree
+    pub fn index_of<T: Hashable + ?Sized>(&self, data: &T) -> Option<usize> {
+        self.find_leaf(&self.hash_leaf(data))
+    }
+
+    /// Returns the indices of every leaf holding `data`, in increasing order
+    /// Each occurrence is proven with `generate_proof_by_index`
+    pub fn occurrences_of<T: Hashable + ?Sized>(&self, data: &T) -> Vec<usize> {
+        let leaf = self.hash_leaf(data);
+        if self.sorted {
+            let start = self.leaves.partition_point(|candidate| candidate < &leaf);
+            let end = self.leaves.partition_point(|candidate| candidate <= &leaf);
+            return (start..end).collect();
+        }
+        (0..self.leaves.len())
+            .filter(|&index| hashes_equal(&self.leaves[index], &leaf))
+            .collect()
+    }
+
+    /// Returns the hash of the leaf at `index`, or None if there is no such leaf
+    pub fn leaf(&self, index: usize) -> Option<[u8; N]> {
+        self.leaves.get(index).copied()
+    }
+
+    /// Returns true if the tree keeps the original data of its leaves
+    pub fn retains_data(&self) -> bool {
+        self.retained.is_some()
+    }
+
+    /// Returns the original data of the leaf at `index`
+    /// None if there is no such leaf or the tree doesn't retain data
+    pub fn data(&self, index: usize) -> Option<&[u8]> {
+        let leaf = self.leaves.get(index)?;
+        let (data, _) = self.retained.as_ref()?.get(leaf)?;
+        Some(data)
+    }
+
+    /// Returns how the tree handles leaves equal to one it already has
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    /// Returns the number of children of each internal node
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Returns how the tree handles the last node of odd levels
+    pub fn odd_node_policy(&self) -> OddNodePolicy<N> {
+        self.odd_node_policy
+    }
+
+    /// Hashes the concatenation of `parts` with the tree's hash function
+    fn digest(&self, parts: &[&[u8]]) -> [u8; N] {
+        self.hasher.hash_parts(parts)
+    }
+
+    /// Hashes `data` into a leaf, unless the tree refuses data of its length
+    pub(crate) fn checked_leaf<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<[u8; N], MerkleError> {
+        if self.is_ambiguous(data) {
+            return Err(MerkleError::AmbiguousLeafLength);
+        }
+        Ok(self.hash_leaf(data))
+    }
+
+    /// True if the tree has a strict leaf length and `data` is as long as the children of a node
+    fn is_ambiguous<T: Hashable + ?Sized>(&self, data: &T) -> bool {
+        self.strict_leaf_length && data.leaf_bytes().len() == self.arity * N
+    }
+
+    /// Hashes a data element into a leaf
+    pub(crate) fn hash_leaf<T: Hashable + ?Sized>(&self, data: &T) -> [u8; N] {
+        let bytes = data.leaf_bytes();
+        #[cfg(feature = "unicode")]
+        let bytes = if self.normalize_nfc {
+            nfc(bytes)
+        } else {
+            bytes
+        };
+        let leaf = if self.domain_separation {
+            self.digest(&[&[LEAF_PREFIX], &bytes])
+        } else {
+            self.hasher.hash_leaf_bytes(&bytes)
+        };
+        #[cfg(feature = "zeroize")]
+        wipe(bytes);
+        match self.leaf_hashing {
+            LeafHashing::Single => leaf,
+            LeafHashing::Double => self.digest(&[&leaf]),
+        }
+    }
+
+    /// Hashes two nodes together into their parent
+    pub(crate) fn hash_nodes(&self, left: &[u8; N], right: &[u8; N]) -> [u8; N] {
+        let (left, right) = if self.sorted_pairs && right < left {
+            (right, left)
+        } else {
+            (left, right)
+        };
+        if self.domain_separation {
+            self.digest(&[&[NODE_PREFIX], left, right])
+        } else {
+            self.digest(&[left, right])
+        }
+    }
+
+    /// Hashes any number of nodes together, in order, into their parent
+    fn hash_children(&self, children: &[[u8; N]]) -> [u8; N] {
+        if let [left, right] = children {
+            return self.hash_nodes(left, right);
+        }
+        let mut parts: Vec<&[u8]> = Vec::with_capacity(children.len() + 1);
+        if self.domain_separation {
+            parts.push(&[NODE_PREFIX]);
+        }
+        parts.extend(children.iter().map(|child| child.as_slice()));
+        self.digest(&parts)
+    }
+
+    /// Returns the number of leaves the tree can hold,
+    /// or `None` if it isn't a fixed-depth tree and can keep growing
+    pub fn max_leaves(&self) -> Option<usize> {
+        let depth = self.zero_hashes.as_ref()?.len() - 1;
+        Some(u32::try_from(depth).map_or(usize::MAX, |depth| {
+            1usize.checked_shl(depth).unwrap_or(usize::MAX)
+        }))
+    }
+
+    /// Fails with `TreeFull` if there is no room for `additional` more leaves
+    fn check_room(&self, additional: usize) -> Result<(), MerkleError> {
+        match self.max_leaves() {
+            Some(max) if self.leaves.len().saturating_add(additional) > max => {
+                Err(MerkleError::TreeFull)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Filters `candidates` according to the duplicate policy, checking them against the leaves
+    /// of the tree and against each other
+    ///
+    /// # Returns
+    /// The leaves to append, or `DuplicateLeaf` with the index the repeated leaf already has,
+    /// or would have had, if the policy is `Reject`
+    fn admit(&self, candidates: Vec<[u8; N]>) -> Result<Vec<[u8; N]>, MerkleError> {
+        if self.duplicate_policy == DuplicatePolicy::Allow {
+            return Ok(candidates);
+        }
+        let mut admitted: Vec<[u8; N]> = Vec::with_capacity(candidates.len());
+        let mut seen = HashSet::new();
+        for leaf in candidates {
+            if !self.leaf_set.contains(&leaf) && seen.insert(leaf) {
+                admitted.push(leaf);
+            } else if self.duplicate_policy == DuplicatePolicy::Reject {
+                return Err(MerkleError::DuplicateLeaf(
+                    self.duplicate_index(&leaf, &admitted),
+                ));
+            }
+        }
+        Ok(admitted)
+    }
+
+    /// Index of `leaf` in the tree, or in `pending` if it is one of the leaves about to be appended
+    fn duplicate_index(&self, leaf: &[u8; N], pending: &[[u8; N]]) -> usize {
+        match self.find_leaf(leaf) {
+            Some(index) => index,
+            None => self.leaves.len() + position_of(pending, leaf).unwrap_or(0),
+        }
+    }
+
+    /// Index of the first leaf equal to `leaf`, found by binary search in canonical trees
+    /// Other trees compare every leaf like `position_of`, the binary search isn't constant-time
+    fn find_leaf(&self, leaf: &[u8; N]) -> Option<usize> {
+        if self.sorted {
+            let index = self.leaves.partition_point(|candidate| candidate < leaf);
+            self.leaves
+                .get(index)
+                .is_some_and(|candidate| hashes_equal(candidate, leaf))
+                .then_some(index)
+        } else {
+            position_of(&self.leaves, leaf)
+        }
+    }
+
+    /// Keeps `data` as the original data of a new `leaf`, when data is retained
+    fn retain(&mut self, leaf: [u8; N], data: &[u8]) {
+        let allow = self.duplicate_policy == DuplicatePolicy::Allow;
+        if let Some(retained) = &mut self.retained {
+            let entry = retained.entry(leaf).or_insert_with(|| (data.to_vec(), 0));
+            // Without duplicates a leaf already held was skipped, not added again
+            if allow || entry.1 == 0 {
+                entry.1 += 1;
+            }
+        }
+    }
+
+    /// Forgets the data of a removed `leaf` once no leaf holds it
+    fn release(&mut self, leaf: &[u8; N]) {
+        if let Some(retained) = &mut self.retained {
+            if let Some(entry) = retained.get_mut(leaf) {
+                entry.1 -= 1;
+                if entry.1 == 0 {
+                    retained.remove(leaf);
+                }
+            }
+        }
+    }
+
+    /// The retained data of `leaves`, counted again for this subset
+    fn retained_for(&self, leaves: &[[u8; N]]) -> Option<RetainedData<N>> {
+        let retained = self.retained.as_ref()?;
+        let mut subset = HashMap::new();
+        for leaf in leaves {
+            if let Some((data, _)) = retained.get(leaf) {
+                subset.entry(*leaf).or_insert_with(|| (data.clone(), 0)).1 += 1;
+            }
+        }
+        Some(subset)
+    }
+
+    /// Records `leaves` as part of the tree when duplicates are not allowed
+    fn track(&mut self, leaves: &[[u8; N]]) {
+        if self.duplicate_policy != DuplicatePolicy::Allow {
+            self.leaf_set.extend(leaves);
+        }
+    }
+
+    /// True if a level with `nodes` nodes, found at `level`, is the level holding the root
+    fn is_top_level(&self, level: usize, nodes: usize) -> bool {
+        match &self.zero_hashes {
+            Some(zero_hashes) => level + 1 == zero_hashes.len(),
+            None => nodes <= 1,
+        }
+    }
+
+    /// The node the last node of `level` is paired with when it has no sibling,
+    /// or `None` if it is promoted to the next level as it is
+    fn lone_sibling(&self, level: usize, node: &[u8; N]) -> Option<[u8; N]> {
+        if let Some(zero_hashes) = &self.zero_hashes {
+            return Some(zero_hashes[level]);
+        }
+        match self.odd_node_policy {
+            OddNodePolicy::DuplicateLast => Some(*node),
+            OddNodePolicy::PromoteToNextLevel => None,
+            OddNodePolicy::PadWithConstant(constant) => Some(constant),
+        }
+    }
+
+    /// Computes the parent at `parent_index` of the given `level`, whose nodes are `nodes`
+    fn parent_at(&self, level: usize, nodes: &[[u8; N]], parent_index: usize) -> [u8; N] {
+        let first = parent_index * self.arity;
+        let children = &nodes[first..nodes.len().min(first + self.arity)];
+        if children.len() == self.arity {
+            return self.hash_children(children);
+        }
+        // Only the last group of a level can be incomplete
+        match self.lone_sibling(level, &children[children.len() - 1]) {
+            Some(padding) => {
+                let mut padded = children.to_vec();
+                padded.resize(self.arity, padding);
+                self.hash_children(&padded)
+            }
+            None if children.len() == 1 => children[0],
+            None => self.hash_children(children),
+        }
+    }
+
+    /// Builds the Merkle Tree using a recursive bottom-up approach.
+    fn build(&mut self) {
+        // Every level is allocated once with its final size
+        let mut levels: Vec<Vec<[u8; N]>> = Vec::with_capacity(level_count(self.leaves.len()));
+        // Add the leaves at first level
+        let mut leaf_level = Vec::with_capacity(self.leaves.capacity());
+        leaf_level.extend_from_slice(&self.leaves);
+        levels.push(leaf_level);
+        self.tree = Arc::new(self.build_levels_above(levels));
+        self.proof_cache.clear();
+    }
+
+    /// Builds the levels above the last one of `levels` until reaching the root
+    fn build_levels_above(&self, mut levels: Vec<Vec<[u8; N]>>) -> Vec<Vec<[u8; N]>> {
+        let mut level = levels.len() - 1;
+        while !self.is_top_level(level, levels[level].len()) {
+            let current_level = &levels[level];
+            let mut next_level: Vec<[u8; N]> = Vec::with_capacity(self.level_capacity(level + 1));
+
+            // process pair of nodes
+            for parent_index in 0..current_level.len().div_ceil(self.arity) {
+                next_level.push(self.parent_at(level, current_level, parent_index));
+            }
+            levels.push(next_level);
+            level += 1;
+        }
+        levels
+    }
+
+    /// Number of nodes the given level can hold without reallocating,
+    /// derived from the capacity reserved for the leaves
+    fn level_capacity(&self, level: usize) -> usize {
+        let leaves = self.leaves.capacity().max(self.leaves.len());
+        (0..level).fold(leaves, |size, _| size.div_ceil(self.arity))
+    }
+
+    /// Returns the root hash of the Merkle tree.
+    /// An empty tree has `empty_root()` as its root,
+    /// unless it has a fixed depth, in which case its root is that of an all-zero tree
+    pub fn root(&self) -> [u8; N] {
+        match (
+            self.tree.last().and_then(|level| level.first()),
+            &self.zero_hashes,
+        ) {
+            (Some(root), _) => *root,
+            (None, Some(zero_hashes)) => zero_hashes[zero_hashes.len() - 1],
+            (None, None) => self.digest(&[]),
+        }
+    }
+
+    /// Checks that every level of the tree is consistent with its leaves
+    /// Each level must have the size expected for the number of leaves,
+    /// and each internal node is recomputed from the level below
+    ///
+    /// # Returns
+    /// `CorruptNode` with the first mismatching (level, index), checking from the leaves up
+    pub fn verify_self(&self) -> Result<(), MerkleError> {
+        let sizes = self.level_sizes(self.leaves.len());
+        for level in 0..sizes.len().max(self.tree.len()) {
+            let actual = self.tree.get(level).map(Vec::len);
+            let expected = sizes.get(level).copied();
+            if actual != expected {
+                let index = actual.unwrap_or(0).min(expected.unwrap_or(0));
+                return Err(MerkleError::CorruptNode(level, index));
+            }
+            let nodes = &self.tree[level];
+            let mismatch = if level == 0 {
+                (0..nodes.len()).find(|&index| nodes[index] != self.leaves[index])
+            } else {
+                let children = &self.tree[level - 1];
+                (0..nodes.len())
+                    .find(|&index| nodes[index] != self.parent_at(level - 1, children, index))
+            };
+            if let Some(index) = mismatch {
+                return Err(MerkleError::CorruptNode(level, index));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of leaves in the tree
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// A proof is a list of hashes that can be used to verify the membership of a leaf in the tree
+    /// For now the proof is simply that a  list of hashes.
+    /// Possible improvements:
+    /// 1. Store the direction of the hash (left or right) and the level of the tree
+    ///
+    /// When several leaves hold `data` the first one is proven, see `generate_proof_nth`
+    pub fn generate_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<MerkleProof<N>, MerkleError> {
+        self.generate_proof_for_hash(&self.hash_leaf(data))
+    }
+
+    /// Generates the proof of each of `items`, tagged with the index of the first leaf holding it
+    /// The leaves are indexed once for all the items instead of being searched for each one.
+    /// Unlike `generate_proof`, the lookup is not constant-time
+    ///
+    /// # Returns
+    /// The proofs in the order of `items`, `LeavesNotFound` with the position in `items` of
+    /// every item that is not in the tree, or `UnsupportedArity` for trees with an arity other
+    /// than 2
+    pub fn generate_proofs_for<T: Hashable>(
+        &self,
+        items: &[T],
+    ) -> Result<Vec<(usize, MerkleProof<N>)>, MerkleError> {
+        if self.arity != 2 {
+            return Err(MerkleError::UnsupportedArity);
+        }
+        let mut indices = HashMap::with_capacity(self.leaves.len());
+        for (index, leaf) in self.leaves.iter().enumerate() {
+            indices.entry(leaf).or_insert(index);
+        }
+        let found: Vec<Option<usize>> = items
+            .iter()
+            .map(|item| indices.get(&self.hash_leaf(item)).copied())
+            .collect();
+        let missing: Vec<usize> = (0..items.len()).filter(|&i| found[i].is_none()).collect();
+        if !missing.is_empty() {
+            return Err(MerkleError::LeavesNotFound(missing));
+        }
+        Ok(found
+            .into_iter()
+            .flatten()
+            .map(|index| (index, self.proof_for_index(index)))
+            .collect())
+    }
+
+    /// Generates a proof for the `n`th leaf holding `data`, counting from 0
+    /// `generate_proof(data)` is `generate_proof_nth(data, 0)`
+    ///
+    /// # Returns
+    /// The proof, or `LeafNotFound` if fewer than `n + 1` leaves hold `data`
+    pub fn generate_proof_nth<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+        n: usize,
+    ) -> Result<MerkleProof<N>, MerkleError> {
+        let index = *self
+            .occurrences_of(data)
+            .get(n)
+            .ok_or(MerkleError::LeafNotFound)?;
+        self.generate_proof_by_index(index)
+    }
+
+    /// Generates a proof for a leaf given its hash instead of the original data
+    /// Useful for trees built with `from_leaves`, where the original data is not available
+    pub fn generate_proof_for_hash(&self, leaf: &[u8; N]) -> Result<MerkleProof<N>, MerkleError> {
+        if self.arity != 2 {
+            return Err(MerkleError::UnsupportedArity);
+        }
+        // Find index of the leaf that corresponds to the given hash
+        let leaf_index = self.find_leaf(leaf).ok_or(MerkleError::LeafNotFound)?;
+
+        Ok(self.proof_for_index(leaf_index))
+    }
+
+    /// Generates a proof for the leaf at `index`, without hashing or looking up its data
+    /// It is the proof `generate_proof` gives for the data at `index`, unless an earlier leaf
+    /// holds the same data. Together with `data(index)` this is everything a verifier needs
+    /// This is how a particular occurrence of duplicated data is proven, see `occurrences_of`
+    ///
+    /// # Returns
+    /// The proof, or `IndexOutOfBounds` if there is no leaf at `index`
+    pub fn generate_proof_by_index(&self, index: usize) -> Result<MerkleProof<N>, MerkleError> {
+        if self.arity != 2 {
+            return Err(MerkleError::UnsupportedArity);
+        }
+        if index >= self.leaves.len() {
+            return Err(MerkleError::IndexOutOfBounds);
+        }
+        Ok(self
+            .proof_cache
+            .get_or_insert_with(index, || self.proof_for_index(index)))
+    }
+
+    /// Caches up to `capacity` proofs of `generate_proof_by_index`, evicting the least recently
+    /// used one when full. Changing the leaves only drops the cached proofs with a sibling
+    /// that changed, the others are still returned without rehashing anything
+    /// Enabling the cache again drops the proofs it holds
+    ///
+    /// # Example
+    /// ```
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let mut tree = MerkleTree::new(&["a", "b", "c", "d"]).unwrap();
+    /// tree.enable_proof_cache(16);
+    /// let proof = tree.generate_proof_by_index(0).unwrap();
+    /// assert_eq!(tree.cached_proof_count(), 1);
+    /// assert_eq!(tree.generate_proof_by_index(0).unwrap(), proof);
+    /// ```
+    pub fn enable_proof_cache(&mut self, capacity: usize) {
+        self.proof_cache.enable(capacity);
+    }
+
+    /// Stops caching proofs and drops the cached ones
+    pub fn disable_proof_cache(&mut self) {
+        self.proof_cache.disable();
+    }
+
+    /// Returns the number of proofs in the cache, 0 if it isn't enabled
+    pub fn cached_proof_count(&self) -> usize {
+        self.proof_cache.len()
+    }
+
+    /// Generates a proof for the leaf at `index` without directions, verified with
+    /// `verify_index_proof`. It has the siblings of `generate_proof_by_index`, a verifier that
+    /// knows the index derives their side from it
+    /// On an odd level the last node is paired with itself or the padding constant, which is
+    /// then its sibling
+    ///
+    /// # Returns
+    /// The proof, `IndexOutOfBounds` if there is no leaf at `index`, or `UnsupportedProof` for
+    /// a tree that promotes odd nodes, since the levels without a sibling depend on the number
+    /// of leaves and not only on the index
+    pub fn generate_index_proof(&self, index: usize) -> Result<IndexProof<N>, MerkleError> {
+        if self.zero_hashes.is_none()
+            && self.odd_node_policy == OddNodePolicy::PromoteToNextLevel
+            && self.len() > 1
+        {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        let proof = self.generate_proof_by_index(index)?;
+        Ok(proof.into_iter().map(|(sibling, _)| sibling).collect())
+    }
+
+    /// Generates a proof for the leaf at `index` that claims its index and the number of
+    /// leaves, verified with `verify_positioned`
+    ///
+    /// # Returns
+    /// The proof, `IndexOutOfBounds` if there is no leaf at `index`, or `UnsupportedProof` for
+    /// a tree that doesn't duplicate odd nodes or has a fixed depth, whose directions don't
+    /// follow from the index and the number of leaves alone
+    pub fn generate_positioned_proof(
+        &self,
+        index: usize,
+    ) -> Result<PositionedProof<N>, MerkleError> {
+        if self.odd_node_policy != OddNodePolicy::DuplicateLast || self.zero_hashes.is_some() {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        Ok(PositionedProof {
+            index,
+            leaf_count: self.len(),
+            proof: self.generate_proof_by_index(index)?,
+        })
+    }
+
+    /// Walks from the leaf at `leaf_index` up to the root collecting the sibling of each node
+    fn proof_for_index(&self, leaf_index: usize) -> MerkleProof<N> {
+        let mut proof = MerkleProof::new();
+        let mut current_index = leaf_index;
+        // loop each level of the tree
+        for level in 0..self.tree.len() - 1 {
+            if let Some((sibling, direction)) = self.sibling_of(level, current_index) {
+                proof.push(sibling, direction);
+            }
+            current_index /= 2;
+        }
+
+        proof
+    }
+
+    /// The sibling of the node at `index` of `level` and its side
+    /// None for a promoted node, which has no sibling on this level
+    fn sibling_of(&self, level: usize, index: usize) -> Option<([u8; N], Direction)> {
+        let current_level = &self.tree[level];
+        match determine_direction(index) {
+            Direction::Left => current_level
+                .get(index + 1)
+                .copied()
+                .or_else(|| self.lone_sibling(level, &current_level[index]))
+                .map(|sibling| (sibling, Direction::Right)),
+            Direction::Right => Some((current_level[index - 1], Direction::Left)),
+        }
+    }
+
+    /// Generates the proof of every leaf, ordered by index
+    /// Each sibling is looked up once per level and shared by the leaves below it, instead of
+    /// searching each leaf like `generate_proof` does. The proofs are the ones of
+    /// `generate_proof_by_index`, which differ from `generate_proof` for repeated data
+    ///
+    /// # Returns
+    /// The proofs, empty for an empty tree, or `UnsupportedArity` for trees with an arity
+    /// other than 2
+    pub fn generate_all_proofs(&self) -> Result<Vec<MerkleProof<N>>, MerkleError> {
+        if self.arity != 2 {
+            return Err(MerkleError::UnsupportedArity);
+        }
+        let height = self.tree.len().saturating_sub(1);
+        let mut proofs = vec![MerkleProof::with_capacity(height); self.leaves.len()];
+        for level in 0..height {
+            let siblings: Vec<_> = (0..self.tree[level].len())
+                .map(|index| self.sibling_of(level, index))
+                .collect();
+            for (leaf_index, proof) in proofs.iter_mut().enumerate() {
+                if let Some((sibling, direction)) = siblings[leaf_index >> level] {
+                    proof.push(sibling, direction);
+                }
+            }
+        }
+        Ok(proofs)
+    }
+
+    /// Validates a Merkle proof for a given piece of data
+    /// Returns true if the proof is valid, false otherwise
+    /// Nothing is a member of an empty tree, so every proof fails against it
+    /// Trees with an arity other than 2 are verified with `verify_kary_proof` instead
+    /// With the settings of `new`, it gives the same answer as the free function `verify` for
+    /// proofs with the shape of the proof of a leaf, see `verify_proof_strict`
+    pub fn verify_proof<T: Hashable + ?Sized>(&self, data: &T, proof: &MerkleProof<N>) -> bool {
+        self.verify_proof_strict(data, proof).is_ok()
+    }
+
+    /// Same as `verify_proof`, telling why the proof failed
+    /// The proof must have the length and directions of the proof of a leaf in a tree of
+    /// `len()` leaves, so an empty proof can't claim an internal node or the root as a leaf
+    /// and extra siblings aren't ignored
+    ///
+    /// # Returns
+    /// `Ok(())` if the proof is valid, `EmptyData` for an empty tree, `UnsupportedArity` for
+    /// trees with an arity other than 2, `AmbiguousLeafLength` for data a tree with a strict
+    /// leaf length refuses, `InvalidProofShape`, or `RootMismatch`
+    pub fn verify_proof_strict<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+        proof: &MerkleProof<N>,
+    ) -> Result<(), MerkleError> {
+        if self.is_ambiguous(data) {
+            return Err(MerkleError::AmbiguousLeafLength);
+        }
+        self.check_proof_for_hash(&self.hash_leaf(data), proof)
+    }
+
+    /// Validates a Merkle proof for a leaf given its hash instead of the original data
+    /// Same as `verify_proof` without hashing the data, see `verify_leaf` to verify with
+    /// nothing but the root
+    pub fn verify_proof_for_hash(&self, leaf: &[u8; N], proof: &MerkleProof<N>) -> bool {
+        self.check_proof_for_hash(leaf, proof).is_ok()
+    }
+
+    /// Same as `verify_proof`, telling where the proof and the tree diverge
+    /// Meant for debugging, it compares the proof with the one the tree generates
+    ///
+    /// # Returns
+    /// `Ok(())` if the proof is valid, otherwise the first difference with the proof of the
+    /// leaf, see `VerifyError`
+    ///
+    /// # Example
+    /// ```
+    /// use rusty_merkle_tree::merkle::{MerkleTree, VerifyError};
+    ///
+    /// let tree = MerkleTree::new(&["a", "b", "c", "d"]).expect("Should create merkle tree");
+    /// let proof = tree.generate_proof("b").expect("Should generate proof");
+    /// assert_eq!(tree.verify_proof_detailed("b", &proof), Ok(()));
+    /// assert_eq!(
+    ///     tree.verify_proof_detailed("c", &proof),
+    ///     Err(VerifyError::LeafMismatch { index: Some(1) })
+    /// );
+    /// ```
+    pub fn verify_proof_detailed<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+        proof: &MerkleProof<N>,
+    ) -> Result<(), VerifyError<N>> {
+        if self.arity != 2 {
+            return Err(VerifyError::UnsupportedArity);
+        }
+        if self.is_empty() || self.is_ambiguous(data) {
+            return Err(VerifyError::LeafMismatch { index: None });
+        }
+        let leaf = self.hash_leaf(data);
+        let Some(index) = self.implied_index(proof, self.len()) else {
+            let Some(holder) = self.find_leaf(&leaf) else {
+                return Err(VerifyError::WrongProofLength {
+                    expected: self.tree.len() - 1,
+                    found: proof.len(),
+                });
+            };
+            let directions = self.proof_directions(holder, self.len());
+            if directions.len() != proof.len() {
+                return Err(VerifyError::WrongProofLength {
+                    expected: directions.len(),
+                    found: proof.len(),
+                });
+            }
+            let level = directions
+                .into_iter()
+                .zip(proof.iter())
+                .position(|(expected, (_, direction))| expected != direction)
+                .expect("The shape of the proof of the leaf is valid");
+            return Err(VerifyError::WrongDirection { level });
+        };
+        if !hashes_equal(&self.leaves[index], &leaf) {
+            return Err(VerifyError::LeafMismatch { index: Some(index) });
+        }
+
+        let expected = self.proof_for_index(index);
+        match expected
+            .iter()
+            .zip(proof.iter())
+            .position(|((expected, _), (sibling, _))| !hashes_equal(expected, sibling))
+        {
+            Some(level) => Err(VerifyError::RootMismatch {
+                computed: self.root_from_proof(leaf, proof),
+                expected: self.root(),
+                level,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn check_proof_for_hash(
+        &self,
+        leaf: &[u8; N],
+        proof: &MerkleProof<N>,
+    ) -> Result<(), MerkleError> {
+        if self.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        if self.arity != 2 {
+            return Err(MerkleError::UnsupportedArity);
+        }
+        if self.implied_index(proof, self.len()).is_none() {
+            return Err(MerkleError::InvalidProofShape);
+        }
+
+        // The root computed from the leaf should match the root
+        if !hashes_equal(&self.root_from_proof(*leaf, proof), &self.root()) {
+            return Err(MerkleError::RootMismatch);
+        }
+        Ok(())
+    }
+
+    /// Generates a proof without directions for a tree created with `with_sorted_pairs`
+    /// The siblings are the same as the ones of `generate_proof`, from the leaf up to the root
+    pub fn generate_sorted_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<SortedProof<N>, MerkleError> {
+        let proof = self.generate_proof(data)?;
+        Ok(proof.into_iter().map(|(sibling, _)| sibling).collect())
+    }
+
+    /// Validates a proof without directions, the same way OpenZeppelin's `MerkleProof.verify` does
+    /// Returns false for trees that don't sort their pairs, since the order of each pair is unknown
+    pub fn verify_sorted_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+        proof: &SortedProof<N>,
+    ) -> bool {
+        if self.is_empty() || !self.sorted_pairs || self.is_ambiguous(data) {
+            return false;
+        }
+        let computed = proof.iter().fold(self.hash_leaf(data), |current, sibling| {
+            self.hash_nodes(&current, sibling)
+        });
+        hashes_equal(&computed, &self.root())
+    }
+
+    /// Works up from `leaf` to the root using the proof, hashing like this tree does
+    pub(crate) fn root_from_proof(&self, leaf: [u8; N], proof: &MerkleProof<N>) -> [u8; N] {
+        proof.fold(leaf, |left, right| self.hash_nodes(left, right))
+    }
+
+    /// Returns the root bound to the number of leaves, `H(count || root)`,
+    /// where the count is 8 little-endian bytes and `H` is the tree's hash function
+    /// Unlike `root()`, it tells apart trees whose leaves only differ by padding
+    pub fn committed_root(&self) -> [u8; N] {
+        self.commit_count(&self.root(), self.len())
+    }
+
+    fn commit_count(&self, root: &[u8; N], count: usize) -> [u8; N] {
+        self.digest(&[&(count as u64).to_le_bytes(), root])
+    }
+
+    /// Validates a proof against a published `committed_root()` and leaf count,
+    /// hashing like this tree does but without using its leaves
+    /// The proof must also have the shape of the proof of a leaf in a tree of `count` leaves,
+    /// so proofs from deeper or shallower trees are rejected
+    pub fn verify_committed_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+        proof: &MerkleProof<N>,
+        committed_root: &[u8; N],
+        count: usize,
+    ) -> bool {
+        if count == 0
+            || self.arity != 2
+            || self.is_ambiguous(data)
+            || self.implied_index(proof, count).is_none()
+        {
+            return false;
+        }
+        let root = self.root_from_proof(self.hash_leaf(data), proof);
+        hashes_equal(&self.commit_count(&root, count), committed_root)
+    }
+
+    /// Directions of the siblings in the proof of the leaf at `index`, in a tree of `count` leaves
+    fn proof_directions(&self, index: usize, count: usize) -> Vec<Direction> {
+        let sizes = self.level_sizes(count);
+        let mut directions = Vec::with_capacity(sizes.len());
+        let mut current_index = index;
+        for (level, &size) in sizes.iter().enumerate().take(sizes.len() - 1) {
+            match determine_direction(current_index) {
+                Direction::Left
+                    if current_index + 1 < size
+                        || self.lone_sibling(level, &[0u8; N]).is_some() =>
+                {
+                    directions.push(Direction::Right)
+                }
+                // A promoted node has no sibling on this level
+                Direction::Left => {}
+                Direction::Right => directions.push(Direction::Left),
+            }
+            current_index /= 2;
+        }
+        directions
+    }
+
+    /// The index of the leaf a proof is for, in a tree of `count` leaves
+    /// None if no leaf of such a tree has a proof with the same directions
+    pub(crate) fn implied_index(&self, proof: &MerkleProof<N>, count: usize) -> Option<usize> {
+        let sizes = self.level_sizes(count);
+        // Each sibling on the left is a set bit of the index, for the levels below `levels`
+        let low_bits = |levels: usize| -> usize {
+            // Deeper levels can't be the proof of a leaf, which the directions then tell
+            proof
+                .iter()
+                .take(levels.min(usize::BITS as usize - 1))
+                .enumerate()
+                .filter(|(_, (_, direction))| *direction == Direction::Left)
+                .map(|(level, _)| 1 << level)
+                .sum()
+        };
+        // Either the node has a sibling on every level, or it is first promoted on some level,
+        // where it must be the last node
+        let mut candidates = vec![low_bits(proof.len())];
+        candidates.extend(
+            (0..sizes.len() - 1)
+                .filter(|&level| level <= proof.len() && level < usize::BITS as usize)
+                .map(|level| low_bits(level) | ((sizes[level] - 1) << level)),
+        );
+        candidates.into_iter().find(|&index| {
+            index < count
+                && self
+                    .proof_directions(index, count)
+                    .into_iter()
+                    .eq(proof.iter().map(|(_, direction)| direction))
+        })
+    }
+
+    /// Number of nodes of each level of a tree over `leaf_count` leaves with the settings
+    /// of `self`, from the leaves up to the root
+    fn level_sizes(&self, leaf_count: usize) -> Vec<usize> {
+        let mut sizes = vec![leaf_count];
+        while !self.is_top_level(sizes.len() - 1, sizes[sizes.len() - 1]) {
+            sizes.push(sizes[sizes.len() - 1].div_ceil(self.arity));
+        }
+        sizes
+    }
+
+    /// Computes the node at `index` of `level` from the leaves alone, keeping only one group
+    /// of children per level in memory
+    fn node_from_leaves(
+        &self,
+        leaves: &[[u8; N]],
+        sizes: &[usize],
+        level: usize,
+        index: usize,
+    ) -> [u8; N] {
+        if level == 0 {
+            return leaves[index];
+        }
+        let first = index * self.arity;
+        let children: Vec<[u8; N]> = (first..sizes[level - 1].min(first + self.arity))
+            .map(|child| self.node_from_leaves(leaves, sizes, level - 1, child))
+            .collect();
+        self.parent_at(level - 1, &children, 0)
+    }
+
+    /// The root of a tree over `leaves` with the settings of `self`, without storing its levels
+    pub(crate) fn root_from_leaves(&self, leaves: &[[u8; N]]) -> [u8; N] {
+        if leaves.is_empty() {
+            return self.settings_only().root();
+        }
+        let sizes = self.level_sizes(leaves.len());
+        self.node_from_leaves(leaves, &sizes, sizes.len() - 1, 0)
+    }
+
+    /// Same as `proof_for_index` for a tree over `leaves` with the settings of `self`,
+    /// computing each sibling from the leaves instead of reading it from the levels
+    pub(crate) fn proof_from_leaves(
+        &self,
+        leaves: &[[u8; N]],
+        leaf_index: usize,
+    ) -> MerkleProof<N> {
+        let sizes = self.level_sizes(leaves.len());
+        self.proof_with_nodes(&sizes, leaf_index, |level, index| {
+            self.node_from_leaves(leaves, &sizes, level, index)
+        })
+    }
+
+    /// Generates the proof the leaf at `index` had when the tree only held its first
+    /// `leaf_count` leaves, which verifies against the root of that time
+    /// The nodes of the smaller tree are the ones of this tree, except the last one of each
+    /// level, so only that right edge is recomputed
+    ///
+    /// # Returns
+    /// The proof, `IndexOutOfBounds` if `leaf_count` is larger than the tree or `index` isn't
+    /// smaller than it, or `UnsupportedArity` for trees with an arity other than 2
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let old = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+    /// let mut tree = old.clone();
+    /// tree.add(b"d".to_vec()).expect("Should add leaf");
+    /// let proof = tree.generate_proof_at_size(3, 2).expect("Should generate proof");
+    /// assert!(old.verify_proof(&"c", &proof));
+    /// ```
+    pub fn generate_proof_at_size(
+        &self,
+        leaf_count: usize,
+        index: usize,
+    ) -> Result<MerkleProof<N>, MerkleError> {
+        if self.arity != 2 {
+            return Err(MerkleError::UnsupportedArity);
+        }
+        if leaf_count > self.leaves.len() || index >= leaf_count {
+            return Err(MerkleError::IndexOutOfBounds);
+        }
+        let sizes = self.level_sizes(leaf_count);
+        // The last node of each level of the smaller tree, from the leaves up
+        let mut edge = vec![self.leaves[leaf_count - 1]];
+        for level in 1..sizes.len() {
+            let first_child = (sizes[level] - 1) * 2;
+            let below = edge[level - 1];
+            let children = if first_child + 1 < sizes[level - 1] {
+                vec![self.tree[level - 1][first_child], below]
+            } else {
+                vec![below]
+            };
+            edge.push(self.parent_at(level - 1, &children, 0));
+        }
+        Ok(self.proof_with_nodes(&sizes, index, |level, index| {
+            if index + 1 == sizes[level] {
+                edge[level]
+            } else {
+                self.tree[level][index]
+            }
+        }))
+    }
+
+    /// Same as `proof_for_index` for a tree with levels of `sizes` nodes, reading the node at
+    /// (level, index) from `node`
+    fn proof_with_nodes(
+        &self,
+        sizes: &[usize],
+        leaf_index: usize,
+        node: impl Fn(usize, usize) -> [u8; N],
+    ) -> MerkleProof<N> {
+        let mut proof = MerkleProof::new();
+        let mut current_index = leaf_index;
+        for (level, &size) in sizes.iter().enumerate().take(sizes.len() - 1) {
+            let node = |index| node(level, index);
+            let sibling = match determine_direction(current_index) {
+                Direction::Left if current_index + 1 < size => {
+                    Some((node(current_index + 1), Direction::Right))
+                }
+                Direction::Left => self
+                    .lone_sibling(level, &node(current_index))
+                    .map(|sibling| (sibling, Direction::Right)),
+                Direction::Right => Some((node(current_index - 1), Direction::Left)),
+            };
+            // A promoted node has no sibling on this level
+            proof.extend(sibling);
+            current_index /= 2;
+        }
+        proof
+    }
+
+    /// A tree with the settings of `self` and no leaves
+    pub(crate) fn settings_only(&self) -> Self {
+        self.with_leaves(vec![])
+    }
+
+    /// Generates a proof for a tree of any arity
+    /// Each level of the proof holds the siblings of the node, in order, and its position among them
+    pub fn generate_kary_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<KaryProof<N>, MerkleError> {
+        let mut current_index = self
+            .find_leaf(&self.hash_leaf(data))
+            .ok_or(MerkleError::LeafNotFound)?;
+
+        let mut proof: KaryProof<N> = Vec::new();
+        for level in 0..self.tree.len() - 1 {
+            let current_level = &self.tree[level];
+            let first = current_index - current_index % self.arity;
+            let end = current_level.len().min(first + self.arity);
+            let position = current_index - first;
+            let mut siblings: Vec<[u8; N]> = (first..end)
+                .filter(|&index| index != current_index)
+                .map(|index| current_level[index])
+                .collect();
+
+            if end - first < self.arity {
+                match self.lone_sibling(level, &current_level[end - 1]) {
+                    Some(padding) => siblings.resize(self.arity - 1, padding),
+                    // A lone promoted node has nothing to prove on this level
+                    None if siblings.is_empty() => {
+                        current_index /= self.arity;
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+            proof.push(KaryProofLevel { position, siblings });
+            current_index /= self.arity;
+        }
+
+        Ok(proof)
+    }
+
+    /// Validates a proof generated by `generate_kary_proof` for a given piece of data
+    /// Returns true if the proof is valid, false otherwise
+    pub fn verify_kary_proof<T: Hashable + ?Sized>(&self, data: &T, proof: &KaryProof<N>) -> bool {
+        if self.is_empty() || self.is_ambiguous(data) {
+            return false;
+        }
+
+        let mut current_hash = self.hash_leaf(data);
+        for level in proof {
+            if level.position > level.siblings.len() || level.siblings.len() >= self.arity {
+                return false;
+            }
+            let mut children = level.siblings.clone();
+            children.insert(level.position, current_hash);
+            current_hash = self.hash_children(&children);
+        }
+
+        hashes_equal(&current_hash, &self.root())
+    }
+
+    /// Add a new element to the tree
+    /// Only the nodes on the path from the new leaf to the root are recomputed,
+    /// so an append costs O(log n) hashes instead of a full rebuild
+    ///
+    /// # Returns
+    /// The index of the new leaf, or `TreeFull` if the tree has a fixed depth and no room left
+    /// If the element is already in the tree, `Reject` fails with `DuplicateLeaf` and
+    /// `Deduplicate` leaves the tree unchanged and returns the index of the existing leaf
+    /// In a canonical tree the leaf is inserted at its sorted position, which is returned
+    pub fn add<T: Hashable>(&mut self, data: T) -> Result<usize, MerkleError> {
+        let (index, inserted) = self.stage_add(&data.leaf_bytes())?;
+        if inserted {
+            self.sync_from(index);
+        }
+        Ok(index)
+    }
+
+    /// Inserts the leaf of `data` into the leaves following the duplicate policy,
+    /// at its sorted position in canonical trees and at the end otherwise
+    /// The levels are left as they were, `sync_from` rehashes them
+    ///
+    /// # Returns
+    /// The index of the leaf and whether it was inserted, it is not if `Deduplicate` found it
+    pub(crate) fn stage_add(&mut self, data: &[u8]) -> Result<(usize, bool), MerkleError> {
+        let leaf = self.checked_leaf(data)?;
+        if self.admit(vec![leaf])?.is_empty() {
+            return Ok((self.duplicate_index(&leaf, &[]), false));
+        }
+        self.check_room(1)?;
+        self.track(&[leaf]);
+        let index = if self.sorted {
+            self.leaves.partition_point(|candidate| *candidate < leaf)
+        } else {
+            self.leaves.len()
+        };
+        Arc::make_mut(&mut self.leaves).insert(index, leaf);
+        self.retain(leaf, data);
+        Ok((index, true))
+    }
+
+    /// Appends a batch of elements to the tree
+    /// All the new leaves are hashed first and the affected nodes are recomputed once at the end,
+    /// instead of once per element as calling `add()` in a loop would
+    ///
+    /// # Returns
+    /// The new root hash and the index of the first inserted leaf,
+    /// the batch occupies the leaves `[start, start + items.len())`
+    /// If the tree has a fixed depth and the batch doesn't fit, `TreeFull` is returned
+    /// and nothing is added
+    /// Under `Deduplicate` the repeated elements are skipped and the batch may occupy fewer leaves,
+    /// under `Reject` a single repeated element fails the whole batch with `DuplicateLeaf`
+    /// In a canonical tree the batch is merged into the sorted leaves, and the returned index is
+    /// the first position that changed
+    pub fn extend_from<T: Hashable>(
+        &mut self,
+        items: &[T],
+    ) -> Result<([u8; N], usize), MerkleError> {
+        let start = self.stage_extend_from(items)?;
+        self.sync_from(start);
+        Ok((self.root(), start))
+    }
+
+    /// Same as `stage_extend`, hashing `items` and retaining their data
+    pub(crate) fn stage_extend_from<T: Hashable>(
+        &mut self,
+        items: &[T],
+    ) -> Result<usize, MerkleError> {
+        let encoded: Vec<Cow<'_, [u8]>> = items.iter().map(Hashable::leaf_bytes).collect();
+        let leaves: Vec<[u8; N]> = encoded
+            .iter()
+            .map(|bytes| self.checked_leaf(&**bytes))
+            .collect::<Result<_, _>>()?;
+        if self.retained.is_none() {
+            return self.stage_extend(leaves);
+        }
+        let start = self.stage_extend(leaves.clone())?;
+        for (leaf, bytes) in leaves.into_iter().zip(&encoded) {
+            self.retain(leaf, bytes);
+        }
+        Ok(start)
+    }
+
+    /// Appends already hashed leaves that pass the duplicate policy, rebuilding once at the end
+    /// Canonical trees merge them into their sorted positions instead
+    ///
+    /// # Returns
+    /// The index of the first leaf that changed
+    pub(crate) fn extend_leaves(&mut self, leaves: Vec<[u8; N]>) -> Result<usize, MerkleError> {
+        let start = self.stage_extend(leaves)?;
+        self.sync_from(start);
+        Ok(start)
+    }
+
+    /// Same as `extend_leaves`, without rehashing the levels
+    pub(crate) fn stage_extend(&mut self, leaves: Vec<[u8; N]>) -> Result<usize, MerkleError> {
+        let new_leaves = self.admit(leaves)?;
+        self.check_room(new_leaves.len())?;
+        self.track(&new_leaves);
+        let start = if self.sorted {
+            new_leaves
+                .iter()
+                .map(|leaf| self.leaves.partition_point(|candidate| candidate < leaf))
+                .min()
+                .unwrap_or(self.leaves.len())
+        } else {
+            self.leaves.len()
+        };
+        let leaves = Arc::make_mut(&mut self.leaves);
+        leaves.extend_from_slice(&new_leaves);
+        if self.sorted {
+            leaves[start..].sort_unstable();
+        }
+        Ok(start)
+    }
+
+    /// Copies the leaves from `start` on into the leaf level and rehashes everything to their right
+    pub(crate) fn sync_from(&mut self, start: usize) {
+        let leaf_level = &mut Arc::make_mut(&mut self.tree)[0];
+        leaf_level.truncate(start);
+        leaf_level.extend_from_slice(&self.leaves[start..]);
+        self.rebuild_from(start);
+    }
+
+    /// Returns a copy of the retained data, to restore it with `discard_staged`
+    pub(crate) fn retained_data(&self) -> Option<RetainedData<N>> {
+        self.retained.clone()
+    }
+
+    /// Drops every change made to the leaves since the levels were last rehashed,
+    /// the leaf level still holds the leaves as they were and `retained` the data it had
+    pub(crate) fn discard_staged(&mut self, retained: Option<RetainedData<N>>) {
+        self.retained = retained;
+        Arc::make_mut(&mut self.leaves).clone_from(&self.tree[0]);
+        if self.duplicate_policy != DuplicatePolicy::Allow {
+            self.leaf_set = self.leaves.iter().copied().collect();
+        }
+    }
+
+    /// Builds a tree hashed with `hasher` over already hashed leaves, which may be empty
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_leaves_with(hasher: D, leaves: Vec<[u8; N]>) -> Self {
+        let mut tree = Self::unbuilt_with(hasher, leaves);
+        tree.build();
+        tree
+    }
+
+    /// Returns the hash function of the tree
+    #[cfg(feature = "serde")]
+    pub(crate) fn hasher(&self) -> &D {
+        &self.hasher
+    }
+
+    /// Returns every leaf of the tree, in order
+    pub(crate) fn leaves(&self) -> &[[u8; N]] {
+        &self.leaves
+    }
+
+    /// Returns the levels of the tree, from the leaves up to the root
+    pub(crate) fn levels(&self) -> &[Vec<[u8; N]>] {
+        &self.tree
+    }
+
+    /// Builds a separate tree over the current leaves, including staged ones
+    pub(crate) fn rebuilt(&self) -> Self {
+        self.with_leaves(self.leaves.to_vec())
+    }
+
+    /// Merges two trees into one over the leaves of `self` followed by the leaves of `other`
+    /// The result is the same as building a single tree over the concatenated data.
+    /// When `self` has a power of two number of leaves its subtree is complete, so the levels of
+    /// both trees are reused as they are and only the nodes above `self`'s root are hashed.
+    ///
+    /// # Returns
+    /// The merged tree, `IncompatibleTrees` if the trees hash or shape their nodes differently,
+    /// or `TreeFull` if the leaves of both trees don't fit in a fixed-depth tree
+    /// Leaves of `other` already in `self` are handled according to the duplicate policy
+    /// The data retained by `other` is kept if `self` retains data too
+    pub fn merge(mut self, mut other: Self) -> Result<Self, MerkleError> {
+        if !self.has_same_settings(&other) {
+            return Err(MerkleError::IncompatibleTrees);
+        }
+        let other_data = other.retained.take().unwrap_or_default();
+        if self.duplicate_policy != DuplicatePolicy::Allow || self.sorted {
+            self.extend_leaves(other.leaves.to_vec())?;
+            self.retain_all(other_data);
+            return Ok(self);
+        }
+        self.check_room(other.len())?;
+
+        if self.len().is_power_of_two() && self.arity == 2 && self.zero_hashes.is_none() {
+            // Level `split_level` and the ones below are `self`'s nodes followed by `other`'s
+            let split_level = self.len().trailing_zeros() as usize;
+            let mut levels = Vec::with_capacity(split_level + 1);
+            let mut other_nodes: Vec<[u8; N]> = Vec::new();
+            for level in 0..=split_level {
+                other_nodes = match other.tree.get(level) {
+                    Some(nodes) => nodes.clone(),
+                    // `other` is shorter, keep hashing its lone root upwards
+                    None => (0..other_nodes.len().div_ceil(2))
+                        .map(|parent_index| self.parent_at(level - 1, &other_nodes, parent_index))
+                        .collect(),
+                };
+                let mut nodes = std::mem::take(&mut Arc::make_mut(&mut self.tree)[level]);
+                nodes.extend_from_slice(&other_nodes);
+                levels.push(nodes);
+            }
+            Arc::make_mut(&mut self.leaves).extend_from_slice(&other.leaves);
+            self.tree = Arc::new(self.build_levels_above(levels));
+            // The height grows by one, no cached proof is left
+            self.proof_cache.clear();
+        } else {
+            self.extend_leaves(other.leaves.to_vec())?;
+        }
+        self.retain_all(other_data);
+        Ok(self)
+    }
+
+    /// Combines subtrees built separately, for example in parallel, into one tree over all of
+    /// their leaves in order. The result is the same as building a single tree over them.
+    /// When every shard but the last has the same power of two number of leaves and the last
+    /// one is not bigger, the shards are complete subtrees: their levels are stitched together
+    /// as they are and only the nodes above them are hashed.
+    /// Ragged shards are accepted too, and are combined with `merge` one after the other.
+    ///
+    /// # Returns
+    /// The combined tree, `EmptyData` if there are no shards, `IncompatibleTrees`
+    /// if they hash or shape their nodes differently, or any error of `merge`
+    pub fn from_shards(shards: Vec<Self>) -> Result<Self, MerkleError> {
+        let mut shards = shards.into_iter();
+        let first = shards.next().ok_or(MerkleError::EmptyData)?;
+        let rest: Vec<Self> = shards.collect();
+        if rest.iter().any(|shard| !first.has_same_settings(shard)) {
+            return Err(MerkleError::IncompatibleTrees);
+        }
+
+        let size = first.len();
+        let aligned = size.is_power_of_two()
+            && first.arity == 2
+            && first.zero_hashes.is_none()
+            && first.duplicate_policy == DuplicatePolicy::Allow
+            && !first.sorted
+            && rest.split_last().is_none_or(|(last, middle)| {
+                middle.iter().all(|shard| shard.len() == size) && (1..=size).contains(&last.len())
+            });
+        if !aligned {
+            return rest.into_iter().try_fold(first, Self::merge);
+        }
+
+        // Levels up to `height` are the nodes of each shard, one shard after the other
+        let height = size.trailing_zeros() as usize;
+        let mut levels: Vec<Vec<[u8; N]>> = vec![Vec::new(); height + 1];
+        let mut leaves = Vec::with_capacity(size * (rest.len() + 1));
+        let mut data = Vec::new();
+        let mut combined = first.with_leaves(vec![]);
+        combined.retained = first.retained.as_ref().map(|_| HashMap::new());
+        for mut shard in std::iter::once(first).chain(rest) {
+            let mut nodes: Vec<[u8; N]> = Vec::new();
+            for (level, combined_level) in levels.iter_mut().enumerate() {
+                nodes = match shard.tree.get(level) {
+                    Some(shard_nodes) => shard_nodes.clone(),
+                    // The last shard is shorter, keep hashing its lone root upwards
+                    None => vec![combined.parent_at(level - 1, &nodes, 0)],
+                };
+                combined_level.extend_from_slice(&nodes);
+            }
+            leaves.extend_from_slice(&shard.leaves);
+            data.extend(shard.retained.take());
+        }
+        combined.leaves = Arc::new(leaves);
+        combined.tree = Arc::new(combined.build_levels_above(levels));
+        for shard_data in data {
+            combined.retain_all(shard_data);
+        }
+        Ok(combined)
+    }
+
+    /// Retains the data of leaves merged from another tree
+    fn retain_all(&mut self, data: RetainedData<N>) {
+        let allow = self.duplicate_policy == DuplicatePolicy::Allow;
+        if let Some(retained) = &mut self.retained {
+            for (leaf, (data, count)) in data {
+                let entry = retained.entry(leaf).or_insert((data, 0));
+                entry.1 = if allow { entry.1 + count } else { 1 };
+            }
+        }
+    }
+
+    /// Splits the tree in two: a tree over the leaves `[0, index)` and a tree over `[index, n)`
+    /// Both trees keep the settings of the original one and are fully built.
+    /// An `index` of 0 or n is accepted, and one of the halves is then an empty tree.
+    ///
+    /// # Returns
+    /// The two trees, or `IndexOutOfBounds` if `index` is greater than the number of leaves
+    pub fn split_at(&self, index: usize) -> Result<(Self, Self), MerkleError> {
+        if index > self.leaves.len() {
+            return Err(MerkleError::IndexOutOfBounds);
+        }
+        let (left, right) = self.leaves.split_at(index);
+        let mut halves = (
+            self.with_leaves(left.to_vec()),
+            self.with_leaves(right.to_vec()),
+        );
+        halves.0.retained = self.retained_for(left);
+        halves.1.retained = self.retained_for(right);
+        Ok(halves)
+    }
+
+    /// A tree with the settings of `self` sharing its levels and leaves, without the data it
+    /// retains, its duplicate tracking or its proof cache, which only mutations and proofs by
+    /// index need. It costs a reference count, the levels are copied by the next mutation of
+    /// either tree
+    pub(crate) fn shared_view(&self) -> Self {
+        MerkleTree {
+            tree: Arc::clone(&self.tree),
+            leaves: Arc::clone(&self.leaves),
+            zero_hashes: self.zero_hashes.clone(),
+            leaf_set: HashSet::new(),
+            retained: None,
+            proof_cache: ProofCache::default(),
+            hasher: self.hasher.clone(),
+            ..*self
+        }
+    }
+
+    /// Builds a tree over `leaves` with the same settings as `self`
+    fn with_leaves(&self, leaves: Vec<[u8; N]>) -> Self {
+        let mut tree = MerkleTree {
+            tree: Arc::new(vec![]),
+            leaves: Arc::new(leaves),
+            zero_hashes: self.zero_hashes.clone(),
+            leaf_set: HashSet::new(),
+            retained: None,
+            proof_cache: ProofCache::default(),
+            hasher: self.hasher.clone(),
+            ..*self
+        };
+        tree.track(&tree.leaves.clone());
+        tree.build();
+        tree
+    }
+
+    /// True if both trees hash and shape their nodes the same way
+    fn has_same_settings(&self, other: &Self) -> bool {
+        self.zero_hashes == other.zero_hashes
+            && self.odd_node_policy == other.odd_node_policy
+            && self.arity == other.arity
+            && self.domain_separation == other.domain_separation
+            && self.duplicate_policy == other.duplicate_policy
+            && self.sorted == other.sorted
+            && self.sorted_pairs == other.sorted_pairs
+            && self.leaf_hashing == other.leaf_hashing
+            && self.normalize_nfc == other.normalize_nfc
+            && self.strict_leaf_length == other.strict_leaf_length
+            && self.hasher.is_same_as(&other.hasher)
+    }
+
+    /// Replaces the leaf at `index` with the hash of `data`
+    /// Only the path from that leaf to the root is rehashed
+    ///
+    /// # Returns
+    /// The new root hash, or `IndexOutOfBounds` if there is no leaf at `index`
+    /// Unless duplicates are allowed, `DuplicateLeaf` is returned if another leaf already holds `data`
+    /// In a canonical tree the new leaf is moved to its sorted position
+    pub fn update<T: Hashable + ?Sized>(
+        &mut self,
+        index: usize,
+        data: &T,
+    ) -> Result<[u8; N], MerkleError> {
+        let start = self.stage_update(index, &data.leaf_bytes())?;
+        if self.sorted {
+            self.sync_from(start);
+        } else {
+            Arc::make_mut(&mut self.tree)[0][index] = self.leaves[index];
+            self.update_path(index);
+        }
+        Ok(self.root())
+    }
+
+    /// Replaces the leaf at `index` with the leaf of `data`, without rehashing the levels
+    ///
+    /// # Returns
+    /// The index of the first leaf that changed, canonical trees move the new leaf
+    /// to its sorted position
+    pub(crate) fn stage_update(&mut self, index: usize, data: &[u8]) -> Result<usize, MerkleError> {
+        if index >= self.leaves.len() {
+            return Err(MerkleError::IndexOutOfBounds);
+        }
+        let leaf = self.checked_leaf(data)?;
+        if self.duplicate_policy != DuplicatePolicy::Allow && leaf != self.leaves[index] {
+            // Dropping the new leaf would shift the leaves, so `Deduplicate` refuses it as well
+            if self.leaf_set.contains(&leaf) {
+                return Err(MerkleError::DuplicateLeaf(self.duplicate_index(&leaf, &[])));
+            }
+            self.leaf_set.remove(&self.leaves[index]);
+            self.leaf_set.insert(leaf);
+        }
+        let old = self.leaves[index];
+        self.release(&old);
+        self.retain(leaf, data);
+        if self.sorted {
+            // The new leaf moves to its sorted position, shifting the ones in between
+            let leaves = Arc::make_mut(&mut self.leaves);
+            leaves.remove(index);
+            let position = leaves.partition_point(|candidate| *candidate < leaf);
+            leaves.insert(position, leaf);
+            return Ok(index.min(position));
+        }
+        Arc::make_mut(&mut self.leaves)[index] = leaf;
+        Ok(index)
+    }
+
+    /// Removes the leaf at `index`, shifting every subsequent leaf one position to the left
+    /// Only the nodes to the right of the removed position are recomputed
+    ///
+    /// A tree can't be left without leaves, so removing the last remaining leaf
+    /// returns `EmptyData` and leaves the tree untouched
+    ///
+    /// # Returns
+    /// The hash of the removed leaf, or `IndexOutOfBounds` if there is no leaf at `index`
+    pub fn remove(&mut self, index: usize) -> Result<[u8; N], MerkleError> {
+        let removed = self.stage_remove(index)?;
+        self.sync_from(index);
+        Ok(removed)
+    }
+
+    /// Removes the leaf at `index` from the leaves, without rehashing the levels
+    pub(crate) fn stage_remove(&mut self, index: usize) -> Result<[u8; N], MerkleError> {
+        if index >= self.leaves.len() {
+            return Err(MerkleError::IndexOutOfBounds);
+        }
+        if self.leaves.len() == 1 {
+            return Err(MerkleError::EmptyData);
+        }
+        let removed = Arc::make_mut(&mut self.leaves).remove(index);
+        self.leaf_set.remove(&removed);
+        self.release(&removed);
+        Ok(removed)
+    }
+
+    /// Shortens the tree to its first `n` leaves, discarding the rest
+    /// Only the right edge of each level is recomputed.
+    /// Truncating to the current size or more does nothing, and truncating to 0 leaves an empty tree.
+    pub fn truncate(&mut self, n: usize) {
+        if n >= self.leaves.len() {
+            return;
+        }
+        for leaf in Arc::make_mut(&mut self.leaves).split_off(n) {
+            self.leaf_set.remove(&leaf);
+            self.release(&leaf);
+        }
+        Arc::make_mut(&mut self.tree)[0].truncate(n);
+        self.rebuild_from(n);
+    }
+
+    /// Recomputes every node to the right of the leaf at `index`, level by level.
+    /// Levels are resized to match the current number of leaves, and levels above the root are dropped.
+    fn rebuild_from(&mut self, index: usize) {
+        let old_height = self.tree.len();
+        let mut start = index;
+        let mut level = 0;
+        while !self.is_top_level(level, self.tree[level].len()) {
+            let current_level = &self.tree[level];
+            let first_parent = start / self.arity;
+            let parents: Vec<[u8; N]> = (first_parent..current_level.len().div_ceil(self.arity))
+                .map(|parent_index| self.parent_at(level, current_level, parent_index))
+                .collect();
+
+            if level + 1 == self.tree.len() {
+                let capacity = self.level_capacity(level + 1);
+                Arc::make_mut(&mut self.tree).push(Vec::with_capacity(capacity));
+            }
+            let next_level = &mut Arc::make_mut(&mut self.tree)[level + 1];
+            next_level.truncate(first_parent);
+            next_level.extend(parents);
+
+            start = first_parent;
+            level += 1;
+        }
+        Arc::make_mut(&mut self.tree).truncate(level + 1);
+        self.proof_cache
+            .invalidate_from(index, old_height, self.tree.len());
+    }
+
+    /// Recomputes the parents of the node at `index` on the leaf level, all the way up to the root.
+    /// Parents that don't exist yet are appended, and a new root level is created if needed,
+    /// so the result is identical to what `build()` would produce.
+    fn update_path(&mut self, index: usize) {
+        let mut current_index = index;
+        let mut level = 0;
+        while !self.is_top_level(level, self.tree[level].len()) {
+            let parent_index = current_index / self.arity;
+            let parent = self.parent_at(level, &self.tree[level], parent_index);
+
+            if level + 1 == self.tree.len() {
+                let capacity = self.level_capacity(level + 1);
+                Arc::make_mut(&mut self.tree).push(Vec::with_capacity(capacity));
+            }
+            let next_level = &mut Arc::make_mut(&mut self.tree)[level + 1];
+            if parent_index < next_level.len() {
+                next_level[parent_index] = parent;
+            } else {
+                next_level.push(parent);
+            }
+
+            current_index = parent_index;
+            level += 1;
+        }
+        let level_sizes: Vec<usize> = self.tree.iter().map(Vec::len).collect();
+        self.proof_cache.invalidate_path(index, &level_sizes);
+    }
+}
+
+impl Default for MerkleTree {
+    /// The default tree is the empty tree
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl<T: Hashable> FromIterator<T> for MerkleTree {
+    /// Builds a tree hashing each element as it arrives
+    /// An empty iterator produces the empty tree
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::try_from_iter(iter).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_vectors::from_hex;
+    #[cfg(feature = "sha2")]
+    use crate::test_vectors::RFC6962;
+    use sha3::Sha3_512;
+
+    #[test]
+    fn test_build_tree() {
+        // Test data
+        let data = vec![
+            b"block1".to_vec(),
+            b"block2".to_vec(),
+            b"block3".to_vec(),
+            b"block4".to_vec(),
+        ];
+
+        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+
+        let leaf1 = hash(&data[0]);
+        let leaf2 = hash(&data[1]);
+        let leaf3 = hash(&data[2]);
+        let leaf4 = hash(&data[3]);
+
+        // Calculate internal nodes
+        let internal1 = hash_internal_node(&leaf1, &leaf2);
+        let internal2 = hash_internal_node(&leaf3, &leaf4);
+
+        // Calculate root
+        let _root = hash_internal_node(&internal1, &internal2);
+
+        assert_eq!(_root, merkle.root());
+
+        // A merkle tree built from 4 elements should have 7 nodes and 3 levels
+        assert_eq!(merkle.tree.len(), 3);
+        let total_nodes: usize = merkle.tree.iter().map(|level| level.len()).sum();
+        assert_eq!(total_nodes, 7);
+
+        // Create an expected tree and compare it to the actual tree
+        let expected_tree = vec![
+            vec![leaf1, leaf2, leaf3, leaf4],
+            vec![internal1, internal2],
+            vec![_root],
+        ];
+
+        assert_eq!(expected_tree, *merkle.tree);
+    }
+
+    #[test]
+    fn test_build_tree_odd_number() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+
+        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+
+        let leaf1 = hash(&data[0]);
+        let leaf2 = hash(&data[1]);
+        let leaf3 = hash(&data[2]);
+
+        let internal1 = hash_internal_node(&leaf1, &leaf2);
+        let internal2 = hash_internal_node(&leaf3, &leaf3);
+
+        let root = hash_internal_node(&internal1, &internal2);
+
+        assert_eq!(root, merkle.root());
+
+        let expected_tree = vec![
+            vec![leaf1, leaf2, leaf3],
+            vec![internal1, internal2],
+            vec![root],
+        ];
+
+        assert_eq!(expected_tree, *merkle.tree);
+    }
+
+    #[test]
+    fn test_generate_proof() {
+        let data = vec![
+            b"block1".to_vec(),
+            b"block2".to_vec(),
+            b"block3".to_vec(),
+            b"block4".to_vec(),
+        ];
+
+        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+
+        let proof = merkle
+            .generate_proof(&data[1])
+            .expect("Should generate proof");
+
+        assert_eq!(proof.len(), 2);
+
+        let leaf1 = hash(&data[0]);
+        let leaf2 = hash(&data[1]);
+        let leaf3 = hash(&data[2]);
+        let leaf4 = hash(&data[3]);
+
+        // Calculate internal nodes
+        let _internal1 = hash_internal_node(&leaf1, &leaf2);
+        let internal2 = hash_internal_node(&leaf3, &leaf4);
+
+        // Left and the other is right
+        let expected_proof = MerkleProof::from(vec![
+            (leaf1, Direction::Left),
+            (internal2, Direction::Right),
+        ]);
+
+        // Print the tree by levels and print the proof
+        for level in merkle.tree.iter() {
+            println!("{:?}", level);
+        }
+
+        println!("{:?}", proof);
+
+        assert_eq!(proof, expected_proof);
+    }
+
+    #[test]
+    fn test_generate_proof_edge_case() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+
+        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+
+        let leaf1 = hash(&data[0]);
+        let leaf2 = hash(&data[1]);
+        let leaf3 = hash(&data[2]);
+
+        let internal1 = hash_internal_node(&leaf1, &leaf2);
+        let internal2 = hash_internal_node(&leaf3, &leaf3);
+
+        let _root = hash_internal_node(&internal1, &internal2);
+
+        let proof = merkle
+            .generate_proof(&data[1])
+            .expect("Should generate proof");
+
+        let expected_proof = MerkleProof::from(vec![
+            (leaf1, Direction::Left),
+            (internal2, Direction::Right),
+        ]);
+
+        assert_eq!(proof, expected_proof);
+    }
+
+    #[test]
+    fn test_verify_proof() {
+        let data = vec![
+            b"block1".to_vec(),
+            b"block2".to_vec(),
+            b"block3".to_vec(),
+            b"block4".to_vec(),
+        ];
+
+        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+
+        // Generate and verify proof for "block2"
+        let proof = merkle
+            .generate_proof(&data[1])
+            .expect("Should generate proof");
+        assert!(merkle.verify_proof(&data[1], &proof));
+
+        // Verify with wrong data (should fail)
+        assert!(!merkle.verify_proof(b"wrong_data", &proof));
+
+        // Verify with wrong proof (should fail)
+        let wrong_proof = merkle
+            .generate_proof(&data[2])
+            .expect("Should generate proof");
+        assert!(!merkle.verify_proof(&data[1], &wrong_proof));
+    }
+
+    #[test]
+    fn test_verify_proof_edge_case() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+
+        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+
+        let proof = merkle
+            .generate_proof(&data[1])
+            .expect("Should generate proof");
+
+        assert!(merkle.verify_proof(&data[1], &proof));
+
+        // Verify with wrong data (should fail)
+        assert!(!merkle.verify_proof(b"wrong_data", &proof));
+    }
+
+    #[test]
+    fn test_generic_data_types() {
+        // Test with different types that implement AsRef<[u8]>
+        let string_data = vec![
+            String::from("test1"),
+            String::from("test2"),
+            String::from("test3"),
+        ];
+
+        let bytes_data = vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
+
+        let str_data = vec!["hello", "world", "!"];
+
+        // Test creation with different types
+        let string_merkle =
+            MerkleTree::new(&string_data).expect("Should create merkle tree from strings");
+        let bytes_merkle =
+            MerkleTree::new(&bytes_data).expect("Should create merkle tree from bytes");
+        let str_merkle = MerkleTree::new(&str_data).expect("Should create merkle tree from str");
+
+        // Test proof generation and verification with different types
+        let string_proof = string_merkle
+            .generate_proof(&string_data[0])
+            .expect("Should generate proof");
+        assert!(string_merkle.verify_proof(&string_data[0], &string_proof));
+
+        let bytes_proof = bytes_merkle
+            .generate_proof(&bytes_data[1])
+            .expect("Should generate proof");
+        assert!(bytes_merkle.verify_proof(&bytes_data[1], &bytes_proof));
+
+        let str_proof = str_merkle
+            .generate_proof(&str_data[2])
+            .expect("Should generate proof");
+        assert!(str_merkle.verify_proof(&str_data[2], &str_proof));
+    }
+
+    #[test]
+    fn test_add_matches_rebuild() {
+        let data: Vec<Vec<u8>> = (0..65)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        let mut merkle = MerkleTree::new(&data[..1]).expect("Should create merkle tree");
+        for size in 1..=data.len() {
+            if size > 1 {
+                merkle.add(data[size - 1].clone()).expect("Should add leaf");
+            }
+            let expected = MerkleTree::new(&data[..size]).expect("Should create merkle tree");
+
+            assert_eq!(merkle.leaves, expected.leaves);
+            assert_eq!(merkle.tree, expected.tree);
+            assert_eq!(merkle.root(), expected.root());
+        }
+    }
+
+    #[test]
+    fn test_add_proofs_verify() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+
+        let mut merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        merkle.add(b"block4".to_vec()).expect("Should add leaf");
+        merkle.add(b"block5".to_vec()).expect("Should add leaf");
+
+        for element in data
+            .iter()
+            .chain([&b"block4".to_vec(), &b"block5".to_vec()])
+        {
+            let proof = merkle
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(merkle.verify_proof(element, &proof));
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let mut data = vec![
+            b"block1".to_vec(),
+            b"block2".to_vec(),
+            b"block3".to_vec(),
+            b"block4".to_vec(),
+            b"block5".to_vec(),
+        ];
+
+        let mut merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        let old_proof = merkle
+            .generate_proof(&data[0])
+            .expect("Should generate proof");
+
+        data[4] = b"amended".to_vec();
+        let root = merkle.update(4, &data[4]).expect("Should update leaf");
+
+        let expected = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(root, expected.root());
+        assert_eq!(merkle.tree, expected.tree);
+
+        // Proofs generated before the update no longer verify
+        assert!(!merkle.verify_proof(&data[0], &old_proof));
+        assert!(merkle.generate_proof(b"block5").is_err());
+
+        // Proofs generated after the update do
+        for element in data.iter() {
+            let proof = merkle
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(merkle.verify_proof(element, &proof));
+        }
+    }
+
+    #[test]
+    fn test_update_out_of_bounds() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec()];
+
+        let mut merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        let root = merkle.root();
+
+        assert!(matches!(
+            merkle.update(2, b"block3"),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        assert_eq!(merkle.root(), root);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut data: Vec<Vec<u8>> = (0..9).map(|i| format!("block{}", i).into_bytes()).collect();
+
+        let mut merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        let old_proof = merkle
+            .generate_proof(&data[7])
+            .expect("Should generate proof");
+
+        let removed = merkle.remove(3).expect("Should remove leaf");
+        assert_eq!(removed, hash(&data[3]));
+        data.remove(3);
+
+        let expected = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(merkle.leaves, expected.leaves);
+        assert_eq!(merkle.tree, expected.tree);
+
+        // Leaves after the removed one shifted, so their old proofs no longer verify
+        assert!(!merkle.verify_proof(&data[6], &old_proof));
+        assert!(merkle.generate_proof(b"block3").is_err());
+    }
+
+    #[test]
+    fn test_remove_last_odd_leaf() {
+        let data: Vec<Vec<u8>> = (0..5).map(|i| format!("block{}", i).into_bytes()).collect();
+
+        // The fifth leaf is the lone node being duplicated on every level
+        let mut merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        merkle.remove(4).expect("Should remove leaf");
+
+        let expected = MerkleTree::new(&data[..4]).expect("Should create merkle tree");
+        assert_eq!(merkle.tree, expected.tree);
+        assert_eq!(merkle.tree.len(), 3);
+    }
+
+    #[test]
+    fn test_remove_from_two_leaves() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec()];
+
+        let mut merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        let old_proof = merkle
+            .generate_proof(&data[1])
+            .expect("Should generate proof");
+
+        merkle.remove(0).expect("Should remove leaf");
+
+        let expected = MerkleTree::new(&data[1..]).expect("Should create merkle tree");
+        assert_eq!(merkle.tree, expected.tree);
+        assert_eq!(merkle.root(), hash(&data[1]));
+        assert!(!merkle.verify_proof(&data[1], &old_proof));
+    }
+
+    #[test]
+    fn test_remove_last_remaining_leaf() {
+        let mut merkle = MerkleTree::new(&[b"block1"]).expect("Should create merkle tree");
+        let root = merkle.root();
+
+        assert!(matches!(merkle.remove(0), Err(MerkleError::EmptyData)));
+        assert!(matches!(
+            merkle.remove(1),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        assert_eq!(merkle.root(), root);
+    }
+
+    #[test]
+    fn test_extend_from() {
+        let data: Vec<Vec<u8>> = (0..23)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        let mut merkle = MerkleTree::new(&data[..5]).expect("Should create merkle tree");
+        let (root, start) = merkle.extend_from(&data[5..]).expect("Should extend tree");
+
+        let expected = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(start, 5);
+        assert_eq!(root, expected.root());
+        assert_eq!(merkle.tree, expected.tree);
+
+        // The returned range can be used to prove every element of the batch
+        for element in &data[start..] {
+            let proof = merkle
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(merkle.verify_proof(element, &proof));
+        }
+    }
+
+    #[test]
+    fn test_extend_from_empty_batch() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+
+        let mut merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        let root = merkle.root();
+
+        let empty: &[Vec<u8>] = &[];
+        assert_eq!(
+            merkle.extend_from(empty).expect("Should extend tree"),
+            (root, 3)
+        );
+    }
+
+    #[test]
+    fn test_from_leaves() {
+        let data: Vec<Vec<u8>> = (0..7).map(|i| format!("block{}", i).into_bytes()).collect();
+
+        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        let from_leaves =
+            MerkleTree::from_leaves(merkle.leaves.to_vec()).expect("Should create merkle tree");
+
+        assert_eq!(from_leaves.root(), merkle.root());
+        assert_eq!(from_leaves.tree, merkle.tree);
+
+        assert!(matches!(
+            MerkleTree::from_leaves(vec![]),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_generate_proof_for_hash() {
+        let data: Vec<Vec<u8>> = (0..7).map(|i| format!("block{}", i).into_bytes()).collect();
+        let leaves: Vec<Hash> = data.iter().map(hash).collect();
+
+        // Only the hashes are available to the tree
+        let merkle = MerkleTree::from_leaves(leaves.clone()).expect("Should create merkle tree");
+
+        let proof = merkle
+            .generate_proof_for_hash(&leaves[5])
+            .expect("Should generate proof");
+        assert_eq!(
+            proof,
+            merkle
+                .generate_proof(&data[5])
+                .expect("Should generate proof")
+        );
+        assert!(merkle.verify_proof(&data[5], &proof));
+        assert!(merkle.verify_proof_for_hash(&leaves[5], &proof));
+        assert!(!merkle.verify_proof_for_hash(&leaves[4], &proof));
+
+        assert!(matches!(
+            merkle.generate_proof_for_hash(&hash(b"missing")),
+            Err(MerkleError::LeafNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_proofs_by_data_and_by_hash_agree() {
+        let data: Vec<Vec<u8>> = (0..9).map(|i| format!("block{}", i).into_bytes()).collect();
+        let trees = [
+            MerkleTree::new(&data).expect("Should create merkle tree"),
+            MerkleTree::with_sorted_pairs(&data).expect("Should create merkle tree"),
+        ];
+        for tree in &trees {
+            for (index, element) in data.iter().enumerate() {
+                let leaf = tree.leaf(index).expect("Should have leaf");
+                let by_data = tree.generate_proof(element).expect("Should generate proof");
+                let by_hash = tree
+                    .generate_proof_for_hash(&leaf)
+                    .expect("Should generate proof");
+                assert_eq!(by_data, by_hash);
+                assert!(tree.verify_proof_for_hash(&leaf, &by_data));
+                assert!(tree.verify_proof(element, &by_hash));
+            }
+        }
+        assert!(!MerkleTree::empty().verify_proof_for_hash(&hash(b"block0"), &MerkleProof::new()));
+    }
+
+    #[test]
+    fn test_prove_each_occurrence() {
+        // "dup" at 2 and 9, and twice as siblings at 4 and 5
+        let data = [
+            "a", "b", "dup", "c", "twin", "twin", "d", "e", "f", "dup", "g",
+        ];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(tree.occurrences_of("dup"), vec![2, 9]);
+        assert_eq!(tree.occurrences_of("twin"), vec![4, 5]);
+        assert_eq!(tree.occurrences_of("a"), vec![0]);
+        assert!(tree.occurrences_of("missing").is_empty());
+
+        let first = tree.generate_proof("dup").expect("Should generate proof");
+        let second = tree
+            .generate_proof_nth("dup", 1)
+            .expect("Should generate proof");
+        assert_eq!(
+            first,
+            tree.generate_proof_nth("dup", 0)
+                .expect("Should generate proof")
+        );
+        assert_eq!(
+            second,
+            tree.generate_proof_by_index(9)
+                .expect("Should generate proof")
+        );
+        assert_ne!(first, second);
+        assert!(tree.verify_proof("dup", &first));
+        assert!(tree.verify_proof("dup", &second));
+        assert!(verify(&tree.root(), "dup", &second));
+
+        // Sibling duplicates have the same siblings above their pair, but not at the leaves
+        let left = tree
+            .generate_proof_nth("twin", 0)
+            .expect("Should generate proof");
+        let right = tree
+            .generate_proof_nth("twin", 1)
+            .expect("Should generate proof");
+        assert_ne!(left, right);
+        assert_eq!(left.iter().next(), Some((&hash("twin"), Direction::Right)));
+        assert_eq!(right.iter().next(), Some((&hash("twin"), Direction::Left)));
+        assert!(verify(&tree.root(), "twin", &left));
+        assert!(verify(&tree.root(), "twin", &right));
+
+        assert!(matches!(
+            tree.generate_proof_nth("dup", 2),
+            Err(MerkleError::LeafNotFound)
+        ));
+
+        let canonical = MerkleTree::canonical(&data).expect("Should create merkle tree");
+        let indices = canonical.occurrences_of("dup");
+        assert_eq!(indices.len(), 2);
+        assert_eq!(indices[1], indices[0] + 1);
+        let proof = canonical
+            .generate_proof_nth("dup", 1)
+            .expect("Should generate proof");
+        assert!(canonical.verify_proof("dup", &proof));
+    }
+
+    #[test]
+    fn test_proof_shape_is_checked() {
+        let data: Vec<Vec<u8>> = (0..6).map(|i| format!("block{}", i).into_bytes()).collect();
+        let trees = [
+            MerkleTree::new(&data).expect("Should create merkle tree"),
+            MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PromoteToNextLevel)
+                .expect("Should create merkle tree"),
+        ];
+        for tree in &trees {
+            let levels = tree.levels();
+            let proof = tree
+                .generate_proof(&data[4])
+                .expect("Should generate proof");
+            assert!(tree.verify_proof_strict(&data[4], &proof).is_ok());
+            assert!(matches!(
+                tree.verify_proof_strict(&data[3], &proof),
+                Err(MerkleError::RootMismatch)
+            ));
+
+            // The root itself claimed as a leaf with an empty proof
+            let empty = MerkleProof::new();
+            assert!(!tree.verify_proof_for_hash(&tree.root(), &empty));
+            assert!(matches!(
+                tree.check_proof_for_hash(&tree.root(), &empty),
+                Err(MerkleError::InvalidProofShape)
+            ));
+
+            // A node of the first level claimed as a leaf, one sibling short
+            let mut siblings = Vec::from(
+                tree.generate_proof(&data[0])
+                    .expect("Should generate proof"),
+            );
+            siblings.remove(0);
+            let node = levels[1][0];
+            let short = MerkleProof::from(siblings);
+            assert_eq!(short.fold(node, hash_internal_node), tree.root());
+            assert!(matches!(
+                tree.check_proof_for_hash(&node, &short),
+                Err(MerkleError::InvalidProofShape)
+            ));
+
+            let mut long = proof.clone();
+            long.push(tree.root(), Direction::Left);
+            assert!(matches!(
+                tree.verify_proof_strict(&data[4], &long),
+                Err(MerkleError::InvalidProofShape)
+            ));
+            assert!(!tree.verify_proof(&data[4], &long));
+        }
+
+        let tree = &trees[0];
+        let proof = tree
+            .generate_proof(&data[4])
+            .expect("Should generate proof");
+        assert!(verify_strict(&tree.root(), &data[4], &proof, 3).is_ok());
+        assert!(matches!(
+            verify_strict(&tree.root(), &data[4], &proof, 4),
+            Err(MerkleError::InvalidProofShape)
+        ));
+        assert!(matches!(
+            verify_strict(&tree.root(), &data[4], &MerkleProof::new(), 3),
+            Err(MerkleError::InvalidProofShape)
+        ));
+        assert!(matches!(
+            verify_strict(&tree.root(), &data[3], &proof, 3),
+            Err(MerkleError::RootMismatch)
+        ));
+        assert!(matches!(
+            MerkleTree::empty().verify_proof_strict(&data[0], &proof),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_verify_proof_detailed() {
+        let data: Vec<Vec<u8>> = (0..5).map(|i| format!("block{}", i).into_bytes()).collect();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = tree
+            .generate_proof(&data[4])
+            .expect("Should generate proof");
+        assert_eq!(tree.verify_proof_detailed(&data[4], &proof), Ok(()));
+
+        assert_eq!(
+            tree.verify_proof_detailed(&data[3], &proof),
+            Err(VerifyError::LeafMismatch { index: Some(4) })
+        );
+        assert_eq!(
+            tree.verify_proof_detailed(b"missing", &proof),
+            Err(VerifyError::LeafMismatch { index: Some(4) })
+        );
+        assert_eq!(
+            MerkleTree::empty().verify_proof_detailed(&data[4], &proof),
+            Err(VerifyError::LeafMismatch { index: None })
+        );
+
+        let mut siblings = Vec::from(proof.clone());
+        siblings[2].0[0] ^= 1;
+        let tampered = MerkleProof::from(siblings);
+        assert_eq!(
+            tree.verify_proof_detailed(&data[4], &tampered),
+            Err(VerifyError::RootMismatch {
+                computed: tree.root_from_proof(hash(&data[4]), &tampered),
+                expected: tree.root(),
+                level: 2,
+            })
+        );
+
+        let short: MerkleProof = proof.iter().take(2).map(|(s, d)| (*s, d)).collect();
+        assert_eq!(
+            tree.verify_proof_detailed(&data[4], &short),
+            Err(VerifyError::WrongProofLength {
+                expected: 3,
+                found: 2
+            })
+        );
+        let mut long = proof.clone();
+        long.push(tree.root(), Direction::Left);
+        assert_eq!(
+            tree.verify_proof_detailed(b"missing", &long),
+            Err(VerifyError::WrongProofLength {
+                expected: 3,
+                found: 4
+            })
+        );
+
+        // Leaf 4 is paired with itself on the first two levels, on the left
+        let mut siblings = Vec::from(proof.clone());
+        siblings[1].1 = Direction::Left;
+        assert_eq!(
+            tree.verify_proof_detailed(&data[4], &MerkleProof::from(siblings)),
+            Err(VerifyError::WrongDirection { level: 1 })
+        );
+
+        let kary = MerkleTree::with_arity(4, &data).expect("Should create merkle tree");
+        assert_eq!(
+            kary.verify_proof_detailed(&data[0], &proof),
+            Err(VerifyError::UnsupportedArity)
+        );
+    }
+
+    #[test]
+    fn test_positioned_proofs() {
+        for size in 1..=9 {
+            let data: Vec<Vec<u8>> = (0..size)
+                .map(|i| format!("block{}", i).into_bytes())
+                .collect();
+            let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+            for (index, element) in data.iter().enumerate() {
+                let proof = tree
+                    .generate_positioned_proof(index)
+                    .expect("Should generate proof");
+                assert!(verify_positioned(&tree.root(), element, &proof));
+                for other in (0..size).filter(|&other| other != index) {
+                    let claimed = PositionedProof {
+                        index: other,
+                        ..proof.clone()
+                    };
+                    assert!(!verify_positioned(&tree.root(), element, &claimed));
+                }
+            }
+        }
+
+        // Same data at 0 and 2, both proofs pass the hash chain for either element
+        let data = ["dup", "a", "dup", "b"];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = tree
+            .generate_positioned_proof(2)
+            .expect("Should generate proof");
+        assert!(verify(&tree.root(), "dup", &proof.proof));
+        assert!(verify_positioned(&tree.root(), "dup", &proof));
+        let moved = PositionedProof { index: 0, ..proof };
+        assert!(verify(&tree.root(), "dup", &moved.proof));
+        assert!(!verify_positioned(&tree.root(), "dup", &moved));
+
+        // The last leaf of an odd level, and its phantom copy
+        let data = ["a", "b", "c", "d", "e"];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = tree
+            .generate_positioned_proof(4)
+            .expect("Should generate proof");
+        let phantom = PositionedProof {
+            index: 5,
+            leaf_count: 6,
+            ..proof.clone()
+        };
+        assert!(verify(&tree.root(), "e", &phantom.proof));
+        assert!(!verify_positioned(&tree.root(), "e", &phantom));
+        let deeper = PositionedProof {
+            leaf_count: 9,
+            ..proof.clone()
+        };
+        assert!(!verify_positioned(&tree.root(), "e", &deeper));
+        assert!(!verify_positioned(
+            &tree.root(),
+            "e",
+            &PositionedProof {
+                leaf_count: usize::MAX,
+                ..proof
+            }
+        ));
+
+        assert!(matches!(
+            tree.generate_positioned_proof(5),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        let promoted = MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PromoteToNextLevel)
+            .expect("Should create merkle tree");
+        assert!(matches!(
+            promoted.generate_positioned_proof(0),
+            Err(MerkleError::UnsupportedProof)
+        ));
+    }
+
+    #[test]
+    fn test_verify_with_size() {
+        let data: Vec<Vec<u8>> = (0..6).map(|i| format!("block{}", i).into_bytes()).collect();
+        let five = MerkleTree::new(&data[..5]).expect("Should create merkle tree");
+        let mut repeated = data[..5].to_vec();
+        repeated.push(data[4].clone());
+        let six = MerkleTree::new(&repeated).expect("Should create merkle tree");
+        assert_eq!(five.root(), six.root());
+
+        // Without the size, the proof of the 5-leaf tree verifies against the 6-leaf tree
+        let proof = five
+            .generate_positioned_proof(4)
+            .expect("Should generate proof");
+        assert!(six.verify_proof(&data[4], &proof.proof));
+        assert!(verify(&six.root(), &data[4], &proof.proof));
+        assert!(verify_positioned(&six.root(), &data[4], &proof));
+        assert!(verify_with_size(&five.root(), 5, &data[4], &proof));
+        assert!(!verify_with_size(&six.root(), 6, &data[4], &proof));
+
+        // A bigger tree passed off as a tree of 5 leaves, where leaf 4 is paired with itself
+        let other = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = other
+            .generate_positioned_proof(4)
+            .expect("Should generate proof");
+        assert!(verify_with_size(&other.root(), 6, &data[4], &proof));
+        let relabeled = PositionedProof {
+            leaf_count: 5,
+            ..proof
+        };
+        assert!(verify_positioned(&other.root(), &data[4], &relabeled));
+        assert!(!verify_with_size(&other.root(), 5, &data[4], &relabeled));
+
+        let data: Vec<Vec<u8>> = (0..9).map(|i| format!("block{}", i).into_bytes()).collect();
+        for size in 1..=9 {
+            let tree = MerkleTree::new(&data[..size]).expect("Should create merkle tree");
+            for (index, element) in data[..size].iter().enumerate() {
+                let proof = tree
+                    .generate_positioned_proof(index)
+                    .expect("Should generate proof");
+                assert!(verify_with_size(&tree.root(), size, element, &proof));
+                assert!(!verify_with_size(&tree.root(), size + 1, element, &proof));
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_all_proofs() {
+        let data: Vec<Vec<u8>> = (0..300)
+            .map(|i| format!("block{}", i % 250).into_bytes())
+            .collect();
+        let trees = [
+            MerkleTree::new(&data).expect("Should create merkle tree"),
+            MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PromoteToNextLevel)
+                .expect("Should create merkle tree"),
+            MerkleTree::with_depth(10, &data).expect("Should create merkle tree"),
+        ];
+        for tree in &trees {
+            let proofs = tree.generate_all_proofs().expect("Should generate proofs");
+            assert_eq!(proofs.len(), data.len());
+            for (index, proof) in proofs.iter().enumerate() {
+                assert_eq!(
+                    proof,
+                    &tree
+                        .generate_proof_by_index(index)
+                        .expect("Should generate proof")
+                );
+                assert!(tree.verify_proof(&data[index], proof));
+            }
+        }
+
+        for size in 1..=9 {
+            let tree = MerkleTree::new(&data[..size]).expect("Should create merkle tree");
+            let proofs = tree.generate_all_proofs().expect("Should generate proofs");
+            for (index, proof) in proofs.iter().enumerate() {
+                assert_eq!(
+                    proof,
+                    &tree
+                        .generate_proof_by_index(index)
+                        .expect("Should generate proof")
+                );
+            }
+        }
+        assert!(MerkleTree::empty()
+            .generate_all_proofs()
+            .expect("Should generate proofs")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_generate_proofs_for() {
+        let data = ["a", "b", "c", "b", "d", "e", "f"];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proofs = tree
+            .generate_proofs_for(&["f", "a", "b"])
+            .expect("Should generate proofs");
+        assert_eq!(
+            proofs.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![6, 0, 1]
+        );
+        for ((_, proof), item) in proofs.iter().zip(["f", "a", "b"]) {
+            assert_eq!(
+                proof,
+                &tree.generate_proof(item).expect("Should generate proof")
+            );
+        }
+
+        assert!(matches!(
+            tree.generate_proofs_for(&["a", "missing", "c", "e", "gone"]),
+            Err(MerkleError::LeavesNotFound(missing)) if missing == vec![1, 4]
+        ));
+        let empty: [&str; 0] = [];
+        assert!(tree
+            .generate_proofs_for(&empty)
+            .expect("Should generate proofs")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_empty_tree() {
+        let merkle = MerkleTree::empty();
+
+        assert!(merkle.is_empty());
+        assert_eq!(merkle.len(), 0);
+        assert_eq!(merkle.root(), empty_root());
+        assert_eq!(merkle.root(), hash(b""));
+
+        assert!(matches!(
+            merkle.generate_proof(b"block1"),
+            Err(MerkleError::LeafNotFound)
+        ));
+        // The empty root is the hash of the empty string, but that doesn't make it a member
+        assert!(!merkle.verify_proof(b"", &MerkleProof::new()));
+    }
+
+    #[test]
+    fn test_add_to_empty_tree() {
+        let mut merkle = MerkleTree::empty();
+        merkle.add(b"block1".to_vec()).expect("Should add leaf");
+
+        let expected = MerkleTree::new(&[b"block1"]).expect("Should create merkle tree");
+        assert_eq!(merkle.root(), expected.root());
+        assert_eq!(merkle.tree, expected.tree);
+
+        let proof = merkle
+            .generate_proof(b"block1")
+            .expect("Should generate proof");
+        assert!(merkle.verify_proof(b"block1", &proof));
+    }
+
+    #[test]
+    fn test_grow_from_default() {
+        let data: Vec<Vec<u8>> = (0..17)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        let mut merkle = MerkleTree::default();
+        for (size, element) in data.iter().enumerate().map(|(i, e)| (i + 1, e)) {
+            merkle.add(element.clone()).expect("Should add leaf");
+
+            let expected = MerkleTree::new(&data[..size]).expect("Should create merkle tree");
+            assert_eq!(merkle.len(), size);
+            assert_eq!(merkle.root(), expected.root());
+
+            // Every element added so far must be provable at every size
+            for added in &data[..size] {
+                let proof = merkle.generate_proof(added).expect("Should generate proof");
+                assert!(merkle.verify_proof(added, &proof));
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_iterator() {
+        let data: Vec<Vec<u8>> = (0..11)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let expected = MerkleTree::new(&data).expect("Should create merkle tree");
+
+        // Borrowed items
+        let borrowed: MerkleTree = data.iter().collect();
+        assert_eq!(borrowed.root(), expected.root());
+        assert_eq!(borrowed.tree, expected.tree);
+
+        // Owned items
+        let owned: MerkleTree = data.clone().into_iter().collect();
+        assert_eq!(owned.root(), expected.root());
+
+        let strings: MerkleTree = (0..11).map(|i| format!("block{}", i)).collect();
+        assert_eq!(strings.root(), expected.root());
+
+        let empty: MerkleTree = Vec::<Vec<u8>>::new().into_iter().collect();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_try_from_iter() {
+        let data = vec!["block1", "block2", "block3"];
+
+        let merkle = MerkleTree::try_from_iter(data.iter()).expect("Should create merkle tree");
+        assert_eq!(
+            merkle.root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+
+        assert!(matches!(
+            MerkleTree::try_from_iter(Vec::<&str>::new()),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_from_reader() {
+        // A little over 3 MiB so the last chunk is partial
+        let data: Vec<u8> = (0..3 * 1024 * 1024 + 1234)
+            .map(|i: usize| (i % 251) as u8)
+            .collect();
+        let chunk_size = 64 * 1024;
+
+        let merkle = MerkleTree::from_reader(data.as_slice(), chunk_size)
+            .expect("Should create merkle tree");
+
+        let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
+        let expected = MerkleTree::new(&chunks).expect("Should create merkle tree");
+        assert_eq!(merkle.len(), 49);
+        assert_eq!(merkle.root(), expected.root());
+
+        let proof = merkle
+            .generate_proof(&chunks[48])
+            .expect("Should generate proof");
+        assert!(merkle.verify_proof(&chunks[48], &proof));
+    }
+
+    #[test]
+    fn test_from_reader_short_reads() {
+        // Yields at most 3 bytes per read call
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = buf.len().min(3).min(self.0.len());
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let data = b"a stream that arrives a few bytes at a time";
+        let merkle = MerkleTree::from_reader(Trickle(data), 8).expect("Should create merkle tree");
+
+        let chunks: Vec<&[u8]> = data.chunks(8).collect();
+        let expected = MerkleTree::new(&chunks).expect("Should create merkle tree");
+        assert_eq!(merkle.root(), expected.root());
+    }
+
+    #[test]
+    fn test_from_reader_errors() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk on fire"))
+            }
+        }
+
+        assert!(matches!(
+            MerkleTree::from_reader(b"data".as_slice(), 0),
+            Err(MerkleError::InvalidChunkSize)
+        ));
+        assert!(matches!(
+            MerkleTree::from_reader(b"".as_slice(), 4),
+            Err(MerkleError::EmptyData)
+        ));
+        assert!(matches!(
+            MerkleTree::from_reader(Failing, 4),
+            Err(MerkleError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let data: Vec<Vec<u8>> = (0..10)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        let mut merkle = MerkleTree::with_capacity(10);
+        assert!(merkle.is_empty());
+        assert_eq!(merkle.root(), empty_root());
+        assert!(merkle.capacity() >= 10);
+
+        let capacity = merkle.capacity();
+        merkle.extend_from(&data).expect("Should extend tree");
+        assert_eq!(merkle.capacity(), capacity);
+
+        // Each level was sized for 10 leaves, so none of them had to grow
+        let level_capacities: Vec<usize> = merkle.tree.iter().map(Vec::capacity).collect();
+        assert_eq!(level_capacities, vec![10, 5, 3, 2, 1]);
+
+        let expected = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(merkle.tree, expected.tree);
+    }
+
+    #[test]
+    fn test_with_capacity_exceeded() {
+        let data: Vec<Vec<u8>> = (0..13)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        let mut merkle = MerkleTree::with_capacity(4);
+        for element in &data {
+            merkle.add(element.clone()).expect("Should add leaf");
+        }
+        assert!(merkle.capacity() >= 13);
+
+        let expected = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(merkle.tree, expected.tree);
+        assert_eq!(merkle.root(), expected.root());
+    }
+
+    #[test]
+    fn test_level_count() {
+        assert_eq!(level_count(0), 1);
+        assert_eq!(level_count(1), 1);
+        assert_eq!(level_count(2), 2);
+        assert_eq!(level_count(5), 4);
+        assert_eq!(level_count(8), 4);
+        assert_eq!(level_count(9), 5);
+    }
+
+    #[test]
+    fn test_with_depth() {
+        let data: Vec<Vec<u8>> = (0..5).map(|i| format!("block{}", i).into_bytes()).collect();
+
+        let merkle = MerkleTree::with_depth(3, &data).expect("Should create merkle tree");
+        assert_eq!(merkle.tree.len(), 4);
+        assert_eq!(merkle.max_leaves(), Some(8));
+
+        // Missing leaves are zero hashes instead of duplicates of the last leaf
+        let mut padded: Vec<Hash> = data.iter().map(hash).collect();
+        padded.resize(8, ZERO_HASH);
+        let expected = MerkleTree::from_leaves(padded).expect("Should create merkle tree");
+        assert_eq!(merkle.root(), expected.root());
+
+        for element in &data {
+            let proof = merkle
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert_eq!(proof.len(), 3);
+            assert!(merkle.verify_proof(element, &proof));
+        }
+    }
+
+    #[test]
+    fn test_with_depth_proof_length_is_constant() {
+        let data: Vec<Vec<u8>> = (0..9).map(|i| format!("block{}", i).into_bytes()).collect();
+
+        // Different numbers of real leaves, same depth
+        let single = MerkleTree::with_depth(20, &data[..1]).expect("Should create merkle tree");
+        let several = MerkleTree::with_depth(20, &data).expect("Should create merkle tree");
+        assert_ne!(single.root(), several.root());
+
+        let single_proof = single
+            .generate_proof(&data[0])
+            .expect("Should generate proof");
+        assert_eq!(single_proof.len(), 20);
+        assert!(single.verify_proof(&data[0], &single_proof));
+        assert!(!several.verify_proof(&data[0], &single_proof));
+
+        for element in &data {
+            let proof = several
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert_eq!(proof.len(), single_proof.len());
+            assert!(several.verify_proof(element, &proof));
+        }
+    }
+
+    #[test]
+    fn test_with_depth_mutations() {
+        let data: Vec<Vec<u8>> = (0..4).map(|i| format!("block{}", i).into_bytes()).collect();
+
+        let mut merkle = MerkleTree::with_depth(2, &data[..1]).expect("Should create merkle tree");
+        for element in &data[1..] {
+            merkle.add(element.clone()).expect("Should add leaf");
+        }
+        assert_eq!(
+            merkle.root(),
+            MerkleTree::with_depth(2, &data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+
+        // The tree is full
+        assert!(matches!(
+            merkle.add(b"block4".to_vec()),
+            Err(MerkleError::TreeFull)
+        ));
+        assert!(matches!(
+            merkle.extend_from(&[b"block4"]),
+            Err(MerkleError::TreeFull)
+        ));
+        assert!(matches!(
+            MerkleTree::with_depth(1, &data),
+            Err(MerkleError::TreeFull)
+        ));
+
+        merkle.remove(3).expect("Should remove leaf");
+        assert_eq!(
+            merkle.root(),
+            MerkleTree::with_depth(2, &data[..3])
+                .expect("Should create merkle tree")
+                .root()
+        );
+        assert_eq!(merkle.tree.len(), 3);
+    }
+
+    #[test]
+    fn test_with_depth_empty() {
+        let merkle = MerkleTree::with_depth(4, &Vec::<Vec<u8>>::new()).expect("Should create tree");
+        assert_eq!(merkle.root(), zero_hashes(4)[4]);
+        assert_eq!(
+            merkle.root(),
+            MerkleTree::from_leaves(vec![ZERO_HASH; 16])
+                .expect("Should create merkle tree")
+                .root()
+        );
+    }
+
+    const ALL_POLICIES: [OddNodePolicy; 3] = [
+        OddNodePolicy::DuplicateLast,
+        OddNodePolicy::PromoteToNextLevel,
+        OddNodePolicy::PadWithConstant([0xff; 32]),
+    ];
+
+    #[test]
+    fn test_odd_node_policies_three_leaves() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+        let leaf1 = hash(&data[0]);
+        let leaf2 = hash(&data[1]);
+        let leaf3 = hash(&data[2]);
+        let internal1 = hash_internal_node(&leaf1, &leaf2);
+
+        let duplicated = MerkleTree::with_odd_node_policy(&data, OddNodePolicy::DuplicateLast)
+            .expect("Should create merkle tree");
+        assert_eq!(
+            duplicated.root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+
+        let promoted = MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PromoteToNextLevel)
+            .expect("Should create merkle tree");
+        assert_eq!(promoted.root(), hash_internal_node(&internal1, &leaf3));
+        assert_eq!(
+            promoted
+                .generate_proof(&data[2])
+                .expect("Should generate proof"),
+            MerkleProof::from(vec![(internal1, Direction::Left)])
+        );
+
+        let constant = [0xff; 32];
+        let padded =
+            MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PadWithConstant(constant))
+                .expect("Should create merkle tree");
+        assert_eq!(
+            padded.root(),
+            hash_internal_node(&internal1, &hash_internal_node(&leaf3, &constant))
+        );
+    }
+
+    #[test]
+    fn test_odd_node_policies_proofs() {
+        for size in [3, 5, 7] {
+            let data: Vec<Vec<u8>> = (0..size)
+                .map(|i| format!("block{}", i).into_bytes())
+                .collect();
+
+            let roots: Vec<Hash> = ALL_POLICIES
+                .iter()
+                .map(|&policy| {
+                    let merkle = MerkleTree::with_odd_node_policy(&data, policy)
+                        .expect("Should create merkle tree");
+                    assert_eq!(merkle.odd_node_policy(), policy);
+
+                    for element in &data {
+                        let proof = merkle
+                            .generate_proof(element)
+                            .expect("Should generate proof");
+                        assert!(merkle.verify_proof(element, &proof));
+                    }
+                    merkle.root()
+                })
+                .collect();
+
+            // Each policy commits to the data differently
+            assert_ne!(roots[0], roots[1]);
+            assert_ne!(roots[0], roots[2]);
+            assert_ne!(roots[1], roots[2]);
+        }
+    }
+
+    #[test]
+    fn test_odd_node_policies_mutations() {
+        let data: Vec<Vec<u8>> = (0..7).map(|i| format!("block{}", i).into_bytes()).collect();
+
+        for policy in ALL_POLICIES {
+            let mut merkle = MerkleTree::with_odd_node_policy(&data[..1], policy)
+                .expect("Should create merkle tree");
+            for element in &data[1..] {
+                merkle.add(element.clone()).expect("Should add leaf");
+            }
+            let expected =
+                MerkleTree::with_odd_node_policy(&data, policy).expect("Should create merkle tree");
+            assert_eq!(merkle.tree, expected.tree);
+
+            merkle.remove(2).expect("Should remove leaf");
+            let mut remaining = data.clone();
+            remaining.remove(2);
+            let expected = MerkleTree::with_odd_node_policy(&remaining, policy)
+                .expect("Should create merkle tree");
+            assert_eq!(merkle.tree, expected.tree);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sha2")]
+    fn test_rfc6962_roots() {
+        for (size, expected_root) in RFC6962.roots.iter().enumerate() {
+            let merkle = MerkleTree::new_rfc6962(&RFC6962.leaves[..size])
+                .expect("Should create merkle tree");
+            assert_eq!(merkle.root(), from_hex(expected_root), "size {}", size);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sha2")]
+    fn test_rfc6962_audit_paths() {
+        for vector in RFC6962.inclusion {
+            let merkle = MerkleTree::new_rfc6962(&RFC6962.leaves[..vector.tree_size])
+                .expect("Should create merkle tree");
+            let leaf = RFC6962.leaves[vector.leaf_index];
+            let proof = merkle.generate_proof(leaf).expect("Should generate proof");
+
+            let siblings: Vec<Hash> = proof.iter().map(|(sibling, _)| *sibling).collect();
+            let expected: Vec<Hash> = vector.path.iter().map(|hex| from_hex(hex)).collect();
+            assert_eq!(siblings, expected);
+            assert!(merkle.verify_proof(leaf, &proof));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sha2")]
+    fn test_rfc6962_mutations() {
+        let mut merkle = MerkleTree::new_rfc6962(&RFC6962.leaves[..3]).expect("Should create tree");
+        for leaf in &RFC6962.leaves[3..] {
+            merkle.add(leaf.to_vec()).expect("Should add leaf");
+            assert!(merkle.verify_proof(
+                leaf,
+                &merkle.generate_proof(leaf).expect("Should generate proof")
+            ));
+        }
+        let expected = MerkleTree::new_rfc6962(RFC6962.leaves).expect("Should create tree");
+        assert_eq!(merkle.tree, expected.tree);
+
+        // The same data in the default mode has a different root
+        let default = MerkleTree::new(RFC6962.leaves).expect("Should create merkle tree");
+        assert_ne!(default.root(), expected.root());
+    }
+
+    #[test]
+    fn test_kary_tree() {
+        let data: Vec<Vec<u8>> = (0..16)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let leaves: Vec<Hash> = data.iter().map(hash).collect();
+
+        let merkle = MerkleTree::with_arity(4, &data).expect("Should create merkle tree");
+        assert_eq!(merkle.arity(), 4);
+        assert_eq!(merkle.tree.len(), 3);
+
+        let hash_four = |children: &[Hash]| -> Hash {
+            let mut hasher = Sha3_256::new();
+            for child in children {
+                hasher.update(child);
+            }
+            hasher.finalize().into()
+        };
+        let internal: Vec<Hash> = leaves.chunks(4).map(hash_four).collect();
+        assert_eq!(merkle.root(), hash_four(&internal));
+
+        for (index, element) in data.iter().enumerate() {
+            let proof = merkle
+                .generate_kary_proof(element)
+                .expect("Should generate proof");
+
+            // 16 leaves in a 4-ary tree are 2 levels deep, with 3 siblings per level
+            assert_eq!(proof.len(), 2);
+            assert_eq!(proof[0].position, index % 4);
+            assert_eq!(proof[1].position, index / 4);
+            assert!(proof.iter().all(|level| level.siblings.len() == 3));
+            assert!(merkle.verify_kary_proof(element, &proof));
+            assert!(!merkle.verify_kary_proof(b"wrong_data", &proof));
+        }
+
+        // Binary proofs don't apply to 4-ary trees
+        assert!(matches!(
+            merkle.generate_proof(&data[0]),
+            Err(MerkleError::UnsupportedArity)
+        ));
+    }
+
+    #[test]
+    fn test_kary_tree_incomplete_groups() {
+        let data: Vec<Vec<u8>> = (0..11)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        for arity in [3, 4, 5] {
+            let mut merkle =
+                MerkleTree::with_arity(arity, &data[..1]).expect("Should create merkle tree");
+            for element in &data[1..] {
+                merkle.add(element.clone()).expect("Should add leaf");
+            }
+            let expected = MerkleTree::with_arity(arity, &data).expect("Should create merkle tree");
+            assert_eq!(merkle.tree, expected.tree);
+
+            for element in &data {
+                let proof = merkle
+                    .generate_kary_proof(element)
+                    .expect("Should generate proof");
+                assert!(merkle.verify_kary_proof(element, &proof));
+            }
+
+            // Claiming a different position among the siblings breaks the proof
+            let mut proof = merkle
+                .generate_kary_proof(&data[0])
+                .expect("Should generate proof");
+            proof[0].position = 1;
+            assert!(!merkle.verify_kary_proof(&data[0], &proof));
+        }
+    }
+
+    #[test]
+    fn test_binary_arity_unchanged() {
+        let data: Vec<Vec<u8>> = (0..7).map(|i| format!("block{}", i).into_bytes()).collect();
+
+        let binary = MerkleTree::with_arity(2, &data).expect("Should create merkle tree");
+        let expected = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(binary.tree, expected.tree);
+
+        for element in &data {
+            let proof = binary
+                .generate_proof(element)
+                .expect("Should generate proof");
+            let kary_proof = binary
+                .generate_kary_proof(element)
+                .expect("Should generate proof");
+
+            // Both proofs carry the same siblings
+            let siblings: Vec<Hash> = kary_proof.iter().map(|level| level.siblings[0]).collect();
+            let expected_siblings: Vec<Hash> = proof.iter().map(|(sibling, _)| *sibling).collect();
+            assert_eq!(siblings, expected_siblings);
+            assert!(binary.verify_kary_proof(element, &kary_proof));
+        }
+
+        assert!(matches!(
+            MerkleTree::with_arity(1, &data),
+            Err(MerkleError::InvalidArity)
+        ));
+    }
+
+    #[test]
+    fn test_merge() {
+        let data: Vec<Vec<u8>> = (0..40)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        // Aligned sizes take the fast path, misaligned ones rebuild the right part
+        let splits = [
+            (1, 1),
+            (4, 1),
+            (4, 3),
+            (4, 4),
+            (8, 13),
+            (16, 3),
+            (3, 4),
+            (5, 9),
+            (7, 33),
+        ];
+        for (left_size, right_size) in splits {
+            let all = &data[..left_size + right_size];
+            let left = MerkleTree::new(&all[..left_size]).expect("Should create merkle tree");
+            let right = MerkleTree::new(&all[left_size..]).expect("Should create merkle tree");
+
+            let merged = left.merge(right).expect("Should merge trees");
+            let expected = MerkleTree::new(all).expect("Should create merkle tree");
+            assert_eq!(merged.root(), expected.root());
+            assert_eq!(merged.tree, expected.tree);
+            assert_eq!(merged.leaves, expected.leaves);
+        }
+    }
+
+    #[test]
+    fn test_merge_with_policy() {
+        let data: Vec<Vec<u8>> = (0..11)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        for policy in ALL_POLICIES {
+            for left_size in [1, 2, 4, 5, 8] {
+                let left = MerkleTree::with_odd_node_policy(&data[..left_size], policy)
+                    .expect("Should create merkle tree");
+                let right = MerkleTree::with_odd_node_policy(&data[left_size..], policy)
+                    .expect("Should create merkle tree");
+
+                let merged = left.merge(right).expect("Should merge trees");
+                let expected = MerkleTree::with_odd_node_policy(&data, policy)
+                    .expect("Should create merkle tree");
+                assert_eq!(merged.tree, expected.tree);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_incompatible() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec()];
+
+        let left = MerkleTree::new(&data).expect("Should create merkle tree");
+        let right = MerkleTree::with_arity(3, &data).expect("Should create merkle tree");
+        assert!(matches!(
+            left.merge(right),
+            Err(MerkleError::IncompatibleTrees)
+        ));
+
+        // Merging with an empty tree changes nothing
+        let left = MerkleTree::new(&data).expect("Should create merkle tree");
+        let root = left.root();
+        let merged = left.merge(MerkleTree::empty()).expect("Should merge trees");
+        assert_eq!(merged.root(), root);
+    }
+
+    #[test]
+    fn test_split_at() {
+        let data: Vec<Vec<u8>> = (0..13)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+
+        for index in 1..data.len() {
+            let (left, right) = merkle.split_at(index).expect("Should split tree");
+            assert_eq!(
+                left.root(),
+                MerkleTree::new(&data[..index])
+                    .expect("Should create merkle tree")
+                    .root()
+            );
+            assert_eq!(
+                right.root(),
+                MerkleTree::new(&data[index..])
+                    .expect("Should create merkle tree")
+                    .root()
+            );
+
+            // Both halves are independently usable for proofs
+            let proof = left
+                .generate_proof(&data[0])
+                .expect("Should generate proof");
+            assert!(left.verify_proof(&data[0], &proof));
+            let proof = right
+                .generate_proof(&data[index])
+                .expect("Should generate proof");
+            assert!(right.verify_proof(&data[index], &proof));
+
+            // Merging the halves back reproduces the original tree
+            let merged = left.merge(right).expect("Should merge trees");
+            assert_eq!(merged.root(), merkle.root());
+            assert_eq!(merged.tree, merkle.tree);
+        }
+    }
+
+    #[test]
+    fn test_split_at_bounds() {
+        let data: Vec<Vec<u8>> = (0..5).map(|i| format!("block{}", i).into_bytes()).collect();
+        let merkle = MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PromoteToNextLevel)
+            .expect("Should create merkle tree");
+
+        let (left, right) = merkle.split_at(0).expect("Should split tree");
+        assert!(left.is_empty());
+        assert_eq!(left.root(), empty_root());
+        assert_eq!(right.root(), merkle.root());
+        assert_eq!(right.odd_node_policy(), OddNodePolicy::PromoteToNextLevel);
+
+        let (left, right) = merkle.split_at(5).expect("Should split tree");
+        assert_eq!(left.root(), merkle.root());
+        assert!(right.is_empty());
+
+        assert!(matches!(
+            merkle.split_at(6),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let data: Vec<Vec<u8>> = (0..33)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        // Sizes on both sides of the power of two boundaries
+        for n in [33, 32, 31, 17, 16, 15, 9, 8, 5, 2, 1] {
+            let mut merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+            merkle.truncate(n);
+
+            let expected = MerkleTree::new(&data[..n]).expect("Should create merkle tree");
+            assert_eq!(merkle.len(), n);
+            assert_eq!(merkle.root(), expected.root());
+            assert_eq!(merkle.tree, expected.tree);
+        }
+    }
+
+    #[test]
+    fn test_truncate_edge_cases() {
+        let data: Vec<Vec<u8>> = (0..6).map(|i| format!("block{}", i).into_bytes()).collect();
+
+        let mut merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        let root = merkle.root();
+        merkle.truncate(6);
+        merkle.truncate(10);
+        assert_eq!(merkle.root(), root);
+
+        merkle.truncate(0);
+        assert!(merkle.is_empty());
+        assert_eq!(merkle.root(), empty_root());
+
+        // The empty tree can grow again
+        merkle.add(data[0].clone()).expect("Should add leaf");
+        assert_eq!(
+            merkle.root(),
+            MerkleTree::new(&data[..1])
+                .expect("Should create merkle tree")
+                .root()
+        );
+
+        let mut fixed = MerkleTree::with_depth(3, &data).expect("Should create merkle tree");
+        fixed.truncate(3);
+        assert_eq!(
+            fixed.root(),
+            MerkleTree::with_depth(3, &data[..3])
+                .expect("Should create merkle tree")
+                .root()
+        );
+    }
+
+    #[test]
+    fn test_duplicate_policy_add() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+
+        let mut allow = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(allow.duplicate_policy(), DuplicatePolicy::Allow);
+        assert_eq!(allow.add(data[1].clone()).expect("Should add leaf"), 3);
+        assert_eq!(allow.len(), 4);
+
+        let mut reject = MerkleTree::with_duplicate_policy(&data, DuplicatePolicy::Reject)
+            .expect("Should create merkle tree");
+        let root = reject.root();
+        assert!(matches!(
+            reject.add(data[1].clone()),
+            Err(MerkleError::DuplicateLeaf(1))
+        ));
+        assert_eq!(reject.len(), 3);
+        assert_eq!(reject.root(), root);
+
+        let mut dedup = MerkleTree::with_duplicate_policy(&data, DuplicatePolicy::Deduplicate)
+            .expect("Should create merkle tree");
+        assert_eq!(dedup.add(data[1].clone()).expect("Should add leaf"), 1);
+        assert_eq!(dedup.len(), 3);
+        assert_eq!(dedup.root(), root);
+
+        // A removed element can be added again
+        reject.remove(1).expect("Should remove leaf");
+        assert_eq!(reject.add(data[1].clone()).expect("Should add leaf"), 2);
+    }
+
+    #[test]
+    fn test_duplicate_policy_construction() {
+        let data = vec![
+            b"block1".to_vec(),
+            b"block2".to_vec(),
+            b"block1".to_vec(),
+            b"block3".to_vec(),
+        ];
+
+        let allow = MerkleTree::with_duplicate_policy(&data, DuplicatePolicy::Allow)
+            .expect("Should create merkle tree");
+        assert_eq!(
+            allow.root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+
+        assert!(matches!(
+            MerkleTree::with_duplicate_policy(&data, DuplicatePolicy::Reject),
+            Err(MerkleError::DuplicateLeaf(0))
+        ));
+
+        let dedup = MerkleTree::with_duplicate_policy(&data, DuplicatePolicy::Deduplicate)
+            .expect("Should create merkle tree");
+        let unique = [data[0].clone(), data[1].clone(), data[3].clone()];
+        assert_eq!(
+            dedup.root(),
+            MerkleTree::new(&unique)
+                .expect("Should create merkle tree")
+                .root()
+        );
+    }
+
+    #[test]
+    fn test_duplicate_policy_extend_and_update() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec()];
+        let batch = vec![b"block3".to_vec(), b"block1".to_vec(), b"block3".to_vec()];
+
+        let mut reject = MerkleTree::with_duplicate_policy(&data, DuplicatePolicy::Reject)
+            .expect("Should create merkle tree");
+        let root = reject.root();
+        assert!(matches!(
+            reject.extend_from(&batch),
+            Err(MerkleError::DuplicateLeaf(0))
+        ));
+        assert!(matches!(
+            reject.extend_from(&[b"block3".to_vec(), b"block3".to_vec()]),
+            Err(MerkleError::DuplicateLeaf(2))
+        ));
+        assert_eq!(reject.root(), root);
+        assert!(matches!(
+            reject.update(1, &data[0]),
+            Err(MerkleError::DuplicateLeaf(0))
+        ));
+        // Writing a leaf's own value back is not a duplicate
+        assert_eq!(
+            reject.update(1, &data[1]).expect("Should update leaf"),
+            root
+        );
+        assert_eq!(reject.root(), root);
+
+        let mut dedup = MerkleTree::with_duplicate_policy(&data, DuplicatePolicy::Deduplicate)
+            .expect("Should create merkle tree");
+        let (_, start) = dedup.extend_from(&batch).expect("Should extend tree");
+        assert_eq!(start, 2);
+        assert_eq!(dedup.len(), 3);
+        assert!(matches!(
+            dedup.update(0, &batch[0]),
+            Err(MerkleError::DuplicateLeaf(2))
+        ));
+        dedup.update(0, b"block4").expect("Should update leaf");
+        // The old value of the updated leaf is free again
+        assert_eq!(dedup.add(data[0].clone()).expect("Should add leaf"), 3);
+
+        let other = MerkleTree::with_duplicate_policy(&batch[..1], DuplicatePolicy::Deduplicate)
+            .expect("Should create merkle tree");
+        let merged = dedup.merge(other).expect("Should merge trees");
+        assert_eq!(merged.len(), 4);
+    }
+
+    #[test]
+    fn test_canonical_order_independent() {
+        let data: Vec<Vec<u8>> = (0..7).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut reversed = data.clone();
+        reversed.reverse();
+
+        let merkle = MerkleTree::canonical(&data).expect("Should create merkle tree");
+        let other = MerkleTree::canonical(&reversed).expect("Should create merkle tree");
+        assert!(merkle.is_canonical());
+        assert_eq!(merkle.root(), other.root());
+
+        // Adding in different orders reaches the same root as building from the whole set
+        let mut left = MerkleTree::canonical(&data[..1]).expect("Should create merkle tree");
+        for element in &data[1..] {
+            left.add(element.clone()).expect("Should add leaf");
+        }
+        let mut right = MerkleTree::canonical(&reversed[..1]).expect("Should create merkle tree");
+        for element in &reversed[1..] {
+            right.add(element.clone()).expect("Should add leaf");
+        }
+        let mut batched = MerkleTree::canonical(&data[3..]).expect("Should create merkle tree");
+        batched.extend_from(&data[..3]).expect("Should extend tree");
+        assert_eq!(left.root(), merkle.root());
+        assert_eq!(right.root(), merkle.root());
+        assert_eq!(batched.root(), merkle.root());
+        assert_eq!(left.tree, merkle.tree);
+
+        for element in &data {
+            let proof = right
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(right.verify_proof(element, &proof));
+            let index = right.index_of(element).expect("Should find leaf");
+            assert_eq!(right.leaves[index], hash(element));
+        }
+        assert_eq!(right.index_of(b"block7"), None);
+    }
+
+    #[test]
+    fn test_canonical_update() {
+        let data: Vec<Vec<u8>> = (0..5).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut merkle = MerkleTree::canonical(&data).expect("Should create merkle tree");
+
+        let index = merkle.index_of(&data[2]).expect("Should find leaf");
+        merkle.update(index, b"block9").expect("Should update leaf");
+        let mut expected_data = data.clone();
+        expected_data[2] = b"block9".to_vec();
+        let expected = MerkleTree::canonical(&expected_data).expect("Should create merkle tree");
+        assert_eq!(merkle.tree, expected.tree);
+        assert!(merkle.leaves.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let proof = merkle
+            .generate_proof(b"block9")
+            .expect("Should generate proof");
+        assert!(merkle.verify_proof(b"block9", &proof));
+    }
+
+    #[test]
+    fn test_retained_data_round_trip() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+        let mut merkle = MerkleTree::with_retained_data(&data).expect("Should create merkle tree");
+        assert!(merkle.retains_data());
+        assert_eq!(
+            merkle.root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+
+        merkle.add(b"block4".to_vec()).expect("Should add leaf");
+        merkle.update(1, b"block5").expect("Should update leaf");
+        merkle.remove(0).expect("Should remove leaf");
+        merkle
+            .extend_from(&[b"block6".to_vec(), b"block3".to_vec()])
+            .expect("Should extend tree");
+        let expected: [&[u8]; 5] = [b"block5", b"block3", b"block4", b"block6", b"block3"];
+        for (index, element) in expected.iter().enumerate() {
+            assert_eq!(merkle.data(index), Some(*element));
+            // The data and the proof of a leaf are all a verifier needs
+            let proof = merkle
+                .generate_proof_by_index(index)
+                .expect("Should generate proof");
+            assert!(merkle.verify_proof(element, &proof));
+        }
+        assert_eq!(merkle.data(5), None);
+        assert!(matches!(
+            merkle.generate_proof_by_index(5),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+
+        // Data of a repeated element stays until its last leaf is gone
+        merkle.remove(1).expect("Should remove leaf");
+        assert_eq!(merkle.data(3), Some(&b"block3"[..]));
+        merkle.truncate(3);
+        assert_eq!(merkle.retained.as_ref().map(HashMap::len), Some(3));
+
+        let (left, right) = merkle.split_at(1).expect("Should split tree");
+        assert_eq!(left.data(0), Some(&b"block5"[..]));
+        assert_eq!(right.data(1), Some(&b"block6"[..]));
+        let merged = right.merge(left).expect("Should merge trees");
+        assert_eq!(merged.data(2), Some(&b"block5"[..]));
+
+        let plain = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert!(!plain.retains_data());
+        assert_eq!(plain.data(0), None);
+    }
+
+    #[derive(Debug, Clone)]
+    struct Transaction {
+        id: u64,
+        amount: u64,
+        payer: String,
+    }
+
+    impl Hashable for Transaction {
+        fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+            let mut bytes = Vec::with_capacity(16 + self.payer.len());
+            bytes.extend_from_slice(&self.id.to_be_bytes());
+            bytes.extend_from_slice(&self.amount.to_be_bytes());
+            bytes.extend_from_slice(self.payer.as_bytes());
+            Cow::Owned(bytes)
+        }
+    }
+
+    #[test]
+    fn test_hashable_structs() {
+        let transactions: Vec<Transaction> = (0..5)
+            .map(|id| Transaction {
+                id,
+                amount: 100 * id,
+                payer: format!("payer{}", id),
+            })
+            .collect();
+        let mut merkle = MerkleTree::new(&transactions[..4]).expect("Should create merkle tree");
+        merkle
+            .add(transactions[4].clone())
+            .expect("Should add leaf");
+        assert_eq!(
+            merkle.root(),
+            MerkleTree::new(&transactions)
+                .expect("Should create merkle tree")
+                .root()
+        );
+
+        let proof = merkle
+            .generate_proof(&transactions[2])
+            .expect("Should generate proof");
+        assert!(merkle.verify_proof(&transactions[2], &proof));
+        assert_eq!(merkle.leaf(2), Some(hash(transactions[2].leaf_bytes())));
+
+        // Changing any field changes the root
+        let changes: [fn(&mut Transaction); 3] = [
+            |transaction| transaction.id += 10,
+            |transaction| transaction.amount += 1,
+            |transaction| transaction.payer.push('!'),
+        ];
+        for change in changes {
+            let mut changed = transactions.clone();
+            change(&mut changed[2]);
+            let other = MerkleTree::new(&changed).expect("Should create merkle tree");
+            assert_ne!(other.root(), merkle.root());
+            assert!(!other.verify_proof(&transactions[2], &proof));
+        }
+
+        // Bytes still hash as they always did
+        assert_eq!(b"block1".leaf_bytes(), &b"block1"[..]);
+    }
+
+    #[test]
+    fn test_hashable_structs_in_every_api() {
+        let transactions: Vec<Transaction> = (0..6)
+            .map(|id| Transaction {
+                id,
+                amount: 100 * id,
+                payer: format!("payer{}", id),
+            })
+            .collect();
+        let encoded: Vec<Vec<u8>> = transactions
+            .iter()
+            .map(|transaction| transaction.leaf_bytes().into_owned())
+            .collect();
+
+        // Every constructor hashes a struct like its encoding
+        let pairs = [
+            (
+                MerkleTree::with_odd_node_policy(&transactions, OddNodePolicy::PromoteToNextLevel),
+                MerkleTree::with_odd_node_policy(&encoded, OddNodePolicy::PromoteToNextLevel),
+            ),
+            (
+                MerkleTree::with_arity(3, &transactions),
+                MerkleTree::with_arity(3, &encoded),
+            ),
+            (
+                MerkleTree::with_duplicate_policy(&transactions, DuplicatePolicy::Reject),
+                MerkleTree::with_duplicate_policy(&encoded, DuplicatePolicy::Reject),
+            ),
+            (
+                MerkleTree::canonical(&transactions),
+                MerkleTree::canonical(&encoded),
+            ),
+            (
+                MerkleTree::with_depth(4, &transactions),
+                MerkleTree::with_depth(4, &encoded),
+            ),
+            (
+                MerkleTree::try_from_iter(transactions.iter().cloned()),
+                MerkleTree::try_from_iter(encoded.iter()),
+            ),
+        ];
+        for (from_structs, from_bytes) in pairs {
+            assert_eq!(
+                from_structs.expect("Should create merkle tree").root(),
+                from_bytes.expect("Should create merkle tree").root()
+            );
+        }
+        let retained =
+            MerkleTree::with_retained_data(&transactions).expect("Should create merkle tree");
+        assert_eq!(retained.data(1), Some(&encoded[1][..]));
+
+        // Mutations and proofs hash them the same way, whatever the settings
+        let mut merkle = MerkleTree::with_domain_separation(&transactions[..2])
+            .expect("Should create merkle tree");
+        merkle
+            .extend_from(&transactions[2..4])
+            .expect("Should extend the tree");
+        merkle
+            .update(0, &transactions[4])
+            .expect("Should update leaf");
+        merkle
+            .transaction(|tx| tx.update(1, &transactions[5]))
+            .expect("Should apply the transaction");
+        let expected = [4, 5, 2, 3].map(|index| &encoded[index]);
+        assert_eq!(
+            merkle.root(),
+            MerkleTree::with_domain_separation(&expected)
+                .expect("Should create merkle tree")
+                .root()
+        );
+        let proof = merkle
+            .generate_proof(&transactions[5])
+            .expect("Should generate proof");
+        assert!(merkle.verify_proof(&transactions[5], &proof));
+
+        // The stateless verifiers and the wrappers take structs as well
+        let merkle = MerkleTree::new(&transactions).expect("Should create merkle tree");
+        let proof = merkle
+            .generate_proof(&transactions[3])
+            .expect("Should generate proof");
+        assert!(verify(&merkle.root(), &transactions[3], &proof));
+        assert!(!verify(&merkle.root(), &transactions[2], &proof));
+        let batch = crate::batch::verify_batch(
+            &merkle.root(),
+            &[
+                (transactions[3].clone(), &proof),
+                (transactions[1].clone(), &proof),
+            ],
+            crate::batch::BatchMode::Exhaustive,
+        );
+        assert_eq!(batch.failed, vec![1]);
+        let multiproof = merkle
+            .generate_multiproof(&[1, 4])
+            .expect("Should generate proof");
+        assert!(crate::multiproof::verify_multiproof(
+            &merkle.root(),
+            &[(1, transactions[1].clone()), (4, transactions[4].clone())],
+            &multiproof
+        ));
+
+        let mut versioned = crate::versioned::VersionedMerkleTree::new(&transactions[..5])
+            .expect("Should create versioned tree");
+        versioned
+            .add(transactions[5].clone())
+            .expect("Should add leaf");
+        let mut lazy =
+            crate::lazy::LazyMerkleTree::new(&transactions[..1]).expect("Should create lazy tree");
+        lazy.extend_from(&transactions[1..])
+            .expect("Should extend the tree");
+        let mut builder = crate::builder::TreeBuilder::new();
+        builder.extend(transactions.iter().cloned());
+        for root in [
+            versioned.root(),
+            lazy.root(),
+            builder.finalize().expect("Should build tree").root(),
+        ] {
+            assert_eq!(root, merkle.root());
+        }
+        assert!(lazy.verify_proof(&transactions[3], &proof));
+    }
+
+    #[test]
+    fn test_from_shards() {
+        let data: Vec<Vec<u8>> = (0..27)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        for policy in ALL_POLICIES {
+            let expected =
+                MerkleTree::with_odd_node_policy(&data, policy).expect("Should create merkle tree");
+            // Aligned shards of 8 leaves with a shorter last one, and ragged shards
+            for bounds in [vec![0, 8, 16, 24, 27], vec![0, 5, 6, 20, 27]] {
+                let shards: Vec<MerkleTree> = bounds
+                    .windows(2)
+                    .map(|range| {
+                        MerkleTree::with_odd_node_policy(&data[range[0]..range[1]], policy)
+                            .expect("Should create merkle tree")
+                    })
+                    .collect();
+                let combined = MerkleTree::from_shards(shards).expect("Should combine shards");
+                assert_eq!(combined.root(), expected.root());
+                assert_eq!(combined.tree, expected.tree);
+                for element in &data {
+                    let proof = combined
+                        .generate_proof(element)
+                        .expect("Should generate proof");
+                    assert!(expected.verify_proof(element, &proof));
+                }
+            }
+        }
+
+        assert!(matches!(
+            MerkleTree::from_shards(Vec::<MerkleTree>::new()),
+            Err(MerkleError::EmptyData)
+        ));
+        let shards = vec![
+            MerkleTree::new(&data[..2]).expect("Should create merkle tree"),
+            MerkleTree::with_arity(4, &data[2..]).expect("Should create merkle tree"),
+        ];
+        assert!(matches!(
+            MerkleTree::from_shards(shards),
+            Err(MerkleError::IncompatibleTrees)
+        ));
+    }
+
+    #[test]
+    fn test_verify_self() {
+        let data: Vec<Vec<u8>> = (0..11)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        for policy in ALL_POLICIES {
+            let tree =
+                MerkleTree::with_odd_node_policy(&data, policy).expect("Should create merkle tree");
+            tree.verify_self().expect("Should be consistent");
+
+            let mut corrupt = tree.clone();
+            Arc::make_mut(&mut corrupt.tree)[2][1] = [0u8; 32];
+            assert!(matches!(
+                corrupt.verify_self(),
+                Err(MerkleError::CorruptNode(2, 1))
+            ));
+
+            let mut corrupt = tree.clone();
+            Arc::make_mut(&mut corrupt.tree)[0][4] = [0u8; 32];
+            assert!(matches!(
+                corrupt.verify_self(),
+                Err(MerkleError::CorruptNode(0, 4))
+            ));
+
+            let mut corrupt = tree.clone();
+            Arc::make_mut(&mut corrupt.tree)[1].pop();
+            assert!(matches!(
+                corrupt.verify_self(),
+                Err(MerkleError::CorruptNode(1, 5))
+            ));
+
+            let mut corrupt = tree;
+            Arc::make_mut(&mut corrupt.tree).push(vec![[0u8; 32]]);
+            assert!(matches!(
+                corrupt.verify_self(),
+                Err(MerkleError::CorruptNode(5, 0))
+            ));
+        }
+
+        let mut tree = MerkleTree::with_depth(4, &data[..3]).expect("Should create merkle tree");
+        tree.verify_self().expect("Should be consistent");
+        tree.add(data[3].clone()).expect("Should add leaf");
+        tree.update(1, &data[7]).expect("Should update leaf");
+        tree.remove(0).expect("Should remove leaf");
+        tree.verify_self().expect("Should be consistent");
+        MerkleTree::with_depth(3, &Vec::<Vec<u8>>::new())
+            .expect("Should create merkle tree")
+            .verify_self()
+            .expect("Should be consistent");
+        MerkleTree::with_arity(3, &data)
+            .expect("Should create merkle tree")
+            .verify_self()
+            .expect("Should be consistent");
+        MerkleTree::empty()
+            .verify_self()
+            .expect("Should be consistent");
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn test_with_digest() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+        let sha3 = MerkleTree::<Sha3_256>::with_digest(&data).expect("Should create merkle tree");
+        let sha2 = MerkleTree::<Sha256>::with_digest(&data).expect("Should create merkle tree");
+
+        // The default digest keeps the roots of `new()`
+        assert_eq!(
+            sha3.root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+        assert_ne!(sha3.root(), sha2.root());
+
+        let leaf1 = digest_parts::<Sha256, 32>(&[&data[0]]);
+        let leaf2 = digest_parts::<Sha256, 32>(&[&data[1]]);
+        assert_eq!(sha2.leaf(0), Some(leaf1));
+        assert_eq!(
+            sha2.tree[1][0],
+            digest_parts::<Sha256, 32>(&[&leaf1, &leaf2])
+        );
+
+        for element in &data {
+            let proof = sha2.generate_proof(element).expect("Should generate proof");
+            assert!(sha2.verify_proof(element, &proof));
+            assert!(!sha3.verify_proof(element, &proof));
+            let proof = sha3.generate_proof(element).expect("Should generate proof");
+            assert!(sha3.verify_proof(element, &proof));
+        }
+
+        let mut grown =
+            MerkleTree::<Sha256>::with_digest(&data[..1]).expect("Should create merkle tree");
+        grown.add(&data[1]).expect("Should add leaf");
+        grown.add(&data[2]).expect("Should add leaf");
+        assert_eq!(grown.root(), sha2.root());
+        assert!(matches!(
+            MerkleTree::<Sha256>::with_digest::<Vec<u8>>(&[]),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_sha3_512_tree() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+        let merkle =
+            MerkleTree::<Sha3_512, 64>::with_digest(&data).expect("Should create merkle tree");
+
+        let leaf1: [u8; 64] = Sha3_512::digest(&data[0]).into();
+        let leaf2: [u8; 64] = Sha3_512::digest(&data[1]).into();
+        assert_eq!(merkle.leaf(0), Some(leaf1));
+        assert_eq!(
+            merkle.tree[1][0],
+            <[u8; 64]>::from(Sha3_512::digest([leaf1, leaf2].concat()))
+        );
+
+        for element in &data {
+            let proof: MerkleProof<64> = merkle
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(merkle.verify_proof(element, &proof));
+            assert!(!merkle.verify_proof(b"block4", &proof));
+        }
+        merkle.verify_self().expect("Should be consistent");
+    }
+
+    #[test]
+    fn test_truncated_tree() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+        let mut merkle = MerkleTree::<Truncated<Sha3_256>, 20>::with_digest(&data)
+            .expect("Should create merkle tree");
+
+        let leaf = merkle.leaf(0).expect("Should have a leaf");
+        assert_eq!(leaf[..], hash(&data[0])[..20]);
+        let mut pair = leaf.to_vec();
+        pair.extend_from_slice(&merkle.leaf(1).expect("Should have a leaf"));
+        assert_eq!(merkle.tree[1][0][..], hash(pair)[..20]);
+
+        merkle.add(b"block4").expect("Should add leaf");
+        for element in [b"block1", b"block2", b"block3", b"block4"] {
+            let proof = merkle
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert_eq!(proof.len(), 2);
+            assert!(merkle.verify_proof(element, &proof));
+        }
+        assert_eq!(merkle.root().len(), 20);
+        merkle.verify_self().expect("Should be consistent");
+    }
+
+    #[test]
+    fn test_with_algorithm() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+        let expected = [
+            (
+                HashAlgorithm::Sha3_256,
+                "4e43ad30c63b7c4518092dec9e00b8ddf39823dec781561997298454db4ce057",
+            ),
+            #[cfg(feature = "sha2")]
+            (
+                HashAlgorithm::Sha2_256,
+                "1902f8c3a744c7a0228d466ff06be7ec7401e3b3c6f886334f27f974407560eb",
+            ),
+            (
+                HashAlgorithm::Keccak256,
+                "93f2b423b374bce517379c856d998f622e7bcfe04565fec6c9d1622729a6088f",
+            ),
+            #[cfg(feature = "blake3")]
+            (
+                HashAlgorithm::Blake3,
+                "c6bdd4ab3fa3ff8c5c547f720b6ac7b1cb9df85db5a0eeb2a54527438d4e3f49",
+            ),
+        ];
+        assert_eq!(expected.len(), HashAlgorithm::ALL.len());
+
+        for (algorithm, root) in expected {
+            let parsed: HashAlgorithm = algorithm.name().parse().expect("Should parse algorithm");
+            assert_eq!(parsed, algorithm);
+
+            let mut tree = MerkleTree::with_algorithm(algorithm, &data[..2])
+                .expect("Should create merkle tree");
+            tree.add(&data[2]).expect("Should add leaf");
+            assert_eq!(tree.algorithm(), algorithm);
+            assert_eq!(tree.root(), from_hex(root));
+            for element in &data {
+                let proof = tree.generate_proof(element).expect("Should generate proof");
+                assert!(tree.verify_proof(element, &proof));
+            }
+        }
+
+        assert_eq!(
+            MerkleTree::with_algorithm(HashAlgorithm::Keccak256, &data)
+                .expect("Should create merkle tree")
+                .root(),
+            MerkleTree::<Keccak256>::with_digest(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+        assert_eq!(
+            MerkleTree::with_algorithm(HashAlgorithm::Sha3_256, &data)
+                .expect("Should create merkle tree")
+                .root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+
+        // Trees hashed with different algorithms can't be merged
+        let sha3 = MerkleTree::with_algorithm(HashAlgorithm::Sha3_256, &data)
+            .expect("Should create merkle tree");
+        let keccak = MerkleTree::with_algorithm(HashAlgorithm::Keccak256, &data)
+            .expect("Should create merkle tree");
+        assert!(matches!(
+            sha3.merge(keccak),
+            Err(MerkleError::IncompatibleTrees)
+        ));
+
+        assert!(matches!(
+            "md5".parse::<HashAlgorithm>(),
+            Err(MerkleError::UnknownAlgorithm(name)) if name == "md5"
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "sha2")]
+    fn test_new_sha256() {
+        let data = [b"a", b"b", b"c", b"d", b"e"];
+        let mut tree = MerkleTree::new_sha256(&data[..4]).expect("Should create merkle tree");
+        tree.add(data[4]).expect("Should add leaf");
+
+        // Computed with Python's hashlib, duplicating the last node of odd levels
+        assert_eq!(
+            tree.root(),
+            from_hex("dd14d0ba516bb654a3052b76f051db026f4e322d0be081468fab99440f9e7305")
+        );
+        assert_eq!(
+            tree.leaf(0),
+            Some(from_hex(
+                "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb"
+            ))
+        );
+        for element in &data {
+            let proof = tree.generate_proof(element).expect("Should generate proof");
+            assert!(tree.verify_proof(element, &proof));
+        }
+        assert!(!tree.verify_proof(
+            b"f",
+            &tree.generate_proof(b"e").expect("Should generate proof")
+        ));
+    }
+
+    #[test]
+    fn test_new_keccak256() {
+        let data = [b"a", b"b", b"c", b"d", b"e"];
+        let tree = MerkleTree::new_keccak256(&data).expect("Should create merkle tree");
+
+        // Computed with an independent Keccak-f[1600] implementation, using the 0x01 padding
+        assert_eq!(
+            tree.leaf(0),
+            Some(from_hex(
+                "3ac225168df54212a25c1c01fd35bebfea408fdac2e31ddd6f80a4bbf9a5f1cb"
+            ))
+        );
+        assert_eq!(
+            tree.root(),
+            from_hex("e467c863b0e6b42bf57865b1d965372858094a175d68652a3d158954f688e22f")
+        );
+        assert_ne!(
+            tree.root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+        for element in &data {
+            let proof = tree.generate_proof(element).expect("Should generate proof");
+            assert!(tree.verify_proof(element, &proof));
+        }
+    }
+
+    #[test]
+    fn test_sorted_pairs() {
+        // Same as merkletreejs with `sortPairs: true` and keccak256 leaves
+        let cases: [(&[&[u8]], &str); 2] = [
+            (
+                &[b"a", b"b", b"c"],
+                "5842148bc6ebeb52af882a317c765fccd3ae80589b21a9b8cbf21abb630e46a7",
+            ),
+            (
+                &[b"a", b"b", b"c", b"d", b"e"],
+                "1dd0d2a6ae466d665cb26e1a31f07c57ae5df7d2bc559cd5826d417be9141a5d",
+            ),
+        ];
+        for (data, root) in cases {
+            let tree = MerkleTree::<Keccak256>::with_sorted_pairs(data)
+                .expect("Should create merkle tree");
+            assert!(tree.has_sorted_pairs());
+            assert_eq!(tree.root(), from_hex(root));
+            for element in data {
+                let proof = tree
+                    .generate_sorted_proof(element)
+                    .expect("Should generate proof");
+                assert!(tree.verify_sorted_proof(element, &proof));
+                let proof = tree.generate_proof(element).expect("Should generate proof");
+                assert!(tree.verify_proof(element, &proof));
+            }
+        }
+
+        let data = [b"a", b"b", b"c"];
+        let tree =
+            MerkleTree::<Keccak256>::with_sorted_pairs(&data).expect("Should create merkle tree");
+        let proof = tree
+            .generate_sorted_proof(b"a")
+            .expect("Should generate proof");
+        assert!(!tree.verify_sorted_proof(b"b", &proof));
+
+        // Without sorted pairs the order of each pair is needed
+        let unsorted = MerkleTree::new_keccak256(&data).expect("Should create merkle tree");
+        let proof = unsorted
+            .generate_sorted_proof(b"a")
+            .expect("Should generate proof");
+        assert!(!unsorted.verify_sorted_proof(b"a", &proof));
+
+        // The root of the README of merkletreejs, SHA-256 leaves whose odd nodes are promoted
+        #[cfg(feature = "sha2")]
+        {
+            let mut readme = MerkleTree::<Sha256> {
+                odd_node_policy: OddNodePolicy::PromoteToNextLevel,
+                ..MerkleTree::unbuilt(vec![])
+            };
+            readme.leaves = Arc::new(
+                data.iter()
+                    .map(|element| readme.hash_leaf(element))
+                    .collect(),
+            );
+            readme.build();
+            assert_eq!(
+                readme.root(),
+                from_hex("7075152d03a5cd92104887b476862778ec0c87be5c2fa1c0a90f87c49fad6eff")
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "blake3")]
+    fn test_blake3() {
+        // BLAKE3 of the empty input, from the reference test vectors
+        let empty = MerkleTree::<Blake3>::with_digest(&[b""]).expect("Should create merkle tree");
+        assert_eq!(
+            empty.root(),
+            from_hex("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262")
+        );
+
+        let data = [b"a", b"b", b"c", b"d", b"e"];
+        let mut tree =
+            MerkleTree::<Blake3>::with_digest(&data[..1]).expect("Should create merkle tree");
+        for element in &data[1..] {
+            tree.add(element).expect("Should add leaf");
+        }
+        let leaves: Vec<Hash> = data
+            .iter()
+            .map(|element| Blake3.hash_parts(&[*element]))
+            .collect();
+        let left = Blake3.hash_parts(&[
+            &Blake3.hash_parts(&[&leaves[0], &leaves[1]]),
+            &Blake3.hash_parts(&[&leaves[2], &leaves[3]]),
+        ]);
+        let right = Blake3.hash_parts(&[&leaves[4], &leaves[4]]);
+        let right = Blake3.hash_parts(&[&right, &right]);
+        assert_eq!(tree.root(), Blake3.hash_parts(&[&left, &right]));
+        assert_eq!(
+            tree.root(),
+            MerkleTree::with_algorithm(HashAlgorithm::Blake3, &data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+        for element in &data {
+            let proof = tree.generate_proof(element).expect("Should generate proof");
+            assert!(tree.verify_proof(element, &proof));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "blake3")]
+    fn test_keyed_blake3() {
+        let data = [b"a", b"b", b"c"];
+        let tenant1 = MerkleTree::new_keyed([1u8; 32], &data).expect("Should create merkle tree");
+        let tenant2 = MerkleTree::new_keyed([2u8; 32], &data).expect("Should create merkle tree");
+        assert_eq!(
+            tenant1.leaf(0),
+            Some(*blake3::keyed_hash(&[1u8; 32], b"a").as_bytes())
+        );
+        assert_ne!(tenant1.root(), tenant2.root());
+        assert!(!format!("{:?}", tenant1).contains("[1, 1"));
+
+        for element in &data {
+            let proof = tenant1
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(tenant1.verify_proof(element, &proof));
+            assert!(!tenant2.verify_proof(element, &proof));
+        }
+        assert!(matches!(
+            tenant1.merge(tenant2),
+            Err(MerkleError::IncompatibleTrees)
+        ));
+
+        let context = "rusty-merkle-tree tests";
+        let derived =
+            MerkleTree::new_derive_key(context, &data).expect("Should create merkle tree");
+        let other =
+            MerkleTree::new_derive_key("other context", &data).expect("Should create merkle tree");
+        assert_eq!(derived.leaf(2), Some(blake3::derive_key(context, b"c")));
+        assert_ne!(derived.root(), other.root());
+        let proof = derived.generate_proof(b"b").expect("Should generate proof");
+        assert!(derived.verify_proof(b"b", &proof));
+        assert!(!other.verify_proof(b"b", &proof));
+    }
+
+    #[test]
+    fn test_domain_separation_prevents_forgery() {
+        let data = vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()];
+
+        // Without prefixes, the concatenation of two leaves hashes to their parent,
+        // so it passes as a member with the proof of the parent
+        let legacy = MerkleTree::new(&data).expect("Should create merkle tree");
+        let forged = [legacy.tree[0][0], legacy.tree[0][1]].concat();
+        let forged_proof = MerkleProof::from(vec![(legacy.tree[1][1], Direction::Right)]);
+        assert!(!legacy.has_domain_separation());
+        assert!(verify(&legacy.root(), &forged, &forged_proof));
+        // The tree itself refuses a proof shorter than the height of the tree
+        assert!(matches!(
+            legacy.verify_proof_strict(&forged, &forged_proof),
+            Err(MerkleError::InvalidProofShape)
+        ));
+
+        let separated =
+            MerkleTree::with_domain_separation(&data).expect("Should create merkle tree");
+        let forged = [separated.tree[0][0], separated.tree[0][1]].concat();
+        let forged_proof = MerkleProof::from(vec![(separated.tree[1][1], Direction::Right)]);
+        assert!(separated.has_domain_separation());
+        assert!(!separated.verify_proof(&forged, &forged_proof));
 
-            current_index /= 2;
+        assert_eq!(
+            separated.leaf(0),
+            Some(hash([&[LEAF_PREFIX], &data[0][..]].concat()))
+        );
+        assert_eq!(
+            separated.tree[1][0],
+            hash(
+                [
+                    &[NODE_PREFIX],
+                    &separated.tree[0][0][..],
+                    &separated.tree[0][1][..]
+                ]
+                .concat()
+            )
+        );
+        assert_ne!(separated.root(), legacy.root());
+        for element in &data {
+            let proof = separated
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(separated.verify_proof(element, &proof));
         }
+    }
 
-        Ok(proof)
+    #[test]
+    fn test_promotion_prevents_duplicate_last_malleability() {
+        let data: Vec<Vec<u8>> = (1..=5)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let mut repeated = data.clone();
+        repeated.push(data[4].clone());
+
+        // Pairing the lone node with itself can't tell both leaf sets apart
+        let five = MerkleTree::new(&data).expect("Should create merkle tree");
+        let six = MerkleTree::new(&repeated).expect("Should create merkle tree");
+        assert_eq!(five.root(), six.root());
+
+        let policy = OddNodePolicy::PromoteToNextLevel;
+        let five =
+            MerkleTree::with_odd_node_policy(&data, policy).expect("Should create merkle tree");
+        let six =
+            MerkleTree::with_odd_node_policy(&repeated, policy).expect("Should create merkle tree");
+        assert_ne!(five.root(), six.root());
+        for tree in [&five, &six] {
+            for element in &data {
+                let proof = tree.generate_proof(element).expect("Should generate proof");
+                assert!(tree.verify_proof(element, &proof));
+            }
+        }
+        let proof = six
+            .generate_proof_by_index(5)
+            .expect("Should generate proof");
+        assert!(six.verify_proof(&data[4], &proof));
+        assert!(!five.verify_proof(&data[4], &proof));
     }
 
-    /// Validates a Merkle proof for a given piece of data
-    /// Returns true if the proof is valid, false otherwise
-    pub fn verify_proof<T: AsRef<[u8]>>(&self, data: &T, proof: &MerkleProof) -> bool {
-        // First hash the data
-        let mut current_hash = hash(data);
-
-        // Get the current root
-        let root = self.root();
-
-        // Work up from the leaf to the root using the proof
-        for (sibling_hash, sibling_direction) in proof {
-            current_hash = match sibling_direction {
-                Direction::Left => hash_internal_node(sibling_hash, &current_hash),
-                Direction::Right => hash_internal_node(&current_hash, sibling_hash),
-            };
+    #[test]
+    fn test_committed_root() {
+        let data: Vec<Vec<u8>> = (1..=4)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+
+        // A zero leaf is indistinguishable from the missing ones of a fixed-depth tree
+        let four = MerkleTree::with_depth(3, &data).expect("Should create merkle tree");
+        let mut padded = four.clone();
+        padded
+            .extend_leaves(vec![ZERO_HASH])
+            .expect("Should add leaf");
+        assert_eq!(four.root(), padded.root());
+        assert_ne!(four.committed_root(), padded.committed_root());
+        assert_eq!(
+            four.committed_root(),
+            hash([&4u64.to_le_bytes()[..], &four.root()].concat())
+        );
+
+        for policy in ALL_POLICIES {
+            for count in 1..=9 {
+                let data: Vec<Vec<u8>> = (0..count)
+                    .map(|i| format!("block{}", i).into_bytes())
+                    .collect();
+                let tree = MerkleTree::with_odd_node_policy(&data, policy)
+                    .expect("Should create merkle tree");
+                let committed = tree.committed_root();
+                for (index, element) in data.iter().enumerate() {
+                    let proof = tree
+                        .generate_proof_by_index(index)
+                        .expect("Should generate proof");
+                    assert_eq!(tree.implied_index(&proof, count), Some(index));
+                    assert!(tree.verify_committed_proof(element, &proof, &committed, count));
+                    assert!(!tree.verify_committed_proof(element, &proof, &committed, count + 1));
+                }
+            }
         }
 
-        // The final hash should match the root
-        current_hash == root
+        // A proof from a deeper tree doesn't have the shape of a 4-leaf proof
+        let deep = MerkleTree::new(&[data.clone(), data.clone()].concat())
+            .expect("Should create merkle tree");
+        let proof = deep
+            .generate_proof(&data[0])
+            .expect("Should generate proof");
+        let committed = MerkleTree::new(&data)
+            .expect("Should create merkle tree")
+            .committed_root();
+        assert!(!deep.verify_committed_proof(&data[0], &proof, &committed, 4));
+        assert!(!deep.verify_committed_proof(&data[0], &proof, &deep.committed_root(), 0));
+        assert!(deep.verify_committed_proof(&data[0], &proof, &deep.committed_root(), 8));
     }
 
-    /// Add a new element to the tree
-    /// This will add a new leaf to the tree and rebuild it
-    pub fn add(&mut self, data: Vec<u8>) {
-        // Add to the leaves and rebuild the tree
-        self.leaves.push(hash(&data));
-        self.build();
-    }
-}
+    #[test]
+    fn test_with_key() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+        let tenant1 =
+            MerkleTree::with_key(b"tenant-1 secret", &data).expect("Should create merkle tree");
+        let tenant2 =
+            MerkleTree::with_key(b"tenant-2 secret", &data).expect("Should create merkle tree");
+        assert_ne!(tenant1.root(), tenant2.root());
+        assert_ne!(
+            tenant1.root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
 
-#[cfg(test)]
-mod tests {
+        let mut mac =
+            Hmac::<Sha3_256>::new_from_slice(b"tenant-1 secret").expect("Should accept key");
+        mac.update(&data[0]);
+        let expected: Hash = mac.finalize().into_bytes().into();
+        assert_eq!(tenant1.leaf(0), Some(expected));
+        assert_eq!(
+            tenant1.tree[1][0],
+            hash_internal_node(&tenant1.tree[0][0], &tenant1.tree[0][1])
+        );
 
-    use super::*;
+        for element in &data {
+            let proof = tenant1
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(tenant1.verify_proof(element, &proof));
+            assert!(!tenant2.verify_proof(element, &proof));
+        }
+        let key_bytes = format!("{:?}", b"tenant-1 secret".to_vec());
+        assert!(!format!("{:?}", tenant1).contains(&key_bytes[1..key_bytes.len() - 1]));
+        assert!(format!("{:?}", tenant1).contains("HmacSha3(..)"));
+        assert!(matches!(
+            tenant1.merge(tenant2),
+            Err(MerkleError::IncompatibleTrees)
+        ));
+    }
 
     #[test]
-    fn test_build_tree() {
-        // Test data
-        let data = vec![
-            b"block1".to_vec(),
-            b"block2".to_vec(),
-            b"block3".to_vec(),
-            b"block4".to_vec(),
+    fn test_leaf_hashing() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+        // Computed with Python's hashlib
+        let expected = [
+            (
+                LeafHashing::Single,
+                "4e43ad30c63b7c4518092dec9e00b8ddf39823dec781561997298454db4ce057",
+            ),
+            (
+                LeafHashing::Double,
+                "28327b0358572057bf3967ace1870228fa12467ad2b4ffc7b62c754594a270dd",
+            ),
         ];
+        for (leaf_hashing, root) in expected {
+            let mut tree = MerkleTree::with_leaf_hashing(&data[..2], leaf_hashing)
+                .expect("Should create merkle tree");
+            tree.add(&data[2]).expect("Should add leaf");
+            assert_eq!(tree.leaf_hashing(), leaf_hashing);
+            assert_eq!(tree.root(), from_hex(root));
+            for element in &data {
+                let proof = tree.generate_proof(element).expect("Should generate proof");
+                assert!(tree.verify_proof(element, &proof));
+            }
+        }
 
-        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        let double = MerkleTree::with_leaf_hashing(&data, LeafHashing::Double)
+            .expect("Should create merkle tree");
+        assert_eq!(double.leaf(0), Some(hash(hash(&data[0]))));
+        // The single hash of the data is not a leaf
+        assert!(matches!(
+            double.generate_proof_for_hash(&hash(&data[0])),
+            Err(MerkleError::LeafNotFound)
+        ));
+        assert_eq!(
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .leaf_hashing(),
+            LeafHashing::Single
+        );
+    }
 
-        let leaf1 = hash(&data[0]);
-        let leaf2 = hash(&data[1]);
-        let leaf3 = hash(&data[2]);
-        let leaf4 = hash(&data[3]);
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_normalize_nfc() {
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        assert_ne!(hash(composed), hash(decomposed));
 
-        // Calculate internal nodes
-        let internal1 = hash_internal_node(&leaf1, &leaf2);
-        let internal2 = hash_internal_node(&leaf3, &leaf4);
+        let tree = MerkleTree::with_normalize_nfc(&[composed, "tea"], true)
+            .expect("Should create merkle tree");
+        assert!(tree.normalize_nfc());
+        assert_eq!(tree.hash_leaf(composed), tree.hash_leaf(decomposed));
+        assert_eq!(tree.leaf(0), Some(hash(composed)));
+        let other = MerkleTree::with_normalize_nfc(&[decomposed, "tea"], true)
+            .expect("Should create merkle tree");
+        assert_eq!(other.root(), tree.root());
 
-        // Calculate root
-        let _root = hash_internal_node(&internal1, &internal2);
+        // Proofs are interchangeable between both forms
+        let proof = tree
+            .generate_proof(decomposed)
+            .expect("Should generate proof");
+        assert!(tree.verify_proof(composed, &proof));
+        assert!(tree.verify_proof(decomposed, &proof));
+        assert_eq!(
+            proof,
+            tree.generate_proof(composed)
+                .expect("Should generate proof")
+        );
 
-        assert_eq!(_root, merkle.root());
+        let mut grown =
+            MerkleTree::with_normalize_nfc(&["tea"], true).expect("Should create merkle tree");
+        grown.add(decomposed).expect("Should add leaf");
+        assert_eq!(grown.leaf(1), Some(hash(composed)));
 
-        // A merkle tree built from 4 elements should have 7 nodes and 3 levels
-        assert_eq!(merkle.tree.len(), 3);
-        let total_nodes: usize = merkle.tree.iter().map(|level| level.len()).sum();
-        assert_eq!(total_nodes, 7);
+        // Non-UTF-8 data is hashed unchanged
+        let bytes = [0xff, 0xfe];
+        assert_eq!(tree.hash_leaf(&bytes), hash(bytes));
 
-        // Create an expected tree and compare it to the actual tree
-        let expected_tree = vec![
-            vec![leaf1, leaf2, leaf3, leaf4],
-            vec![internal1, internal2],
-            vec![_root],
-        ];
+        // Without normalization the forms stay distinct
+        let plain = MerkleTree::with_normalize_nfc(&[composed, "tea"], false)
+            .expect("Should create merkle tree");
+        assert!(!plain.normalize_nfc());
+        assert_eq!(
+            plain.root(),
+            MerkleTree::new(&[composed, "tea"])
+                .expect("Should create merkle tree")
+                .root()
+        );
+        assert!(matches!(
+            plain.generate_proof(decomposed),
+            Err(MerkleError::LeafNotFound)
+        ));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize_key_holders() {
+        fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+        assert_zeroize_on_drop::<HmacSha3>();
+        #[cfg(feature = "blake3")]
+        assert_zeroize_on_drop::<KeyedBlake3>();
+
+        let tree =
+            MerkleTree::with_key(b"api-token", &[b"block1"]).expect("Should create merkle tree");
+        let mut hasher = tree.hasher.clone();
+        assert_eq!(hasher.key, b"api-token");
+        hasher.zeroize();
+        assert!(hasher.key.is_empty());
+
+        #[cfg(feature = "blake3")]
+        {
+            let mut keyed = KeyedBlake3::Keyed([7u8; 32]);
+            keyed.zeroize();
+            assert!(matches!(keyed, KeyedBlake3::Keyed(key) if key == [0u8; 32]));
+            let mut derived = KeyedBlake3::DeriveKey("tenant".to_string());
+            derived.zeroize();
+            assert!(matches!(&derived, KeyedBlake3::DeriveKey(context) if context.is_empty()));
+        }
 
-        assert_eq!(expected_tree, merkle.tree);
+        // Owned copies are wiped, borrowed data is untouched
+        wipe(Cow::Owned(b"secret".to_vec()));
+        let data = b"secret".to_vec();
+        wipe(Cow::Borrowed(&data));
+        assert_eq!(data, b"secret");
     }
 
     #[test]
-    fn test_build_tree_odd_number() {
-        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+    fn test_hash_comparisons() {
+        let a = hash(b"block1");
+        let mut b = a;
+        assert!(hashes_equal(&a, &b));
+        // Differences in the first and last byte are both caught
+        b[0] ^= 1;
+        assert!(!hashes_equal(&a, &b));
+        b = a;
+        b[31] ^= 0x80;
+        assert!(!hashes_equal(&a, &b));
 
-        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        let hashes = [hash(b"block1"), hash(b"block2"), hash(b"block1")];
+        assert_eq!(position_of(&hashes, &hash(b"block1")), Some(0));
+        assert_eq!(position_of(&hashes, &hash(b"block2")), Some(1));
+        assert_eq!(position_of(&hashes, &hash(b"block3")), None);
+        assert_eq!(position_of::<32>(&[], &hash(b"block1")), None);
+    }
 
-        let leaf1 = hash(&data[0]);
-        let leaf2 = hash(&data[1]);
-        let leaf3 = hash(&data[2]);
+    #[test]
+    fn test_verification_unchanged_by_constant_time_comparisons() {
+        let data: Vec<Vec<u8>> = (0..7).map(|i| format!("block{}", i).into_bytes()).collect();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        for (index, element) in data.iter().enumerate() {
+            assert_eq!(tree.index_of(element), Some(index));
+            let proof = tree.generate_proof(element).expect("Should generate proof");
+            assert!(tree.verify_proof(element, &proof));
+            let mut tampered = Vec::from(proof);
+            tampered[0].0[31] ^= 1;
+            assert!(!tree.verify_proof(element, &tampered.into()));
+        }
+        assert_eq!(tree.index_of(b"block7"), None);
 
-        let internal1 = hash_internal_node(&leaf1, &leaf2);
-        let internal2 = hash_internal_node(&leaf3, &leaf3);
+        let canonical = MerkleTree::canonical(&data).expect("Should create merkle tree");
+        for element in &data {
+            let index = canonical.index_of(element).expect("Should find leaf");
+            assert_eq!(canonical.leaf(index), Some(hash(element)));
+        }
+        assert_eq!(canonical.index_of(b"block7"), None);
 
-        let root = hash_internal_node(&internal1, &internal2);
+        let kary = MerkleTree::with_arity(3, &data).expect("Should create merkle tree");
+        let proof = kary
+            .generate_kary_proof(&data[4])
+            .expect("Should generate proof");
+        assert!(kary.verify_kary_proof(&data[4], &proof));
+        assert!(!kary.verify_kary_proof(&data[5], &proof));
 
-        assert_eq!(root, merkle.root());
+        let committed = tree.committed_root();
+        let proof = tree
+            .generate_proof(&data[2])
+            .expect("Should generate proof");
+        assert!(tree.verify_committed_proof(&data[2], &proof, &committed, data.len()));
+        assert!(!tree.verify_committed_proof(&data[2], &proof, &tree.root(), data.len()));
+    }
 
-        let expected_tree = vec![
-            vec![leaf1, leaf2, leaf3],
-            vec![internal1, internal2],
-            vec![root],
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_dummy_roots() {
+        // Computed with Python's hashlib
+        let expected = [
+            (
+                1,
+                "aa6c290f56f0f7eb3a8da563ae72efc5d10cc89b88a3112c612d8e3825e20aad",
+            ),
+            (
+                2,
+                "88635701f78007f7b2baf6f8d2cdf261162232a79d4fbd8577d1e2d2bac47523",
+            ),
+            (
+                5,
+                "a5fdf24139340ba9797fecad0cf74d4137dbaa38c18430f80d5d6915695b01cd",
+            ),
+            (
+                100,
+                "df0b8d5b9c68dadd90983e06a5edd0329543d0dd3f28fcc33f294e294b454f02",
+            ),
         ];
+        for (n, root) in expected {
+            let tree = MerkleTree::dummy(n);
+            assert_eq!(tree.len(), n);
+            assert_eq!(tree.root(), from_hex(root));
+            let data: Vec<Vec<u8>> = (0..n).map(MerkleTree::dummy_leaf).collect();
+            assert_eq!(
+                tree.root(),
+                MerkleTree::new(&data)
+                    .expect("Should create merkle tree")
+                    .root()
+            );
+            let last = MerkleTree::dummy_leaf(n - 1);
+            let proof = tree.generate_proof(&last).expect("Should generate proof");
+            assert!(tree.verify_proof(&last, &proof));
+        }
+        assert_eq!(MerkleTree::dummy_leaf(7), b"leaf-7");
+        assert!(MerkleTree::dummy(0).is_empty());
+        assert_eq!(MerkleTree::dummy(0).root(), empty_root());
+    }
+
+    #[test]
+    fn test_proof_by_index_matches_proof_by_data() {
+        for size in [1, 2, 3, 5, 8, 13, 33] {
+            let data: Vec<Vec<u8>> = (0..size)
+                .map(|i| format!("block{}", i).into_bytes())
+                .collect();
+            let trees = [
+                MerkleTree::new(&data).expect("Should create merkle tree"),
+                MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PromoteToNextLevel)
+                    .expect("Should create merkle tree"),
+                MerkleTree::with_depth(6, &data).expect("Should create merkle tree"),
+            ];
+            for tree in &trees {
+                for (index, element) in data.iter().enumerate() {
+                    let proof = tree
+                        .generate_proof_by_index(index)
+                        .expect("Should generate proof");
+                    assert_eq!(
+                        proof,
+                        tree.generate_proof(element).expect("Should generate proof")
+                    );
+                    assert!(tree.verify_proof(element, &proof));
+                }
+                assert!(matches!(
+                    tree.generate_proof_by_index(size),
+                    Err(MerkleError::IndexOutOfBounds)
+                ));
+            }
+        }
 
-        assert_eq!(expected_tree, merkle.tree);
+        // The last leaf of an odd level is its own sibling
+        let data = [b"a", b"b", b"c"];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = tree
+            .generate_proof_by_index(2)
+            .expect("Should generate proof");
+        assert_eq!(proof.iter().next(), Some((&hash(b"c"), Direction::Right)));
+        assert!(tree.verify_proof(b"c", &proof));
+        assert!(matches!(
+            MerkleTree::empty().generate_proof_by_index(0),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
     }
 
     #[test]
-    fn test_generate_proof() {
-        let data = vec![
-            b"block1".to_vec(),
-            b"block2".to_vec(),
-            b"block3".to_vec(),
-            b"block4".to_vec(),
-        ];
+    fn test_stateless_verify() {
+        let data = vec!["a", "b", "c", "d", "e"];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let root = tree.root();
 
-        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        for element in &data {
+            let proof = tree.generate_proof(element).expect("Should generate proof");
+            assert!(verify(&root, element, &proof));
+            assert!(verify_leaf(&root, &hash(element), &proof));
+            assert_eq!(
+                verify(&root, element, &proof),
+                tree.verify_proof(element, &proof)
+            );
+        }
 
-        let proof = merkle
-            .generate_proof(&data[1])
+        let proof = tree.generate_proof(&"c").expect("Should generate proof");
+        assert!(!verify(&root, "x", &proof));
+
+        let other = MerkleTree::new(&["a", "b", "c", "d", "f"]).expect("Should create merkle tree");
+        assert!(!verify(&other.root(), "c", &proof));
+        assert!(!verify_leaf(&other.root(), &hash("c"), &proof));
+    }
+
+    #[test]
+    fn test_proof_struct() {
+        let data = [b"a", b"b", b"c", b"d", b"e"];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = tree
+            .generate_proof_by_index(1)
             .expect("Should generate proof");
+        assert_eq!(proof.len(), 3);
+        assert!(!proof.is_empty());
+        assert_eq!(proof.iter().next(), Some((&hash(b"a"), Direction::Left)));
 
-        assert_eq!(proof.len(), 2);
+        let mut rebuilt = MerkleProof::new();
+        assert!(rebuilt.is_empty());
+        for (sibling, direction) in &proof {
+            rebuilt.push(*sibling, direction);
+        }
+        assert_eq!(rebuilt, proof);
+        assert!(tree.verify_proof(b"b", &rebuilt));
 
-        let leaf1 = hash(&data[0]);
-        let leaf2 = hash(&data[1]);
-        let leaf3 = hash(&data[2]);
-        let leaf4 = hash(&data[3]);
+        let siblings: Vec<([u8; 32], Direction)> = proof.clone().into();
+        assert_eq!(MerkleProof::from(siblings.clone()), proof);
+        assert_eq!(proof.into_iter().collect::<Vec<_>>(), siblings);
+    }
 
-        // Calculate internal nodes
-        let _internal1 = hash_internal_node(&leaf1, &leaf2);
-        let internal2 = hash_internal_node(&leaf3, &leaf4);
+    #[test]
+    fn test_proof_compute_root() {
+        let data = [b"a", b"b", b"c", b"d", b"e"];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        for element in &data {
+            let proof = tree.generate_proof(element).expect("Should generate proof");
+            assert_eq!(proof.compute_root(element), tree.root());
+            assert_eq!(proof.compute_root_from_leaf(&hash(element)), tree.root());
+            assert!(proof.verify(&tree.root(), element));
+        }
 
-        // Left and the other is right
-        let expected_proof = vec![(leaf1, Direction::Left), (internal2, Direction::Right)];
+        let proof = tree.generate_proof(&b"c").expect("Should generate proof");
+        let mut tampered = Vec::from(proof);
+        tampered[1].0[0] ^= 1;
+        let tampered = MerkleProof::from(tampered);
+        let reconstructed = tampered.compute_root(b"c");
+        assert_ne!(reconstructed, tree.root());
+        assert_eq!(
+            reconstructed,
+            tree.root_from_proof(hash(b"c"), &tampered),
+            "The wrong root should be the one the tree computes"
+        );
+        assert!(!tampered.verify(&tree.root(), b"c"));
+    }
 
-        // Print the tree by levels and print the proof
-        for level in merkle.tree.iter() {
-            println!("{:?}", level);
+    #[test]
+    fn test_proof_bytes_round_trip() {
+        let data = [b"a", b"b", b"c", b"d", b"e"];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        for element in &data {
+            let proof = tree.generate_proof(element).expect("Should generate proof");
+            let bytes = proof.to_bytes();
+            assert_eq!(bytes.len(), 5 + proof.len() * 33);
+            let decoded = MerkleProof::from_bytes(&bytes).expect("Should decode proof");
+            assert_eq!(decoded, proof);
+            assert!(tree.verify_proof(element, &decoded));
         }
 
-        println!("{:?}", proof);
+        let empty = MerkleProof::<32>::new();
+        assert_eq!(empty.to_bytes(), vec![1, 0, 0, 0, 0]);
+        assert_eq!(
+            MerkleProof::from_bytes(&empty.to_bytes()).expect("Should decode proof"),
+            empty
+        );
 
-        assert_eq!(proof, expected_proof);
+        let wide = MerkleProof::from(vec![([7u8; 64], Direction::Right)]);
+        assert_eq!(
+            MerkleProof::<64>::from_bytes(&wide.to_bytes()).expect("Should decode proof"),
+            wide
+        );
     }
 
     #[test]
-    fn test_generate_proof_edge_case() {
-        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+    fn test_proof_bytes_fixture() {
+        let tree = MerkleTree::new(&[b"a", b"b", b"c"]).expect("Should create merkle tree");
+        let proof = tree
+            .generate_proof_by_index(0)
+            .expect("Should generate proof");
+        // Version 1, two siblings: H(b) on the right, then H(H(c) || H(c)) on the right
+        let fixture = concat!(
+            "0102000000",
+            "b039179a8a4ce2c252aa6f2f25798251c19b75fc1508d9d511a191e0487d64a701",
+            "8567ac2c608bb6a5e183a4d8653f72cd45dd36bde181a2b6082878e90ea0285101",
+        );
+        let hex: String = proof
+            .to_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(hex, fixture);
+    }
 
-        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+    #[test]
+    fn test_proof_bytes_errors() {
+        let tree = MerkleTree::new(&[b"a", b"b", b"c"]).expect("Should create merkle tree");
+        let bytes = tree
+            .generate_proof_by_index(1)
+            .expect("Should generate proof")
+            .to_bytes();
 
-        let leaf1 = hash(&data[0]);
-        let leaf2 = hash(&data[1]);
-        let leaf3 = hash(&data[2]);
+        for length in 0..bytes.len() {
+            assert!(matches!(
+                MerkleProof::<32>::from_bytes(&bytes[..length]),
+                Err(MerkleError::TruncatedInput)
+            ));
+        }
 
-        let internal1 = hash_internal_node(&leaf1, &leaf2);
-        let internal2 = hash_internal_node(&leaf3, &leaf3);
+        let mut other_version = bytes.clone();
+        other_version[0] = 2;
+        assert!(matches!(
+            MerkleProof::<32>::from_bytes(&other_version),
+            Err(MerkleError::UnknownFormatVersion(2))
+        ));
 
-        let _root = hash_internal_node(&internal1, &internal2);
+        let mut too_long = bytes.clone();
+        too_long[1..5].copy_from_slice(&(MAX_PROOF_LEN as u32 + 1).to_le_bytes());
+        assert!(matches!(
+            MerkleProof::<32>::from_bytes(&too_long),
+            Err(MerkleError::ProofTooLong)
+        ));
+        too_long[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            MerkleProof::<32>::from_bytes(&too_long),
+            Err(MerkleError::ProofTooLong)
+        ));
 
-        let proof = merkle
-            .generate_proof(&data[1])
-            .expect("Should generate proof");
+        let mut bad_direction = bytes.clone();
+        let last = bad_direction.len() - 1;
+        bad_direction[last] = 2;
+        assert!(matches!(
+            MerkleProof::<32>::from_bytes(&bad_direction),
+            Err(MerkleError::InvalidEncoding)
+        ));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            MerkleProof::<32>::from_bytes(&trailing),
+            Err(MerkleError::InvalidEncoding)
+        ));
+    }
+
+    #[test]
+    fn test_compact_proof_round_trip() {
+        let empty = MerkleProof::<32>::new();
+        assert_eq!(empty.to_compact_bytes(), vec![0]);
+        assert_eq!(
+            MerkleProof::from_compact_bytes(&[0]).expect("Should decode proof"),
+            empty
+        );
 
-        let expected_proof = vec![(leaf1, Direction::Left), (internal2, Direction::Right)];
+        for depth in 1..=20usize {
+            let proof: MerkleProof = (0..depth)
+                .map(|level| match level % 3 {
+                    0 => ([level as u8; 32], Direction::Right),
+                    _ => ([level as u8; 32], Direction::Left),
+                })
+                .collect();
 
-        assert_eq!(proof, expected_proof);
+            let bytes = proof.to_compact_bytes();
+            assert_eq!(
+                bytes.len(),
+                1 + depth.div_ceil(8) + depth * 32,
+                "depth {}",
+                depth
+            );
+            let decoded = MerkleProof::from_compact_bytes(&bytes).expect("Should decode proof");
+            assert_eq!(decoded, proof, "depth {}", depth);
+        }
     }
 
     #[test]
-    fn test_verify_proof() {
-        let data = vec![
-            b"block1".to_vec(),
-            b"block2".to_vec(),
-            b"block3".to_vec(),
-            b"block4".to_vec(),
-        ];
+    fn test_compact_proof_errors() {
+        let proof: MerkleProof = (0..9)
+            .map(|level| ([level as u8; 32], Direction::Right))
+            .collect();
+        let bytes = proof.to_compact_bytes();
+        assert_eq!(&bytes[..3], &[9, 0xff, 0x01]);
 
-        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+        for length in 0..bytes.len() {
+            assert!(matches!(
+                MerkleProof::<32>::from_compact_bytes(&bytes[..length]),
+                Err(MerkleError::TruncatedInput)
+            ));
+        }
 
-        // Generate and verify proof for "block2"
-        let proof = merkle
-            .generate_proof(&data[1])
-            .expect("Should generate proof");
-        assert!(merkle.verify_proof(&data[1], &proof));
+        let mut unused_bit = bytes.clone();
+        unused_bit[2] |= 0x02;
+        assert!(matches!(
+            MerkleProof::<32>::from_compact_bytes(&unused_bit),
+            Err(MerkleError::InvalidEncoding)
+        ));
 
-        // Verify with wrong data (should fail)
-        assert!(!merkle.verify_proof(b"wrong_data", &proof));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            MerkleProof::<32>::from_compact_bytes(&trailing),
+            Err(MerkleError::InvalidEncoding)
+        ));
 
-        // Verify with wrong proof (should fail)
-        let wrong_proof = merkle
-            .generate_proof(&data[2])
-            .expect("Should generate proof");
-        assert!(!merkle.verify_proof(&data[1], &wrong_proof));
+        assert!(matches!(
+            MerkleProof::<32>::from_compact_bytes(&[MAX_PROOF_LEN as u8 + 1]),
+            Err(MerkleError::ProofTooLong)
+        ));
     }
 
     #[test]
-    fn test_verify_proof_edge_case() {
-        let data = vec![b"block1".to_vec(), b"block2".to_vec(), b"block3".to_vec()];
+    fn test_compact_proof_verifies() {
+        let data: Vec<[u8; 4]> = (0..37u32).map(u32::to_le_bytes).collect();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        for (index, element) in data.iter().enumerate() {
+            let proof = tree
+                .generate_proof_by_index(index)
+                .expect("Should generate proof");
+            let decoded = MerkleProof::from_compact_bytes(&proof.to_compact_bytes())
+                .expect("Should decode proof");
+            assert!(tree.verify_proof(element, &decoded));
+        }
+    }
 
-        let merkle = MerkleTree::new(&data).expect("Should create merkle tree");
+    #[test]
+    fn test_size_estimates_match_generated_proofs() {
+        let mut tree = MerkleTree::new(&[0u32.to_le_bytes()]).expect("Should create merkle tree");
+        for size in 1..=1000usize {
+            if size > 1 {
+                tree.add((size as u32 - 1).to_le_bytes())
+                    .expect("Should add leaf");
+            }
+            assert_eq!(expected_depth(size), tree.tree.len() - 1);
+            for index in [0, size / 2, size - 1] {
+                let proof = tree
+                    .generate_proof_by_index(index)
+                    .expect("Should generate proof");
+                assert_eq!(proof.len(), expected_proof_len(size));
+                assert_eq!(
+                    proof.serialized_size(ProofEncoding::Plain),
+                    proof.to_bytes().len()
+                );
+                assert_eq!(
+                    proof.serialized_size(ProofEncoding::Compact),
+                    proof.to_compact_bytes().len()
+                );
+            }
+        }
+        assert_eq!(expected_depth(0), 0);
+        assert_eq!(expected_depth(usize::MAX), MAX_PROOF_LEN);
 
-        let proof = merkle
-            .generate_proof(&data[1])
+        // Proofs of a tree that promotes odd nodes skip levels
+        let data: Vec<[u8; 4]> = (0..5u32).map(u32::to_le_bytes).collect();
+        let tree = MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PromoteToNextLevel)
+            .expect("Should create merkle tree");
+        let proof = tree
+            .generate_proof_by_index(4)
             .expect("Should generate proof");
+        assert!(proof.len() < expected_proof_len(5));
+    }
 
-        assert!(merkle.verify_proof(&data[1], &proof));
+    #[test]
+    fn test_index_proof() {
+        for size in 1..=9 {
+            let data: Vec<String> = (0..size).map(|i| format!("block{}", i)).collect();
+            let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+            for (index, element) in data.iter().enumerate() {
+                let proof = tree
+                    .generate_index_proof(index)
+                    .expect("Should generate proof");
+                assert!(verify_index_proof(&tree.root(), index, element, &proof));
+                for other in (0..size).filter(|&other| other != index) {
+                    assert!(!verify_index_proof(&tree.root(), other, element, &proof));
+                }
+                assert!(!verify_index_proof(
+                    &tree.root(),
+                    index + (1 << proof.len()),
+                    element,
+                    &proof
+                ));
+            }
+        }
+        assert!(matches!(
+            MerkleTree::new(&[b"a"])
+                .expect("Should create merkle tree")
+                .generate_index_proof(1),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+    }
 
-        // Verify with wrong data (should fail)
-        assert!(!merkle.verify_proof(b"wrong_data", &proof));
+    #[test]
+    fn test_index_proof_duplicated_sibling() {
+        let data = [b"a", b"b", b"c"];
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = tree.generate_index_proof(2).expect("Should generate proof");
+        // The last leaf of the odd level is its own sibling
+        assert_eq!(proof[0], hash(b"c"));
+        assert!(verify_index_proof(&tree.root(), 2, b"c", &proof));
+        assert!(!verify_index_proof(&tree.root(), 0, b"c", &proof));
+        assert!(!verify_index_proof(&tree.root(), 1, b"c", &proof));
+        // Both sides of a node paired with itself give the same parent, so the duplicate
+        // verifies at the index it would have
+        assert!(verify_index_proof(&tree.root(), 3, b"c", &proof));
+
+        let promoted = MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PromoteToNextLevel)
+            .expect("Should create merkle tree");
+        assert!(matches!(
+            promoted.generate_index_proof(0),
+            Err(MerkleError::UnsupportedProof)
+        ));
     }
 
     #[test]
-    fn test_generic_data_types() {
-        // Test with different types that implement AsRef<[u8]>
-        let string_data = vec![
-            String::from("test1"),
-            String::from("test2"),
-            String::from("test3"),
-        ];
+    fn test_eth_abi_matches_reference_encoder() {
+        use ethabi::{ParamType, Token};
 
-        let bytes_data = vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
+        let data: Vec<String> = (0..11).map(|i| format!("leaf{}", i)).collect();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        for (index, item) in data.iter().enumerate() {
+            let proof = tree.generate_proof(item).expect("Should generate proof");
+            assert_eq!(proof.direction_index(), index as u64);
+            let siblings: Vec<Token> = proof
+                .to_bytes32_array()
+                .into_iter()
+                .map(|sibling| Token::FixedBytes(sibling.to_vec()))
+                .collect();
 
-        let str_data = vec!["hello", "world", "!"];
+            let expected =
+                ethabi::encode(&[Token::Array(siblings.clone()), Token::Uint(index.into())]);
+            assert_eq!(proof.to_eth_abi(), expected);
+            let kind = ParamType::Array(Box::new(ParamType::FixedBytes(32)));
+            let decoded = ethabi::decode(&[kind.clone(), ParamType::Uint(256)], &expected)
+                .expect("Should decode ABI");
+            assert_eq!(decoded[0], Token::Array(siblings.clone()));
 
-        // Test creation with different types
-        let string_merkle =
-            MerkleTree::new(&string_data).expect("Should create merkle tree from strings");
-        let bytes_merkle =
-            MerkleTree::new(&bytes_data).expect("Should create merkle tree from bytes");
-        let str_merkle = MerkleTree::new(&str_data).expect("Should create merkle tree from str");
+            let expected = ethabi::encode(&[Token::Array(siblings.clone())]);
+            assert_eq!(proof.to_eth_abi_sorted(), expected);
+            let decoded = ethabi::decode(&[kind], &expected).expect("Should decode ABI");
+            assert_eq!(decoded, vec![Token::Array(siblings)]);
+        }
+    }
 
-        // Test proof generation and verification with different types
-        let string_proof = string_merkle
-            .generate_proof(&string_data[0])
+    #[test]
+    fn test_eth_abi_fixture() {
+        let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+        let proof = tree.generate_proof(&"b").expect("Should generate proof");
+        let hex: String = proof
+            .to_eth_abi()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(
+            hex,
+            concat!(
+                "0000000000000000000000000000000000000000000000000000000000000040",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "80084bf2fba02475726feb2cab2d8215eab14bc6bdd8bfb2c8151257032ecd8b",
+                "8567ac2c608bb6a5e183a4d8653f72cd45dd36bde181a2b6082878e90ea02851",
+            )
+        );
+    }
+
+    #[test]
+    fn test_eth_abi_of_sorted_proof() {
+        let data = ["a", "b", "c", "d", "e"];
+        let tree =
+            MerkleTree::<Keccak256>::with_sorted_pairs(&data).expect("Should create merkle tree");
+        let proof = tree.generate_proof(&"d").expect("Should generate proof");
+        let sorted = tree
+            .generate_sorted_proof(&"d")
             .expect("Should generate proof");
-        assert!(string_merkle.verify_proof(&string_data[0], &string_proof));
+        assert_eq!(proof.to_bytes32_array(), sorted);
+        let abi = proof.to_eth_abi_sorted();
+        assert_eq!(abi.len(), 32 * (2 + sorted.len()));
+        assert_eq!(&abi[64..96], &sorted[0]);
+        assert!(MerkleProof::new().to_eth_abi_sorted()[32..]
+            .iter()
+            .all(|&byte| byte == 0));
+    }
 
-        let bytes_proof = bytes_merkle
-            .generate_proof(&bytes_data[1])
+    #[test]
+    fn test_witness_reconstructs_root() {
+        let data: Vec<String> = (0..13).map(|i| format!("leaf{}", i)).collect();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        for item in &data {
+            let proof = tree.generate_proof(item).expect("Should generate proof");
+            for depth in [proof.len(), 8, 32] {
+                let witness = proof.to_witness(depth).expect("Should fit the circuit");
+                assert_eq!(witness.siblings.len(), depth);
+                assert_eq!(witness.path_bits.len(), depth);
+                assert_eq!(witness.levels, proof.len());
+                assert!(witness.siblings[proof.len()..]
+                    .iter()
+                    .all(|s| *s == ZERO_HASH));
+                assert!(!witness.path_bits[proof.len()..].contains(&true));
+                assert_eq!(
+                    witness.compute_root(&Sha3_256::default(), &hash(item)),
+                    tree.root()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_witness_rejects_shallow_circuit() {
+        let data: Vec<String> = (0..9).map(|i| format!("leaf{}", i)).collect();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = tree
+            .generate_proof(&data[0])
             .expect("Should generate proof");
-        assert!(bytes_merkle.verify_proof(&bytes_data[1], &bytes_proof));
+        assert_eq!(proof.len(), 4);
+        assert!(matches!(
+            proof.to_witness(3),
+            Err(MerkleError::ProofTooLong)
+        ));
+        assert!(proof.to_witness(4).is_ok());
+    }
 
-        let str_proof = str_merkle
-            .generate_proof(&str_data[2])
+    #[test]
+    fn test_verify_proof_at_index() {
+        let data: Vec<String> = (0..11).map(|i| format!("leaf{}", i)).collect();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        for (index, item) in data.iter().enumerate() {
+            let proof = tree.generate_proof(item).expect("Should generate proof");
+            assert!(verify_proof_at_index(&tree.root(), index, item, &proof));
+            for wrong in (0..64).filter(|&wrong| wrong != index) {
+                assert!(
+                    !verify_proof_at_index(&tree.root(), wrong, item, &proof),
+                    "{} claimed at {}",
+                    index,
+                    wrong
+                );
+            }
+            assert!(!verify_proof_at_index(
+                &tree.root(),
+                usize::MAX,
+                item,
+                &proof
+            ));
+            assert!(!verify_proof_at_index(&tree.root(), index, "other", &proof));
+        }
+    }
+
+    #[test]
+    fn test_splice_proofs() {
+        let days: Vec<Vec<String>> = (0..5)
+            .map(|day| {
+                (0..day + 2)
+                    .map(|i| format!("day{}-record{}", day, i))
+                    .collect()
+            })
+            .collect();
+        let day_trees: Vec<MerkleTree> = days
+            .iter()
+            .map(|records| MerkleTree::new(records).expect("Should create merkle tree"))
+            .collect();
+        let top = MerkleTree::from_leaves(day_trees.iter().map(|tree| tree.root()).collect())
+            .expect("Should create merkle tree");
+
+        for (day, tree) in day_trees.iter().enumerate() {
+            let outer = top
+                .generate_proof_for_hash(&tree.root())
+                .expect("Should generate proof");
+            for (index, record) in days[day].iter().enumerate() {
+                let inner = tree.generate_proof(record).expect("Should generate proof");
+                let leaf = tree.leaf(index).expect("Should have leaf");
+                let proof = MerkleProof::splice(&leaf, inner.clone(), &tree.root(), outer.clone())
+                    .expect("Should splice proofs");
+                assert_eq!(proof.len(), inner.len() + outer.len());
+                assert!(verify(&top.root(), record, &proof));
+                assert!(!verify(&tree.root(), record, &proof));
+            }
+        }
+    }
+
+    #[test]
+    fn test_splice_rejects_mismatched_proofs() {
+        let first = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+        let second = MerkleTree::new(&["d", "e"]).expect("Should create merkle tree");
+        let top = MerkleTree::from_leaves(vec![first.root(), second.root()])
+            .expect("Should create merkle tree");
+        let inner = first.generate_proof(&"a").expect("Should generate proof");
+        let outer = top
+            .generate_proof_for_hash(&second.root())
             .expect("Should generate proof");
-        assert!(str_merkle.verify_proof(&str_data[2], &str_proof));
+        let leaf = first.leaf(0).expect("Should have leaf");
+        assert!(matches!(
+            MerkleProof::splice(&leaf, inner.clone(), &second.root(), outer),
+            Err(MerkleError::RootMismatch)
+        ));
+        let outer = top
+            .generate_proof_for_hash(&first.root())
+            .expect("Should generate proof");
+        let other = first.leaf(1).expect("Should have leaf");
+        assert!(matches!(
+            MerkleProof::splice(&other, inner, &first.root(), outer),
+            Err(MerkleError::RootMismatch)
+        ));
     }
 }
-
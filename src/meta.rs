@@ -0,0 +1,209 @@
+use crate::merkle::{Hash, Hashable, MerkleError, MerkleTree};
+
+/// A Merkle Tree with an optional metadata value attached to each leaf
+/// The metadata is never hashed, so the root is the same as the one of the wrapped tree,
+/// and it moves along with its leaf through every mutation
+#[derive(Debug, Clone)]
+pub struct MetaMerkleTree<M> {
+    tree: MerkleTree,
+    /// Metadata of each leaf, indexed like the leaves
+    meta: Vec<Option<M>>,
+}
+
+impl<M> MetaMerkleTree<M> {
+    /// Creates a tree over `data` without any metadata
+    ///
+    /// # Returns
+    /// A MetaMerkleTree instance if the data is not empty, otherwise an error
+    pub fn new<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        Self::from_tree(MerkleTree::new(data)?)
+    }
+
+    /// Creates a tree without any leaves
+    pub fn empty() -> Self {
+        Self::from_tree(MerkleTree::empty()).expect("The empty tree is not canonical")
+    }
+
+    /// Attaches metadata to the leaves of an existing tree, none of them has any yet
+    ///
+    /// # Returns
+    /// The tree, or `IncompatibleTrees` if `tree` is canonical, since its leaves move
+    /// when others are added
+    pub fn from_tree(tree: MerkleTree) -> Result<Self, MerkleError> {
+        if tree.is_canonical() {
+            return Err(MerkleError::IncompatibleTrees);
+        }
+        let meta = std::iter::repeat_with(|| None).take(tree.len()).collect();
+        Ok(MetaMerkleTree { tree, meta })
+    }
+
+    /// Returns the wrapped tree
+    pub fn tree(&self) -> &MerkleTree {
+        &self.tree
+    }
+
+    /// Returns the root hash, which doesn't depend on the metadata
+    pub fn root(&self) -> Hash {
+        self.tree.root()
+    }
+
+    /// Returns the number of leaves
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns the metadata of the leaf at `index`, or None if it has none
+    pub fn meta(&self, index: usize) -> Option<&M> {
+        self.meta.get(index)?.as_ref()
+    }
+
+    /// Replaces the metadata of the leaf at `index`
+    ///
+    /// # Returns
+    /// The previous metadata, or `IndexOutOfBounds` if there is no leaf at `index`
+    pub fn set_meta(&mut self, index: usize, meta: M) -> Result<Option<M>, MerkleError> {
+        let slot = self
+            .meta
+            .get_mut(index)
+            .ok_or(MerkleError::IndexOutOfBounds)?;
+        Ok(slot.replace(meta))
+    }
+
+    /// Iterates over the index, hash and metadata of every leaf that has metadata
+    pub fn iter_meta(&self) -> impl Iterator<Item = (usize, Hash, &M)> + '_ {
+        self.meta.iter().enumerate().filter_map(|(index, meta)| {
+            let leaf = self
+                .tree
+                .leaf(index)
+                .expect("Metadata is aligned with the leaves");
+            meta.as_ref().map(|meta| (index, leaf, meta))
+        })
+    }
+
+    /// Adds a new element without metadata, see `MerkleTree::add`
+    pub fn add<T: Hashable>(&mut self, data: T) -> Result<usize, MerkleError> {
+        self.insert(data, None)
+    }
+
+    /// Adds a new element with `meta` attached to its leaf, see `MerkleTree::add`
+    /// If the tree deduplicates its leaves and already has the element, `meta` is dropped
+    pub fn add_with_meta<T: Hashable>(&mut self, data: T, meta: M) -> Result<usize, MerkleError> {
+        self.insert(data, Some(meta))
+    }
+
+    /// Replaces the leaf at `index`, keeping its metadata, see `MerkleTree::update`
+    pub fn update<T: Hashable + ?Sized>(
+        &mut self,
+        index: usize,
+        data: &T,
+    ) -> Result<Hash, MerkleError> {
+        self.tree.update(index, data)
+    }
+
+    /// Removes the leaf at `index` along with its metadata, see `MerkleTree::remove`
+    ///
+    /// # Returns
+    /// The hash of the removed leaf and its metadata
+    pub fn remove(&mut self, index: usize) -> Result<(Hash, Option<M>), MerkleError> {
+        let removed = self.tree.remove(index)?;
+        Ok((removed, self.meta.remove(index)))
+    }
+
+    /// Shortens the tree to its first `n` leaves, dropping the metadata of the rest
+    pub fn truncate(&mut self, n: usize) {
+        self.tree.truncate(n);
+        self.meta.truncate(n);
+    }
+
+    fn insert<T: Hashable>(&mut self, data: T, meta: Option<M>) -> Result<usize, MerkleError> {
+        let len = self.tree.len();
+        let index = self.tree.add(data)?;
+        if self.tree.len() > len {
+            self.meta.insert(index, meta);
+        }
+        Ok(index)
+    }
+}
+
+impl<M> Default for MetaMerkleTree<M> {
+    /// The default tree is the empty tree
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct Record {
+        id: u64,
+        timestamp: u64,
+    }
+
+    fn record(id: u64) -> Record {
+        Record {
+            id,
+            timestamp: 1_700_000_000 + id,
+        }
+    }
+
+    #[test]
+    fn test_meta_does_not_change_root() {
+        let data: Vec<Vec<u8>> = (0..4).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut with_meta = MetaMerkleTree::empty();
+        for (id, element) in data.iter().enumerate() {
+            with_meta
+                .add_with_meta(element.clone(), record(id as u64))
+                .expect("Should add leaf");
+        }
+        let plain = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert_eq!(with_meta.root(), plain.root());
+
+        let entries: Vec<(usize, Hash, &Record)> = with_meta.iter_meta().collect();
+        assert_eq!(entries.len(), 4);
+        for (index, leaf, meta) in entries {
+            assert_eq!(Some(leaf), plain.leaf(index));
+            assert_eq!(meta, &record(index as u64));
+        }
+    }
+
+    #[test]
+    fn test_meta_alignment() {
+        let data: Vec<Vec<u8>> = (0..6).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut merkle = MetaMerkleTree::new(&data[..1]).expect("Should create merkle tree");
+        assert_eq!(merkle.meta(0), None);
+        for (id, element) in data.iter().enumerate().skip(1) {
+            merkle
+                .add_with_meta(element.clone(), record(id as u64))
+                .expect("Should add leaf");
+        }
+        merkle.set_meta(0, record(0)).expect("Should set metadata");
+
+        // Updating a leaf keeps its metadata
+        merkle.update(2, b"block9").expect("Should update leaf");
+        assert_eq!(merkle.meta(2), Some(&record(2)));
+
+        // Removing a leaf shifts the metadata of the following ones with them
+        let (_, removed) = merkle.remove(1).expect("Should remove leaf");
+        assert_eq!(removed, Some(record(1)));
+        assert_eq!(merkle.meta(1), Some(&record(2)));
+        assert_eq!(merkle.meta(4), Some(&record(5)));
+
+        merkle.truncate(3);
+        assert_eq!(merkle.meta(2), Some(&record(3)));
+        assert_eq!(merkle.meta(3), None);
+        let ids: Vec<u64> = merkle.iter_meta().map(|(_, _, meta)| meta.id).collect();
+        assert_eq!(ids, vec![0, 2, 3]);
+        assert!(matches!(
+            merkle.set_meta(3, record(3)),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+    }
+}
@@ -0,0 +1,1266 @@
+//! Proofs of several leaves at once
+//!
+//! The proofs of leaves of the same tree share the nodes above the point where their paths
+//! meet, and a node on the path of one leaf doesn't need to be sent as the sibling of another.
+//! A `MultiProof` carries every sibling that can't be computed from the proven leaves exactly
+//! once, level by level from the leaves up and left to right within a level.
+//!
+//! A `PartialMerkleTree` holds the same hashes with the leaves themselves, a pruned tree that
+//! recomputes its root on its own.
+//!
+//! Trees created with `with_sorted_pairs` also have a `SortedMultiProof`, the
+//! `(proof, proofFlags, leaves)` arguments of OpenZeppelin's `MerkleProof.multiProofVerify`.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+
+use sha3::Sha3_256;
+
+use crate::merkle::{
+    hash, hash_internal_node, hashes_equal, Direction, Hash, Hashable, LeafHashing, MerkleError,
+    MerkleProof, MerkleTree, OddNodePolicy, TreeHasher,
+};
+
+/// A proof of membership of several leaves of the same tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "Vec<[u8; N]>: serde::Serialize",
+        deserialize = "Vec<[u8; N]>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct MultiProof<const N: usize = 32> {
+    /// Number of leaves of the tree, which sets the size of each level
+    /// The root doesn't commit to it: since the last node of an odd level is paired with
+    /// itself, counts that give levels of the same shape around the proven leaves verify alike
+    pub leaf_count: usize,
+    /// Indices of the proven leaves, sorted and without duplicates
+    pub indices: Vec<usize>,
+    /// Siblings that can't be computed from the proven leaves, from the leaves up to the root
+    pub hashes: Vec<[u8; N]>,
+}
+
+/// A proof of several leaves of a tree created with `with_sorted_pairs`, in the format of
+/// OpenZeppelin's `MerkleProof.multiProofVerify(proof, proofFlags, root, leaves)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "Vec<[u8; N]>: serde::Serialize",
+        deserialize = "Vec<[u8; N]>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct SortedMultiProof<const N: usize = 32> {
+    /// Siblings that can't be computed from the proven leaves, in the order they are used
+    pub proof: Vec<[u8; N]>,
+    /// One flag per hash computed by the verifier: `true` if its second node is the next
+    /// leaf or computed hash, `false` if it is the next sibling of `proof`
+    pub proof_flags: Vec<bool>,
+    /// Hashes of the proven leaves, in the order of their indices
+    pub leaves: Vec<[u8; N]>,
+}
+
+/// `indices` sorted, without duplicates, if they are all leaves of a tree of `len` leaves
+fn sorted_indices(indices: &[usize], len: usize) -> Result<Vec<usize>, MerkleError> {
+    if indices.is_empty() {
+        return Err(MerkleError::EmptyData);
+    }
+    if indices.iter().any(|&index| index >= len) {
+        return Err(MerkleError::IndexOutOfBounds);
+    }
+    let mut indices = indices.to_vec();
+    indices.sort_unstable();
+    indices.dedup();
+    Ok(indices)
+}
+
+impl MerkleTree {
+    /// Generates a single proof for the leaves at `indices`, verified with `verify_multiproof`
+    /// The indices can be in any order and repeat, each leaf is proven once
+    ///
+    /// # Returns
+    /// The proof, `EmptyData` if `indices` is empty, `IndexOutOfBounds` if one of them has no
+    /// leaf, `UnsupportedArity` for trees with an arity other than 2, or `UnsupportedProof`
+    /// for trees that don't pair the last node of an odd level with itself, or don't hash
+    /// their leaves and nodes like `MerkleTree::new`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let tree = MerkleTree::new(&["a", "b", "c", "d"]).expect("Should create merkle tree");
+    /// let proof = tree.generate_multiproof(&[1, 0]).expect("Should generate proof");
+    /// assert_eq!(proof.indices, vec![0, 1]);
+    /// // Only the parent of "c" and "d" is needed
+    /// assert_eq!(proof.hashes.len(), 1);
+    /// ```
+    pub fn generate_multiproof(&self, indices: &[usize]) -> Result<MultiProof, MerkleError> {
+        if self.has_sorted_pairs()
+            || self.has_domain_separation()
+            || self.leaf_hashing() != LeafHashing::Single
+            || self.normalize_nfc()
+        {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        self.multiproof(indices)
+    }
+}
+
+impl<D: TreeHasher<N>, const N: usize> MerkleTree<D, N> {
+    /// The multiproof of the leaves at `indices`, whatever the hash function of the tree
+    fn multiproof(&self, indices: &[usize]) -> Result<MultiProof<N>, MerkleError> {
+        if self.arity() != 2 {
+            return Err(MerkleError::UnsupportedArity);
+        }
+        if self.odd_node_policy() != OddNodePolicy::DuplicateLast || self.max_leaves().is_some() {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        let indices = sorted_indices(indices, self.len())?;
+
+        let levels = self.levels();
+        let mut hashes = Vec::new();
+        let mut known = indices.clone();
+        for level in &levels[..levels.len() - 1] {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut position = 0;
+            while position < known.len() {
+                let index = known[position];
+                let sibling = index ^ 1;
+                if known.get(position + 1) == Some(&sibling) {
+                    // Both children are known, the pair needs no sibling
+                    position += 1;
+                } else if sibling < level.len() {
+                    hashes.push(level[sibling]);
+                }
+                // A node without a sibling is paired with itself
+                parents.push(index / 2);
+                position += 1;
+            }
+            known = parents;
+        }
+
+        Ok(MultiProof {
+            leaf_count: self.len(),
+            indices,
+            hashes,
+        })
+    }
+
+    /// Generates the `(proof, proofFlags, leaves)` that OpenZeppelin's
+    /// `MerkleProof.multiProofVerify` takes to prove the leaves at `indices`
+    /// The verifier hashes its queue of leaves and computed hashes in order, so the last node of
+    /// an odd level, which is promoted without being hashed, has to wait for its sibling at
+    /// the front of the queue. When that sibling is computed from other proven leaves the
+    /// queue can't express the proof, a power-of-two number of leaves never has the problem
+    ///
+    /// # Returns
+    /// The proof, `EmptyData` if `indices` is empty, `IndexOutOfBounds` if one of them has no
+    /// leaf, or `UnsupportedProof` for a tree not created with `with_sorted_pairs` or leaves
+    /// the queue can't prove together
+    pub fn generate_sorted_multiproof(
+        &self,
+        indices: &[usize],
+    ) -> Result<SortedMultiProof<N>, MerkleError> {
+        if !self.has_sorted_pairs()
+            || self.odd_node_policy() != OddNodePolicy::PromoteToNextLevel
+            || self.max_leaves().is_some()
+        {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        let indices = sorted_indices(indices, self.len())?;
+
+        let levels = self.levels();
+        let top = levels.len() - 1;
+        // Whether the node at `index` of `level` is computed from a proven leaf
+        let is_proven = |level: usize, index: usize| {
+            let first = indices.partition_point(|&leaf| leaf >> level < index);
+            indices
+                .get(first)
+                .is_some_and(|&leaf| leaf >> level == index)
+        };
+
+        let mut proof = Vec::new();
+        let mut proof_flags = Vec::new();
+        // (level, index) of each node of the verifier's queue
+        let mut queue: VecDeque<(usize, usize)> = indices.iter().map(|&index| (0, index)).collect();
+        while let Some((level, index)) = queue.pop_front() {
+            if level == top {
+                break;
+            }
+            let sibling = index ^ 1;
+            if sibling >= levels[level].len() {
+                // Promoted, it stays at the front of the queue one level up
+                queue.push_front((level + 1, index / 2));
+                continue;
+            }
+            if is_proven(level, sibling) {
+                if queue.pop_front() != Some((level, sibling)) {
+                    return Err(MerkleError::UnsupportedProof);
+                }
+                proof_flags.push(true);
+            } else {
+                proof.push(levels[level][sibling]);
+                proof_flags.push(false);
+            }
+            queue.push_back((level + 1, index / 2));
+        }
+
+        Ok(SortedMultiProof {
+            proof,
+            proof_flags,
+            leaves: indices.iter().map(|&index| levels[0][index]).collect(),
+        })
+    }
+
+    /// Computes the root a `SortedMultiProof` leads to, with the same algorithm as
+    /// OpenZeppelin's `MerkleProof.processMultiProof`
+    ///
+    /// # Returns
+    /// The root, or `None` where `processMultiProof` reverts: when the number of leaves and
+    /// siblings doesn't match the number of flags or a sibling is left unused
+    pub fn process_sorted_multiproof(&self, proof: &SortedMultiProof<N>) -> Option<[u8; N]> {
+        let total_hashes = proof.proof_flags.len();
+        if proof.leaves.len() + proof.proof.len() != total_hashes + 1 {
+            return None;
+        }
+        let mut queue: VecDeque<[u8; N]> = proof.leaves.iter().copied().collect();
+        let mut siblings = proof.proof.iter();
+        let mut last = None;
+        for &flag in &proof.proof_flags {
+            let a = queue.pop_front()?;
+            let b = if flag {
+                queue.pop_front()?
+            } else {
+                *siblings.next()?
+            };
+            let parent = self.hash_nodes(&a, &b);
+            queue.push_back(parent);
+            last = Some(parent);
+        }
+        if siblings.next().is_some() {
+            return None;
+        }
+        last.or_else(|| proof.leaves.first().or(proof.proof.first()).copied())
+    }
+
+    /// Validates a `SortedMultiProof` like OpenZeppelin's `MerkleProof.multiProofVerify`
+    /// Returns false for trees that don't sort their pairs, since the order of each pair is unknown
+    pub fn verify_sorted_multiproof(&self, proof: &SortedMultiProof<N>) -> bool {
+        if self.is_empty() || !self.has_sorted_pairs() {
+            return false;
+        }
+        self.process_sorted_multiproof(proof)
+            .is_some_and(|root| hashes_equal(&root, &self.root()))
+    }
+}
+
+/// Verifies a proof of `generate_multiproof` for the claimed `(index, data)` leaves with nothing
+/// but the root of the tree
+/// The tree must hash like `MerkleTree::new`, see `merkle::verify`
+///
+/// # Returns
+///
+/// `true` if the claimed leaves are exactly the proven ones and the root computed from them and
+/// the helper hashes matches `root`. A proof with a missing or an extra helper hash fails, and
+/// so does a claim of a leaf at another index than the one it was proven at
+///
+/// # Example
+///
+/// ```
+/// use rusty_merkle_tree::merkle::MerkleTree;
+/// use rusty_merkle_tree::multiproof::verify_multiproof;
+///
+/// let tree = MerkleTree::new(&["a", "b", "c", "d"]).expect("Should create merkle tree");
+/// let proof = tree.generate_multiproof(&[0, 2]).expect("Should generate proof");
+/// assert!(verify_multiproof(&tree.root(), &[(0, "a"), (2, "c")], &proof));
+/// assert!(!verify_multiproof(&tree.root(), &[(0, "c"), (2, "a")], &proof));
+/// ```
+pub fn verify_multiproof(
+    root: &Hash,
+    leaves: &[(usize, impl Hashable)],
+    proof: &MultiProof,
+) -> bool {
+    let mut known: Vec<(usize, Hash)> = leaves
+        .iter()
+        .map(|(index, data)| (*index, hash(data.leaf_bytes())))
+        .collect();
+    known.sort_unstable_by_key(|(index, _)| *index);
+    if known.is_empty()
+        || !known
+            .iter()
+            .map(|(index, _)| index)
+            .eq(proof.indices.iter())
+        || known[known.len() - 1].0 >= proof.leaf_count
+    {
+        return false;
+    }
+    root_from_nodes(known, proof.leaf_count, &proof.hashes, hash_internal_node)
+        .is_some_and(|computed| hashes_equal(&computed, root))
+}
+
+/// The root computed from the `(index, leaf)` of `known`, sorted by index, and the helper
+/// hashes of a tree of `leaf_count` leaves
+/// None if a helper hash is missing or left over
+fn root_from_nodes(
+    mut known: Vec<(usize, Hash)>,
+    leaf_count: usize,
+    hashes: &[Hash],
+    hash_nodes: impl Fn(&Hash, &Hash) -> Hash,
+) -> Option<Hash> {
+    let mut helpers = hashes.iter();
+    let mut size = leaf_count;
+    while size > 1 {
+        let mut parents = Vec::with_capacity(known.len());
+        let mut position = 0;
+        while position < known.len() {
+            let (index, node) = known[position];
+            let sibling = index ^ 1;
+            let parent = match known.get(position + 1) {
+                Some((next, right)) if *next == sibling => {
+                    position += 1;
+                    hash_nodes(&node, right)
+                }
+                _ if sibling < size => {
+                    let helper = helpers.next()?;
+                    if index % 2 == 0 {
+                        hash_nodes(&node, helper)
+                    } else {
+                        hash_nodes(helper, &node)
+                    }
+                }
+                _ => hash_nodes(&node, &node),
+            };
+            parents.push((index / 2, parent));
+            position += 1;
+        }
+        known = parents;
+        size = size.div_ceil(2);
+    }
+
+    helpers.next().is_none().then_some(known[0].1)
+}
+
+/// The (level, index) of each helper hash of the multiproof of `indices`, sorted and without
+/// duplicates, in a tree of `leaf_count` leaves that duplicates odd nodes
+#[cfg(feature = "sha2")]
+pub(crate) fn helper_positions(indices: &[usize], leaf_count: usize) -> Vec<(usize, usize)> {
+    let mut positions = Vec::new();
+    let mut known = indices.to_vec();
+    let mut size = leaf_count;
+    let mut level = 0;
+    while size > 1 {
+        let mut parents = Vec::with_capacity(known.len());
+        let mut position = 0;
+        while position < known.len() {
+            let index = known[position];
+            let sibling = index ^ 1;
+            if known.get(position + 1) == Some(&sibling) {
+                position += 1;
+            } else if sibling < size {
+                positions.push((level, sibling));
+            }
+            parents.push(index / 2);
+            position += 1;
+        }
+        known = parents;
+        size = size.div_ceil(2);
+        level += 1;
+    }
+    positions
+}
+
+impl<const N: usize> MerkleProof<N> {
+    /// Merges the proofs of the leaves at the given indices into one `MultiProof`, keeping each
+    /// sibling once and dropping the ones on the path of another leaf
+    /// The proofs must come from a tree that duplicates odd nodes, where every level has a
+    /// sibling. They don't tell the number of leaves, so the result has the `leaf_count` of the
+    /// complete tree of their height and keeps the copies of lone nodes as siblings, which
+    /// `verify_multiproof` accepts all the same
+    ///
+    /// # Returns
+    /// The multiproof, `EmptyData` without proofs, `ConflictingSibling` with the (level, index)
+    /// of a sibling two proofs disagree on, `IndexOutOfBounds` for an index past the height of
+    /// its proof, or `UnsupportedProof` if the proofs don't have the same height or a
+    /// direction doesn't match the index
+    pub fn aggregate(proofs: &[(usize, MerkleProof<N>)]) -> Result<MultiProof<N>, MerkleError> {
+        let height = proofs.first().ok_or(MerkleError::EmptyData)?.1.len();
+        let leaf_count = u32::try_from(height)
+            .ok()
+            .and_then(|height| 1usize.checked_shl(height))
+            .ok_or(MerkleError::ProofTooLong)?;
+
+        let mut siblings = BTreeMap::new();
+        for (index, proof) in proofs {
+            if proof.len() != height {
+                return Err(MerkleError::UnsupportedProof);
+            }
+            if *index >= leaf_count {
+                return Err(MerkleError::IndexOutOfBounds);
+            }
+            for (level, (sibling, direction)) in proof.iter().enumerate() {
+                let node = index >> level;
+                let expected = if node % 2 == 0 {
+                    Direction::Right
+                } else {
+                    Direction::Left
+                };
+                if direction != expected {
+                    return Err(MerkleError::UnsupportedProof);
+                }
+                match siblings.insert((level, node ^ 1), *sibling) {
+                    Some(previous) if !hashes_equal(&previous, sibling) => {
+                        return Err(MerkleError::ConflictingSibling(level, node ^ 1));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let indices: Vec<usize> = proofs.iter().map(|(index, _)| *index).collect();
+        let indices = sorted_indices(&indices, leaf_count)?;
+        let mut hashes = Vec::new();
+        let mut known = indices.clone();
+        for level in 0..height {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut position = 0;
+            while position < known.len() {
+                let index = known[position];
+                if known.get(position + 1) == Some(&(index ^ 1)) {
+                    position += 1;
+                } else {
+                    hashes.push(siblings[&(level, index ^ 1)]);
+                }
+                parents.push(index / 2);
+                position += 1;
+            }
+            known = parents;
+        }
+
+        Ok(MultiProof {
+            leaf_count,
+            indices,
+            hashes,
+        })
+    }
+}
+
+/// A tree pruned down to some of its leaves and the hashes needed to recompute its root
+/// It is a `MultiProof` carrying the hashes of its leaves, so a verifier only needs the
+/// expected root
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "Vec<[u8; N]>: serde::Serialize",
+        deserialize = "Vec<[u8; N]>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct PartialMerkleTree<const N: usize = 32> {
+    /// Number of leaves of the full tree
+    pub leaf_count: usize,
+    /// Indices of the kept leaves, in increasing order
+    pub indices: Vec<usize>,
+    /// Hashes of the kept leaves, in the order of `indices`
+    pub leaves: Vec<[u8; N]>,
+    /// The helper hashes of the `MultiProof` of the kept leaves
+    pub hashes: Vec<[u8; N]>,
+}
+
+impl<D: TreeHasher<N>, const N: usize> MerkleTree<D, N> {
+    /// Prunes the tree down to the leaves at `indices`, see `PartialMerkleTree`
+    ///
+    /// # Returns
+    /// The partial tree, or the errors of `generate_multiproof` for trees that don't pair the
+    /// last node of an odd level with itself. Other hash functions are supported, see
+    /// `PartialMerkleTree::root_with`
+    ///
+    /// # Example
+    /// ```
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let tree = MerkleTree::new(&["a", "b", "c", "d", "e"]).expect("Should create merkle tree");
+    /// let partial = tree.extract_partial(&[1, 4]).expect("Should extract leaves");
+    /// assert!(partial.contains(4) && !partial.contains(0));
+    /// assert!(partial.verify(&tree.root()));
+    /// ```
+    pub fn extract_partial(&self, indices: &[usize]) -> Result<PartialMerkleTree<N>, MerkleError> {
+        let proof = self.multiproof(indices)?;
+        let leaves = self.leaves();
+        Ok(PartialMerkleTree {
+            leaf_count: proof.leaf_count,
+            leaves: proof.indices.iter().map(|&index| leaves[index]).collect(),
+            indices: proof.indices,
+            hashes: proof.hashes,
+        })
+    }
+}
+
+impl<const N: usize> PartialMerkleTree<N> {
+    /// Returns true if the leaf at `index` was kept
+    pub fn contains(&self, index: usize) -> bool {
+        self.indices.binary_search(&index).is_ok()
+    }
+
+    /// Returns the hash of the kept leaf at `index`, or None if it wasn't kept
+    pub fn leaf(&self, index: usize) -> Option<[u8; N]> {
+        let position = self.indices.binary_search(&index).ok()?;
+        self.leaves.get(position).copied()
+    }
+}
+
+impl PartialMerkleTree {
+    /// Recomputes the root of the tree, hashing like `MerkleTree::new`
+    ///
+    /// # Returns
+    /// The root, or None if the partial tree is malformed: no leaves, indices out of order or
+    /// past `leaf_count`, a hash per index missing, or a missing or extra helper hash
+    pub fn root(&self) -> Option<Hash> {
+        self.root_with(&Sha3_256::default())
+    }
+
+    /// Same as `root`, hashing the internal nodes with `hasher` like a tree of `with_digest`
+    pub fn root_with<D: TreeHasher>(&self, hasher: &D) -> Option<Hash> {
+        if !self.is_well_formed() {
+            return None;
+        }
+        let known = self
+            .indices
+            .iter()
+            .copied()
+            .zip(self.leaves.iter().copied());
+        root_from_nodes(
+            known.collect(),
+            self.leaf_count,
+            &self.hashes,
+            |left, right| hasher.hash_parts(&[left, right]),
+        )
+    }
+
+    /// True if there are leaves, a hash for each index, and the indices are in increasing
+    /// order below `leaf_count`
+    pub(crate) fn is_well_formed(&self) -> bool {
+        !self.indices.is_empty()
+            && self.indices.len() == self.leaves.len()
+            && self.indices.windows(2).all(|pair| pair[0] < pair[1])
+            && self.indices[self.indices.len() - 1] < self.leaf_count
+    }
+
+    /// Returns true if the partial tree recomputes `expected_root`
+    pub fn verify(&self, expected_root: &Hash) -> bool {
+        self.root()
+            .is_some_and(|computed| hashes_equal(&computed, expected_root))
+    }
+}
+
+/// A proof of the contiguous leaves of a range, with only the siblings at its boundaries
+/// The leaves inside the range hash into each other, so at most two siblings per level are
+/// needed, one on each side
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "Vec<[u8; N]>: serde::Serialize",
+        deserialize = "Vec<[u8; N]>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct RangeProof<const N: usize = 32> {
+    /// Number of leaves of the tree, see `MultiProof::leaf_count`
+    pub leaf_count: usize,
+    /// Siblings at the boundaries of the range, from the leaves up to the root
+    pub hashes: Vec<[u8; N]>,
+}
+
+impl MerkleTree {
+    /// Generates a proof of the leaves in `range`, verified with `verify_range`
+    /// It is the multiproof of the leaves of the range, whose size only grows with the
+    /// height of the tree
+    ///
+    /// # Returns
+    /// The proof, `EmptyData` for an empty range, which has nothing to prove,
+    /// `IndexOutOfBounds` if the range goes past the last leaf, or the errors of
+    /// `generate_multiproof` for trees it doesn't support
+    ///
+    /// # Example
+    /// ```
+    /// use rusty_merkle_tree::merkle::MerkleTree;
+    /// use rusty_merkle_tree::multiproof::verify_range;
+    ///
+    /// let data = ["a", "b", "c", "d", "e"];
+    /// let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+    /// let proof = tree.prove_range(1..4).expect("Should generate proof");
+    /// assert!(verify_range(&tree.root(), 1, &data[1..4], &proof));
+    /// ```
+    pub fn prove_range(&self, range: Range<usize>) -> Result<RangeProof, MerkleError> {
+        if range.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        if range.end > self.len() {
+            return Err(MerkleError::IndexOutOfBounds);
+        }
+        let proof = self.generate_multiproof(&range.collect::<Vec<_>>())?;
+        Ok(RangeProof {
+            leaf_count: proof.leaf_count,
+            hashes: proof.hashes,
+        })
+    }
+}
+
+/// Verifies a proof of `prove_range` for the leaves `leaves_data`, starting at index `start`,
+/// with nothing but the root of the tree
+/// The tree must hash like `MerkleTree::new`, see `merkle::verify`
+///
+/// # Returns
+///
+/// `true` if the root computed from the leaves and the proof matches `root`. An empty range
+/// and a range that goes past `leaf_count`, into the copies of the last leaf that complete odd
+/// levels, always fail
+pub fn verify_range(
+    root: &Hash,
+    start: usize,
+    leaves_data: &[impl Hashable],
+    proof: &RangeProof,
+) -> bool {
+    let Some(end) = start.checked_add(leaves_data.len()) else {
+        return false;
+    };
+    if leaves_data.is_empty() || end > proof.leaf_count {
+        return false;
+    }
+    let known = leaves_data
+        .iter()
+        .enumerate()
+        .map(|(offset, data)| (start + offset, hash(data.leaf_bytes())))
+        .collect();
+    root_from_nodes(known, proof.leaf_count, &proof.hashes, hash_internal_node)
+        .is_some_and(|computed| hashes_equal(&computed, root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::from_hex;
+    use sha3::Keccak256;
+
+    fn blocks(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_multiproof_shares_nodes() {
+        let tree = MerkleTree::new(&blocks(8)).expect("Should create merkle tree");
+        let levels = tree.levels();
+
+        let proof = tree
+            .generate_multiproof(&[1, 0])
+            .expect("Should generate proof");
+        assert_eq!(proof.leaf_count, 8);
+        assert_eq!(proof.indices, vec![0, 1]);
+        assert_eq!(proof.hashes, vec![levels[1][1], levels[2][1]]);
+
+        let proof = tree
+            .generate_multiproof(&[6, 1, 6, 1])
+            .expect("Should generate proof");
+        assert_eq!(proof.indices, vec![1, 6]);
+        assert_eq!(
+            proof.hashes,
+            vec![levels[0][0], levels[0][7], levels[1][1], levels[1][2]]
+        );
+
+        let every = tree
+            .generate_multiproof(&(0..8).collect::<Vec<_>>())
+            .expect("Should generate proof");
+        assert!(every.hashes.is_empty());
+    }
+
+    #[test]
+    fn test_multiproof_odd_levels() {
+        let tree = MerkleTree::new(&blocks(5)).expect("Should create merkle tree");
+        let levels = tree.levels();
+        // The last leaf is paired with itself on every level below the root
+        let proof = tree
+            .generate_multiproof(&[4])
+            .expect("Should generate proof");
+        assert_eq!(proof.hashes, vec![levels[2][0]]);
+
+        let single = MerkleTree::new(&blocks(1)).expect("Should create merkle tree");
+        let proof = single
+            .generate_multiproof(&[0])
+            .expect("Should generate proof");
+        assert!(proof.hashes.is_empty());
+    }
+
+    #[test]
+    fn test_multiproof_errors() {
+        let tree = MerkleTree::new(&blocks(4)).expect("Should create merkle tree");
+        assert!(matches!(
+            tree.generate_multiproof(&[]),
+            Err(MerkleError::EmptyData)
+        ));
+        assert!(matches!(
+            tree.generate_multiproof(&[0, 4]),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+
+        let promoted =
+            MerkleTree::with_odd_node_policy(&blocks(3), OddNodePolicy::PromoteToNextLevel)
+                .expect("Should create merkle tree");
+        assert!(matches!(
+            promoted.generate_multiproof(&[0]),
+            Err(MerkleError::UnsupportedProof)
+        ));
+    }
+
+    #[test]
+    fn test_multiproof_refuses_trees_the_verifier_cant_hash() {
+        let data = blocks(6);
+        let trees = [
+            MerkleTree::with_sorted_pairs(&data),
+            MerkleTree::with_domain_separation(&data),
+            MerkleTree::with_leaf_hashing(&data, LeafHashing::Double),
+        ];
+        for tree in trees {
+            let tree = tree.expect("Should create merkle tree");
+            assert!(matches!(
+                tree.generate_multiproof(&[1, 4]),
+                Err(MerkleError::UnsupportedProof)
+            ));
+            assert!(matches!(
+                tree.prove_range(1..4),
+                Err(MerkleError::UnsupportedProof)
+            ));
+        }
+        // Other hash functions only get a partial tree, recomputed with their hasher
+        let keccak =
+            MerkleTree::<Keccak256>::with_digest(&data).expect("Should create merkle tree");
+        let partial = keccak
+            .extract_partial(&[1, 4])
+            .expect("Should extract leaves");
+        assert_eq!(
+            partial.root_with(&Keccak256::default()),
+            Some(keccak.root())
+        );
+    }
+
+    #[test]
+    fn test_multiproof_verifies() {
+        let data = blocks(13);
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        for indices in [
+            vec![0],
+            vec![12],
+            vec![3, 4],
+            vec![0, 5, 6, 11, 12],
+            vec![7, 2, 7],
+        ] {
+            let proof = tree
+                .generate_multiproof(&indices)
+                .expect("Should generate proof");
+            let leaves: Vec<(usize, &[u8])> = proof
+                .indices
+                .iter()
+                .map(|&index| (index, data[index].as_slice()))
+                .collect();
+            assert!(verify_multiproof(&tree.root(), &leaves, &proof));
+
+            // The claimed leaves can be in any order
+            let reversed: Vec<_> = leaves.iter().rev().copied().collect();
+            assert!(verify_multiproof(&tree.root(), &reversed, &proof));
+        }
+
+        let every: Vec<usize> = (0..13).collect();
+        let proof = tree
+            .generate_multiproof(&every)
+            .expect("Should generate proof");
+        let leaves: Vec<(usize, &Vec<u8>)> = data.iter().enumerate().collect();
+        assert!(verify_multiproof(&tree.root(), &leaves, &proof));
+    }
+
+    #[test]
+    fn test_multiproof_rejects_tampering() {
+        let data = blocks(13);
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proof = tree
+            .generate_multiproof(&[1, 4, 9])
+            .expect("Should generate proof");
+        let leaves = [(1, &data[1]), (4, &data[4]), (9, &data[9])];
+        assert!(verify_multiproof(&tree.root(), &leaves, &proof));
+
+        // Two leaves swapped
+        let swapped = [(1, &data[4]), (4, &data[1]), (9, &data[9])];
+        assert!(!verify_multiproof(&tree.root(), &swapped, &proof));
+
+        // A leaf claimed at another index
+        let moved = [(1, &data[1]), (5, &data[4]), (9, &data[9])];
+        assert!(!verify_multiproof(&tree.root(), &moved, &proof));
+        let mut moved_proof = proof.clone();
+        moved_proof.indices = vec![1, 5, 9];
+        assert!(!verify_multiproof(&tree.root(), &moved, &moved_proof));
+
+        // A leaf missing or claimed twice
+        assert!(!verify_multiproof(&tree.root(), &leaves[..2], &proof));
+        let repeated = [(1, &data[1]), (4, &data[4]), (4, &data[4]), (9, &data[9])];
+        assert!(!verify_multiproof(&tree.root(), &repeated, &proof));
+
+        // A helper hash dropped or added
+        for position in 0..proof.hashes.len() {
+            let mut dropped = proof.clone();
+            dropped.hashes.remove(position);
+            assert!(!verify_multiproof(&tree.root(), &leaves, &dropped));
+        }
+        let mut extra = proof.clone();
+        extra.hashes.push(tree.root());
+        assert!(!verify_multiproof(&tree.root(), &leaves, &extra));
+
+        // A helper hash from another tree
+        let other = MerkleTree::new(&blocks(14)).expect("Should create merkle tree");
+        let other_proof = other
+            .generate_multiproof(&[1, 4, 9])
+            .expect("Should generate proof");
+        for position in 0..proof.hashes.len() {
+            let mut foreign = proof.clone();
+            foreign.hashes[position] = other_proof.hashes[position];
+            if foreign != proof {
+                assert!(!verify_multiproof(&tree.root(), &leaves, &foreign));
+            }
+        }
+
+        // A number of leaves that doesn't hold every proven leaf
+        let mut resized = proof.clone();
+        resized.leaf_count = 9;
+        assert!(!verify_multiproof(&tree.root(), &leaves, &resized));
+    }
+
+    #[test]
+    fn test_sorted_multiproof_fixture() {
+        // Keccak-256 leaves of "a" to "h" with sorted pairs, proving the leaves at 1, 2 and 5
+        // The values were not captured from `merkletreejs`: they come from a separate
+        // implementation of its level by level multiproof, checked with a port of
+        // OpenZeppelin's `processMultiProof`. They should be replaced by the output of
+        //     npm install merkletreejs@0.4.0 keccak256@1.0.6
+        //     node -e "const { MerkleTree } = require('merkletreejs');
+        //       const keccak256 = require('keccak256');
+        //       const leaves = [...'abcdefgh'].map((x) => keccak256(x));
+        //       const tree = new MerkleTree(leaves, keccak256, { sortPairs: true });
+        //       const proof = tree.getMultiProof([1, 2, 5]);
+        //       const picked = [1, 2, 5].map((i) => leaves[i]);
+        //       console.log(tree.getHexRoot(), proof.map((p) => p.toString('hex')),
+        //         tree.getProofFlags(picked, proof));"
+        let data: Vec<&str> = vec!["a", "b", "c", "d", "e", "f", "g", "h"];
+        let tree =
+            MerkleTree::<Keccak256>::with_sorted_pairs(&data).expect("Should create merkle tree");
+        assert_eq!(
+            tree.root(),
+            from_hex("f284dc8832fbf4a18f7b7b893c69e5a5cf3961f75f31b151855acc16ea9fceb6")
+        );
+
+        let proof = tree
+            .generate_sorted_multiproof(&[5, 1, 2])
+            .expect("Should generate proof");
+        let expected = SortedMultiProof {
+            proof: [
+                "3ac225168df54212a25c1c01fd35bebfea408fdac2e31ddd6f80a4bbf9a5f1cb",
+                "f1918e8562236eb17adc8502332f4c9c82bc14e19bfc0aa10ab674ff75b3d2f3",
+                "a8982c89d80987fb9a510e25981ee9170206be21af3c8e0eb312ef1d3382e761",
+                "e18a5c2ee5202ecdefed683f03145b1343304dbed01aecb94032b7f801844f0a",
+            ]
+            .map(from_hex)
+            .to_vec(),
+            proof_flags: vec![false, false, false, true, false, true],
+            leaves: [
+                "b5553de315e0edf504d9150af82dafa5c4667fa618ed0a6f19c69b41166c5510",
+                "0b42b6393c1f53060fe3ddbfcd7aadcca894465a5a438f69c87d790b2299b9b2",
+                "d1e8aeb79500496ef3dc2e57ba746a8315d048b7a664a2bf948db4fa91960483",
+            ]
+            .map(from_hex)
+            .to_vec(),
+        };
+        assert_eq!(proof, expected);
+        assert!(tree.verify_sorted_multiproof(&proof));
+    }
+
+    #[test]
+    fn test_sorted_multiproof_verifies() {
+        for size in 1..=12 {
+            let data = blocks(size);
+            let tree = MerkleTree::<Keccak256>::with_sorted_pairs(&data)
+                .expect("Should create merkle tree");
+            for (first, element) in data.iter().enumerate() {
+                for second in first..size {
+                    // Some sets of leaves of an odd level can't be proven with the queue
+                    let Ok(proof) = tree.generate_sorted_multiproof(&[first, second]) else {
+                        assert!(!size.is_power_of_two(), "size {}", size);
+                        continue;
+                    };
+                    assert!(tree.verify_sorted_multiproof(&proof), "size {}", size);
+                }
+                let proof = tree
+                    .generate_sorted_multiproof(&[first])
+                    .expect("Should generate proof");
+                assert_eq!(
+                    proof.proof,
+                    tree.generate_sorted_proof(element)
+                        .expect("Should generate proof")
+                );
+                assert!(tree.verify_sorted_multiproof(&proof));
+            }
+            if size.is_power_of_two() {
+                let every: Vec<usize> = (0..size).collect();
+                let proof = tree
+                    .generate_sorted_multiproof(&every)
+                    .expect("Should generate proof");
+                assert!(proof.proof.is_empty());
+                assert!(tree.verify_sorted_multiproof(&proof));
+            }
+        }
+    }
+
+    #[test]
+    fn test_sorted_multiproof_rejects_tampering() {
+        let tree = MerkleTree::<Keccak256>::with_sorted_pairs(&blocks(8))
+            .expect("Should create merkle tree");
+        let proof = tree
+            .generate_sorted_multiproof(&[1, 2, 5])
+            .expect("Should generate proof");
+
+        let mut flipped = proof.clone();
+        flipped.proof_flags[3] = false;
+        assert!(!tree.verify_sorted_multiproof(&flipped));
+
+        let mut dropped = proof.clone();
+        dropped.proof.pop();
+        assert!(!tree.verify_sorted_multiproof(&dropped));
+
+        let mut extra = proof.clone();
+        extra.proof.push(tree.root());
+        extra.proof_flags.push(false);
+        assert!(!tree.verify_sorted_multiproof(&extra));
+
+        let mut forged = proof.clone();
+        forged.leaves[0] = tree.leaf(0).expect("Should have a leaf");
+        assert!(!tree.verify_sorted_multiproof(&forged));
+
+        // The last leaf of 5 is promoted twice and would have to wait for the node above
+        // leaves 0 to 3 at the front of the queue
+        let odd = MerkleTree::<Keccak256>::with_sorted_pairs(&blocks(5))
+            .expect("Should create merkle tree");
+        assert!(matches!(
+            odd.generate_sorted_multiproof(&[1, 4]),
+            Err(MerkleError::UnsupportedProof)
+        ));
+        assert!(matches!(
+            MerkleTree::new(&blocks(4))
+                .expect("Should create merkle tree")
+                .generate_sorted_multiproof(&[0]),
+            Err(MerkleError::UnsupportedProof)
+        ));
+    }
+
+    #[test]
+    fn test_aggregate_proofs() {
+        for size in [1, 2, 5, 8, 11] {
+            let data = blocks(size);
+            let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+            let sets: [&[usize]; 4] = [&[0], &[size - 1], &[0, size - 1, 0], &[size / 2, 0]];
+            for indices in sets {
+                let proofs: Vec<(usize, MerkleProof)> = indices
+                    .iter()
+                    .map(|&index| {
+                        let proof = tree
+                            .generate_proof_by_index(index)
+                            .expect("Should generate proof");
+                        (index, proof)
+                    })
+                    .collect();
+                let aggregated = MerkleProof::aggregate(&proofs).expect("Should aggregate");
+                let leaves: Vec<(usize, &Vec<u8>)> = aggregated
+                    .indices
+                    .iter()
+                    .map(|&index| (index, &data[index]))
+                    .collect();
+                assert!(
+                    verify_multiproof(&tree.root(), &leaves, &aggregated),
+                    "{:?} of {}",
+                    indices,
+                    size
+                );
+                assert!(aggregated.hashes.len() <= proofs.iter().map(|p| p.1.len()).sum());
+            }
+        }
+
+        // The siblings shared by both proofs are kept once
+        let data = blocks(8);
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let proofs: Vec<(usize, MerkleProof)> = [4, 5]
+            .into_iter()
+            .map(|index| {
+                (
+                    index,
+                    tree.generate_proof_by_index(index)
+                        .expect("Should generate proof"),
+                )
+            })
+            .collect();
+        let aggregated = MerkleProof::aggregate(&proofs).expect("Should aggregate");
+        assert_eq!(
+            aggregated,
+            tree.generate_multiproof(&[4, 5])
+                .expect("Should generate proof")
+        );
+    }
+
+    #[test]
+    fn test_aggregate_inconsistent_proofs() {
+        let data = blocks(8);
+        let old = MerkleTree::new(&data).expect("Should create merkle tree");
+        let mut changed = data.clone();
+        changed[7] = b"changed".to_vec();
+        let new = MerkleTree::new(&changed).expect("Should create merkle tree");
+
+        // Leaves 1 and 2 share the sibling of the pair 4..8 at level 2, which changed
+        let proofs = [
+            (
+                1,
+                old.generate_proof_by_index(1)
+                    .expect("Should generate proof"),
+            ),
+            (
+                2,
+                new.generate_proof_by_index(2)
+                    .expect("Should generate proof"),
+            ),
+        ];
+        assert!(matches!(
+            MerkleProof::aggregate(&proofs),
+            Err(MerkleError::ConflictingSibling(2, 1))
+        ));
+
+        let proofs = [
+            (
+                1,
+                old.generate_proof_by_index(1)
+                    .expect("Should generate proof"),
+            ),
+            (
+                2,
+                old.generate_proof_by_index(1)
+                    .expect("Should generate proof"),
+            ),
+        ];
+        assert!(matches!(
+            MerkleProof::aggregate(&proofs),
+            Err(MerkleError::UnsupportedProof)
+        ));
+        let small = MerkleTree::new(&blocks(4)).expect("Should create merkle tree");
+        let proofs = [
+            (
+                1,
+                old.generate_proof_by_index(1)
+                    .expect("Should generate proof"),
+            ),
+            (
+                2,
+                small
+                    .generate_proof_by_index(2)
+                    .expect("Should generate proof"),
+            ),
+        ];
+        assert!(matches!(
+            MerkleProof::aggregate(&proofs),
+            Err(MerkleError::UnsupportedProof)
+        ));
+        let proofs = [(
+            8,
+            old.generate_proof_by_index(0)
+                .expect("Should generate proof"),
+        )];
+        assert!(matches!(
+            MerkleProof::aggregate(&proofs),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        assert!(matches!(
+            MerkleProof::<32>::aggregate(&[]),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_partial_tree() {
+        for size in 1..=12 {
+            let tree = MerkleTree::new(&blocks(size)).expect("Should create merkle tree");
+            let all: Vec<usize> = (0..size).collect();
+            let partial = tree.extract_partial(&all).expect("Should extract leaves");
+            assert!(partial.hashes.is_empty());
+            assert_eq!(partial.root(), Some(tree.root()));
+
+            let partial = tree
+                .extract_partial(&[size - 1, 0])
+                .expect("Should extract leaves");
+            assert_eq!(partial.root(), Some(tree.root()));
+            assert!(partial.contains(0) && partial.contains(size - 1));
+            assert_eq!(partial.leaf(0), tree.leaf(0));
+            if size > 2 {
+                assert!(!partial.contains(1));
+                assert_eq!(partial.leaf(1), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tampered_partial_tree() {
+        let tree = MerkleTree::new(&blocks(11)).expect("Should create merkle tree");
+        let partial = tree
+            .extract_partial(&[2, 3, 7])
+            .expect("Should extract leaves");
+        assert!(partial.verify(&tree.root()));
+
+        let mut leaf = partial.clone();
+        leaf.leaves[1][0] ^= 1;
+        assert!(!leaf.verify(&tree.root()));
+
+        let mut internal = partial.clone();
+        internal.hashes[2][31] ^= 1;
+        assert!(!internal.verify(&tree.root()));
+
+        let mut swapped = partial.clone();
+        swapped.leaves.swap(0, 1);
+        assert!(!swapped.verify(&tree.root()));
+
+        let mut extra = partial.clone();
+        extra.hashes.push(tree.root());
+        assert_eq!(extra.root(), None);
+        let mut missing = partial.clone();
+        missing.hashes.pop();
+        assert_eq!(missing.root(), None);
+        let mut unordered = partial.clone();
+        unordered.indices.swap(0, 2);
+        assert_eq!(unordered.root(), None);
+        let mut past_end = partial.clone();
+        past_end.indices[2] = 11;
+        assert_eq!(past_end.root(), None);
+        let mut unpaired = partial.clone();
+        unpaired.leaves.pop();
+        assert_eq!(unpaired.root(), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_partial_tree_serialization() {
+        let tree = MerkleTree::new(&blocks(9)).expect("Should create merkle tree");
+        let partial = tree
+            .extract_partial(&[0, 5])
+            .expect("Should extract leaves");
+        let bytes = crate::serialized::encode(&partial).expect("Should encode partial tree");
+        let decoded: PartialMerkleTree =
+            crate::serialized::decode(&bytes).expect("Should decode partial tree");
+        assert_eq!(decoded, partial);
+        assert!(decoded.verify(&tree.root()));
+    }
+
+    #[test]
+    fn test_range_proofs() {
+        for size in 1..=13 {
+            let data = blocks(size);
+            let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+            for start in 0..size {
+                for end in start + 1..=size {
+                    let proof = tree.prove_range(start..end).expect("Should generate proof");
+                    assert!(
+                        verify_range(&tree.root(), start, &data[start..end], &proof),
+                        "{}..{} of {}",
+                        start,
+                        end,
+                        size
+                    );
+                    if end - start > 1 {
+                        assert!(!verify_range(
+                            &tree.root(),
+                            start,
+                            &data[start..end - 1],
+                            &proof
+                        ));
+                    }
+                    if start > 0 {
+                        assert!(!verify_range(
+                            &tree.root(),
+                            start - 1,
+                            &data[start..end],
+                            &proof
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_range_proof_edges() {
+        let data = blocks(5);
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        assert!(matches!(
+            tree.prove_range(2..2),
+            Err(MerkleError::EmptyData)
+        ));
+        assert!(matches!(
+            tree.prove_range(3..6),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+
+        // The last leaf is paired with its copy, which can't be claimed as a sixth leaf
+        let proof = tree.prove_range(4..5).expect("Should generate proof");
+        assert!(verify_range(&tree.root(), 4, &data[4..], &proof));
+        let with_copy = [&data[4], &data[4]];
+        assert!(!verify_range(&tree.root(), 4, &with_copy, &proof));
+        let empty: [&[u8]; 0] = [];
+        assert!(!verify_range(&tree.root(), 4, &empty, &proof));
+        assert!(!verify_range(&tree.root(), usize::MAX, &data[4..], &proof));
+    }
+
+    #[test]
+    fn test_range_proof_size() {
+        let size = 1 << 12;
+        let data = blocks(size);
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        for (start, end) in [(0, 1), (100, 612), (1000, 3000), (7, size)] {
+            let proof = tree.prove_range(start..end).expect("Should generate proof");
+            // At most one sibling on each side of the range per level
+            assert!(proof.hashes.len() <= 2 * 12, "{}..{}", start, end);
+            assert!(verify_range(&tree.root(), start, &data[start..end], &proof));
+        }
+    }
+
+    #[test]
+    fn test_multiproof_smaller_than_single_proofs() {
+        let tree = MerkleTree::new(&blocks(1 << 12)).expect("Should create merkle tree");
+        let indices: Vec<usize> = (0..100).map(|i| (i * 37 + i * i) % (1 << 12)).collect();
+        let proof = tree
+            .generate_multiproof(&indices)
+            .expect("Should generate proof");
+
+        let mut distinct = indices.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        let single: usize = distinct
+            .iter()
+            .map(|&index| {
+                tree.generate_proof_by_index(index)
+                    .expect("Should generate proof")
+                    .len()
+            })
+            .sum();
+        assert_eq!(single, distinct.len() * 12);
+        assert!(
+            proof.hashes.len() * 2 < single,
+            "{} hashes against {} for single proofs",
+            proof.hashes.len(),
+            single
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use crate::merkle::{
+    empty_root, hash, hash_internal_node, position_of, Direction, Hash, Hashable, MerkleError,
+    MerkleProof,
+};
+use std::sync::Arc;
+
+/// A node of a persistent tree, shared between every version that contains it
+#[derive(Debug)]
+struct Node {
+    hash: Hash,
+    left: Option<Arc<Node>>,
+    /// Missing when the subtree only has leaves on its left half, the left child is then duplicated
+    right: Option<Arc<Node>>,
+}
+
+impl Node {
+    fn leaf(hash: Hash) -> Arc<Node> {
+        Arc::new(Node {
+            hash,
+            left: None,
+            right: None,
+        })
+    }
+
+    fn branch(left: Arc<Node>, right: Option<Arc<Node>>) -> Arc<Node> {
+        let right_hash = right.as_ref().map_or(left.hash, |node| node.hash);
+        Arc::new(Node {
+            hash: hash_internal_node(&left.hash, &right_hash),
+            left: Some(left),
+            right,
+        })
+    }
+
+    fn left(&self) -> &Arc<Node> {
+        self.left
+            .as_ref()
+            .expect("Branches always have a left child")
+    }
+}
+
+/// An immutable Merkle Tree where every mutation returns a new tree
+/// The new tree shares every subtree the mutation didn't touch with the old one, so `add()` and
+/// `update()` only allocate the O(log n) nodes on the path to the changed leaf and cloning a tree
+/// is O(1)
+/// Roots and proofs are the same as the ones of a `MerkleTree` built over the same data with
+/// the default settings
+#[derive(Debug, Clone)]
+pub struct PersistentMerkleTree {
+    root: Option<Arc<Node>>,
+    len: usize,
+    /// Number of levels above the leaves
+    height: usize,
+}
+
+impl PersistentMerkleTree {
+    /// Creates a new persistent tree over `data`
+    ///
+    /// # Returns
+    /// A PersistentMerkleTree instance if the data is not empty, otherwise an error
+    pub fn new<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyData);
+        }
+        let leaves: Vec<Hash> = data
+            .iter()
+            .map(|element| hash(element.leaf_bytes()))
+            .collect();
+        let height = data.len().next_power_of_two().trailing_zeros() as usize;
+        Ok(PersistentMerkleTree {
+            root: Some(build(&leaves, height)),
+            len: leaves.len(),
+            height,
+        })
+    }
+
+    /// Creates a persistent tree without any leaves
+    pub fn empty() -> Self {
+        PersistentMerkleTree {
+            root: None,
+            len: 0,
+            height: 0,
+        }
+    }
+
+    /// Returns the root hash of the tree, `empty_root()` if it has no leaves
+    pub fn root(&self) -> Hash {
+        self.root.as_ref().map_or_else(empty_root, |node| node.hash)
+    }
+
+    /// Returns the number of leaves in the tree
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the leaf hashes, in order
+    pub fn leaves(&self) -> Vec<Hash> {
+        let mut leaves = Vec::with_capacity(self.len);
+        if let Some(root) = &self.root {
+            collect_leaves(root, &mut leaves);
+        }
+        leaves
+    }
+
+    /// Returns a new tree with `data` appended, leaving `self` unchanged
+    pub fn add<T: Hashable + ?Sized>(&self, data: &T) -> Self {
+        let leaf = Node::leaf(hash(data.leaf_bytes()));
+        let (root, height) = match &self.root {
+            None => (leaf, 0),
+            // The tree is complete, the old root becomes the left child of a new one
+            Some(root) if self.len == 1 << self.height => (
+                Node::branch(Arc::clone(root), Some(lone_path(leaf, self.height))),
+                self.height + 1,
+            ),
+            Some(root) => (insert(root, self.height, self.len, leaf), self.height),
+        };
+        PersistentMerkleTree {
+            root: Some(root),
+            len: self.len + 1,
+            height,
+        }
+    }
+
+    /// Returns a new tree with the leaf at `index` replaced by the hash of `data`,
+    /// leaving `self` unchanged
+    ///
+    /// # Returns
+    /// The new tree, or `IndexOutOfBounds` if there is no leaf at `index`
+    pub fn update<T: Hashable + ?Sized>(
+        &self,
+        index: usize,
+        data: &T,
+    ) -> Result<Self, MerkleError> {
+        match &self.root {
+            Some(root) if index < self.len => Ok(PersistentMerkleTree {
+                root: Some(replace(
+                    root,
+                    self.height,
+                    index,
+                    Node::leaf(hash(data.leaf_bytes())),
+                )),
+                ..*self
+            }),
+            _ => Err(MerkleError::IndexOutOfBounds),
+        }
+    }
+
+    /// Generates a proof of membership for the first leaf holding `data`
+    /// The proof has the same format as the ones of `MerkleTree::generate_proof`
+    pub fn generate_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<MerkleProof, MerkleError> {
+        let leaf = hash(data.leaf_bytes());
+        let index = position_of(&self.leaves(), &leaf).ok_or(MerkleError::LeafNotFound)?;
+
+        let mut siblings = Vec::with_capacity(self.height);
+        let mut node = self.root.as_ref().expect("A leaf was found");
+        for level in (1..=self.height).rev() {
+            let half = 1 << (level - 1);
+            let left = node.left();
+            if index % (half << 1) < half {
+                let sibling = node.right.as_ref().unwrap_or(left);
+                siblings.push((sibling.hash, Direction::Right));
+                node = left;
+            } else {
+                siblings.push((left.hash, Direction::Left));
+                node = node
+                    .right
+                    .as_ref()
+                    .expect("The leaf is in the right subtree");
+            }
+        }
+        // The proof goes from the leaf up to the root
+        siblings.reverse();
+        Ok(MerkleProof::from(siblings))
+    }
+
+    /// Validates a Merkle proof for a given piece of data against the root of this tree
+    /// Nothing is a member of an empty tree, so every proof fails against it
+    pub fn verify_proof<T: Hashable + ?Sized>(&self, data: &T, proof: &MerkleProof) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        proof.verify(&self.root(), data)
+    }
+}
+
+impl Default for PersistentMerkleTree {
+    /// The default tree is the empty tree
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// Builds the subtree of `height` levels over `leaves`, which fit in its left-aligned range
+fn build(leaves: &[Hash], height: usize) -> Arc<Node> {
+    if height == 0 {
+        return Node::leaf(leaves[0]);
+    }
+    let half = 1 << (height - 1);
+    if leaves.len() <= half {
+        return Node::branch(build(leaves, height - 1), None);
+    }
+    Node::branch(
+        build(&leaves[..half], height - 1),
+        Some(build(&leaves[half..], height - 1)),
+    )
+}
+
+/// Wraps `leaf` in `height` levels of nodes without a right child
+fn lone_path(leaf: Arc<Node>, height: usize) -> Arc<Node> {
+    (0..height).fold(leaf, |node, _| Node::branch(node, None))
+}
+
+/// Copies the path to the first free position `index` of a subtree that is not complete,
+/// placing `leaf` there
+fn insert(node: &Arc<Node>, height: usize, index: usize, leaf: Arc<Node>) -> Arc<Node> {
+    let half = 1 << (height - 1);
+    if index < half {
+        Node::branch(insert(node.left(), height - 1, index, leaf), None)
+    } else {
+        let right = match &node.right {
+            Some(right) => insert(right, height - 1, index - half, leaf),
+            None => lone_path(leaf, height - 1),
+        };
+        Node::branch(Arc::clone(node.left()), Some(right))
+    }
+}
+
+/// Copies the path to the leaf at `index`, replacing it with `leaf`
+fn replace(node: &Arc<Node>, height: usize, index: usize, leaf: Arc<Node>) -> Arc<Node> {
+    if height == 0 {
+        return leaf;
+    }
+    let half = 1 << (height - 1);
+    if index < half {
+        Node::branch(
+            replace(node.left(), height - 1, index, leaf),
+            node.right.clone(),
+        )
+    } else {
+        let right = node
+            .right
+            .as_ref()
+            .expect("The leaf is in the right subtree");
+        Node::branch(
+            Arc::clone(node.left()),
+            Some(replace(right, height - 1, index - half, leaf)),
+        )
+    }
+}
+
+fn collect_leaves(node: &Node, leaves: &mut Vec<Hash>) {
+    match &node.left {
+        None => leaves.push(node.hash),
+        Some(left) => {
+            collect_leaves(left, leaves);
+            if let Some(right) = &node.right {
+                collect_leaves(right, leaves);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::MerkleTree;
+
+    #[test]
+    fn test_matches_merkle_tree() {
+        let data: Vec<Vec<u8>> = (0..9).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut persistent = PersistentMerkleTree::empty();
+        assert_eq!(persistent.root(), empty_root());
+        for n in 1..=data.len() {
+            persistent = persistent.add(&data[n - 1]);
+            let expected = MerkleTree::new(&data[..n]).expect("Should create merkle tree");
+            assert_eq!(persistent.len(), n);
+            assert_eq!(persistent.root(), expected.root());
+            assert_eq!(
+                PersistentMerkleTree::new(&data[..n])
+                    .expect("Should create persistent tree")
+                    .root(),
+                expected.root()
+            );
+            for element in &data[..n] {
+                assert_eq!(
+                    persistent
+                        .generate_proof(element)
+                        .expect("Should generate proof"),
+                    expected
+                        .generate_proof(element)
+                        .expect("Should generate proof")
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let data: Vec<Vec<u8>> = (0..5).map(|i| format!("block{}", i).into_bytes()).collect();
+        let first = PersistentMerkleTree::new(&data[..3]).expect("Should create persistent tree");
+        let second = first.add(&data[3]).add(&data[4]);
+        let third = second.update(1, b"block9").expect("Should update leaf");
+        assert!(matches!(
+            third.update(5, b"block9"),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+
+        // Each snapshot keeps its own root and proofs
+        let mut updated = data.clone();
+        updated[1] = b"block9".to_vec();
+        let snapshots = [
+            (&first, &data[..3]),
+            (&second, &data[..]),
+            (&third, &updated[..]),
+        ];
+        for (snapshot, elements) in snapshots {
+            let expected = MerkleTree::new(elements).expect("Should create merkle tree");
+            assert_eq!(snapshot.root(), expected.root());
+            for element in elements {
+                let proof = snapshot
+                    .generate_proof(element)
+                    .expect("Should generate proof");
+                assert!(snapshot.verify_proof(element, &proof));
+            }
+        }
+        assert!(first.generate_proof(&data[3]).is_err());
+        assert!(third.generate_proof(&data[1]).is_err());
+
+        // Unchanged subtrees are shared, not copied
+        let first_root = first.root.as_ref().expect("Should have a root");
+        let second_root = second.root.as_ref().expect("Should have a root");
+        let third_root = third.root.as_ref().expect("Should have a root");
+        assert!(Arc::ptr_eq(first_root.left(), second_root.left().left()));
+        assert!(Arc::ptr_eq(
+            second_root
+                .right
+                .as_ref()
+                .expect("Should have a right child"),
+            third_root
+                .right
+                .as_ref()
+                .expect("Should have a right child")
+        ));
+    }
+}
@@ -0,0 +1,195 @@
+//! Merkle trees over the BN254 scalar field, hashed with Poseidon
+//!
+//! Nodes are hashed with the circom parameters of `light-poseidon`, so they match
+//! `poseidon` of circomlib and circomlibjs, and witnesses can be fed to circom circuits.
+use crate::merkle::{Direction, Hash, MerkleError, MerkleTree, TreeHasher};
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+use light_poseidon::{Poseidon as PoseidonHash, PoseidonHasher};
+
+/// Most inputs hashed at once by the circom parameters
+const MAX_INPUTS: usize = 12;
+
+/// Poseidon over the BN254 scalar field, hashing nodes as `Poseidon(left, right)`
+/// Every input is read as 32-byte big-endian field elements, reduced modulo the field order.
+/// Leaves are the field elements themselves, so they can be checked in a circuit as they are
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Poseidon;
+
+/// Encodes a field element as 32 big-endian bytes
+fn to_hash(element: Fr) -> Hash {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&element.into_bigint().to_bytes_be());
+    hash
+}
+
+/// Hashes up to `MAX_INPUTS` elements with the circom parameters for that many inputs
+fn poseidon(inputs: &[Fr]) -> Fr {
+    PoseidonHash::<Fr>::new_circom(inputs.len())
+        .and_then(|mut hasher| hasher.hash(inputs))
+        .expect("Should hash between 1 and 12 inputs")
+}
+
+impl TreeHasher for Poseidon {
+    /// Up to 12 elements are hashed at once, longer inputs are chained, hashing the
+    /// previous result with up to 11 more elements
+    fn hash_parts(&self, parts: &[&[u8]]) -> Hash {
+        let bytes = parts.concat();
+        let mut elements: Vec<Fr> = bytes.chunks(32).map(Fr::from_be_bytes_mod_order).collect();
+        if elements.is_empty() {
+            elements.push(Fr::from(0u64));
+        }
+        let first = elements.len().min(MAX_INPUTS);
+        let mut hash = poseidon(&elements[..first]);
+        for chunk in elements[first..].chunks(MAX_INPUTS - 1) {
+            let inputs: Vec<Fr> = std::iter::once(hash).chain(chunk.iter().copied()).collect();
+            hash = poseidon(&inputs);
+        }
+        to_hash(hash)
+    }
+
+    /// The leaf is the data read as a field element, without hashing it
+    fn hash_leaf_bytes(&self, data: &[u8]) -> Hash {
+        to_hash(Fr::from_be_bytes_mod_order(data))
+    }
+}
+
+/// A membership witness as field elements, laid out like circom Merkle circuits expect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoseidonWitness {
+    pub leaf: Fr,
+    /// The sibling of each level, from the leaf up to the root
+    pub path_elements: Vec<Fr>,
+    /// For each level, 0 if the node is the left child and 1 if it is the right one
+    pub path_indices: Vec<u8>,
+    pub root: Fr,
+}
+
+impl MerkleTree<Poseidon> {
+    /// Creates a new Merkle Tree whose leaves are field elements, encoded as 32 big-endian bytes
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the elements are not empty, otherwise an error
+    /// `NotInField` is returned for the first element that is not smaller than the field order
+    pub fn from_field_elements(elements: &[Hash]) -> Result<Self, MerkleError> {
+        if let Some(index) = elements
+            .iter()
+            .position(|element| to_hash(Fr::from_be_bytes_mod_order(element)) != *element)
+        {
+            return Err(MerkleError::NotInField(index));
+        }
+        Self::from_leaves_with_digest(elements.to_vec())
+    }
+
+    /// Generates the membership witness of `element` as field elements
+    ///
+    /// # Returns
+    /// The witness, or `LeafNotFound` if the element is not a leaf of the tree
+    pub fn generate_witness(&self, element: &Hash) -> Result<PoseidonWitness, MerkleError> {
+        let proof = self.generate_proof(element)?;
+        let read = |hash: &Hash| Fr::from_be_bytes_mod_order(hash);
+        Ok(PoseidonWitness {
+            leaf: read(element),
+            path_elements: proof.iter().map(|(sibling, _)| read(sibling)).collect(),
+            path_indices: proof
+                .iter()
+                .map(|(_, direction)| u8::from(direction == Direction::Left))
+                .collect(),
+            root: read(&self.root()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::bytes_from_hex;
+
+    fn element(value: u64) -> Hash {
+        to_hash(Fr::from(value))
+    }
+
+    #[test]
+    fn test_matches_circomlib() {
+        let tree =
+            MerkleTree::from_field_elements(&[element(1), element(2), element(3), element(4)])
+                .expect("Should create merkle tree");
+        // poseidon([1, 2]) of circomlibjs
+        let reference = "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a";
+        let expected = bytes_from_hex(reference);
+        let left = Poseidon.hash_parts(&[&element(1), &element(2)]);
+        assert_eq!(left.to_vec(), expected);
+        let right = Poseidon.hash_parts(&[&element(3), &element(4)]);
+        assert_eq!(tree.root(), Poseidon.hash_parts(&[&left, &right]));
+
+        for value in 1..=4 {
+            let proof = tree
+                .generate_proof(&element(value))
+                .expect("Should generate proof");
+            assert!(tree.verify_proof(&element(value), &proof));
+        }
+    }
+
+    #[test]
+    fn test_witness() {
+        let elements: Vec<Hash> = (10..15).map(element).collect();
+        let tree = MerkleTree::from_field_elements(&elements).expect("Should create merkle tree");
+        for element in &elements {
+            let witness = tree
+                .generate_witness(element)
+                .expect("Should generate witness");
+            // Recompute the root the way a circuit does
+            let root = witness
+                .path_elements
+                .iter()
+                .zip(&witness.path_indices)
+                .fold(witness.leaf, |node, (sibling, index)| match index {
+                    0 => poseidon(&[node, *sibling]),
+                    _ => poseidon(&[*sibling, node]),
+                });
+            assert_eq!(root, witness.root);
+            assert_eq!(to_hash(witness.root), tree.root());
+        }
+        assert!(matches!(
+            tree.generate_witness(&element(99)),
+            Err(MerkleError::LeafNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_range_checks() {
+        // The BN254 scalar field order is not a field element, and neither is anything above it
+        let mut order = [0u8; 32];
+        order.copy_from_slice(&Fr::MODULUS.to_bytes_be());
+        assert!(matches!(
+            MerkleTree::from_field_elements(&[element(1), order]),
+            Err(MerkleError::NotInField(1))
+        ));
+        assert!(matches!(
+            MerkleTree::from_field_elements(&[[0xff; 32]]),
+            Err(MerkleError::NotInField(0))
+        ));
+        assert!(matches!(
+            MerkleTree::from_field_elements(&[]),
+            Err(MerkleError::EmptyData)
+        ));
+    }
+
+    #[test]
+    fn test_proof_witness_matches_poseidon_witness() {
+        let elements: Vec<Hash> = (1..=6).map(element).collect();
+        let tree = MerkleTree::from_field_elements(&elements).expect("Should create merkle tree");
+        for element in &elements {
+            let proof = tree.generate_proof(element).expect("Should generate proof");
+            let witness = proof.to_witness(16).expect("Should fit the circuit");
+            let expected = tree
+                .generate_witness(element)
+                .expect("Should generate witness");
+            assert_eq!(witness.levels, expected.path_indices.len());
+            for (bit, index) in witness.path_bits.iter().zip(&expected.path_indices) {
+                assert_eq!(u8::from(*bit), *index);
+            }
+            assert_eq!(witness.compute_root(&Poseidon, element), tree.root());
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::merkle::{empty_root, hash, Hash, Hashable, MerkleError, MerkleProof, MerkleTree};
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+/// A Merkle Tree over the last `window` elements pushed to it
+/// The root is always the same as the one of a fresh `MerkleTree` over the current window.
+/// Evicting the oldest leaf shifts every other leaf one position, which changes the pairing of
+/// every level, so no node survives an eviction: `push` only hashes the new leaf, and the nodes
+/// are built once on the first read after a push
+#[derive(Debug)]
+pub struct RollingMerkleTree {
+    window: usize,
+    leaves: VecDeque<Hash>,
+    /// The tree over the current window, built on the first read
+    tree: OnceLock<MerkleTree>,
+}
+
+impl RollingMerkleTree {
+    /// Creates an empty rolling tree keeping up to `window` leaves
+    ///
+    /// # Returns
+    /// The rolling tree, or `InvalidWindowSize` if `window` is 0
+    pub fn with_window(window: usize) -> Result<Self, MerkleError> {
+        if window == 0 {
+            return Err(MerkleError::InvalidWindowSize);
+        }
+        Ok(RollingMerkleTree {
+            window,
+            leaves: VecDeque::with_capacity(window),
+            tree: OnceLock::new(),
+        })
+    }
+
+    /// Appends an element, evicting the oldest one if the window is full
+    ///
+    /// # Returns
+    /// The hash of the evicted leaf, if there was one
+    pub fn push<T: Hashable>(&mut self, item: T) -> Option<Hash> {
+        self.tree.take();
+        self.leaves.push_back(hash(item.leaf_bytes()));
+        if self.leaves.len() > self.window {
+            self.leaves.pop_front()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the maximum number of leaves
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Returns the number of leaves currently in the window
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns true if nothing was pushed yet
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Returns the root hash of the current window, `empty_root()` if nothing was pushed yet
+    pub fn root(&self) -> Hash {
+        match self.built() {
+            Some(tree) => tree.root(),
+            None => empty_root(),
+        }
+    }
+
+    /// Generates a proof of membership in the current window
+    pub fn generate_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<MerkleProof, MerkleError> {
+        self.built()
+            .ok_or(MerkleError::LeafNotFound)?
+            .generate_proof(data)
+    }
+
+    /// Validates a proof against the root of the current window
+    pub fn verify_proof<T: Hashable + ?Sized>(&self, data: &T, proof: &MerkleProof) -> bool {
+        self.built()
+            .is_some_and(|tree| tree.verify_proof(data, proof))
+    }
+
+    fn built(&self) -> Option<&MerkleTree> {
+        if self.leaves.is_empty() {
+            return None;
+        }
+        Some(self.tree.get_or_init(|| {
+            MerkleTree::from_leaves(self.leaves.iter().copied().collect())
+                .expect("The window is not empty")
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root_matches_window() {
+        let window = 5;
+        let data: Vec<Vec<u8>> = (0..3 * window)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let mut rolling = RollingMerkleTree::with_window(window).expect("Should create tree");
+        assert_eq!(rolling.root(), empty_root());
+
+        for (n, element) in data.iter().enumerate() {
+            let evicted = rolling.push(element);
+            let start = (n + 1).saturating_sub(window);
+            assert_eq!(evicted, start.checked_sub(1).map(|i| hash(&data[i])));
+            // Check at several points, before and after the window fills up
+            if n % 4 == 1 {
+                let expected =
+                    MerkleTree::new(&data[start..=n]).expect("Should create merkle tree");
+                assert_eq!(rolling.len(), n + 1 - start);
+                assert_eq!(rolling.root(), expected.root());
+            }
+        }
+
+        let expected = MerkleTree::new(&data[2 * window..]).expect("Should create merkle tree");
+        assert_eq!(rolling.root(), expected.root());
+        let proof = rolling
+            .generate_proof(&data[3 * window - 1])
+            .expect("Should generate proof");
+        assert!(rolling.verify_proof(&data[3 * window - 1], &proof));
+        assert!(rolling.generate_proof(&data[0]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_window() {
+        assert!(matches!(
+            RollingMerkleTree::with_window(0),
+            Err(MerkleError::InvalidWindowSize)
+        ));
+    }
+}
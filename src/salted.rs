@@ -0,0 +1,257 @@
+#[cfg(feature = "zeroize")]
+use crate::merkle::wipe;
+use crate::merkle::{digest_parts, Hash, Hashable, MerkleError, MerkleProof, MerkleTree};
+use rand_core::RngCore;
+use sha3::Sha3_256;
+#[cfg(feature = "zeroize")]
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// A random value mixed into a leaf so that its hash can't be guessed from its data
+pub type Salt = [u8; 32];
+
+/// A Merkle Tree whose leaves are hashed with a random salt each, as `H(salt || data)`
+/// Publishing a proof reveals the hashes of other leaves, which could be brute-forced
+/// when their data has little entropy, but not without their salts
+/// With the `zeroize` feature, the salts are wiped when the tree is dropped
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "zeroize", derive(Zeroize, ZeroizeOnDrop))]
+pub struct SaltedMerkleTree {
+    #[cfg_attr(feature = "zeroize", zeroize(skip))]
+    tree: MerkleTree,
+    /// Salt of each leaf, indexed like the leaves
+    salts: Vec<Salt>,
+}
+
+/// A proof of membership for a salted tree, carrying the salt of the proven leaf
+/// With the `zeroize` feature, the salt is wiped when the proof is dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "zeroize", derive(Zeroize, ZeroizeOnDrop))]
+pub struct SaltedProof {
+    pub salt: Salt,
+    #[cfg_attr(feature = "zeroize", zeroize(skip))]
+    pub proof: MerkleProof,
+}
+
+fn salted_leaf<T: Hashable + ?Sized>(salt: &Salt, data: &T) -> Hash {
+    let bytes = data.leaf_bytes();
+    let leaf = digest_parts::<Sha3_256, 32>(&[salt, &bytes]);
+    #[cfg(feature = "zeroize")]
+    wipe(bytes);
+    leaf
+}
+
+fn random_salt<R: RngCore>(rng: &mut R) -> Salt {
+    let mut salt = [0u8; 32];
+    rng.fill_bytes(&mut salt);
+    salt
+}
+
+impl SaltedMerkleTree {
+    /// Creates a tree over `data`, drawing a new salt for each element from `rng`
+    /// `rng` should be a cryptographically secure generator, like `rand::rngs::OsRng`
+    ///
+    /// # Returns
+    /// A SaltedMerkleTree instance if the data is not empty, otherwise an error
+    pub fn new_salted<T: Hashable, R: RngCore>(
+        data: &[T],
+        rng: &mut R,
+    ) -> Result<Self, MerkleError> {
+        let salts: Vec<Salt> = data.iter().map(|_| random_salt(rng)).collect();
+        let leaves = data
+            .iter()
+            .zip(&salts)
+            .map(|(element, salt)| salted_leaf(salt, element))
+            .collect();
+        Ok(SaltedMerkleTree {
+            tree: MerkleTree::from_leaves(leaves)?,
+            salts,
+        })
+    }
+
+    /// Returns the wrapped tree, whose leaves are the salted hashes
+    pub fn tree(&self) -> &MerkleTree {
+        &self.tree
+    }
+
+    /// Returns the root hash
+    pub fn root(&self) -> Hash {
+        self.tree.root()
+    }
+
+    /// Returns the number of leaves
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns the salt of the leaf at `index`, or None if there is no such leaf
+    pub fn salt(&self, index: usize) -> Option<&Salt> {
+        self.salts.get(index)
+    }
+
+    /// Appends an element with a new salt drawn from `rng`
+    ///
+    /// # Returns
+    /// The index of the new leaf
+    pub fn add<T: Hashable, R: RngCore>(
+        &mut self,
+        data: &T,
+        rng: &mut R,
+    ) -> Result<usize, MerkleError> {
+        let salt = random_salt(rng);
+        self.tree.extend_leaves(vec![salted_leaf(&salt, data)])?;
+        // Growing the salts in place would leave a copy of them in the old allocation
+        #[cfg(feature = "zeroize")]
+        if self.salts.len() == self.salts.capacity() {
+            let mut grown = Vec::with_capacity((2 * self.salts.len()).max(4));
+            grown.extend_from_slice(&self.salts);
+            std::mem::replace(&mut self.salts, grown).zeroize();
+        }
+        self.salts.push(salt);
+        Ok(self.salts.len() - 1)
+    }
+
+    /// Generates a proof of membership for the leaf at `index`, carrying its salt
+    /// Proofs are generated by index, since the data alone doesn't identify a salted leaf
+    ///
+    /// # Returns
+    /// The proof, or `IndexOutOfBounds` if there is no leaf at `index`
+    pub fn generate_proof(&self, index: usize) -> Result<SaltedProof, MerkleError> {
+        let salt = *self.salt(index).ok_or(MerkleError::IndexOutOfBounds)?;
+        Ok(SaltedProof {
+            salt,
+            proof: self.tree.generate_proof_by_index(index)?,
+        })
+    }
+
+    /// Validates a salted proof for a given piece of data
+    /// Returns true if the proof is valid, false otherwise
+    /// The proof must have the shape of the proof of a leaf, so the children of an internal
+    /// node can't pass as a salt and its data
+    pub fn verify_proof<T: Hashable + ?Sized>(&self, data: &T, proof: &SaltedProof) -> bool {
+        self.tree
+            .verify_proof_for_hash(&salted_leaf(&proof.salt, data), &proof.proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn salaries() -> Vec<Vec<u8>> {
+        [52_000u32, 61_000, 61_000, 75_000, 98_000]
+            .iter()
+            .map(|salary| salary.to_be_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_salted_proofs() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let data = salaries();
+        let mut tree =
+            SaltedMerkleTree::new_salted(&data[..4], &mut rng).expect("Should create salted tree");
+        tree.add(&data[4], &mut rng).expect("Should add leaf");
+
+        // Equal data still gets unrelated leaves
+        assert_ne!(tree.salt(1), tree.salt(2));
+        assert_ne!(tree.tree().leaf(1), tree.tree().leaf(2));
+        assert_ne!(
+            tree.root(),
+            MerkleTree::new(&data)
+                .expect("Should create merkle tree")
+                .root()
+        );
+
+        for (index, element) in data.iter().enumerate() {
+            let proof = tree.generate_proof(index).expect("Should generate proof");
+            assert_eq!(&proof.salt, tree.salt(index).expect("Should have a salt"));
+            assert!(tree.verify_proof(element, &proof));
+
+            let unsalted = SaltedProof {
+                salt: [0u8; 32],
+                proof: proof.proof.clone(),
+            };
+            assert!(!tree.verify_proof(element, &unsalted));
+        }
+        let proof = tree.generate_proof(0).expect("Should generate proof");
+        assert!(!tree.verify_proof(&data[3], &proof));
+        assert!(matches!(
+            tree.generate_proof(5),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn test_rejects_children_of_internal_node_as_salt_and_data() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let tree = SaltedMerkleTree::new_salted(&salaries()[..4], &mut rng)
+            .expect("Should create salted tree");
+        let levels = tree.tree().levels();
+        // The parent of the first two leaves is `H(left || right)`, like a salted leaf
+        let forged = SaltedProof {
+            salt: levels[0][0],
+            proof: tree
+                .generate_proof(0)
+                .expect("Should generate proof")
+                .proof
+                .iter()
+                .skip(1)
+                .map(|(sibling, direction)| (*sibling, direction))
+                .collect::<Vec<_>>()
+                .into(),
+        };
+        assert!(!tree.verify_proof(&levels[0][1], &forged));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialized_proof_carries_salt() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let data = salaries();
+        let tree =
+            SaltedMerkleTree::new_salted(&data, &mut rng).expect("Should create salted tree");
+        let proof = tree.generate_proof(3).expect("Should generate proof");
+        let bytes = bincode::serialize(&proof).expect("Should serialize proof");
+        let decoded: SaltedProof = bincode::deserialize(&bytes).expect("Should deserialize proof");
+        assert_eq!(decoded, proof);
+        assert!(tree.verify_proof(&data[3], &decoded));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize_salts() {
+        fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+        assert_zeroize_on_drop::<SaltedMerkleTree>();
+        assert_zeroize_on_drop::<SaltedProof>();
+
+        let mut rng = StdRng::seed_from_u64(13);
+        let data = salaries();
+        let mut tree =
+            SaltedMerkleTree::new_salted(&data[..1], &mut rng).expect("Should create salted tree");
+        for element in &data[1..] {
+            tree.add(element, &mut rng).expect("Should add leaf");
+        }
+        for (index, element) in data.iter().enumerate() {
+            let proof = tree.generate_proof(index).expect("Should generate proof");
+            assert!(tree.verify_proof(element, &proof));
+        }
+
+        let mut proof = tree.generate_proof(0).expect("Should generate proof");
+        proof.zeroize();
+        assert_eq!(proof.salt, [0u8; 32]);
+        // The proof path is public and kept
+        assert!(!proof.proof.is_empty());
+        let root = tree.root();
+        tree.zeroize();
+        assert_eq!(tree.salt(0), None);
+        assert_eq!(tree.root(), root);
+    }
+}
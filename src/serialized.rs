@@ -0,0 +1,687 @@
+//! Leaves for any `Serialize` type
+//!
+//! Each item is encoded with bincode 1.x's default configuration before it is hashed:
+//! little-endian fixed-size integers, every length as a `u64`, enum variants as a `u32` index,
+//! struct fields in declaration order without their names, and `Option` as a `0` or `1` byte
+//! followed by the value. A verifier that encodes an item the same way and hashes the bytes
+//! like any other leaf gets the same leaf hash.
+//! Maps and sets are encoded in iteration order, so only ordered ones like `BTreeMap` have a
+//! single encoding, a `HashMap` can encode differently from one run to the next.
+//!
+//! Proofs and trees are serialized as a `TaggedProof` or `TaggedTree`, which carry the
+//! `HashAlgorithm` of the tree as its name. Loading one into a tree that hashes differently fails
+//! with `AlgorithmMismatch` instead of a proof that just doesn't verify.
+//!
+//! Decoding is meant for untrusted input: a length prefix can't make the decoder read or allocate
+//! more than the input holds, trailing bytes are refused, and proofs longer than `MAX_PROOF_LEN`
+//! are refused before they are verified. The fuzz targets in `fuzz/` exercise these paths.
+//!
+//! Proofs meant to be archived are wrapped in a `ProofEnvelope`, which also carries the index
+//! of the leaf, the number of leaves of the tree, the format version and a caller's timestamp
+//! and label, everything needed to verify it against a root long after the tree is gone.
+
+#[cfg(feature = "blake3")]
+use crate::merkle::Blake3;
+use crate::merkle::{
+    hashes_equal, positioned_root, Hash, HashAlgorithm, Hashable, LeafHashing, MerkleError,
+    MerkleProof, MerkleTree, OddNodePolicy, PositionedProof, TreeHasher, PROOF_FORMAT_VERSION,
+};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "sha2")]
+use sha2::Sha256;
+use sha3::{Keccak256, Sha3_256};
+
+/// Encodes `item` with the encoding described in the module documentation
+/// The buffer is allocated with the exact encoded size, so it is never reallocated
+pub fn encode<T: Serialize + ?Sized>(item: &T) -> Result<Vec<u8>, MerkleError> {
+    Ok(bincode::serialize(item)?)
+}
+
+/// Decodes an item encoded with `encode`
+/// The whole input has to be used, trailing bytes are a `Serialization` error
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, MerkleError> {
+    Ok(bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .with_limit(bytes.len() as u64)
+        .deserialize(bytes)?)
+}
+
+pub use crate::merkle::MAX_PROOF_LEN;
+
+impl Serialize for HashAlgorithm {
+    /// Serialized as its name, like `"keccak256"`
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for HashAlgorithm {
+    /// Names of algorithms whose feature is disabled are rejected like unknown ones
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse()
+            .map_err(|_| serde::de::Error::custom(format!("unknown hash algorithm {}", name)))
+    }
+}
+
+/// A hash function with a `HashAlgorithm` identifier, which tags the serialized proofs and trees
+/// Keyed hash functions have none, their trees can't be serialized without the key
+pub trait AlgorithmTag {
+    fn algorithm_tag(&self) -> HashAlgorithm;
+}
+
+impl AlgorithmTag for HashAlgorithm {
+    fn algorithm_tag(&self) -> HashAlgorithm {
+        *self
+    }
+}
+
+impl AlgorithmTag for Sha3_256 {
+    fn algorithm_tag(&self) -> HashAlgorithm {
+        HashAlgorithm::Sha3_256
+    }
+}
+
+#[cfg(feature = "sha2")]
+impl AlgorithmTag for Sha256 {
+    fn algorithm_tag(&self) -> HashAlgorithm {
+        HashAlgorithm::Sha2_256
+    }
+}
+
+impl AlgorithmTag for Keccak256 {
+    fn algorithm_tag(&self) -> HashAlgorithm {
+        HashAlgorithm::Keccak256
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl AlgorithmTag for Blake3 {
+    fn algorithm_tag(&self) -> HashAlgorithm {
+        HashAlgorithm::Blake3
+    }
+}
+
+/// A proof tagged with the hash function of the tree that generated it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaggedProof {
+    pub algorithm: HashAlgorithm,
+    pub proof: MerkleProof,
+}
+
+/// The leaves of a tree tagged with its hash function, the format of `to_tagged_bytes`
+/// Other settings are not saved, the tree is rebuilt with the defaults of `with_digest`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaggedTree {
+    pub algorithm: HashAlgorithm,
+    pub leaves: Vec<Hash>,
+}
+
+impl<D: TreeHasher + AlgorithmTag> MerkleTree<D> {
+    /// Generates a proof of membership tagged with the hash function of the tree
+    pub fn generate_tagged_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<TaggedProof, MerkleError> {
+        Ok(TaggedProof {
+            algorithm: self.hasher().algorithm_tag(),
+            proof: self.generate_proof(data)?,
+        })
+    }
+
+    /// Validates a tagged proof for a given piece of data
+    ///
+    /// # Returns
+    /// Whether the proof is valid, `AlgorithmMismatch` if it was generated with another
+    /// hash function, or `ProofTooLong` if it has more than `MAX_PROOF_LEN` siblings
+    pub fn verify_tagged_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+        proof: &TaggedProof,
+    ) -> Result<bool, MerkleError> {
+        self.check_proof(proof)?;
+        Ok(self.verify_proof(data, &proof.proof))
+    }
+
+    /// Decodes an encoded `TaggedProof` for this tree
+    ///
+    /// # Returns
+    /// The proof, `AlgorithmMismatch` if it was generated with another hash function,
+    /// `ProofTooLong` if it has more than `MAX_PROOF_LEN` siblings,
+    /// or `Serialization` if the bytes can't be decoded
+    pub fn decode_proof(&self, bytes: &[u8]) -> Result<MerkleProof, MerkleError> {
+        let tagged: TaggedProof = decode(bytes)?;
+        self.check_proof(&tagged)?;
+        Ok(tagged.proof)
+    }
+
+    /// Encodes the leaves of the tree as a `TaggedTree`
+    pub fn to_tagged_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        encode(&TaggedTree {
+            algorithm: self.hasher().algorithm_tag(),
+            leaves: self.leaves().to_vec(),
+        })
+    }
+
+    /// Rebuilds a tree hashed with `hasher` from the output of `to_tagged_bytes`
+    ///
+    /// # Returns
+    /// The tree, `AlgorithmMismatch` if it was saved with another hash function than `hasher`,
+    /// or `Serialization` if the bytes can't be decoded
+    pub fn from_tagged_bytes(hasher: D, bytes: &[u8]) -> Result<Self, MerkleError> {
+        let tagged: TaggedTree = decode(bytes)?;
+        let expected = hasher.algorithm_tag();
+        if tagged.algorithm != expected {
+            return Err(MerkleError::AlgorithmMismatch {
+                expected,
+                found: tagged.algorithm,
+            });
+        }
+        Ok(Self::from_leaves_with(hasher, tagged.leaves))
+    }
+
+    fn check_proof(&self, proof: &TaggedProof) -> Result<(), MerkleError> {
+        let expected = self.hasher().algorithm_tag();
+        if proof.algorithm != expected {
+            return Err(MerkleError::AlgorithmMismatch {
+                expected,
+                found: proof.algorithm,
+            });
+        }
+        if proof.proof.len() > MAX_PROOF_LEN {
+            return Err(MerkleError::ProofTooLong);
+        }
+        Ok(())
+    }
+}
+
+/// A proof with everything needed to verify it years later, see the module documentation
+/// Its first byte is the format version, so a decoder can refuse a format it doesn't know
+/// before reading anything else
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofEnvelope {
+    /// `PROOF_FORMAT_VERSION` when the envelope was created
+    #[serde(deserialize_with = "known_format_version")]
+    pub version: u8,
+    pub algorithm: HashAlgorithm,
+    /// The proof with the index of its leaf and the number of leaves of the tree
+    pub proof: PositionedProof,
+    /// Set by the caller, like the seconds since the Unix epoch when the proof was generated
+    pub timestamp: Option<u64>,
+    pub label: Option<String>,
+}
+
+fn known_format_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let version = u8::deserialize(deserializer)?;
+    if version != PROOF_FORMAT_VERSION {
+        return Err(serde::de::Error::custom(format!(
+            "unknown proof format version {}",
+            version
+        )));
+    }
+    Ok(version)
+}
+
+impl ProofEnvelope {
+    /// Encodes the envelope with the encoding described in the module documentation
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        encode(self)
+    }
+
+    /// Decodes the output of `to_bytes`
+    ///
+    /// # Returns
+    /// The envelope, `TruncatedInput` for empty input, `UnknownFormatVersion` for another
+    /// format version, `ProofTooLong` if the proof has more than `MAX_PROOF_LEN` siblings, or
+    /// `Serialization` if the bytes can't be decoded
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let version = *bytes.first().ok_or(MerkleError::TruncatedInput)?;
+        if version != PROOF_FORMAT_VERSION {
+            return Err(MerkleError::UnknownFormatVersion(version));
+        }
+        let envelope: Self = decode(bytes)?;
+        if envelope.proof.proof.len() > MAX_PROOF_LEN {
+            return Err(MerkleError::ProofTooLong);
+        }
+        Ok(envelope)
+    }
+
+    /// Verifies the proof for `data` against `root`, hashing with the algorithm of the envelope
+    /// like a tree with the default settings
+    /// The proof must have the siblings of the leaf at its index in a tree of its number of
+    /// leaves, see `merkle::verify_with_size`, so changing either field makes it fail
+    pub fn verify<T: Hashable + ?Sized>(&self, root: &Hash, data: &T) -> bool {
+        let leaf = self.algorithm.hash_leaf_bytes(&data.leaf_bytes());
+        positioned_root(&self.algorithm, leaf, &self.proof)
+            .is_some_and(|computed| hashes_equal(&computed, root))
+    }
+}
+
+impl<D: TreeHasher + AlgorithmTag> MerkleTree<D> {
+    /// Generates a `ProofEnvelope` for the first leaf holding `data`, without timestamp or label
+    ///
+    /// # Returns
+    /// The envelope, `LeafNotFound` if `data` is not in the tree, or `UnsupportedProof` for a
+    /// tree that doesn't hash like `with_digest`, or doesn't duplicate odd nodes
+    pub fn generate_envelope<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<ProofEnvelope, MerkleError> {
+        if self.arity() != 2
+            || self.odd_node_policy() != OddNodePolicy::DuplicateLast
+            || self.has_sorted_pairs()
+            || self.has_domain_separation()
+            || self.leaf_hashing() != LeafHashing::Single
+            || self.normalize_nfc()
+        {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        let index = self.index_of(data).ok_or(MerkleError::LeafNotFound)?;
+        Ok(ProofEnvelope {
+            version: PROOF_FORMAT_VERSION,
+            algorithm: self.hasher().algorithm_tag(),
+            proof: self.generate_positioned_proof(index)?,
+            timestamp: None,
+            label: None,
+        })
+    }
+}
+
+impl MerkleTree {
+    /// Creates a new Merkle Tree whose leaves are the hashes of the encoded `items`
+    ///
+    /// # Returns
+    /// A MerkleTree instance if the items are not empty, otherwise an error
+    /// `Serialization` is returned if an item can't be encoded
+    pub fn from_serialized<T: Serialize>(items: &[T]) -> Result<Self, MerkleError> {
+        let encoded = items.iter().map(encode).collect::<Result<Vec<_>, _>>()?;
+        #[cfg(feature = "zeroize")]
+        let encoded = zeroize::Zeroizing::new(encoded);
+        Self::new(&encoded[..])
+    }
+
+    /// Generates a proof of membership for an item of a tree built with `from_serialized`
+    pub fn generate_proof_serialized<T: Serialize + ?Sized>(
+        &self,
+        item: &T,
+    ) -> Result<MerkleProof, MerkleError> {
+        self.generate_proof(&encode(item)?)
+    }
+
+    /// Validates a proof for an item of a tree built with `from_serialized`
+    /// Items that can't be encoded are never members
+    pub fn verify_proof_serialized<T: Serialize + ?Sized>(
+        &self,
+        item: &T,
+        proof: &MerkleProof,
+    ) -> bool {
+        encode(item).is_ok_and(|encoded| self.verify_proof(&encoded, proof))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::{hash, Direction};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    fn blocks() -> Vec<Vec<u8>> {
+        (0..5).map(|i| format!("block{}", i).into_bytes()).collect()
+    }
+
+    #[derive(Serialize)]
+    struct Account {
+        id: u64,
+        owner: String,
+    }
+
+    #[test]
+    fn test_serialized_membership() {
+        let accounts: Vec<Account> = (0..4)
+            .map(|id| Account {
+                id,
+                owner: format!("owner{}", id),
+            })
+            .collect();
+        let merkle = MerkleTree::from_serialized(&accounts).expect("Should create merkle tree");
+        let proof = merkle
+            .generate_proof_serialized(&accounts[1])
+            .expect("Should generate proof");
+        assert!(merkle.verify_proof_serialized(&accounts[1], &proof));
+
+        // The documented encoding: id as a little-endian u64, then the length and bytes of owner
+        let mut expected = 1u64.to_le_bytes().to_vec();
+        expected.extend_from_slice(&6u64.to_le_bytes());
+        expected.extend_from_slice(b"owner1");
+        assert_eq!(merkle.leaf(1), Some(hash(&expected)));
+    }
+
+    #[test]
+    fn test_canonical_encoding() {
+        let mut inserted_in_order = BTreeMap::new();
+        inserted_in_order.insert("id".to_string(), "1".to_string());
+        inserted_in_order.insert("owner".to_string(), "owner1".to_string());
+        let mut inserted_reversed = BTreeMap::new();
+        inserted_reversed.insert("owner".to_string(), "owner1".to_string());
+        inserted_reversed.insert("id".to_string(), "1".to_string());
+
+        // An ordered map has a single encoding whatever the insertion order
+        assert_eq!(
+            encode(&inserted_in_order).expect("Should encode map"),
+            encode(&inserted_reversed).expect("Should encode map")
+        );
+        let merkle =
+            MerkleTree::from_serialized(&[inserted_in_order]).expect("Should create merkle tree");
+        let proof = merkle
+            .generate_proof_serialized(&inserted_reversed)
+            .expect("Should generate proof");
+        assert!(merkle.verify_proof_serialized(&inserted_reversed, &proof));
+
+        // Field names are not encoded, so the same data as a struct is a different leaf
+        let account = Account {
+            id: 1,
+            owner: "owner1".to_string(),
+        };
+        assert!(merkle.generate_proof_serialized(&account).is_err());
+        assert!(!merkle.verify_proof_serialized(&account, &proof));
+    }
+
+    #[test]
+    fn test_tagged_proof_round_trip() {
+        let data = blocks();
+        for algorithm in HashAlgorithm::ALL {
+            let tree =
+                MerkleTree::with_algorithm(*algorithm, &data).expect("Should create merkle tree");
+            let tagged = tree
+                .generate_tagged_proof(&data[3])
+                .expect("Should generate proof");
+            assert_eq!(tagged.algorithm, *algorithm);
+            let bytes = encode(&tagged).expect("Should encode proof");
+            let proof = tree.decode_proof(&bytes).expect("Should decode proof");
+            assert_eq!(proof, tagged.proof);
+            assert!(tree.verify_proof(&data[3], &proof));
+            assert!(tree
+                .verify_tagged_proof(&data[3], &tagged)
+                .expect("Should have the same algorithm"));
+        }
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let data = blocks();
+        for algorithm in HashAlgorithm::ALL {
+            let tree =
+                MerkleTree::with_algorithm(*algorithm, &data).expect("Should create merkle tree");
+            let mut envelope = tree
+                .generate_envelope(&data[4])
+                .expect("Should generate envelope");
+            envelope.timestamp = Some(1_700_000_000);
+            envelope.label = Some("archive 2023".to_string());
+            assert_eq!(envelope.version, PROOF_FORMAT_VERSION);
+            assert_eq!(envelope.algorithm, *algorithm);
+            assert_eq!(envelope.proof.index, 4);
+            assert_eq!(envelope.proof.leaf_count, 5);
+
+            let bytes = envelope.to_bytes().expect("Should encode envelope");
+            assert_eq!(bytes[0], PROOF_FORMAT_VERSION);
+            let decoded = ProofEnvelope::from_bytes(&bytes).expect("Should decode envelope");
+            assert_eq!(decoded, envelope);
+            assert!(decoded.verify(&tree.root(), &data[4]));
+            assert!(!decoded.verify(&tree.root(), &data[3]));
+
+            let json = serde_json::to_string(&envelope).expect("Should serialize envelope");
+            let decoded: ProofEnvelope =
+                serde_json::from_str(&json).expect("Should deserialize envelope");
+            assert_eq!(decoded, envelope);
+        }
+
+        // A tree without data, like any tree with the default settings
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let envelope = tree
+            .generate_envelope(&data[0])
+            .expect("Should generate envelope");
+        assert!(envelope.verify(&tree.root(), &data[0]));
+        assert!(envelope.timestamp.is_none() && envelope.label.is_none());
+    }
+
+    #[test]
+    fn test_envelope_mismatches() {
+        let data = blocks();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let envelope = tree
+            .generate_envelope(&data[2])
+            .expect("Should generate envelope");
+        assert!(envelope.verify(&tree.root(), &data[2]));
+
+        let other = ProofEnvelope {
+            algorithm: HashAlgorithm::Keccak256,
+            ..envelope.clone()
+        };
+        assert!(!other.verify(&tree.root(), &data[2]));
+        let mut resized = envelope.clone();
+        // Sizes 5 through 8 give leaf 2 the same siblings, see `verify_with_size`
+        resized.proof.leaf_count = 9;
+        assert!(!resized.verify(&tree.root(), &data[2]));
+        resized.proof.leaf_count = 4;
+        assert!(!resized.verify(&tree.root(), &data[2]));
+        let mut moved = envelope.clone();
+        moved.proof.index = 3;
+        assert!(!moved.verify(&tree.root(), &data[2]));
+
+        let mut bytes = envelope.to_bytes().expect("Should encode envelope");
+        bytes[0] = PROOF_FORMAT_VERSION + 1;
+        assert!(matches!(
+            ProofEnvelope::from_bytes(&bytes),
+            Err(MerkleError::UnknownFormatVersion(v)) if v == PROOF_FORMAT_VERSION + 1
+        ));
+        assert!(matches!(
+            ProofEnvelope::from_bytes(&[]),
+            Err(MerkleError::TruncatedInput)
+        ));
+        let future = ProofEnvelope {
+            version: PROOF_FORMAT_VERSION + 1,
+            ..envelope.clone()
+        };
+        let json = serde_json::to_string(&future).expect("Should serialize envelope");
+        assert!(serde_json::from_str::<ProofEnvelope>(&json).is_err());
+        let trailing = [
+            envelope.to_bytes().expect("Should encode envelope"),
+            vec![0],
+        ]
+        .concat();
+        assert!(matches!(
+            ProofEnvelope::from_bytes(&trailing),
+            Err(MerkleError::Serialization(_))
+        ));
+
+        let separated =
+            MerkleTree::with_domain_separation(&data).expect("Should create merkle tree");
+        assert!(matches!(
+            separated.generate_envelope(&data[0]),
+            Err(MerkleError::UnsupportedProof)
+        ));
+        assert!(matches!(
+            tree.generate_envelope(b"missing"),
+            Err(MerkleError::LeafNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_algorithm_mismatch_on_load() {
+        let data = blocks();
+        let sha3 = MerkleTree::new(&data).expect("Should create merkle tree");
+        let keccak = MerkleTree::new_keccak256(&data).expect("Should create merkle tree");
+        let dynamic = MerkleTree::with_algorithm(HashAlgorithm::Keccak256, &data)
+            .expect("Should create merkle tree");
+
+        let tagged = sha3
+            .generate_tagged_proof(&data[1])
+            .expect("Should generate proof");
+        let bytes = encode(&tagged).expect("Should encode proof");
+        assert!(matches!(
+            keccak.decode_proof(&bytes),
+            Err(MerkleError::AlgorithmMismatch {
+                expected: HashAlgorithm::Keccak256,
+                found: HashAlgorithm::Sha3_256,
+            })
+        ));
+        assert!(matches!(
+            dynamic.verify_tagged_proof(&data[1], &tagged),
+            Err(MerkleError::AlgorithmMismatch { .. })
+        ));
+        // The same tags are used by static and runtime algorithms
+        let tagged = keccak
+            .generate_tagged_proof(&data[1])
+            .expect("Should generate proof");
+        assert!(dynamic
+            .verify_tagged_proof(&data[1], &tagged)
+            .expect("Should have the same algorithm"));
+
+        // Tree files
+        let file = sha3.to_tagged_bytes().expect("Should encode tree");
+        let loaded =
+            MerkleTree::from_tagged_bytes(Sha3_256::default(), &file).expect("Should load tree");
+        assert_eq!(loaded.root(), sha3.root());
+        assert!(matches!(
+            MerkleTree::from_tagged_bytes(HashAlgorithm::Keccak256, &file),
+            Err(MerkleError::AlgorithmMismatch {
+                expected: HashAlgorithm::Keccak256,
+                found: HashAlgorithm::Sha3_256,
+            })
+        ));
+        let file = dynamic.to_tagged_bytes().expect("Should encode tree");
+        let loaded =
+            MerkleTree::from_tagged_bytes(Keccak256::default(), &file).expect("Should load tree");
+        assert_eq!(loaded.root(), keccak.root());
+
+        let empty = MerkleTree::empty()
+            .to_tagged_bytes()
+            .expect("Should encode tree");
+        let loaded =
+            MerkleTree::from_tagged_bytes(Sha3_256::default(), &empty).expect("Should load tree");
+        assert!(loaded.is_empty());
+        assert_eq!(loaded.root(), MerkleTree::empty().root());
+    }
+
+    #[test]
+    fn test_tagged_json() {
+        let data = blocks();
+        let keccak = MerkleTree::new_keccak256(&data).expect("Should create merkle tree");
+        let sha3 = MerkleTree::new(&data).expect("Should create merkle tree");
+        let tagged = keccak
+            .generate_tagged_proof(&data[0])
+            .expect("Should generate proof");
+        let json = serde_json::to_string(&tagged).expect("Should serialize proof");
+        assert!(json.starts_with(r#"{"algorithm":"keccak256","#));
+
+        let loaded: TaggedProof = serde_json::from_str(&json).expect("Should deserialize proof");
+        assert_eq!(loaded, tagged);
+        assert!(matches!(
+            sha3.verify_tagged_proof(&data[0], &loaded),
+            Err(MerkleError::AlgorithmMismatch {
+                expected: HashAlgorithm::Sha3_256,
+                found: HashAlgorithm::Keccak256,
+            })
+        ));
+
+        let unknown = json.replace("keccak256", "md5");
+        assert!(serde_json::from_str::<TaggedProof>(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_decode_hostile_input() {
+        let data = blocks();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let bytes = encode(
+            &tree
+                .generate_tagged_proof(&data[2])
+                .expect("Should generate proof"),
+        )
+        .expect("Should encode proof");
+
+        // Every truncation and any trailing byte is refused
+        for end in 0..bytes.len() {
+            assert!(matches!(
+                tree.decode_proof(&bytes[..end]),
+                Err(MerkleError::Serialization(_))
+            ));
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            tree.decode_proof(&trailing),
+            Err(MerkleError::Serialization(_))
+        ));
+
+        // Huge length prefixes fail without allocating them
+        let mut huge_name = u64::MAX.to_le_bytes().to_vec();
+        huge_name.extend_from_slice(b"sha3-256");
+        assert!(tree.decode_proof(&huge_name).is_err());
+        let name_len = 8 + "sha3-256".len();
+        let mut huge_proof = bytes[..name_len].to_vec();
+        huge_proof.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(tree.decode_proof(&huge_proof).is_err());
+        let mut huge_tree = bytes[..name_len].to_vec();
+        huge_tree.extend_from_slice(&(1u64 << 60).to_le_bytes());
+        assert!(MerkleTree::from_tagged_bytes(Sha3_256::default(), &huge_tree).is_err());
+
+        // Unknown direction variants are refused
+        let mut bad_direction = bytes.clone();
+        let last = bad_direction.len() - 4;
+        bad_direction[last..].copy_from_slice(&2u32.to_le_bytes());
+        assert!(tree.decode_proof(&bad_direction).is_err());
+
+        let too_long = TaggedProof {
+            algorithm: HashAlgorithm::Sha3_256,
+            proof: vec![([0u8; 32], Direction::Left); MAX_PROOF_LEN + 1].into(),
+        };
+        let bytes = encode(&too_long).expect("Should encode proof");
+        assert!(matches!(
+            tree.decode_proof(&bytes),
+            Err(MerkleError::ProofTooLong)
+        ));
+        assert!(matches!(
+            tree.verify_tagged_proof(&data[0], &too_long),
+            Err(MerkleError::ProofTooLong)
+        ));
+    }
+
+    #[test]
+    fn test_garbage_never_verifies() {
+        let data = blocks();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let valid = encode(
+            &tree
+                .generate_tagged_proof(&data[4])
+                .expect("Should generate proof"),
+        )
+        .expect("Should encode proof");
+        let mut rng = StdRng::seed_from_u64(60);
+        for _ in 0..2000 {
+            // Random bytes behind a valid header, or a valid proof with flipped bits
+            let mut bytes = valid.clone();
+            if rng.gen_bool(0.5) {
+                let len = rng.gen_range(16..bytes.len() + 64);
+                bytes.truncate(16);
+                bytes.resize_with(len, || rng.gen());
+            } else {
+                for _ in 0..rng.gen_range(1..4) {
+                    let index = rng.gen_range(0..bytes.len());
+                    bytes[index] ^= 1 << rng.gen_range(0..8);
+                }
+            }
+            if let Ok(proof) = tree.decode_proof(&bytes) {
+                assert!(!tree.verify_proof(b"block9", &proof));
+            }
+            if let Ok(loaded) = MerkleTree::from_tagged_bytes(Sha3_256::default(), &bytes) {
+                loaded.verify_self().expect("Should be consistent");
+            }
+        }
+    }
+}
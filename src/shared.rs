@@ -0,0 +1,122 @@
+use crate::merkle::{Hash, Hashable, MerkleError, MerkleProof, MerkleTree};
+use crate::snapshot::MerkleSnapshot;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A Merkle Tree that can be appended to and proven from several threads at once
+/// Clones share the same tree. Appends take the write lock and every read takes the read lock,
+/// so each call sees the tree between two appends, never in the middle of one
+#[derive(Debug, Clone, Default)]
+pub struct SharedMerkleTree {
+    tree: Arc<RwLock<MerkleTree>>,
+}
+
+impl SharedMerkleTree {
+    /// Shares an existing tree
+    pub fn new(tree: MerkleTree) -> Self {
+        SharedMerkleTree {
+            tree: Arc::new(RwLock::new(tree)),
+        }
+    }
+
+    /// Appends an element, see `MerkleTree::add`
+    pub fn append<T: Hashable>(&self, data: T) -> Result<usize, MerkleError> {
+        self.write().add(data)
+    }
+
+    /// Returns the current root hash
+    pub fn root(&self) -> Hash {
+        self.read().root()
+    }
+
+    /// Returns the current number of leaves
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Returns true if the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// Generates a proof for the leaf at `index` against the current root
+    /// The root may change before the caller reads it, use `prove_with_root` to get both at once
+    pub fn prove(&self, index: usize) -> Result<MerkleProof, MerkleError> {
+        self.read().generate_proof_by_index(index)
+    }
+
+    /// Generates a proof for the leaf at `index` along with the root it proves against,
+    /// both taken from the same state of the tree
+    pub fn prove_with_root(&self, index: usize) -> Result<(Hash, MerkleProof), MerkleError> {
+        let tree = self.read();
+        Ok((tree.root(), tree.generate_proof_by_index(index)?))
+    }
+
+    /// Captures the current state of the tree, to generate several proofs against the same root
+    pub fn snapshot(&self) -> MerkleSnapshot {
+        self.read().snapshot()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, MerkleTree> {
+        self.tree.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, MerkleTree> {
+        self.tree.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl From<MerkleTree> for SharedMerkleTree {
+    fn from(tree: MerkleTree) -> Self {
+        Self::new(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn event(index: usize) -> Vec<u8> {
+        format!("event{}", index).into_bytes()
+    }
+
+    #[test]
+    fn test_concurrent_append_and_prove() {
+        let shared =
+            SharedMerkleTree::new(MerkleTree::new(&[event(0)]).expect("Should create tree"));
+        let events = 300;
+
+        thread::scope(|scope| {
+            let writer = shared.clone();
+            scope.spawn(move || {
+                for index in 1..events {
+                    assert_eq!(writer.append(event(index)).expect("Should append"), index);
+                }
+            });
+
+            for prover_id in 0..4 {
+                let prover = shared.clone();
+                scope.spawn(move || {
+                    for round in 0..200 {
+                        let index = (round * 7 + prover_id) % prover.len();
+                        let (root, proof) = prover
+                            .prove_with_root(index)
+                            .expect("Should generate proof");
+                        assert!(proof.verify(&root, &event(index)));
+                    }
+                });
+            }
+        });
+
+        assert_eq!(shared.len(), events);
+        let data: Vec<Vec<u8>> = (0..events).map(event).collect();
+        assert_eq!(
+            shared.root(),
+            MerkleTree::new(&data).expect("Should create tree").root()
+        );
+        assert!(matches!(
+            shared.prove(events),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+    }
+}
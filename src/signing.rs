@@ -0,0 +1,313 @@
+//! Roots signed with ed25519, and proofs bundled with the signed root they verify against
+//!
+//! A `SignedRoot` signs `root || tree_size`, the size as 8 little-endian bytes, so a verifier
+//! holding the public key of the publisher knows both came from it. A `ProofBundle` carries
+//! everything a third party needs to check a leaf: the proof, the leaf and its index, and the
+//! signed root.
+
+use crate::merkle::{
+    hash, hashes_equal, positioned_root, Hash, Hashable, LeafHashing, MerkleError, MerkleProof,
+    MerkleTree, OddNodePolicy, PositionedProof,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha3::Sha3_256;
+
+/// Length of the encoding of a `SignedRoot`: the root, the size and the signature
+pub const SIGNED_ROOT_LEN: usize = 32 + 8 + 64;
+
+/// A root and the number of leaves of its tree, signed by the publisher of the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedRoot {
+    pub root: Hash,
+    pub tree_size: u64,
+    /// The ed25519 signature of `root || tree_size`
+    pub signature: [u8; 64],
+}
+
+/// A proof of a leaf with the signed root it verifies against, see `MerkleTree::generate_bundle`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofBundle {
+    pub proof: MerkleProof,
+    pub leaf_hash: Hash,
+    pub index: u64,
+    pub signed_root: SignedRoot,
+}
+
+/// The message signed for a root: the root, then the size as 8 little-endian bytes
+fn signed_message(root: &Hash, tree_size: u64) -> [u8; 40] {
+    let mut message = [0u8; 40];
+    message[..32].copy_from_slice(root);
+    message[32..].copy_from_slice(&tree_size.to_le_bytes());
+    message
+}
+
+impl SignedRoot {
+    /// Signs `root` and `tree_size` with `signing_key`
+    pub fn sign(root: Hash, tree_size: u64, signing_key: &SigningKey) -> Self {
+        let signature = signing_key.sign(&signed_message(&root, tree_size));
+        SignedRoot {
+            root,
+            tree_size,
+            signature: signature.to_bytes(),
+        }
+    }
+
+    /// Returns true if the signature of the root and size is valid for `verifying_key`
+    pub fn verify(&self, verifying_key: &VerifyingKey) -> bool {
+        verifying_key
+            .verify_strict(
+                &signed_message(&self.root, self.tree_size),
+                &Signature::from_bytes(&self.signature),
+            )
+            .is_ok()
+    }
+
+    /// Encodes the signed root as its root, its size as 8 little-endian bytes and its
+    /// signature, `SIGNED_ROOT_LEN` bytes in all
+    pub fn to_bytes(&self) -> [u8; SIGNED_ROOT_LEN] {
+        let mut bytes = [0u8; SIGNED_ROOT_LEN];
+        bytes[..40].copy_from_slice(&signed_message(&self.root, self.tree_size));
+        bytes[40..].copy_from_slice(&self.signature);
+        bytes
+    }
+
+    /// Decodes a signed root encoded with `to_bytes`, without checking the signature
+    ///
+    /// # Returns
+    /// The signed root, `TruncatedInput` if the input is shorter than `SIGNED_ROOT_LEN`, or
+    /// `InvalidEncoding` if it is longer
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        if bytes.len() < SIGNED_ROOT_LEN {
+            return Err(MerkleError::TruncatedInput);
+        }
+        if bytes.len() > SIGNED_ROOT_LEN {
+            return Err(MerkleError::InvalidEncoding);
+        }
+        let mut root = [0u8; 32];
+        root.copy_from_slice(&bytes[..32]);
+        let mut size = [0u8; 8];
+        size.copy_from_slice(&bytes[32..40]);
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&bytes[40..]);
+        Ok(SignedRoot {
+            root,
+            tree_size: u64::from_le_bytes(size),
+            signature,
+        })
+    }
+}
+
+impl ProofBundle {
+    /// Verifies the bundle for `data`: the signature of the root with `verifying_key`, that
+    /// `leaf_hash` is the hash of the data, and that the proof leads from it to the signed
+    /// root with the directions of `index` in a tree of the signed size
+    pub fn verify<T: Hashable + ?Sized>(&self, verifying_key: &VerifyingKey, data: &T) -> bool {
+        let (Ok(index), Ok(leaf_count)) = (
+            usize::try_from(self.index),
+            usize::try_from(self.signed_root.tree_size),
+        ) else {
+            return false;
+        };
+        let positioned = PositionedProof {
+            index,
+            leaf_count,
+            proof: self.proof.clone(),
+        };
+        self.signed_root.verify(verifying_key)
+            && hashes_equal(&self.leaf_hash, &hash(data.leaf_bytes()))
+            && positioned_root(&Sha3_256::default(), self.leaf_hash, &positioned)
+                .is_some_and(|root| hashes_equal(&root, &self.signed_root.root))
+    }
+
+    /// Encodes the bundle to hand it to third parties:
+    ///
+    /// - `SIGNED_ROOT_LEN` bytes: the signed root, see `SignedRoot::to_bytes`
+    /// - 32 bytes: the leaf hash
+    /// - 8 bytes: the index, little-endian
+    /// - the proof, encoded with `MerkleProof::to_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let proof = self.proof.to_bytes();
+        let mut bytes = Vec::with_capacity(SIGNED_ROOT_LEN + 40 + proof.len());
+        bytes.extend_from_slice(&self.signed_root.to_bytes());
+        bytes.extend_from_slice(&self.leaf_hash);
+        bytes.extend_from_slice(&self.index.to_le_bytes());
+        bytes.extend_from_slice(&proof);
+        bytes
+    }
+
+    /// Decodes a bundle encoded with `to_bytes`, without verifying it
+    ///
+    /// # Returns
+    /// The bundle, `TruncatedInput` if the input ends early, or the error of
+    /// `MerkleProof::from_bytes` for a malformed proof
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        if bytes.len() < SIGNED_ROOT_LEN + 40 {
+            return Err(MerkleError::TruncatedInput);
+        }
+        let (signed_root, rest) = bytes.split_at(SIGNED_ROOT_LEN);
+        let (leaf_hash, rest) = rest.split_at(32);
+        let (index, proof) = rest.split_at(8);
+        Ok(ProofBundle {
+            proof: MerkleProof::from_bytes(proof)?,
+            leaf_hash: leaf_hash.try_into().expect("The leaf hash has 32 bytes"),
+            index: u64::from_le_bytes(index.try_into().expect("The index has 8 bytes")),
+            signed_root: SignedRoot::from_bytes(signed_root)?,
+        })
+    }
+}
+
+impl MerkleTree {
+    /// Signs the root and the number of leaves of the tree with `signing_key`
+    ///
+    /// # Example
+    /// ```
+    /// use ed25519_dalek::SigningKey;
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    ///
+    /// let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+    /// let tree = MerkleTree::new(&["a", "b", "c"]).expect("Should create merkle tree");
+    /// let signed_root = tree.sign_root(&signing_key);
+    /// assert!(signed_root.verify(&signing_key.verifying_key()));
+    ///
+    /// let bundle = tree.generate_bundle(&"b", &signed_root).expect("Should generate bundle");
+    /// assert!(bundle.verify(&signing_key.verifying_key(), "b"));
+    /// ```
+    pub fn sign_root(&self, signing_key: &SigningKey) -> SignedRoot {
+        SignedRoot::sign(self.root(), self.len() as u64, signing_key)
+    }
+
+    /// Bundles the proof of the first leaf holding `data` with `signed_root`
+    ///
+    /// # Returns
+    /// The bundle, `LeafNotFound` if `data` is not in the tree, `RootMismatch` if
+    /// `signed_root` isn't the one of this tree, or `UnsupportedProof` for a tree that
+    /// doesn't hash like `MerkleTree::new` or doesn't duplicate odd nodes
+    pub fn generate_bundle<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+        signed_root: &SignedRoot,
+    ) -> Result<ProofBundle, MerkleError> {
+        if self.arity() != 2
+            || self.odd_node_policy() != OddNodePolicy::DuplicateLast
+            || self.has_sorted_pairs()
+            || self.has_domain_separation()
+            || self.leaf_hashing() != LeafHashing::Single
+            || self.normalize_nfc()
+        {
+            return Err(MerkleError::UnsupportedProof);
+        }
+        if !hashes_equal(&signed_root.root, &self.root())
+            || signed_root.tree_size != self.len() as u64
+        {
+            return Err(MerkleError::RootMismatch);
+        }
+        let index = self.index_of(data).ok_or(MerkleError::LeafNotFound)?;
+        let positioned = self.generate_positioned_proof(index)?;
+        Ok(ProofBundle {
+            proof: positioned.proof,
+            leaf_hash: self.leaf(index).expect("The index is in the tree"),
+            index: index as u64,
+            signed_root: signed_root.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::Direction;
+
+    fn setup() -> (SigningKey, MerkleTree, Vec<String>) {
+        let data: Vec<String> = (0..7).map(|i| format!("record{}", i)).collect();
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        (SigningKey::from_bytes(&[42u8; 32]), tree, data)
+    }
+
+    #[test]
+    fn test_signed_bundles_verify() {
+        let (signing_key, tree, data) = setup();
+        let verifying_key = signing_key.verifying_key();
+        let signed_root = tree.sign_root(&signing_key);
+        assert!(signed_root.verify(&verifying_key));
+        for item in &data {
+            let bundle = tree
+                .generate_bundle(item, &signed_root)
+                .expect("Should generate bundle");
+            assert!(bundle.verify(&verifying_key, item));
+            assert!(!bundle.verify(&verifying_key, "other"));
+
+            let decoded =
+                ProofBundle::from_bytes(&bundle.to_bytes()).expect("Should decode bundle");
+            assert_eq!(decoded, bundle);
+            assert!(decoded.verify(&verifying_key, item));
+        }
+    }
+
+    #[test]
+    fn test_bundle_rejects_wrong_key() {
+        let (signing_key, tree, data) = setup();
+        let signed_root = tree.sign_root(&signing_key);
+        let bundle = tree
+            .generate_bundle(&data[2], &signed_root)
+            .expect("Should generate bundle");
+        let other_key = SigningKey::from_bytes(&[43u8; 32]).verifying_key();
+        assert!(!signed_root.verify(&other_key));
+        assert!(!bundle.verify(&other_key, &data[2]));
+    }
+
+    #[test]
+    fn test_bundle_rejects_tampered_root() {
+        let (signing_key, tree, data) = setup();
+        let verifying_key = signing_key.verifying_key();
+        let signed_root = tree.sign_root(&signing_key);
+        let bundle = tree
+            .generate_bundle(&data[2], &signed_root)
+            .expect("Should generate bundle");
+
+        let mut tampered = bundle.clone();
+        tampered.signed_root.root[0] ^= 1;
+        assert!(!tampered.verify(&verifying_key, &data[2]));
+        let mut tampered = bundle.clone();
+        tampered.signed_root.tree_size += 1;
+        assert!(!tampered.verify(&verifying_key, &data[2]));
+
+        // A root signed by the key but for another tree doesn't match the proof
+        let other = MerkleTree::new(&["x", "y"]).expect("Should create merkle tree");
+        let mut tampered = bundle.clone();
+        tampered.signed_root = other.sign_root(&signing_key);
+        assert!(!tampered.verify(&verifying_key, &data[2]));
+        assert!(matches!(
+            tree.generate_bundle(&data[2], &other.sign_root(&signing_key)),
+            Err(MerkleError::RootMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_bundle_rejects_tampered_proof() {
+        let (signing_key, tree, data) = setup();
+        let verifying_key = signing_key.verifying_key();
+        let signed_root = tree.sign_root(&signing_key);
+        let bundle = tree
+            .generate_bundle(&data[5], &signed_root)
+            .expect("Should generate bundle");
+
+        let mut siblings: Vec<(Hash, Direction)> = bundle.proof.clone().into();
+        siblings[1].0[0] ^= 1;
+        let mut tampered = bundle.clone();
+        tampered.proof = siblings.into();
+        assert!(!tampered.verify(&verifying_key, &data[5]));
+
+        let mut tampered = bundle.clone();
+        tampered.index = 4;
+        assert!(!tampered.verify(&verifying_key, &data[5]));
+        let mut tampered = bundle.clone();
+        tampered.leaf_hash = tree.leaf(4).expect("Should have leaf");
+        assert!(!tampered.verify(&verifying_key, &data[4]));
+
+        let bytes = bundle.to_bytes();
+        assert!(matches!(
+            ProofBundle::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MerkleError::TruncatedInput)
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::merkle::{Hash, Hashable, MerkleError, MerkleProof, MerkleTree};
+use std::sync::Arc;
+
+/// A read-only view of a tree as it was when `MerkleTree::snapshot` was called
+/// Later changes to the tree don't affect it, and cloning it only clones a reference,
+/// so one snapshot can serve proofs from several threads while the tree keeps growing
+#[derive(Debug, Clone)]
+pub struct MerkleSnapshot {
+    tree: Arc<MerkleTree>,
+}
+
+impl MerkleTree {
+    /// Captures the current state of the tree
+    /// The snapshot shares the levels and the leaves of the tree, so taking it copies nothing
+    /// The next mutation of the tree copies them once before changing them, later ones don't
+    pub fn snapshot(&self) -> MerkleSnapshot {
+        MerkleSnapshot {
+            tree: Arc::new(self.shared_view()),
+        }
+    }
+}
+
+impl MerkleSnapshot {
+    /// Returns the root hash the tree had
+    pub fn root(&self) -> Hash {
+        self.tree.root()
+    }
+
+    /// Returns the number of leaves the tree had
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns true if the tree had no leaves
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Generates a proof of membership against the root of the snapshot
+    pub fn generate_proof<T: Hashable + ?Sized>(
+        &self,
+        data: &T,
+    ) -> Result<MerkleProof, MerkleError> {
+        self.tree.generate_proof(data)
+    }
+
+    /// Validates a proof against the root of the snapshot
+    pub fn verify_proof<T: Hashable + ?Sized>(&self, data: &T, proof: &MerkleProof) -> bool {
+        self.tree.verify_proof(data, proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_unaffected_by_mutations() {
+        let data: Vec<Vec<u8>> = (0..8).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut merkle = MerkleTree::new(&data[..5]).expect("Should create merkle tree");
+        let snapshot = merkle.snapshot();
+        let root = merkle.root();
+
+        merkle.extend_from(&data[5..]).expect("Should extend tree");
+        merkle.update(0, b"block9").expect("Should update leaf");
+        assert_ne!(merkle.root(), root);
+
+        let shared = snapshot.clone();
+        assert_eq!(shared.root(), root);
+        assert_eq!(shared.len(), 5);
+        for element in &data[..5] {
+            let proof = shared
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(shared.verify_proof(element, &proof));
+        }
+        // The leaves added after the snapshot are not members of it
+        assert!(shared.generate_proof(&data[6]).is_err());
+        let proof = merkle
+            .generate_proof(&data[6])
+            .expect("Should generate proof");
+        assert!(!shared.verify_proof(&data[6], &proof));
+    }
+
+    #[test]
+    fn test_snapshot_shares_levels_until_mutation() {
+        let data: Vec<Vec<u8>> = (0..6).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut merkle =
+            MerkleTree::with_retained_data(&data[..5]).expect("Should create merkle tree");
+        let snapshot = merkle.snapshot();
+        assert!(std::ptr::eq(snapshot.tree.levels(), merkle.levels()));
+        assert!(std::ptr::eq(
+            snapshot.tree.levels(),
+            snapshot.clone().tree.levels()
+        ));
+
+        let root = merkle.root();
+        merkle.add(&data[5]).expect("Should add leaf");
+        assert!(!std::ptr::eq(snapshot.tree.levels(), merkle.levels()));
+        assert_eq!(snapshot.root(), root);
+        assert_eq!(merkle.data(5), Some(&data[5][..]));
+        let proof = snapshot
+            .generate_proof(&data[4])
+            .expect("Should generate proof");
+        assert!(snapshot.verify_proof(&data[4], &proof));
+    }
+}
@@ -0,0 +1,203 @@
+//! proptest strategies and `Arbitrary` implementations, with the `proptest` feature
+//!
+//! The strategies yield the data a tree was built from along with the tree, so properties
+//! can relate proofs to the original elements.
+//!
+//! # Example
+//! ```
+//! # use rusty_merkle_tree::strategies::arb_tree;
+//! use proptest::prelude::*;
+//!
+//! proptest!(|((tree, data) in arb_tree(1..50))| {
+//!     let proof = tree.generate_proof(&data[0]).expect("Should generate proof");
+//!     prop_assert!(tree.verify_proof(&data[0], &proof));
+//! });
+//! ```
+
+use crate::merkle::{Direction, HashAlgorithm, KaryProofLevel, MerkleProof, MerkleTree};
+use crate::salted::SaltedProof;
+use proptest::collection::{vec, SizeRange};
+use proptest::prelude::*;
+
+/// Elements of up to 64 bytes, as many as `size` allows
+pub fn arb_data(size: impl Into<SizeRange>) -> impl Strategy<Value = Vec<Vec<u8>>> {
+    vec(vec(any::<u8>(), 0..=64), size)
+}
+
+/// A tree built with `MerkleTree::new` over `arb_data(size)`, with that data
+/// `size` should not allow 0 elements, since `new` refuses them
+pub fn arb_tree(size: impl Into<SizeRange>) -> impl Strategy<Value = (MerkleTree, Vec<Vec<u8>>)> {
+    arb_data(size).prop_map(|data| {
+        let tree = MerkleTree::new(&data).expect("The data is not empty");
+        (tree, data)
+    })
+}
+
+/// A tree of `arb_tree(size)`, one of its elements and the proof of that element
+pub fn arb_membership(
+    size: impl Into<SizeRange>,
+) -> impl Strategy<Value = (MerkleTree, Vec<u8>, MerkleProof)> {
+    arb_tree(size).prop_flat_map(|(tree, data)| {
+        (0..data.len()).prop_map(move |index| {
+            let proof = tree
+                .generate_proof(&data[index])
+                .expect("The element is in the tree");
+            (tree.clone(), data[index].clone(), proof)
+        })
+    })
+}
+
+impl Arbitrary for MerkleTree {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// A tree of `arb_tree(1..100)`, without its data
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        arb_tree(1..100).prop_map(|(tree, _)| tree).boxed()
+    }
+}
+
+impl Arbitrary for Direction {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        prop_oneof![Just(Direction::Left), Just(Direction::Right)].boxed()
+    }
+}
+
+impl Arbitrary for MerkleProof {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// Up to 99 random siblings, which almost never verify against any tree
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        vec(any::<([u8; 32], Direction)>(), 0..100)
+            .prop_map(MerkleProof::from)
+            .boxed()
+    }
+}
+
+impl Arbitrary for HashAlgorithm {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// One of the algorithms available with the enabled features
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        proptest::sample::select(HashAlgorithm::ALL).boxed()
+    }
+}
+
+impl Arbitrary for KaryProofLevel {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// A level with up to 15 siblings and a position among them
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        vec(any::<[u8; 32]>(), 0..16)
+            .prop_flat_map(|siblings| {
+                (0..=siblings.len()).prop_map(move |position| KaryProofLevel {
+                    position,
+                    siblings: siblings.clone(),
+                })
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for SaltedProof {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (any::<[u8; 32]>(), any::<MerkleProof>())
+            .prop_map(|(salt, proof)| SaltedProof { salt, proof })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::{expected_proof_len, ProofEncoding};
+
+    proptest! {
+        #[test]
+        fn test_every_proof_verifies((tree, data) in arb_tree(1..64)) {
+            for element in &data {
+                let proof = tree.generate_proof(element).expect("Should generate proof");
+                prop_assert!(tree.verify_proof(element, &proof));
+            }
+        }
+
+        #[test]
+        fn test_mutated_proof_fails(
+            (tree, element, proof) in arb_membership(2..64),
+            position in any::<prop::sample::Index>(),
+            bit in 0..8u8,
+        ) {
+            let mut mutated = Vec::from(proof);
+            let byte = position.index(mutated.len() * 32);
+            mutated[byte / 32].0[byte % 32] ^= 1 << bit;
+            prop_assert!(!tree.verify_proof(&element, &mutated.into()));
+        }
+
+        #[test]
+        fn test_flipped_direction_fails((tree, element, proof) in arb_membership(2..64)) {
+            // A sibling equal to the node it is hashed with doesn't depend on the direction
+            for (level, (sibling, direction)) in proof.iter().enumerate() {
+                let mut mutated = Vec::from(proof.clone());
+                mutated[level].1 = match direction {
+                    Direction::Left => Direction::Right,
+                    Direction::Right => Direction::Left,
+                };
+                let below = proof.iter().take(level).map(|(sibling, direction)| (*sibling, direction)).collect();
+                let current = tree.root_from_proof(tree.hash_leaf(&element), &below);
+                prop_assume!(current != *sibling);
+                prop_assert!(!tree.verify_proof(&element, &mutated.into()));
+            }
+        }
+
+        #[test]
+        fn test_add_matches_rebuild((tree, data) in arb_tree(1..100), extra in arb_data(1..20)) {
+            let mut grown = tree.clone();
+            for element in &extra {
+                grown.add(element).expect("Should add leaf");
+            }
+            let all: Vec<Vec<u8>> = data.into_iter().chain(extra).collect();
+            let rebuilt = MerkleTree::new(&all).expect("Should create merkle tree");
+            prop_assert_eq!(grown.root(), rebuilt.root());
+            grown.verify_self().expect("Should be consistent");
+        }
+
+        #[test]
+        fn test_size_estimates_match_proofs(
+            (tree, data) in arb_tree(1..=1000),
+            index in any::<prop::sample::Index>(),
+        ) {
+            let proof = tree
+                .generate_proof_by_index(index.index(data.len()))
+                .expect("Should generate proof");
+            prop_assert_eq!(proof.len(), expected_proof_len(data.len()));
+            prop_assert_eq!(proof.serialized_size(ProofEncoding::Plain), proof.to_bytes().len());
+            prop_assert_eq!(
+                proof.serialized_size(ProofEncoding::Compact),
+                proof.to_compact_bytes().len()
+            );
+        }
+
+        #[test]
+        fn test_arbitrary_proofs_fail_for_other_data(
+            tree in any::<MerkleTree>(),
+            proof in any::<MerkleProof>(),
+        ) {
+            // Generated elements are at most 64 bytes long, so this one is not in the tree
+            prop_assert!(!tree.verify_proof(&[0u8; 65][..], &proof));
+        }
+
+        #[test]
+        fn test_arbitrary_kary_levels(level in any::<KaryProofLevel>()) {
+            prop_assert!(level.position <= level.siblings.len());
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Verification of the leaves of a tree as they arrive, in any order
+//!
+//! A `StreamVerifier` knows only the root and the number of leaves, and checks each leaf
+//! against its proof as it is submitted, keeping track of the slots still missing.
+
+use crate::merkle::{
+    hash, hashes_equal, positioned_root, Direction, Hash, Hashable, MerkleProof, PositionedProof,
+    VerifyError,
+};
+use sha3::Sha3_256;
+
+/// Verifies the leaves of a tree with the default settings of `MerkleTree::new` one at a time,
+/// each with the proof of `generate_proof`
+///
+/// # Example
+///
+/// ```
+/// use rusty_merkle_tree::merkle::MerkleTree;
+/// use rusty_merkle_tree::stream::StreamVerifier;
+///
+/// let data = ["a", "b", "c"];
+/// let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+/// let mut verifier = StreamVerifier::new(tree.root(), data.len());
+/// let proof = tree.generate_proof(&"c").expect("Should generate proof");
+/// verifier.submit(2, "c", &proof).expect("Should accept leaf");
+/// assert_eq!(verifier.missing(), vec![0, 1]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamVerifier {
+    root: Hash,
+    /// The hash of each verified leaf, by index
+    leaves: Vec<Option<Hash>>,
+    verified: usize,
+}
+
+impl StreamVerifier {
+    /// Creates a verifier for the tree of `leaf_count` leaves with the given root
+    pub fn new(root: Hash, leaf_count: usize) -> Self {
+        StreamVerifier {
+            root,
+            leaves: vec![None; leaf_count],
+            verified: 0,
+        }
+    }
+
+    /// Verifies that `data` is the leaf at `index` and records it
+    /// Submitting a verified leaf again with the same data succeeds without checking the proof
+    ///
+    /// # Returns
+    /// `Ok(())` if the leaf is verified, or:
+    /// - `IndexOutOfBounds` if the tree has no leaf at `index`
+    /// - `ConflictingLeaf` if another data was already verified at `index`
+    /// - `WrongProofLength` if the proof doesn't have one sibling per level of the tree
+    /// - `WrongDirection` with the first sibling on the wrong side for `index`
+    /// - `RootMismatch` if the proof computes another root
+    pub fn submit<T: Hashable + ?Sized>(
+        &mut self,
+        index: usize,
+        data: &T,
+        proof: &MerkleProof,
+    ) -> Result<(), VerifyError> {
+        let leaf_count = self.leaves.len();
+        let slot = self
+            .leaves
+            .get_mut(index)
+            .ok_or(VerifyError::IndexOutOfBounds { index, leaf_count })?;
+        let leaf = hash(data.leaf_bytes());
+        if let Some(verified) = slot {
+            if hashes_equal(verified, &leaf) {
+                return Ok(());
+            }
+            return Err(VerifyError::ConflictingLeaf { index });
+        }
+
+        let expected = leaf_count.next_power_of_two().trailing_zeros() as usize;
+        if proof.len() != expected {
+            return Err(VerifyError::WrongProofLength {
+                expected,
+                found: proof.len(),
+            });
+        }
+        if let Some(level) = proof
+            .iter()
+            .enumerate()
+            .position(|(level, (_, direction))| {
+                // The sibling of a right child is on its left
+                let is_right = index >> level & 1 == 1;
+                is_right != (direction == Direction::Left)
+            })
+        {
+            return Err(VerifyError::WrongDirection { level });
+        }
+        let positioned = PositionedProof {
+            index,
+            leaf_count,
+            proof: proof.clone(),
+        };
+        match positioned_root(&Sha3_256::default(), leaf, &positioned) {
+            Some(computed) if hashes_equal(&computed, &self.root) => {}
+            _ => {
+                return Err(VerifyError::RootMismatch {
+                    computed: proof.compute_root_from_leaf(&leaf),
+                    expected: self.root,
+                    level: proof.len(),
+                })
+            }
+        }
+
+        *slot = Some(leaf);
+        self.verified += 1;
+        Ok(())
+    }
+
+    /// Returns the indices of the leaves not verified yet, in increasing order
+    pub fn missing(&self) -> Vec<usize> {
+        self.leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| leaf.is_none())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns true once every leaf of the tree is verified
+    pub fn is_complete(&self) -> bool {
+        self.verified == self.leaves.len()
+    }
+
+    /// Returns the number of verified leaves
+    pub fn verified_count(&self) -> usize {
+        self.verified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::MerkleTree;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    fn chunks(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("chunk{}", i)).collect()
+    }
+
+    #[test]
+    fn test_stream_verifier_out_of_order() {
+        let data = chunks(23);
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let mut order: Vec<usize> = (0..data.len()).collect();
+        order.shuffle(&mut StdRng::seed_from_u64(23));
+        let dropped: Vec<usize> = order[..5].to_vec();
+
+        let mut verifier = StreamVerifier::new(tree.root(), data.len());
+        for &index in &order[5..] {
+            let proof = tree
+                .generate_proof(&data[index])
+                .expect("Should generate proof");
+            verifier
+                .submit(index, &data[index], &proof)
+                .expect("Should accept leaf");
+        }
+        let mut gaps = dropped.clone();
+        gaps.sort_unstable();
+        assert_eq!(verifier.missing(), gaps);
+        assert!(!verifier.is_complete());
+        assert_eq!(verifier.verified_count(), data.len() - 5);
+
+        for &index in &dropped {
+            let proof = tree
+                .generate_proof(&data[index])
+                .expect("Should generate proof");
+            verifier
+                .submit(index, &data[index], &proof)
+                .expect("Should accept leaf");
+        }
+        assert!(verifier.missing().is_empty());
+        assert!(verifier.is_complete());
+    }
+
+    #[test]
+    fn test_stream_verifier_flags_conflicting_leaf() {
+        let data = chunks(6);
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let mut verifier = StreamVerifier::new(tree.root(), data.len());
+        let proof = tree
+            .generate_proof(&data[3])
+            .expect("Should generate proof");
+        verifier
+            .submit(3, &data[3], &proof)
+            .expect("Should accept leaf");
+        verifier
+            .submit(3, &data[3], &proof)
+            .expect("Should accept the same leaf again");
+        assert_eq!(
+            verifier.submit(3, "tampered", &proof),
+            Err(VerifyError::ConflictingLeaf { index: 3 })
+        );
+        assert_eq!(verifier.verified_count(), 1);
+    }
+
+    #[test]
+    fn test_stream_verifier_rejects_invalid_submissions() {
+        let data = chunks(5);
+        let tree = MerkleTree::new(&data).expect("Should create merkle tree");
+        let mut verifier = StreamVerifier::new(tree.root(), data.len());
+        let proof = tree
+            .generate_proof(&data[1])
+            .expect("Should generate proof");
+
+        assert_eq!(
+            verifier.submit(5, &data[1], &proof),
+            Err(VerifyError::IndexOutOfBounds {
+                index: 5,
+                leaf_count: 5
+            })
+        );
+        assert_eq!(
+            verifier.submit(0, &data[1], &proof),
+            Err(VerifyError::WrongDirection { level: 0 })
+        );
+        let short: MerkleProof = proof.iter().take(2).map(|(s, d)| (*s, d)).collect();
+        assert_eq!(
+            verifier.submit(1, &data[1], &short),
+            Err(VerifyError::WrongProofLength {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert!(matches!(
+            verifier.submit(1, "other", &proof),
+            Err(VerifyError::RootMismatch { .. })
+        ));
+        assert_eq!(verifier.missing(), vec![0, 1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,694 @@
+//! Known-answer test vectors for every hash function and tree mode
+//!
+//! Each vector is the tree over `INPUTS`, with its root and the proofs of the first and last
+//! leaves, from the leaf up to the root. Hashes are lowercase hex. The crate's own tests check
+//! every vector, so they always match what this version of the crate computes, and a verifier
+//! written in another language can be checked against the same values.
+//!
+//! Unless its mode says otherwise, a leaf is `H(data)`, a node is `H(left || right)` and the
+//! last node of an odd level is paired with itself.
+//!
+//! `RFC6962` holds the reference vectors published with RFC 6962 for `Mode::Rfc6962`, over
+//! its own leaves, with the roots, audit paths and consistency proofs of several tree sizes.
+
+use crate::merkle::{Direction, Hash};
+
+/// The data of the leaves of every vector, as UTF-8
+pub const INPUTS: &[&str] = &["a", "b", "c", "d", "e"];
+
+/// How the tree of a vector is hashed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// SHA3-256, the tree of `MerkleTree::new`
+    Sha3_256,
+    /// SHA-256, the tree of `MerkleTree::new_sha256`
+    #[cfg(feature = "sha2")]
+    Sha2_256,
+    /// Keccak-256 as used by Ethereum, the tree of `MerkleTree::new_keccak256`
+    Keccak256,
+    /// BLAKE3, the tree of `MerkleTree::<Blake3>::with_digest`
+    #[cfg(feature = "blake3")]
+    Blake3,
+    /// SHA3-256 with leaves hashed as `H(0x00 || data)` and nodes as `H(0x01 || left || right)`,
+    /// the tree of `MerkleTree::with_domain_separation`
+    DomainSeparation,
+    /// SHA-256 following RFC 6962: prefixed like `DomainSeparation` and with the last node of
+    /// odd levels promoted, the tree of `MerkleTree::new_rfc6962`
+    #[cfg(feature = "sha2")]
+    Rfc6962,
+    /// Keccak-256 with each pair sorted before hashing and the last node of odd levels promoted,
+    /// like OpenZeppelin, the tree of `MerkleTree::<Keccak256>::with_sorted_pairs`
+    /// Verifiers ignore the directions, which the proofs still carry
+    SortedPairs,
+    /// SHA3-256 with leaves hashed as `H(H(data))`, the tree of `MerkleTree::with_leaf_hashing`
+    DoubleLeafHashing,
+    /// SHA3-256 with the last node of odd levels promoted unchanged,
+    /// the tree of `MerkleTree::with_odd_node_policy`
+    PromoteToNextLevel,
+}
+
+/// The proof of the leaf at `index`, from the leaf up to the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofVector {
+    pub index: usize,
+    pub siblings: &'static [(&'static str, Direction)],
+}
+
+/// The root and some proofs of the tree over `INPUTS` in one mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestVector {
+    pub mode: Mode,
+    pub root: &'static str,
+    pub proofs: &'static [ProofVector],
+}
+
+/// Every vector available with the enabled features
+pub const VECTORS: &[TestVector] = &[
+    TestVector {
+        mode: Mode::Sha3_256,
+        root: "ea7309981ed6c66a52557fb5f41beeef217cd85230f4e2f2fa682b15485cd513",
+        proofs: &[
+            ProofVector {
+                index: 0,
+                siblings: &[
+                    (
+                        "b039179a8a4ce2c252aa6f2f25798251c19b75fc1508d9d511a191e0487d64a7",
+                        Direction::Right,
+                    ),
+                    (
+                        "19a84217e939015aaa26d5da6b9ca673eae0df32877593df597cd3e5157982b1",
+                        Direction::Right,
+                    ),
+                    (
+                        "8dc2ee0313eaaa287618e4a73574c7f68820e8f99fea5b72def8827c0c8b0115",
+                        Direction::Right,
+                    ),
+                ],
+            },
+            ProofVector {
+                index: 4,
+                siblings: &[
+                    (
+                        "42538602949f370aa331d2c07a1ee7ff26caac9cc676288f94b82eb2188b8465",
+                        Direction::Right,
+                    ),
+                    (
+                        "bea15d99f21ed91991661ffa10fc5c76cefc8aa0f49dbac2531fd3b929abffe1",
+                        Direction::Right,
+                    ),
+                    (
+                        "5267fec4a5327f9d287233f95213afa39d3aad2fee1fa1384b032b79fb3441e8",
+                        Direction::Left,
+                    ),
+                ],
+            },
+        ],
+    },
+    #[cfg(feature = "sha2")]
+    TestVector {
+        mode: Mode::Sha2_256,
+        root: "dd14d0ba516bb654a3052b76f051db026f4e322d0be081468fab99440f9e7305",
+        proofs: &[
+            ProofVector {
+                index: 0,
+                siblings: &[
+                    (
+                        "3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d",
+                        Direction::Right,
+                    ),
+                    (
+                        "bffe0b34dba16bc6fac17c08bac55d676cded5a4ade41fe2c9924a5dde8f3e5b",
+                        Direction::Right,
+                    ),
+                    (
+                        "de913ac41aae6129f7358dadea47a987a81509a6fb267b01f0508280f8dd5b46",
+                        Direction::Right,
+                    ),
+                ],
+            },
+            ProofVector {
+                index: 4,
+                siblings: &[
+                    (
+                        "3f79bb7b435b05321651daefd374cdc681dc06faa65e374e38337b88ca046dea",
+                        Direction::Right,
+                    ),
+                    (
+                        "75de222d8adebd767f99a5fe35a5f3f58dbfa3d51ec28b54e9da4225ec8f170d",
+                        Direction::Right,
+                    ),
+                    (
+                        "14ede5e8e97ad9372327728f5099b95604a39593cac3bd38a343ad76205213e7",
+                        Direction::Left,
+                    ),
+                ],
+            },
+        ],
+    },
+    TestVector {
+        mode: Mode::Keccak256,
+        root: "e467c863b0e6b42bf57865b1d965372858094a175d68652a3d158954f688e22f",
+        proofs: &[
+            ProofVector {
+                index: 0,
+                siblings: &[
+                    (
+                        "b5553de315e0edf504d9150af82dafa5c4667fa618ed0a6f19c69b41166c5510",
+                        Direction::Right,
+                    ),
+                    (
+                        "d253a52d4cb00de2895e85f2529e2976e6aaaa5c18106b68ab66813e14415669",
+                        Direction::Right,
+                    ),
+                    (
+                        "74d4f59c533502aa36eec4e26cd63757388dfd415f1709c4046cebfc00a604ab",
+                        Direction::Right,
+                    ),
+                ],
+            },
+            ProofVector {
+                index: 4,
+                siblings: &[
+                    (
+                        "a8982c89d80987fb9a510e25981ee9170206be21af3c8e0eb312ef1d3382e761",
+                        Direction::Right,
+                    ),
+                    (
+                        "521ebe609e7806f5689e2e79733ff5c801a8be884c10392a96e775578704e529",
+                        Direction::Right,
+                    ),
+                    (
+                        "68203f90e9d07dc5859259d7536e87a6ba9d345f2552b5b9de2999ddce9ce1bf",
+                        Direction::Left,
+                    ),
+                ],
+            },
+        ],
+    },
+    #[cfg(feature = "blake3")]
+    TestVector {
+        mode: Mode::Blake3,
+        root: "a76273f7bc6916d0e93594954f1ad80affe4c33cd2e600449844e63f740aa509",
+        proofs: &[
+            ProofVector {
+                index: 0,
+                siblings: &[
+                    (
+                        "10e5cf3d3c8a4f9f3468c8cc58eea84892a22fdadbc1acb22410190044c1d553",
+                        Direction::Right,
+                    ),
+                    (
+                        "a77a720d29e9dfa24461260e8ceb053ebf346dca2d81aa2b4182cb491fd43219",
+                        Direction::Right,
+                    ),
+                    (
+                        "f0eb5009cac0be3ca47a6fb4f23ca63a97a13519722a23827a45e40050b13bf4",
+                        Direction::Right,
+                    ),
+                ],
+            },
+            ProofVector {
+                index: 4,
+                siblings: &[
+                    (
+                        "27bb492e108bf5e9c724176d7ae75d4cedc422fe4065020bd6140c3fcad3a9e7",
+                        Direction::Right,
+                    ),
+                    (
+                        "65ded67f2641c689fd70352d7f016e0564c2fb292e9b05d45ee262dab1101d21",
+                        Direction::Right,
+                    ),
+                    (
+                        "15b05807bd481249f1ad113b96863e0bd70b8ef2d807400d8997c7b8fc0f82b1",
+                        Direction::Left,
+                    ),
+                ],
+            },
+        ],
+    },
+    TestVector {
+        mode: Mode::DomainSeparation,
+        root: "032cbf010a823f9ab6f71eb5fa675951f30ab7e4dd0cd470c25f999bf112b70f",
+        proofs: &[
+            ProofVector {
+                index: 0,
+                siblings: &[
+                    (
+                        "a84d04ed0773f2f7e8d1b02c58a796ae30695a211a9cf492793e7d1627dab180",
+                        Direction::Right,
+                    ),
+                    (
+                        "235c8cee20b9163196c0755766b6c6f6b4b3aac6b3ebd675fc52517a63ede8ad",
+                        Direction::Right,
+                    ),
+                    (
+                        "f10911ae8aa7d0aeb33eb57f52eec860861b5ec071a2e0331920fbda6d928fa8",
+                        Direction::Right,
+                    ),
+                ],
+            },
+            ProofVector {
+                index: 4,
+                siblings: &[
+                    (
+                        "0bbd548f64fc49ac99323c1c9f31a9d304edefffd2fd5604769817f04deb74a1",
+                        Direction::Right,
+                    ),
+                    (
+                        "baad5fd7f4cf7db07c86c6b7375b95aea22d6bd2328e03c44ef09da2f7704b67",
+                        Direction::Right,
+                    ),
+                    (
+                        "8129e2860f2dff051735954d6be24aa6cb62a060f36497d07b811b8da6d99abb",
+                        Direction::Left,
+                    ),
+                ],
+            },
+        ],
+    },
+    #[cfg(feature = "sha2")]
+    TestVector {
+        mode: Mode::Rfc6962,
+        root: "fe14a5426fbd70c0fa73f52342afed0da0bd23c4838662ccf6b88a3070ead97b",
+        proofs: &[
+            ProofVector {
+                index: 0,
+                siblings: &[
+                    (
+                        "57eb35615d47f34ec714cacdf5fd74608a5e8e102724e80b24b287c0c27b6a31",
+                        Direction::Right,
+                    ),
+                    (
+                        "dbbd68c325614a73dacb4e7a87a2b7b4ae9724b489e5629ee83151fe8f0eafd7",
+                        Direction::Right,
+                    ),
+                    (
+                        "2824a7ccda2caa720c85c9fba1e8b5b735eecfdb03878e4f8dfe6c3625030bc4",
+                        Direction::Right,
+                    ),
+                ],
+            },
+            ProofVector {
+                index: 4,
+                siblings: &[(
+                    "33376a3bd63e9993708a84ddfe6c28ae58b83505dd1fed711bd924ec5a6239f0",
+                    Direction::Left,
+                )],
+            },
+        ],
+    },
+    TestVector {
+        mode: Mode::SortedPairs,
+        root: "1dd0d2a6ae466d665cb26e1a31f07c57ae5df7d2bc559cd5826d417be9141a5d",
+        proofs: &[
+            ProofVector {
+                index: 0,
+                siblings: &[
+                    (
+                        "b5553de315e0edf504d9150af82dafa5c4667fa618ed0a6f19c69b41166c5510",
+                        Direction::Right,
+                    ),
+                    (
+                        "d253a52d4cb00de2895e85f2529e2976e6aaaa5c18106b68ab66813e14415669",
+                        Direction::Right,
+                    ),
+                    (
+                        "a8982c89d80987fb9a510e25981ee9170206be21af3c8e0eb312ef1d3382e761",
+                        Direction::Right,
+                    ),
+                ],
+            },
+            ProofVector {
+                index: 4,
+                siblings: &[(
+                    "68203f90e9d07dc5859259d7536e87a6ba9d345f2552b5b9de2999ddce9ce1bf",
+                    Direction::Left,
+                )],
+            },
+        ],
+    },
+    TestVector {
+        mode: Mode::DoubleLeafHashing,
+        root: "5bccc769af0b909e616dab563b3d51db409c9367191ee43d5fb31de901939e44",
+        proofs: &[
+            ProofVector {
+                index: 0,
+                siblings: &[
+                    (
+                        "8985c4299259b917d2ff8527c2f6dacc3b256fa98f592800d055bdeb574f2e46",
+                        Direction::Right,
+                    ),
+                    (
+                        "74e829bffbf36b37a547dfe18e6a218b9fa4876d826abd7cf60be42ecfede654",
+                        Direction::Right,
+                    ),
+                    (
+                        "4fcbac12d305ed018486e65337b1aabdf4d400c26ca430cb8561231b6c5bd1e0",
+                        Direction::Right,
+                    ),
+                ],
+            },
+            ProofVector {
+                index: 4,
+                siblings: &[
+                    (
+                        "fd5b97f5d630c91a666f7819b6dc13cc73cb31f100e2964ded503fd4b3542b51",
+                        Direction::Right,
+                    ),
+                    (
+                        "31c13e172280c8a8039a28ad7139707461afb6ebadb561d51de3ed270a6945a6",
+                        Direction::Right,
+                    ),
+                    (
+                        "57623df326dc72fa601b428fa943740eead630ed83dab3556fe2e7640ad9dd8a",
+                        Direction::Left,
+                    ),
+                ],
+            },
+        ],
+    },
+    TestVector {
+        mode: Mode::PromoteToNextLevel,
+        root: "b8efa384f64647583db7ea069c46ec746d4d8c0c1815040431db4134bc0b41fd",
+        proofs: &[
+            ProofVector {
+                index: 0,
+                siblings: &[
+                    (
+                        "b039179a8a4ce2c252aa6f2f25798251c19b75fc1508d9d511a191e0487d64a7",
+                        Direction::Right,
+                    ),
+                    (
+                        "19a84217e939015aaa26d5da6b9ca673eae0df32877593df597cd3e5157982b1",
+                        Direction::Right,
+                    ),
+                    (
+                        "42538602949f370aa331d2c07a1ee7ff26caac9cc676288f94b82eb2188b8465",
+                        Direction::Right,
+                    ),
+                ],
+            },
+            ProofVector {
+                index: 4,
+                siblings: &[(
+                    "5267fec4a5327f9d287233f95213afa39d3aad2fee1fa1384b032b79fb3441e8",
+                    Direction::Left,
+                )],
+            },
+        ],
+    },
+];
+
+/// The audit path of the leaf at `leaf_index` in the tree of `tree_size` leaves, from the
+/// leaf up to the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InclusionVector {
+    pub leaf_index: usize,
+    pub tree_size: usize,
+    pub path: &'static [&'static str],
+}
+
+/// The proof that the tree of `old_size` leaves is a prefix of the tree of `new_size` leaves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsistencyVector {
+    pub old_size: usize,
+    pub new_size: usize,
+    pub proof: &'static [&'static str],
+}
+
+/// Trees over prefixes of `leaves`: `roots[n]` is the root of the tree over the first `n`
+/// leaves, and the proofs are between those trees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceSet {
+    pub mode: Mode,
+    pub leaves: &'static [&'static [u8]],
+    pub roots: &'static [&'static str],
+    pub inclusion: &'static [InclusionVector],
+    pub consistency: &'static [ConsistencyVector],
+}
+
+/// The test vectors of RFC 6962, as published with the Certificate Transparency reference
+/// implementation
+#[cfg(feature = "sha2")]
+pub const RFC6962: ReferenceSet = ReferenceSet {
+    mode: Mode::Rfc6962,
+    leaves: &[
+        b"",
+        b"\x00",
+        b"\x10",
+        b"\x20\x21",
+        b"\x30\x31",
+        b"\x40\x41\x42\x43",
+        b"\x50\x51\x52\x53\x54\x55\x56\x57",
+        b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+    ],
+    roots: &[
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ],
+    inclusion: &[
+        InclusionVector {
+            leaf_index: 0,
+            tree_size: 8,
+            path: &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ],
+        },
+        InclusionVector {
+            leaf_index: 5,
+            tree_size: 8,
+            path: &[
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ],
+        },
+        InclusionVector {
+            leaf_index: 2,
+            tree_size: 3,
+            path: &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"],
+        },
+        InclusionVector {
+            leaf_index: 1,
+            tree_size: 5,
+            path: &[
+                "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ],
+        },
+        InclusionVector {
+            leaf_index: 6,
+            tree_size: 7,
+            path: &[
+                "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ],
+        },
+    ],
+    consistency: &[
+        ConsistencyVector {
+            old_size: 1,
+            new_size: 1,
+            proof: &[],
+        },
+        ConsistencyVector {
+            old_size: 1,
+            new_size: 8,
+            proof: &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ],
+        },
+        ConsistencyVector {
+            old_size: 6,
+            new_size: 8,
+            proof: &[
+                "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ],
+        },
+        ConsistencyVector {
+            old_size: 2,
+            new_size: 5,
+            proof: &[
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ],
+        },
+        ConsistencyVector {
+            old_size: 3,
+            new_size: 7,
+            proof: &[
+                "0298d122906dcfc10892cb53a73992fc5b9f493ea4c9badb27b791b4127a7fe7",
+                "07506a85fd9dd2f120eb694f86011e5bb4662e5c415a62917033d4a9624487e7",
+                "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+                "837dbb152e9b079010717e84e865da4ebc0fa198a806d59d31bf15accef22d0e",
+            ],
+        },
+        ConsistencyVector {
+            old_size: 4,
+            new_size: 7,
+            proof: &["837dbb152e9b079010717e84e865da4ebc0fa198a806d59d31bf15accef22d0e"],
+        },
+    ],
+};
+
+/// Parses bytes written as hex digits, two per byte
+///
+/// # Panics
+/// If `hex` has an odd length or a character that is not a hex digit
+pub fn bytes_from_hex(hex: &str) -> Vec<u8> {
+    assert!(
+        hex.len().is_multiple_of(2),
+        "Bytes should be two hex digits each"
+    );
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16).expect("Should only have hex digits")
+        })
+        .collect()
+}
+
+/// Parses a hash written as 64 hex digits, like the ones of the vectors
+///
+/// # Panics
+/// If `hex` is not 64 hex digits
+pub fn from_hex(hex: &str) -> Hash {
+    assert_eq!(hex.len(), 64, "A hash should be 64 hex digits");
+    bytes_from_hex(hex)
+        .try_into()
+        .expect("A hash should be 64 hex digits")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "blake3")]
+    use crate::merkle::Blake3;
+    use crate::merkle::{LeafHashing, MerkleProof, MerkleTree, OddNodePolicy, TreeHasher};
+    use sha3::Keccak256;
+
+    fn expected_proof(vector: &ProofVector) -> MerkleProof {
+        vector
+            .siblings
+            .iter()
+            .map(|(sibling, direction)| (from_hex(sibling), *direction))
+            .collect()
+    }
+
+    fn check<D: TreeHasher>(tree: MerkleTree<D>, vector: &TestVector) {
+        assert_eq!(tree.root(), from_hex(vector.root), "{:?}", vector.mode);
+        for proof in vector.proofs {
+            let expected = expected_proof(proof);
+            assert_eq!(
+                tree.generate_proof_by_index(proof.index)
+                    .expect("Should generate proof"),
+                expected,
+                "{:?}",
+                vector.mode
+            );
+            assert!(tree.verify_proof(INPUTS[proof.index], &expected));
+        }
+    }
+
+    #[test]
+    fn test_vectors_match_implementation() {
+        for vector in VECTORS {
+            match vector.mode {
+                Mode::Sha3_256 => check(
+                    MerkleTree::new(INPUTS).expect("Should create merkle tree"),
+                    vector,
+                ),
+                #[cfg(feature = "sha2")]
+                Mode::Sha2_256 => check(
+                    MerkleTree::new_sha256(INPUTS).expect("Should create merkle tree"),
+                    vector,
+                ),
+                Mode::Keccak256 => check(
+                    MerkleTree::new_keccak256(INPUTS).expect("Should create merkle tree"),
+                    vector,
+                ),
+                #[cfg(feature = "blake3")]
+                Mode::Blake3 => check(
+                    MerkleTree::<Blake3>::with_digest(INPUTS).expect("Should create merkle tree"),
+                    vector,
+                ),
+                Mode::DomainSeparation => check(
+                    MerkleTree::with_domain_separation(INPUTS).expect("Should create merkle tree"),
+                    vector,
+                ),
+                #[cfg(feature = "sha2")]
+                Mode::Rfc6962 => check(
+                    MerkleTree::new_rfc6962(INPUTS).expect("Should create merkle tree"),
+                    vector,
+                ),
+                Mode::SortedPairs => {
+                    let tree = MerkleTree::<Keccak256>::with_sorted_pairs(INPUTS)
+                        .expect("Should create merkle tree");
+                    for proof in vector.proofs {
+                        let siblings = expected_proof(proof)
+                            .into_iter()
+                            .map(|(sibling, _)| sibling)
+                            .collect();
+                        assert!(tree.verify_sorted_proof(INPUTS[proof.index], &siblings));
+                    }
+                    check(tree, vector)
+                }
+                Mode::DoubleLeafHashing => check(
+                    MerkleTree::with_leaf_hashing(INPUTS, LeafHashing::Double)
+                        .expect("Should create merkle tree"),
+                    vector,
+                ),
+                Mode::PromoteToNextLevel => check(
+                    MerkleTree::with_odd_node_policy(INPUTS, OddNodePolicy::PromoteToNextLevel)
+                        .expect("Should create merkle tree"),
+                    vector,
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_mode_has_a_vector() {
+        let modes: Vec<Mode> = VECTORS.iter().map(|vector| vector.mode).collect();
+        assert!(modes
+            .iter()
+            .enumerate()
+            .all(|(index, mode)| !modes[..index].contains(mode)));
+        assert_eq!(
+            modes.len(),
+            6 + 2 * usize::from(cfg!(feature = "sha2")) + usize::from(cfg!(feature = "blake3"))
+        );
+        for vector in VECTORS {
+            assert!(vector.proofs.iter().all(|proof| proof.index < INPUTS.len()));
+        }
+    }
+
+    #[test]
+    fn test_bytes_from_hex() {
+        assert_eq!(bytes_from_hex(""), Vec::<u8>::new());
+        assert_eq!(bytes_from_hex("00ff10"), [0x00, 0xff, 0x10]);
+    }
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(from_hex(&"00".repeat(32)), [0u8; 32]);
+        let mut expected = [0u8; 32];
+        expected[0] = 0xab;
+        expected[31] = 0x0f;
+        assert_eq!(from_hex(&format!("ab{}0f", "00".repeat(30))), expected);
+    }
+}
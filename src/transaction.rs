@@ -0,0 +1,212 @@
+use crate::merkle::{Hash, Hashable, MerkleError, MerkleTree};
+
+/// Changes staged on a tree by `MerkleTree::transaction`
+/// Every method checks its change the same way the tree's own method would, but only the leaves
+/// are modified, the nodes are rehashed once when the transaction succeeds
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    tree: &'a mut MerkleTree,
+    /// Index of the first leaf changed by the transaction
+    dirty_from: Option<usize>,
+}
+
+impl MerkleTree {
+    /// Applies the changes staged by `changes` all at once
+    /// If `changes` returns an error the tree is left exactly as it was, otherwise every staged
+    /// change is applied and the tree is rehashed once
+    ///
+    /// # Example
+    /// ```
+    /// # use rusty_merkle_tree::merkle::MerkleTree;
+    /// let mut tree = MerkleTree::new(&[b"block1", b"block2"]).expect("Should create merkle tree");
+    /// let root = tree.root();
+    /// let result = tree.transaction(|tx| {
+    ///     tx.add(b"block3".to_vec())?;
+    ///     tx.update(5, b"block4")?;
+    ///     Ok(())
+    /// });
+    /// assert!(result.is_err());
+    /// assert_eq!(tree.root(), root);
+    /// ```
+    ///
+    /// # Returns
+    /// The value returned by `changes`, or its error
+    pub fn transaction<F, R>(&mut self, changes: F) -> Result<R, MerkleError>
+    where
+        F: FnOnce(&mut Transaction<'_>) -> Result<R, MerkleError>,
+    {
+        // Removed leaves lose their data, so it is restored from a copy if the transaction fails
+        let retained = self.retained_data();
+        let mut tx = Transaction {
+            tree: self,
+            dirty_from: None,
+        };
+        match changes(&mut tx) {
+            Ok(value) => {
+                if let Some(start) = tx.dirty_from {
+                    tx.tree.sync_from(start);
+                }
+                Ok(value)
+            }
+            Err(error) => {
+                if tx.dirty_from.is_some() {
+                    tx.tree.discard_staged(retained);
+                }
+                Err(error)
+            }
+        }
+    }
+}
+
+impl Transaction<'_> {
+    /// Stages a new element, see `MerkleTree::add`
+    pub fn add<T: Hashable>(&mut self, data: T) -> Result<usize, MerkleError> {
+        let (index, inserted) = self.tree.stage_add(&data.leaf_bytes())?;
+        if inserted {
+            self.mark(index);
+        }
+        Ok(index)
+    }
+
+    /// Stages a batch of elements, see `MerkleTree::extend_from`
+    ///
+    /// # Returns
+    /// The index of the first inserted leaf
+    pub fn extend_from<T: Hashable>(&mut self, items: &[T]) -> Result<usize, MerkleError> {
+        let start = self.tree.stage_extend_from(items)?;
+        self.mark(start);
+        Ok(start)
+    }
+
+    /// Stages the replacement of the leaf at `index`, see `MerkleTree::update`
+    pub fn update<T: Hashable + ?Sized>(
+        &mut self,
+        index: usize,
+        data: &T,
+    ) -> Result<(), MerkleError> {
+        let start = self.tree.stage_update(index, &data.leaf_bytes())?;
+        self.mark(start);
+        Ok(())
+    }
+
+    /// Stages the removal of the leaf at `index`, see `MerkleTree::remove`
+    pub fn remove(&mut self, index: usize) -> Result<Hash, MerkleError> {
+        let removed = self.tree.stage_remove(index)?;
+        self.mark(index);
+        Ok(removed)
+    }
+
+    /// Returns the number of leaves the tree will have if the transaction succeeds
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns true if the tree will have no leaves if the transaction succeeds
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns the root the tree will have if the transaction succeeds
+    /// The staged leaves are hashed into a separate tree, so this costs a full build
+    pub fn root_preview(&self) -> Hash {
+        match self.dirty_from {
+            Some(_) => self.tree.rebuilt().root(),
+            None => self.tree.root(),
+        }
+    }
+
+    fn mark(&mut self, index: usize) {
+        self.dirty_from = Some(self.dirty_from.map_or(index, |start| start.min(index)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::DuplicatePolicy;
+
+    #[test]
+    fn test_successful_transaction() {
+        let data: Vec<Vec<u8>> = (0..6).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut merkle = MerkleTree::new(&data[..4]).expect("Should create merkle tree");
+
+        let preview = merkle
+            .transaction(|tx| {
+                assert_eq!(tx.add(data[4].clone())?, 4);
+                tx.extend_from(&data[5..])?;
+                tx.update(1, b"block9")?;
+                tx.remove(0)?;
+                assert_eq!(tx.len(), 5);
+                Ok(tx.root_preview())
+            })
+            .expect("Should apply transaction");
+
+        let expected_data = [
+            b"block9".to_vec(),
+            data[2].clone(),
+            data[3].clone(),
+            data[4].clone(),
+            data[5].clone(),
+        ];
+        let expected = MerkleTree::new(&expected_data).expect("Should create merkle tree");
+        assert_eq!(merkle.root(), expected.root());
+        assert_eq!(preview, expected.root());
+        let proof = merkle
+            .generate_proof(b"block9")
+            .expect("Should generate proof");
+        assert!(merkle.verify_proof(b"block9", &proof));
+    }
+
+    #[test]
+    fn test_failed_transaction() {
+        let data: Vec<Vec<u8>> = (0..4).map(|i| format!("block{}", i).into_bytes()).collect();
+        let mut merkle = MerkleTree::with_duplicate_policy(&data[..3], DuplicatePolicy::Reject)
+            .expect("Should create merkle tree");
+        let root = merkle.root();
+
+        let result = merkle.transaction(|tx| {
+            tx.add(data[3].clone())?;
+            tx.update(0, b"block9")?;
+            tx.remove(1)?;
+            // Fails halfway through, after the changes above were staged
+            tx.add(data[2].clone())?;
+            tx.add(b"block10".to_vec())
+        });
+        assert!(matches!(result, Err(MerkleError::DuplicateLeaf(1))));
+        assert_eq!(merkle.root(), root);
+        assert_eq!(merkle.len(), 3);
+        for element in &data[..3] {
+            let proof = merkle
+                .generate_proof(element)
+                .expect("Should generate proof");
+            assert!(merkle.verify_proof(element, &proof));
+        }
+
+        // The duplicate check sees the leaves as they were before the failed transaction
+        assert!(matches!(
+            merkle.add(data[0].clone()),
+            Err(MerkleError::DuplicateLeaf(0))
+        ));
+        assert_eq!(merkle.add(b"block9".to_vec()).expect("Should add leaf"), 3);
+    }
+
+    #[test]
+    fn test_failed_transaction_keeps_data() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec()];
+        let mut merkle = MerkleTree::with_retained_data(&data).expect("Should create merkle tree");
+
+        let result = merkle.transaction(|tx| {
+            tx.remove(0)?;
+            tx.update(0, b"block3")?;
+            tx.remove(5)
+        });
+        assert!(matches!(result, Err(MerkleError::IndexOutOfBounds)));
+        assert_eq!(merkle.data(0), Some(&b"block1"[..]));
+        assert_eq!(merkle.data(1), Some(&b"block2"[..]));
+
+        merkle
+            .transaction(|tx| tx.update(0, b"block3"))
+            .expect("Should apply transaction");
+        assert_eq!(merkle.data(0), Some(&b"block3"[..]));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::merkle::{Hash, Hashable, MerkleError, MerkleProof, MerkleTree};
+
+/// An append-only Merkle Tree that remembers the root after every append
+/// Version 0 is the tree it was created with, and each successful `add()` or `extend_from()`
+/// creates the next one
+/// Only the number of leaves and the root of each version are stored, an old version is
+/// restored by truncating the leaves, which is why the tree can't be updated in place
+#[derive(Debug)]
+pub struct VersionedMerkleTree {
+    tree: MerkleTree,
+    /// Number of leaves and root of each version, indexed by version
+    versions: Vec<(usize, Hash)>,
+}
+
+impl VersionedMerkleTree {
+    /// Creates a versioned tree over `data`, which becomes version 0
+    ///
+    /// # Returns
+    /// A VersionedMerkleTree instance if the data is not empty, otherwise an error
+    pub fn new<T: Hashable>(data: &[T]) -> Result<Self, MerkleError> {
+        Self::from_tree(MerkleTree::new(data)?)
+    }
+
+    /// Creates a versioned tree without any leaves
+    pub fn empty() -> Self {
+        Self::from_tree(MerkleTree::empty()).expect("The empty tree is not canonical")
+    }
+
+    /// Starts tracking the versions of an existing tree, which becomes version 0
+    ///
+    /// # Returns
+    /// The versioned tree, or `IncompatibleTrees` if `tree` is canonical, since inserting
+    /// into sorted leaves can't be undone by truncating them
+    pub fn from_tree(tree: MerkleTree) -> Result<Self, MerkleError> {
+        if tree.is_canonical() {
+            return Err(MerkleError::IncompatibleTrees);
+        }
+        let versions = vec![(tree.len(), tree.root())];
+        Ok(VersionedMerkleTree { tree, versions })
+    }
+
+    /// Returns the current version
+    pub fn version(&self) -> u64 {
+        (self.versions.len() - 1) as u64
+    }
+
+    /// Returns the root the tree had at `version`, or None if there is no such version
+    pub fn root_at_version(&self, version: u64) -> Option<Hash> {
+        let index = usize::try_from(version).ok()?;
+        self.versions.get(index).map(|&(_, root)| root)
+    }
+
+    /// Generates the proof of the leaf at `index` against `root_at_version(version)`, see
+    /// `MerkleTree::generate_proof_at_size`
+    ///
+    /// # Returns
+    /// The proof, `UnknownVersion` if there is no such version, `IndexOutOfBounds` if the tree
+    /// had no leaf at `index` at that version, or `UnsupportedArity` for trees with an arity
+    /// other than 2
+    pub fn generate_proof_at_version(
+        &self,
+        version: u64,
+        index: usize,
+    ) -> Result<MerkleProof, MerkleError> {
+        let version = usize::try_from(version).map_err(|_| MerkleError::UnknownVersion)?;
+        let &(leaf_count, _) = self
+            .versions
+            .get(version)
+            .ok_or(MerkleError::UnknownVersion)?;
+        self.tree.generate_proof_at_size(leaf_count, index)
+    }
+
+    /// Returns the current root hash
+    pub fn root(&self) -> Hash {
+        self.tree.root()
+    }
+
+    /// Returns the tree at its current version
+    pub fn tree(&self) -> &MerkleTree {
+        &self.tree
+    }
+
+    /// Appends an element to the tree, creating a new version
+    ///
+    /// # Returns
+    /// The index of the new leaf, or the error of `MerkleTree::add()`, in which
+    /// case no version is created
+    pub fn add<T: Hashable>(&mut self, data: T) -> Result<usize, MerkleError> {
+        let index = self.tree.add(data)?;
+        self.record();
+        Ok(index)
+    }
+
+    /// Appends a batch of elements to the tree, creating a single new version
+    ///
+    /// # Returns
+    /// The same as `MerkleTree::extend_from()`, no version is created if it fails
+    pub fn extend_from<T: Hashable>(&mut self, items: &[T]) -> Result<(Hash, usize), MerkleError> {
+        let result = self.tree.extend_from(items)?;
+        self.record();
+        Ok(result)
+    }
+
+    /// Restores the leaves the tree had at `version` and forgets every later version
+    ///
+    /// # Returns
+    /// `UnknownVersion` if there is no such version
+    pub fn rollback_to(&mut self, version: u64) -> Result<(), MerkleError> {
+        let index = usize::try_from(version).map_err(|_| MerkleError::UnknownVersion)?;
+        let &(leaf_count, root) = self
+            .versions
+            .get(index)
+            .ok_or(MerkleError::UnknownVersion)?;
+        self.tree.truncate(leaf_count);
+        self.versions.truncate(index + 1);
+        debug_assert_eq!(self.tree.root(), root);
+        Ok(())
+    }
+
+    fn record(&mut self) {
+        self.versions.push((self.tree.len(), self.tree.root()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::{verify, OddNodePolicy};
+
+    #[test]
+    fn test_rollback() {
+        let data: Vec<Vec<u8>> = (0..10)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let mut versioned = VersionedMerkleTree::empty();
+        for element in &data {
+            versioned.add(element.clone()).expect("Should add leaf");
+        }
+        assert_eq!(versioned.version(), 10);
+
+        let expected = MerkleTree::new(&data[..4]).expect("Should create merkle tree");
+        assert_eq!(versioned.root_at_version(4), Some(expected.root()));
+        assert_eq!(versioned.root_at_version(11), None);
+
+        versioned.rollback_to(4).expect("Should roll back");
+        assert_eq!(versioned.version(), 4);
+        assert_eq!(versioned.root(), expected.root());
+        assert_eq!(versioned.tree().len(), 4);
+        assert_eq!(versioned.root_at_version(5), None);
+
+        let proof = versioned
+            .tree()
+            .generate_proof(&data[3])
+            .expect("Should generate proof");
+        assert!(expected.verify_proof(&data[3], &proof));
+
+        // Appending after a rollback continues from the restored version
+        versioned
+            .extend_from(&data[4..6])
+            .expect("Should extend tree");
+        assert_eq!(versioned.version(), 5);
+        assert_eq!(
+            versioned.root(),
+            MerkleTree::new(&data[..6])
+                .expect("Should create merkle tree")
+                .root()
+        );
+        assert!(matches!(
+            versioned.rollback_to(6),
+            Err(MerkleError::UnknownVersion)
+        ));
+    }
+
+    #[test]
+    fn test_from_tree() {
+        let data = vec![b"block1".to_vec(), b"block2".to_vec()];
+        let versioned = VersionedMerkleTree::new(&data).expect("Should create versioned tree");
+        assert_eq!(versioned.version(), 0);
+        assert_eq!(versioned.root_at_version(0), Some(versioned.root()));
+
+        let canonical = MerkleTree::canonical(&data).expect("Should create merkle tree");
+        assert!(matches!(
+            VersionedMerkleTree::from_tree(canonical),
+            Err(MerkleError::IncompatibleTrees)
+        ));
+    }
+
+    #[test]
+    fn test_proofs_at_version() {
+        let data: Vec<Vec<u8>> = (0..10)
+            .map(|i| format!("block{}", i).into_bytes())
+            .collect();
+        let mut versioned = VersionedMerkleTree::empty();
+        for element in &data {
+            versioned.add(element.clone()).expect("Should add leaf");
+        }
+
+        for (version, indices) in [(4u64, vec![0, 2, 3]), (7, vec![0, 3, 4, 6])] {
+            let root = versioned
+                .root_at_version(version)
+                .expect("Should have version");
+            let old =
+                MerkleTree::new(&data[..version as usize]).expect("Should create merkle tree");
+            for index in indices {
+                let proof = versioned
+                    .generate_proof_at_version(version, index)
+                    .expect("Should generate proof");
+                assert_eq!(
+                    proof,
+                    old.generate_proof_by_index(index)
+                        .expect("Should generate proof")
+                );
+                assert!(old.verify_proof(&data[index], &proof));
+                assert!(verify(&root, &data[index], &proof));
+                assert!(!verify(&versioned.root(), &data[index], &proof));
+            }
+        }
+
+        assert!(matches!(
+            versioned.generate_proof_at_version(4, 4),
+            Err(MerkleError::IndexOutOfBounds)
+        ));
+        assert!(matches!(
+            versioned.generate_proof_at_version(11, 0),
+            Err(MerkleError::UnknownVersion)
+        ));
+    }
+
+    #[test]
+    fn test_proofs_at_every_size() {
+        let data: Vec<Vec<u8>> = (0..13).map(|i| vec![i as u8]).collect();
+        let trees = [
+            MerkleTree::new(&data).expect("Should create merkle tree"),
+            MerkleTree::with_odd_node_policy(&data, OddNodePolicy::PromoteToNextLevel)
+                .expect("Should create merkle tree"),
+            MerkleTree::with_depth(5, &data).expect("Should create merkle tree"),
+        ];
+        for tree in trees {
+            for size in 1..=data.len() {
+                let mut old = tree.clone();
+                old.truncate(size);
+                for index in 0..size {
+                    let proof = tree
+                        .generate_proof_at_size(size, index)
+                        .expect("Should generate proof");
+                    assert_eq!(
+                        proof,
+                        old.generate_proof_by_index(index)
+                            .expect("Should generate proof")
+                    );
+                }
+            }
+        }
+    }
+}